@@ -0,0 +1,138 @@
+//! Cycles recorded by the firmware's debug recorder, as CSV printed by the `record dump` command.
+//! Cycles with an RSSI were connected to WiFi. The slow clock starts over after a power loss, so
+//! only increasing times count as intervals.
+
+use crate::profile::Profile;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cycle {
+    /// Slow clock time in seconds.
+    pub time: u32,
+    pub connected: bool,
+    pub awake_ms: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub cycles: usize,
+    pub connected: usize,
+    /// Mean interval between cycles in seconds, if there are successive cycles.
+    pub interval: Option<f64>,
+    /// Mean time awake in ms of cycles without and with WiFi.
+    pub offline_ms: Option<f64>,
+    pub connected_ms: Option<f64>,
+}
+
+pub fn parse(text: &str) -> Result<Vec<Cycle>, String> {
+    let mut cycles = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("time,") {
+            continue;
+        }
+        let columns: Vec<_> = line.split(',').collect();
+        let cycle = match columns[..] {
+            [time, _value, rssi, awake_ms] | [time, _value, rssi, awake_ms, _] => Some(Cycle {
+                time: time
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid time", i + 1))?,
+                connected: !rssi.is_empty(),
+                awake_ms: awake_ms
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid awake time", i + 1))?,
+            }),
+            _ => None,
+        };
+        cycles.push(cycle.ok_or_else(|| format!("line {}: expected 4 or 5 columns", i + 1))?);
+    }
+    Ok(cycles)
+}
+
+pub fn summarize(cycles: &[Cycle]) -> Summary {
+    let mean = |values: Vec<f64>| {
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+    let awake = |connected: bool| {
+        let awake = cycles.iter().filter(|c| c.connected == connected);
+        mean(awake.map(|c| f64::from(c.awake_ms)).collect())
+    };
+    let intervals = cycles
+        .windows(2)
+        .filter(|pair| pair[1].time > pair[0].time)
+        .map(|pair| f64::from(pair[1].time - pair[0].time))
+        .collect();
+    Summary {
+        cycles: cycles.len(),
+        connected: cycles.iter().filter(|c| c.connected).count(),
+        interval: mean(intervals),
+        offline_ms: awake(false),
+        connected_ms: awake(true),
+    }
+}
+
+impl Summary {
+    /// Replaces the interval and timings of `profile` by the recorded ones. The recorder doesn't
+    /// split the time of connected cycles into phases, so all of it beyond sampling counts as
+    /// radio time.
+    pub fn apply(&self, profile: &mut Profile) {
+        if let Some(interval) = self.interval {
+            profile.interval = interval;
+        }
+        if self.connected > 0 {
+            profile.upload_every = self.cycles as f64 / self.connected as f64;
+        }
+        if let Some(offline_ms) = self.offline_ms {
+            profile.sample_ms = offline_ms;
+        }
+        if let Some(connected_ms) = self.connected_ms {
+            profile.connect_ms = (connected_ms - profile.sample_ms).max(0.0);
+            profile.sync_ms = 0.0;
+            profile.upload_ms = 0.0;
+        }
+    }
+}
+
+#[test]
+pub fn test_summarize() {
+    let cycles = parse(
+        "time,value,rssi,awake_ms,samples\n\
+         1000,2100,,400,2100 2101 2099\n\
+         4600,2110,,200\n\
+         8200,2120,-67,4300\n\
+         10,2130,,300\n\
+         3610,2140,-70,5700\n",
+    )
+    .unwrap();
+    assert_eq!(
+        cycles[2],
+        Cycle {
+            time: 8200,
+            connected: true,
+            awake_ms: 4300
+        }
+    );
+    let summary = summarize(&cycles);
+    assert_eq!(
+        summary,
+        Summary {
+            cycles: 5,
+            connected: 2,
+            interval: Some(3600.0),
+            offline_ms: Some(300.0),
+            connected_ms: Some(5000.0),
+        }
+    );
+
+    let mut profile = Profile::default();
+    summary.apply(&mut profile);
+    assert_eq!(profile.upload_every, 2.5);
+    assert_eq!(profile.sample_ms, 300.0);
+    assert_eq!(
+        profile.connect_ms + profile.sync_ms + profile.upload_ms,
+        4700.0
+    );
+
+    assert_eq!(summarize(&[]).interval, None);
+    assert!(parse("1000,2100,-67\n").is_err());
+    assert!(parse("soon,2100,-67,4000\n").is_err());
+}
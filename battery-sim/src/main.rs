@@ -0,0 +1,143 @@
+//! Projects the battery life of the sensor for a configuration, to compare settings before
+//! deploying. The current profile and phase timings come from a profile file and `key=value`
+//! arguments, and the interval and timings can be taken from cycles recorded on a sensor.
+//!
+//! ```text
+//! battery-sim [--profile FILE] [--cycles FILE] [key=value ...]
+//! ```
+
+mod cycles;
+mod profile;
+
+use crate::profile::Profile;
+use std::{env, fs, process};
+
+const DAY: f64 = 24.0 * 3600.0;
+
+#[derive(Debug, PartialEq)]
+struct Projection {
+    /// Charge of a cycle without and with an upload in µC.
+    offline_uc: f64,
+    upload_uc: f64,
+    /// Mean current in µA.
+    average_ua: f64,
+    /// Battery life in days.
+    days: f64,
+}
+
+fn project(profile: &Profile) -> Projection {
+    // mA times ms is µC.
+    let offline_uc = profile.sample_ms * profile.awake_ma;
+    let radio_ms = profile.connect_ms + profile.sync_ms + profile.upload_ms;
+    let upload_uc = offline_uc + radio_ms * (profile.awake_ma + profile.radio_ma);
+    let cycles = profile.upload_every.max(1.0);
+    let awake_s = (cycles * profile.sample_ms + radio_ms) / 1000.0;
+    let period_s = (cycles * profile.interval).max(awake_s);
+    let charge_uc =
+        (cycles - 1.0) * offline_uc + upload_uc + profile.sleep_ua * (period_s - awake_s);
+    let average_ua = charge_uc / period_s;
+    let usable_uc = profile.capacity_mah * profile.usable_percent / 100.0 * 3600.0 * 1000.0;
+    Projection {
+        offline_uc,
+        upload_uc,
+        average_ua,
+        days: usable_uc / average_ua / DAY,
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut profile = Profile::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => {
+                let path = args.next().ok_or("--profile needs a file")?;
+                let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                profile
+                    .apply(&text)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            }
+            "--cycles" => {
+                let path = args.next().ok_or("--cycles needs a file")?;
+                let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                let cycles = cycles::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+                let summary = cycles::summarize(&cycles);
+                println!(
+                    "{} recorded cycles, {} connected",
+                    summary.cycles, summary.connected
+                );
+                summary.apply(&mut profile);
+            }
+            _ => {
+                let (key, value) = arg
+                    .split_once('=')
+                    .ok_or_else(|| format!("unexpected argument {:?}", arg))?;
+                profile.set(key, value)?;
+            }
+        }
+    }
+
+    let projection = project(&profile);
+    println!(
+        "interval {:.0} s, upload every {:.1} cycles",
+        profile.interval, profile.upload_every
+    );
+    println!(
+        "cycle without upload {:.1} mC, with upload {:.1} mC",
+        projection.offline_uc / 1000.0,
+        projection.upload_uc / 1000.0
+    );
+    println!("average current {:.1} µA", projection.average_ua);
+    println!(
+        "battery life {:.0} days with {:.0} % of {:.0} mAh usable",
+        projection.days, profile.usable_percent, profile.capacity_mah
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[test]
+pub fn test_project() {
+    let profile = Profile {
+        capacity_mah: 1000.0,
+        usable_percent: 100.0,
+        sleep_ua: 0.0,
+        awake_ma: 20.0,
+        radio_ma: 80.0,
+        interval: 1000.0,
+        upload_every: 2.0,
+        sample_ms: 500.0,
+        connect_ms: 1000.0,
+        sync_ms: 500.0,
+        upload_ms: 500.0,
+    };
+    let projection = project(&profile);
+    assert_eq!(projection.offline_uc, 10_000.0);
+    // 10 mC sampling and 2 s at 100 mA.
+    assert_eq!(projection.upload_uc, 210_000.0);
+    // 220 mC over 2000 s.
+    assert_eq!(projection.average_ua, 110.0);
+    // 1000 mAh is 3600 C.
+    assert!((projection.days - 3_600_000_000.0 / 110.0 / DAY).abs() < 1e-9);
+
+    let sleeping = project(&Profile {
+        sleep_ua: 50.0,
+        ..profile.clone()
+    });
+    // Awake for 3 s of the 2000 s.
+    assert_eq!(sleeping.average_ua, 110.0 + 50.0 * 1997.0 / 2000.0);
+
+    let default = project(&Profile::default());
+    let longer = project(&Profile {
+        interval: 7200.0,
+        ..Default::default()
+    });
+    assert!(longer.days > default.days);
+}
@@ -0,0 +1,157 @@
+//! Current draw of the board and timing of the phases of a cycle. Profiles are files of
+//! `key = value` lines, a subset of TOML, and each key can also be given on the command line. The
+//! defaults are rough estimates for the ESP32-C3 board, to be replaced by measurements.
+
+/// Typical time in ms to send an upload over each transport, with a TLS connection set up during
+/// the time sync for HTTP, and a new connection to the broker for MQTT.
+const TRANSPORTS: [(&str, f64); 5] = [
+    ("influx", 800.0),
+    ("otlp", 1000.0),
+    ("postgrest", 1000.0),
+    ("mqtt", 1500.0),
+    ("graphite", 200.0),
+];
+
+/// Time sync policies: waiting for SNTP, or for the fallback wait of the firmware before the
+/// manually set or peer time is used when no SNTP server is reachable.
+const SYNC_POLICIES: [(&str, f64); 2] = [("sntp", 1000.0), ("fallback", 10_000.0)];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// Capacity of the battery in mAh.
+    pub capacity_mah: f64,
+    /// Share of the capacity usable before the supply voltage gets too low, in percent.
+    pub usable_percent: f64,
+    /// Current in deep sleep in µA.
+    pub sleep_ua: f64,
+    /// Current while awake in mA.
+    pub awake_ma: f64,
+    /// Current while the radio is on in mA, in addition to `awake_ma`.
+    pub radio_ma: f64,
+    /// Interval between cycles in seconds.
+    pub interval: f64,
+    /// Cycles per upload, 6 with the buffering of the firmware.
+    pub upload_every: f64,
+    /// Time awake in ms in every cycle, mostly sampling the probe.
+    pub sample_ms: f64,
+    /// Time in ms for association and DHCP in cycles that upload.
+    pub connect_ms: f64,
+    /// Time in ms for the time sync in cycles that upload.
+    pub sync_ms: f64,
+    /// Time in ms for sending the upload.
+    pub upload_ms: f64,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            capacity_mah: 1000.0,
+            usable_percent: 80.0,
+            sleep_ua: 40.0,
+            awake_ma: 25.0,
+            radio_ma: 55.0,
+            interval: 3600.0,
+            upload_every: 6.0,
+            sample_ms: 300.0,
+            connect_ms: 2500.0,
+            sync_ms: SYNC_POLICIES[0].1,
+            upload_ms: TRANSPORTS[0].1,
+        }
+    }
+}
+
+impl Profile {
+    /// Sets `key` to `value`. `transport` and `sync` set the upload and sync times of a transport
+    /// and time sync policy.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim().trim_matches('"');
+        let named = |table: &[(&str, f64)]| {
+            let names: Vec<_> = table.iter().map(|(name, _)| *name).collect();
+            table
+                .iter()
+                .find(|(name, _)| *name == value)
+                .map(|(_, ms)| *ms)
+                .ok_or_else(|| format!("{} must be one of {}", key, names.join(", ")))
+        };
+        match key {
+            "transport" => self.upload_ms = named(&TRANSPORTS)?,
+            "sync" => self.sync_ms = named(&SYNC_POLICIES)?,
+            _ => *self.field(key)? = parse_number(key, value)?,
+        }
+        Ok(())
+    }
+
+    fn field(&mut self, key: &str) -> Result<&mut f64, String> {
+        Ok(match key {
+            "capacity_mah" => &mut self.capacity_mah,
+            "usable_percent" => &mut self.usable_percent,
+            "sleep_ua" => &mut self.sleep_ua,
+            "awake_ma" => &mut self.awake_ma,
+            "radio_ma" => &mut self.radio_ma,
+            "interval" => &mut self.interval,
+            "upload_every" => &mut self.upload_every,
+            "sample_ms" => &mut self.sample_ms,
+            "connect_ms" => &mut self.connect_ms,
+            "sync_ms" => &mut self.sync_ms,
+            "upload_ms" => &mut self.upload_ms,
+            _ => return Err(format!("unknown setting {:?}", key)),
+        })
+    }
+
+    /// Applies the `key = value` lines of `text`, skipping comments and blank lines.
+    pub fn apply(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected key = value", i + 1))?;
+            self.set(key.trim(), value)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!(
+            "{} must be a non-negative number: {:?}",
+            key, value
+        )),
+    }
+}
+
+#[test]
+pub fn test_apply() {
+    let mut profile = Profile::default();
+    profile
+        .apply(
+            "# Two sensors on a 2000 mAh cell\n\
+             capacity_mah = 2000\n\
+             \n\
+             interval = 1800 # half an hour\n\
+             transport = \"mqtt\"\n\
+             sync = \"fallback\"\n",
+        )
+        .unwrap();
+    assert_eq!(
+        profile,
+        Profile {
+            capacity_mah: 2000.0,
+            interval: 1800.0,
+            upload_ms: 1500.0,
+            sync_ms: 10_000.0,
+            ..Default::default()
+        }
+    );
+
+    assert!(profile.set("interval", "-1").is_err());
+    assert!(profile.set("interval", "hourly").is_err());
+    assert!(profile.set("transport", "http").is_err());
+    assert!(profile.set("brightness", "100").is_err());
+    assert!(profile.apply("interval 60\n").is_err());
+}
@@ -1,4 +1,9 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() -> anyhow::Result<()> {
+    let build_timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+
     embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
     embuild::build::LinkArgs::output_propagated("ESP_IDF")
 }
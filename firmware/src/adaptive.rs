@@ -0,0 +1,65 @@
+//! Adaptive interval: instead of following the schedule bands, the interval until the next cycle
+//! follows how fast readings change. It drops to the minimum when consecutive readings change
+//! rapidly, e.g. after watering, and doubles with every flat reading up to the maximum, so that
+//! stable soil costs few wakes. Readings changing at a rate in between keep the interval. The
+//! settings are stored here, the interval is adapted by [`crate::scheduler`].
+
+use crate::schedule;
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+pub use crate::scheduler::AdaptiveSettings as Settings;
+
+const NVS_NAMESPACE: &str = "adaptive";
+const NVS_KEY: &str = "settings";
+
+impl Settings {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Settings> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+}
+
+pub fn validate(settings: &Settings) -> Result<()> {
+    if settings.min_interval < schedule::MIN_INTERVAL {
+        bail!(
+            "adaptive minimum interval is shorter than {} s",
+            schedule::MIN_INTERVAL
+        );
+    }
+    if settings.max_interval < settings.min_interval {
+        bail!("adaptive maximum interval is shorter than the minimum");
+    }
+    if settings.flat_rate >= settings.rapid_rate {
+        bail!("adaptive flat rate must be below the rapid rate");
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_validate() {
+    let settings = Settings {
+        enabled: true,
+        ..Default::default()
+    };
+    validate(&settings).unwrap();
+    assert!(validate(&Settings {
+        min_interval: 30,
+        ..Default::default()
+    })
+    .is_err());
+    assert!(validate(&Settings {
+        max_interval: 600,
+        ..Default::default()
+    })
+    .is_err());
+    assert!(validate(&Settings {
+        flat_rate: 100,
+        ..Default::default()
+    })
+    .is_err());
+}
@@ -0,0 +1,187 @@
+//! Raw ADC traces of the probe for support cases, so that electrical issues of a probe in the field
+//! can be analyzed without shipping it back. The remote command `trace capture` requests a trace,
+//! which the next cycle takes after its reading: `SAMPLES` consecutive samples from the start of
+//! the excitation, unfiltered, written to NVS in chunks. The chunks are uploaded as `trace` points,
+//! `CHUNKS_PER_UPLOAD` with every upload, so that a trace doesn't hold up the measurements. A chunk
+//! is only marked as uploaded once acknowledged, so a failed upload resumes with the same chunk.
+
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+const NVS_NAMESPACE: &str = "adc_trace";
+const STATE_NVS_KEY: &str = "state";
+/// Samples of a trace, which take well under a second of one-shot reads.
+pub const SAMPLES: usize = 4096;
+/// Samples per chunk, stored in a value of its own.
+const CHUNK_SAMPLES: usize = 512;
+const CHUNKS_PER_UPLOAD: u16 = 2;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    /// Channel of a requested trace not taken yet.
+    requested: Option<u8>,
+    captured: Option<Capture>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capture {
+    /// Slow clock time in seconds at which the trace was taken, which also identifies it.
+    pub time: u32,
+    /// ADC1 channel of the probe.
+    pub channel: u8,
+    pub chunks: u16,
+    /// Chunks uploaded so far.
+    pub uploaded: u16,
+}
+
+impl Capture {
+    fn next_chunks(&self) -> Range<u16> {
+        self.uploaded..self.chunks.min(self.uploaded + CHUNKS_PER_UPLOAD)
+    }
+}
+
+pub struct Chunk {
+    pub capture: Capture,
+    pub index: u16,
+    /// Samples in mV.
+    pub samples: Vec<u16>,
+}
+
+/// Requests a trace of the probe on ADC1 `channel`, replacing a trace not fully uploaded yet.
+pub fn request(partition: &EspDefaultNvsPartition, channel: u8) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    namespace.set(
+        STATE_NVS_KEY,
+        &State {
+            requested: Some(channel),
+            captured: None,
+        },
+    )
+}
+
+/// Returns the channel of the requested trace, if any.
+pub fn requested(partition: &EspDefaultNvsPartition) -> Result<Option<u8>> {
+    Ok(load(&Namespace::open(partition, NVS_NAMESPACE)?)?.requested)
+}
+
+/// Stores the trace `samples` taken at slow clock `time`, ending the request.
+pub fn store(
+    partition: &EspDefaultNvsPartition,
+    time: u32,
+    channel: u8,
+    samples: &[u16],
+) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut chunks = 0;
+    for chunk in samples.chunks(CHUNK_SAMPLES) {
+        namespace.set_bytes(&chunk_key(chunks), &encode(chunk))?;
+        chunks += 1;
+    }
+    let capture = Capture {
+        time,
+        channel,
+        chunks,
+        uploaded: 0,
+    };
+    println!(
+        "stored trace of {} samples in {} chunks",
+        samples.len(),
+        chunks
+    );
+    namespace.set(
+        STATE_NVS_KEY,
+        &State {
+            requested: None,
+            captured: Some(capture),
+        },
+    )
+}
+
+/// Returns the chunks to upload next.
+pub fn pending(partition: &EspDefaultNvsPartition) -> Result<Vec<Chunk>> {
+    let namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let capture = match load(&namespace)?.captured {
+        Some(capture) => capture,
+        None => return Ok(Vec::new()),
+    };
+    let mut chunks = Vec::new();
+    for index in capture.next_chunks() {
+        if let Some(data) = namespace.get_bytes(&chunk_key(index))? {
+            chunks.push(Chunk {
+                capture,
+                index,
+                samples: decode(&data),
+            });
+        }
+    }
+    Ok(chunks)
+}
+
+/// Marks the chunks returned by [`pending`] as uploaded, removing the trace after its last chunk.
+pub fn mark_uploaded(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut state = load(&namespace)?;
+    let capture = match state.captured.as_mut() {
+        Some(capture) => capture,
+        None => return Ok(()),
+    };
+    capture.uploaded = capture.next_chunks().end;
+    if capture.uploaded < capture.chunks {
+        return namespace.set(STATE_NVS_KEY, &state);
+    }
+    println!("uploaded trace of {} chunks", capture.chunks);
+    for index in 0..capture.chunks {
+        namespace.remove(&chunk_key(index))?;
+    }
+    namespace.remove(STATE_NVS_KEY)
+}
+
+fn load(namespace: &Namespace) -> Result<State> {
+    namespace.get_or_default(STATE_NVS_KEY)
+}
+
+fn chunk_key(index: u16) -> String {
+    format!("chunk{}", index)
+}
+
+/// Samples are stored as little-endian bytes rather than JSON to fit a chunk into a single value.
+fn encode(samples: &[u16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+fn decode(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Formats `samples` as space separated values, to be uploaded as a string field.
+pub fn format_samples(samples: &[u16]) -> String {
+    let samples: Vec<_> = samples.iter().map(u16::to_string).collect();
+    samples.join(" ")
+}
+
+#[test]
+pub fn test_chunks() {
+    let samples: Vec<u16> = (0..CHUNK_SAMPLES as u16 + 3).map(|i| 2000 + i).collect();
+    assert_eq!(decode(&encode(&samples)), samples);
+    assert_eq!(encode(&samples[..CHUNK_SAMPLES]).len(), 1024);
+    assert_eq!(format_samples(&[2100, 980, 3]), "2100 980 3");
+
+    let mut capture = Capture {
+        time: 100,
+        channel: 4,
+        chunks: (SAMPLES / CHUNK_SAMPLES) as u16,
+        uploaded: 0,
+    };
+    assert_eq!(capture.next_chunks(), 0..2);
+    capture.uploaded = 6;
+    assert_eq!(capture.next_chunks(), 6..8);
+    capture.chunks = 7;
+    assert_eq!(capture.next_chunks(), 6..7);
+    capture.uploaded = 7;
+    assert!(capture.next_chunks().is_empty());
+}
@@ -0,0 +1,230 @@
+//! Dryness alert. An alert is raised once when a reading reaches the configured threshold, kept
+//! until it has been notified in a connected session, and cleared when the soil is moist again.
+//! An alert raised as warning is raised again when the soil gets critically dry. A snoozed alert
+//! isn't raised again until the snooze ends. Readings during maintenance don't change the alert
+//! state.
+
+use crate::rtc::STATE;
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const NVS_NAMESPACE: &str = "alert";
+const NVS_KEY: &str = "policy";
+/// Margin below the threshold a reading has to fall to clear the alert, so that readings
+/// fluctuating around the threshold don't raise it again and again.
+const HYSTERESIS: u16 = 50;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// Reading in mV at and above which the soil is too dry. Alerts are disabled if 0.
+    pub dry_above: u16,
+    /// Reading in mV at and above which the soil is critically dry. Only warnings are raised if 0.
+    pub critical_above: u16,
+}
+
+impl Policy {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Policy> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    /// Returns the severity of `value` and the threshold it reaches, if it's too dry.
+    fn classify(&self, value: u16) -> Option<(Severity, u16)> {
+        if self.critical_above != 0 && value >= self.critical_above {
+            Some((Severity::Critical, self.critical_above))
+        } else if value >= self.dry_above {
+            Some((Severity::Warning, self.dry_above))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Alert {
+    pub severity: Severity,
+    pub value: u16,
+    /// Threshold of the severity.
+    pub threshold: u16,
+    /// Slow clock time in seconds of the reading.
+    pub time: u32,
+}
+
+impl Alert {
+    pub fn message(&self, plant: &str) -> String {
+        let (icon, needs) = match self.severity {
+            Severity::Warning => ("🌱", "needs"),
+            Severity::Critical => ("🥀", "urgently needs"),
+        };
+        format!(
+            "{} {} {} water: the soil reads {} mV, the dryness threshold is {} mV.",
+            icon, plant, needs, self.value, self.threshold
+        )
+    }
+}
+
+#[derive(Hash)]
+pub struct State {
+    /// Severity of the raised alert, if any.
+    active: Option<Severity>,
+    /// Raised alert not notified yet.
+    pending: Option<Alert>,
+    /// Slow clock time in seconds until which no alert is raised.
+    snoozed_until: u32,
+}
+
+impl State {
+    pub const fn new() -> State {
+        State {
+            active: None,
+            pending: None,
+            snoozed_until: 0,
+        }
+    }
+
+    fn evaluate(&mut self, policy: &Policy, value: u16, time: u32, maintenance: bool) {
+        if maintenance || policy.dry_above == 0 {
+            return;
+        }
+        if self.active.is_some() && value.saturating_add(HYSTERESIS) < policy.dry_above {
+            println!("soil moist again, clearing alert");
+            self.active = None;
+            self.pending = None;
+            return;
+        }
+        let (severity, threshold) = match policy.classify(value) {
+            Some(classified) => classified,
+            None => return,
+        };
+        if time < self.snoozed_until || self.active >= Some(severity) {
+            return;
+        }
+        println!("soil too dry, raising {:?} alert", severity);
+        self.active = Some(severity);
+        self.pending = Some(Alert {
+            severity,
+            value,
+            threshold,
+            time,
+        });
+    }
+
+    fn snooze(&mut self, now: u32, duration: Duration) {
+        self.active = None;
+        self.pending = None;
+        self.snoozed_until = now.saturating_add(duration.as_secs().try_into().unwrap_or(u32::MAX));
+    }
+}
+
+/// Updates the alert state with a reading.
+pub fn evaluate(policy: &Policy, value: u16, time: u32, maintenance: bool) {
+    unsafe { STATE.alert.evaluate(policy, value, time, maintenance) }
+}
+
+/// Returns the alert to notify, if any.
+pub fn pending() -> Option<Alert> {
+    unsafe { STATE.alert.pending }
+}
+
+/// Whether an alert has been raised and not cleared or snoozed since.
+pub fn is_active() -> bool {
+    unsafe { STATE.alert.active.is_some() }
+}
+
+pub fn mark_notified() {
+    unsafe {
+        STATE.alert.pending = None;
+    }
+}
+
+/// Drops the alert, e.g. because the plant has been watered, and raises none for `duration` from
+/// slow clock time `now`. If the soil is still dry afterwards, the alert is raised again.
+pub fn snooze(now: u32, duration: Duration) {
+    println!("snoozing alerts for {} min", duration.as_secs() / 60);
+    unsafe { STATE.alert.snooze(now, duration) }
+}
+
+#[test]
+pub fn test_evaluate() {
+    let policy = Policy {
+        dry_above: 2000,
+        critical_above: 2400,
+    };
+    let mut state = State::new();
+    state.evaluate(&policy, 1990, 1, false);
+    assert_eq!(state.pending, None);
+    state.evaluate(&policy, 2100, 2, true);
+    assert_eq!(state.pending, None);
+    state.evaluate(&policy, 2000, 3, false);
+    assert_eq!(
+        state.pending,
+        Some(Alert {
+            severity: Severity::Warning,
+            value: 2000,
+            threshold: 2000,
+            time: 3
+        })
+    );
+
+    state.pending = None;
+    state.evaluate(&policy, 2100, 4, false);
+    state.evaluate(&policy, 1960, 5, false);
+    assert_eq!(state.pending, None);
+    assert_eq!(state.active, Some(Severity::Warning));
+    state.evaluate(&policy, 2500, 6, false);
+    assert_eq!(
+        state.pending.map(|alert| (alert.severity, alert.threshold)),
+        Some((Severity::Critical, 2400))
+    );
+    state.pending = None;
+    state.evaluate(&policy, 2100, 7, false);
+    assert_eq!(state.pending, None);
+    state.evaluate(&policy, 1940, 8, false);
+    assert_eq!(state.active, None);
+
+    state.evaluate(&policy, 2500, 9, false);
+    state.snooze(10, Duration::from_secs(100));
+    assert_eq!((state.active, state.pending), (None, None));
+    state.evaluate(&policy, 2500, 109, false);
+    assert_eq!(state.pending, None);
+    state.evaluate(&policy, 2500, 110, false);
+    assert_eq!(state.active, Some(Severity::Critical));
+
+    let mut state = State::new();
+    state.evaluate(&Policy::default(), 3000, 11, false);
+    assert_eq!(state.active, None);
+
+    let alert = Alert {
+        severity: Severity::Warning,
+        value: 2100,
+        threshold: 2000,
+        time: 0,
+    };
+    assert_eq!(
+        alert.message("Basil"),
+        "🌱 Basil needs water: the soil reads 2100 mV, the dryness threshold is 2000 mV."
+    );
+    let alert = Alert {
+        severity: Severity::Critical,
+        value: 2500,
+        threshold: 2400,
+        ..alert
+    };
+    assert_eq!(
+        alert.message("Basil"),
+        "🥀 Basil urgently needs water: the soil reads 2500 mV, the dryness threshold is 2400 mV."
+    );
+}
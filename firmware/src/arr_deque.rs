@@ -1,5 +1,16 @@
 use std::mem::MaybeUninit;
 
+/// Position of the elements of a deque in its array, see [`ArrDeque::span`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    start: usize,
+    len: usize,
+}
+
+impl Span {
+    pub const EMPTY: Span = Span { start: 0, len: 0 };
+}
+
 pub struct ArrDeque<T, const N: usize> {
     full: bool,
     start: usize,
@@ -32,11 +43,27 @@ impl<T, const N: usize> ArrDeque<T, N> {
         !self.full && self.start == self.end
     }
 
-    pub fn overwriting_push_back(&mut self, value: T) {
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Appends `value`, returning the front element if it had to be removed to make room.
+    pub fn overwriting_push_back(&mut self, value: T) -> Option<T> {
+        let overwritten = if self.full { self.pop_front() } else { None };
+        self.push_back_unchecked(value);
+        overwritten
+    }
+
+    /// Appends `value`, or returns it if the deque is full.
+    pub fn try_push_back(&mut self, value: T) -> Result<(), T> {
         if self.full {
-            self.pop_front();
+            return Err(value);
         }
+        self.push_back_unchecked(value);
+        Ok(())
+    }
 
+    fn push_back_unchecked(&mut self, value: T) {
         self.arr[self.end].write(value);
         if self.end < N - 1 {
             self.end += 1;
@@ -64,14 +91,120 @@ impl<T, const N: usize> ArrDeque<T, N> {
         Some(value)
     }
 
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(unsafe { self.arr[self.start].assume_init_ref() })
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        let pos = if self.end > 0 { self.end - 1 } else { N - 1 };
+        Some(unsafe { self.arr[pos].assume_init_ref() })
+    }
+
     pub fn iter(&self) -> Iter<T, N> {
         Iter::new(self)
     }
+
+    /// Returns the position of the elements in the array, to keep the deque in place while
+    /// recording its contents elsewhere.
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            len: self.len(),
+        }
+    }
+
+    /// Makes the deque hold the elements at `span` of its array, without dropping the elements it
+    /// held before.
+    ///
+    /// # Safety
+    ///
+    /// The elements at `span` must be initialized, as they are at a span returned by `span` until
+    /// they are removed.
+    pub unsafe fn set_span(&mut self, span: Span) {
+        self.start = span.start % N;
+        self.end = (span.start + span.len) % N;
+        self.full = span.len == N;
+    }
+
+    /// Removes and yields elements from the front. Elements that aren't yielded stay in the
+    /// deque, so a consumer can stop after a part of it.
+    pub fn drain(&mut self) -> Drain<T, N> {
+        Drain { deque: self }
+    }
 }
 
 impl<T, const N: usize> Drop for ArrDeque<T, N> {
     fn drop(&mut self) {
-        while let Some(_) = self.pop_front() {}
+        self.clear();
+    }
+}
+
+/// Appends the elements, overwriting the oldest ones when full.
+impl<T, const N: usize> Extend<T> for ArrDeque<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.overwriting_push_back(value);
+        }
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a ArrDeque<T, N> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for ArrDeque<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { deque: self }
+    }
+}
+
+pub struct IntoIter<T, const N: usize> {
+    deque: ArrDeque<T, N>,
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.deque.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.deque.len(), Some(self.deque.len()))
+    }
+}
+
+pub struct Drain<'a, T, const N: usize> {
+    deque: &'a mut ArrDeque<T, N>,
+}
+
+impl<'a, T, const N: usize> Iterator for Drain<'a, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.deque.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.deque.len(), Some(self.deque.len()))
     }
 }
 
@@ -118,9 +251,11 @@ pub fn test_arr_deque() {
     let mut deque: ArrDeque<u8, 5> = ArrDeque::new();
     for _ in 0..3 {
         for _ in 0..3 {
-            for i in 0..7 {
+            for i in 0..5 {
                 deque.overwriting_push_back(i);
             }
+            assert_eq!(deque.overwriting_push_back(5), Some(0));
+            assert_eq!(deque.overwriting_push_back(6), Some(1));
             let mut iter = deque.iter();
             for i in 2..7 {
                 assert_eq!(iter.next().cloned(), Some(i));
@@ -132,4 +267,50 @@ pub fn test_arr_deque() {
         }
         assert_eq!(deque.pop_front(), None);
     }
+
+    assert_eq!(deque.capacity(), 5);
+    assert_eq!((deque.front(), deque.back()), (None, None));
+    for i in 0..5 {
+        assert_eq!(deque.try_push_back(i), Ok(()));
+    }
+    assert_eq!(deque.try_push_back(5), Err(5));
+    assert_eq!((deque.front(), deque.back()), (Some(&0), Some(&4)));
+    assert_eq!(deque.pop_front(), Some(0));
+    assert_eq!(deque.try_push_back(5), Ok(()));
+    assert_eq!((deque.front(), deque.back()), (Some(&1), Some(&5)));
+    deque.clear();
+    assert!(deque.is_empty());
+    assert_eq!(deque.back(), None);
+    assert_eq!(deque.try_push_back(6), Ok(()));
+    assert_eq!((deque.front(), deque.back()), (Some(&6), Some(&6)));
+
+    deque.extend(7..12);
+    assert_eq!((&deque).into_iter().count(), 5);
+    let drained: Vec<_> = deque.drain().take(2).collect();
+    assert_eq!(drained, vec![7, 8]);
+    assert_eq!(deque.front(), Some(&9));
+    let owned: Vec<_> = deque.into_iter().collect();
+    assert_eq!(owned, vec![9, 10, 11]);
+
+    let mut deque: ArrDeque<u8, 5> = ArrDeque::new();
+    deque.extend(0..3);
+    let span = deque.span();
+    deque.pop_front();
+    deque.overwriting_push_back(3);
+    unsafe { deque.set_span(span) };
+    assert_eq!(deque.iter().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+    unsafe { deque.set_span(Span::EMPTY) };
+    assert!(deque.is_empty());
+
+    // Owned elements are dropped exactly once, whether yielded or left in the deque.
+    let counter = std::rc::Rc::new(());
+    let mut deque: ArrDeque<std::rc::Rc<()>, 3> = ArrDeque::new();
+    deque.extend((0..4).map(|_| counter.clone()));
+    assert_eq!(std::rc::Rc::strong_count(&counter), 4);
+    deque.drain().next();
+    assert_eq!(std::rc::Rc::strong_count(&counter), 3);
+    let mut iter = deque.into_iter();
+    iter.next();
+    drop(iter);
+    assert_eq!(std::rc::Rc::strong_count(&counter), 1);
 }
@@ -0,0 +1,202 @@
+//! Limit of the time a cycle stays awake, so that a step that never returns, such as waiting for
+//! WiFi to connect or for an IP address, doesn't drain the battery. A timer forces deep sleep once
+//! the cycle has been awake for the configured limit. Steps that wait for a person, like the
+//! console, calibration and provisioning, and the download of an update don't count towards it.
+//! Powered sensors stay awake on purpose, so the limit ends before they start serving.
+//!
+//! The forced sleep is recorded in RTC memory and uploaded with its error code by the next cycle,
+//! which doesn't resume the aborted one.
+
+use crate::error_code::{self, ErrorCode};
+use crate::power;
+use crate::retry;
+use crate::rtc::STATE;
+use crate::storage::Namespace;
+use crate::Phase;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::{self as sys, esp};
+use serde::{Deserialize, Serialize};
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const NVS_NAMESPACE: &str = "awake";
+const NVS_KEY: &str = "limit";
+/// Shortest limit, which leaves time to connect and upload.
+const MIN_SECONDS: u32 = 15;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Time awake in seconds after which the cycle is ended, or 0 for no limit. The default leaves
+    /// time to connect beyond the budget of the session.
+    pub seconds: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings { seconds: 90 }
+    }
+}
+
+impl Settings {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Settings> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    pub fn limit(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.seconds.into())).filter(|limit| !limit.is_zero())
+    }
+}
+
+pub fn validate(settings: &Settings) -> Result<()> {
+    if settings.seconds != 0 && settings.seconds < MIN_SECONDS {
+        bail!("awake time limit must be 0 or at least {} s", MIN_SECONDS);
+    }
+    Ok(())
+}
+
+/// Whether the previous cycle was ended by the limit. Kept across deep sleep.
+#[link_section = ".rtc.data.awake_limit"]
+static mut FORCED: bool = false;
+
+struct Timer {
+    handle: sys::esp_timer_handle_t,
+    /// Time since boot in µs at which the limit is reached.
+    deadline: i64,
+}
+
+// The handle is only passed to the esp_timer API, which is thread-safe.
+unsafe impl Send for Timer {}
+
+static TIMER: Mutex<Option<Timer>> = Mutex::new(None);
+
+impl Timer {
+    fn start(&self) -> Result<()> {
+        let remaining = (self.deadline - now()).max(0);
+        esp!(unsafe { sys::esp_timer_start_once(self.handle, remaining as u64) })?;
+        Ok(())
+    }
+}
+
+/// Starts the limit, counting the time awake since boot.
+pub fn arm(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let limit = match Settings::load(partition)?.limit() {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let args = sys::esp_timer_create_args_t {
+        callback: Some(expired),
+        arg: ptr::null_mut(),
+        dispatch_method: sys::esp_timer_dispatch_t_ESP_TIMER_TASK,
+        name: b"awake_limit\0".as_ptr() as _,
+        skip_unhandled_events: false,
+    };
+    let mut handle = ptr::null_mut();
+    esp!(unsafe { sys::esp_timer_create(&args, &mut handle) })?;
+    let timer = Timer {
+        handle,
+        deadline: limit.as_micros() as i64,
+    };
+    timer.start()?;
+    *TIMER.lock().unwrap() = Some(timer);
+    Ok(())
+}
+
+/// Runs `f` without counting its time towards the limit.
+pub fn paused<T>(f: impl FnOnce() -> T) -> T {
+    let start = now();
+    if let Some(timer) = TIMER.lock().unwrap().as_ref() {
+        // Fails if the timer isn't running, which leaves nothing to stop.
+        unsafe { sys::esp_timer_stop(timer.handle) };
+    }
+    let result = f();
+    if let Some(timer) = TIMER.lock().unwrap().as_mut() {
+        timer.deadline += now() - start;
+        if let Err(e) = timer.start() {
+            println!("error restarting awake time limit: {}", e);
+        }
+    }
+    result
+}
+
+/// Ends the limit, before going to sleep or for a cycle that stays awake on purpose.
+pub fn disarm() {
+    if let Some(timer) = TIMER.lock().unwrap().take() {
+        unsafe {
+            sys::esp_timer_stop(timer.handle);
+            sys::esp_timer_delete(timer.handle);
+        }
+    }
+}
+
+/// Runs in the task of the timer while the main task is stuck, so the RTC state isn't committed.
+/// The next cycle starts from the snapshot taken at the start of the stuck phase.
+extern "C" fn expired(_: *mut c_void) {
+    let awake = Duration::from_micros(now() as u64);
+    println!(
+        "awake for {} ms, longer than the limit, forcing deep sleep",
+        awake.as_millis()
+    );
+    unsafe {
+        ptr::write_volatile(&mut FORCED, true);
+        power::force_power_save();
+        crate::go_to_sleep(awake);
+    }
+}
+
+/// Records the sleep forced in the previous cycle, if any, at slow clock `time`. Network phases
+/// count as failed, so that they are retried with the same backoff as after an error.
+pub fn record_forced(time: u32) {
+    if !unsafe { ptr::replace(&mut FORCED, false) } {
+        return;
+    }
+    let phase = unsafe { STATE.phase };
+    println!(
+        "previous cycle exceeded the awake time limit in {:?}",
+        phase
+    );
+    error_code::record(ErrorCode::AwakeLimit, time);
+    if matches!(
+        phase,
+        Phase::Connect | Phase::Sync | Phase::ConfigPoll | Phase::Upload
+    ) {
+        retry::record_failure();
+    }
+    unsafe {
+        STATE.phase = Phase::Sleep;
+        STATE.resumed = false;
+    }
+}
+
+/// Time since boot in µs.
+fn now() -> i64 {
+    unsafe { sys::esp_timer_get_time() }
+}
+
+#[test]
+pub fn test_awake_limit() {
+    assert_eq!(Settings::default().limit(), Some(Duration::from_secs(90)));
+    assert_eq!(Settings { seconds: 0 }.limit(), None);
+    validate(&Settings::default()).unwrap();
+    validate(&Settings { seconds: 0 }).unwrap();
+    assert!(validate(&Settings { seconds: 5 }).is_err());
+
+    unsafe {
+        FORCED = true;
+        STATE.phase = Phase::Connect;
+    }
+    record_forced(100);
+    let failure = error_code::pending().unwrap();
+    assert_eq!((failure.code, failure.time), (ErrorCode::AwakeLimit, 100));
+    assert_eq!(unsafe { STATE.phase }, Phase::Sleep);
+    error_code::clear();
+    record_forced(200);
+    assert_eq!(error_code::pending(), None);
+}
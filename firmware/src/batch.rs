@@ -0,0 +1,40 @@
+//! Size of the batches that uploads are split into, so that requests stay within what constrained
+//! servers accept. Each batch is sent as its own request, and only the measurements of
+//! acknowledged batches count as uploaded. The rest stay buffered for the next upload.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const NVS_NAMESPACE: &str = "upload";
+const NVS_KEY: &str = "batch_size";
+pub const DEFAULT_SIZE: u16 = 100;
+/// Most measurements per batch, as many as can be buffered.
+const MAX_SIZE: u16 = crate::MAX_RECORDED_MEASUREMENTS as u16;
+
+/// Returns the number of measurements per batch.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<u16> {
+    Ok(Namespace::open(partition, NVS_NAMESPACE)?
+        .get(NVS_KEY)?
+        .unwrap_or(DEFAULT_SIZE))
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, size: u16) -> Result<()> {
+    Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, &size)
+}
+
+pub fn validate(size: u16) -> Result<()> {
+    if !(1..=MAX_SIZE).contains(&size) {
+        bail!("upload batch size must be from 1 to {}", MAX_SIZE);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_validate() {
+    validate(DEFAULT_SIZE).unwrap();
+    validate(1).unwrap();
+    validate(MAX_SIZE).unwrap();
+    assert!(validate(0).is_err());
+    assert!(validate(MAX_SIZE + 1).is_err());
+}
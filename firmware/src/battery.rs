@@ -0,0 +1,21 @@
+//! Battery voltage, read via a voltage divider from the battery to GPIO3, enabled with the
+//! `battery` feature. The divider isn't on the board and has to be added, e.g. two equal resistors
+//! of 100 kΩ or more, so that it drains little current.
+
+use anyhow::Result;
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::{adc, gpio};
+
+/// Ratio of the battery voltage to the voltage at the pin.
+const DIVIDER_RATIO: u16 = 2;
+
+/// Returns the battery voltage in mV.
+pub fn read(
+    adc: impl Peripheral<P = adc::ADC1>,
+    adc_pin: impl Peripheral<P = gpio::Gpio3>,
+) -> Result<u16> {
+    let mut adc_driver = adc::AdcDriver::new(adc, &adc::config::Config::new().calibration(true))?;
+    let mut adc_channel_driver: adc::AdcChannelDriver<gpio::Gpio3, adc::Atten11dB<_>> =
+        adc::AdcChannelDriver::new(adc_pin)?;
+    Ok(adc_driver.read(&mut adc_channel_driver)? * DIVIDER_RATIO)
+}
@@ -0,0 +1,248 @@
+//! Owns the peripherals of the board. Drivers that are only needed for a short time, such as those
+//! of the probe, are created on demand and released right after use. Dropping the board puts all
+//! pins it drives into their low power state before deep sleep.
+
+use crate::debounce::{Debouncer, Input};
+use crate::error::FirmwareError;
+#[cfg(not(feature = "fake-sensor"))]
+use crate::error_code::ErrorCode;
+#[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+use crate::expander::{self, Expander, ExpanderInput, ExpanderOutput};
+use crate::led::Led;
+use crate::power::Regulator;
+use crate::probes;
+use crate::sampling::Sampled;
+#[cfg(not(feature = "fake-sensor"))]
+use anyhow::bail;
+use anyhow::{Context, Result};
+use esp_idf_hal::gpio::{self, PinDriver};
+use esp_idf_hal::{adc, ledc, modem, peripherals};
+use std::time::Instant;
+#[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+use std::{cell::RefCell, rc::Rc};
+
+#[cfg_attr(feature = "fake-sensor", allow(dead_code))]
+pub struct Board {
+    led: Led,
+    button: PinDriver<'static, gpio::Gpio9, gpio::Input>,
+    button_debouncer: Debouncer,
+    /// Switched to power save mode when dropped.
+    _regulator: Regulator,
+    adc: adc::ADC1,
+    probe_pin: gpio::Gpio4,
+    #[cfg(feature = "multi-probe")]
+    second_probe_pin: gpio::Gpio3,
+    #[cfg(feature = "battery")]
+    battery_pin: gpio::Gpio3,
+    #[cfg(feature = "reference")]
+    reference_pin: gpio::Gpio3,
+    pwm_channel: ledc::CHANNEL0,
+    pwm_timer: ledc::TIMER0,
+    pwm_pin: gpio::Gpio5,
+    modem: Option<modem::Modem>,
+    #[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+    expander: Rc<RefCell<Expander>>,
+}
+
+impl Board {
+    pub fn take() -> Result<Board> {
+        let peripherals = peripherals::Peripherals::take().context("peripherals already taken")?;
+
+        let led = Led::new(
+            peripherals.ledc.channel1,
+            peripherals.ledc.timer1,
+            peripherals.pins.gpio7,
+        )?;
+
+        let mut button = PinDriver::input(peripherals.pins.gpio9)?;
+        button.set_pull(gpio::Pull::Up)?;
+
+        let regulator = Regulator::new(peripherals.pins.gpio10)?;
+
+        #[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+        let expander = {
+            use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
+            use esp_idf_hal::units::FromValueType;
+            let config = I2cConfig::new().baudrate(100.kHz().into());
+            let i2c = I2cDriver::new(
+                peripherals.i2c0,
+                peripherals.pins.gpio6,
+                peripherals.pins.gpio8,
+                &config,
+            )?;
+            Expander::new(i2c).context("initializing GPIO expander")?
+        };
+
+        Ok(Board {
+            led,
+            button,
+            button_debouncer: Debouncer::new(Input::Button, false),
+            _regulator: regulator,
+            adc: peripherals.adc1,
+            probe_pin: peripherals.pins.gpio4,
+            #[cfg(feature = "multi-probe")]
+            second_probe_pin: peripherals.pins.gpio3,
+            #[cfg(feature = "battery")]
+            battery_pin: peripherals.pins.gpio3,
+            #[cfg(feature = "reference")]
+            reference_pin: peripherals.pins.gpio3,
+            pwm_channel: peripherals.ledc.channel0,
+            pwm_timer: peripherals.ledc.timer0,
+            pwm_pin: peripherals.pins.gpio5,
+            modem: Some(peripherals.modem),
+            #[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+            expander,
+        })
+    }
+
+    pub fn led(&mut self) -> &mut Led {
+        &mut self.led
+    }
+
+    /// Samples the button, returning whether it's pressed after debouncing.
+    pub fn is_button_pressed(&mut self) -> bool {
+        let sampled = self.button.is_low();
+        self.button_debouncer.update(sampled, Instant::now())
+    }
+
+    /// Whether a press or release of the button is still being debounced.
+    pub fn is_button_settling(&self) -> bool {
+        self.button_debouncer.is_settling()
+    }
+
+    /// Reads the probe, or the simulated sensor if the `fake-sensor` feature is enabled.
+    pub fn read_probe(&mut self) -> Result<u16, FirmwareError> {
+        Ok(self.sample_probe()?.value)
+    }
+
+    /// Reads the probe like [`Board::read_probe`], returning the variance of the samples as well.
+    /// The simulated sensor has none.
+    pub fn sample_probe(&mut self) -> Result<Sampled, FirmwareError> {
+        self.sample_probe_at(probes::PRIMARY_CHANNEL)
+    }
+
+    /// Reads the probe on ADC1 `channel`. The simulated sensor is the same on all channels.
+    pub fn sample_probe_at(&mut self, channel: u8) -> Result<Sampled, FirmwareError> {
+        let mut samples = self.probe_samples_at(channel)?;
+        Ok(crate::sampling::filter(&mut samples))
+    }
+
+    /// Reads the unfiltered samples of the probe, see [`crate::sampling`].
+    pub fn probe_samples(&mut self) -> Result<Vec<u16>, FirmwareError> {
+        self.probe_samples_at(probes::PRIMARY_CHANNEL)
+    }
+
+    /// Reads the unfiltered samples of the probe on ADC1 `channel`. The simulated sensor has
+    /// identical samples.
+    pub fn probe_samples_at(&mut self, channel: u8) -> Result<Vec<u16>, FirmwareError> {
+        #[cfg(not(feature = "fake-sensor"))]
+        match channel {
+            probes::PRIMARY_CHANNEL => crate::probe::read(
+                &mut self.adc,
+                &mut self.probe_pin,
+                channel,
+                &mut self.pwm_channel,
+                &mut self.pwm_timer,
+                &mut self.pwm_pin,
+            ),
+            #[cfg(feature = "multi-probe")]
+            probes::SECOND_CHANNEL => crate::probe::read(
+                &mut self.adc,
+                &mut self.second_probe_pin,
+                channel,
+                &mut self.pwm_channel,
+                &mut self.pwm_timer,
+                &mut self.pwm_pin,
+            ),
+            _ => Err(FirmwareError::Sensor {
+                code: ErrorCode::ProbeConfig,
+                context: format!("no probe on ADC1 channel {}", channel),
+            }),
+        }
+        #[cfg(feature = "fake-sensor")]
+        {
+            let _ = channel;
+            Ok(vec![
+                crate::fake_sensor::read(crate::slow_clock_seconds());
+                crate::sampling::SAMPLES
+            ])
+        }
+    }
+
+    /// Reads a raw trace of `count` samples of the probe on ADC1 `channel`, see
+    /// [`crate::adc_trace`]. The simulated sensor has a constant trace.
+    pub fn trace_probe_at(&mut self, channel: u8, count: usize) -> Result<Vec<u16>> {
+        #[cfg(not(feature = "fake-sensor"))]
+        match channel {
+            probes::PRIMARY_CHANNEL => crate::probe::trace(
+                &mut self.adc,
+                &mut self.probe_pin,
+                &mut self.pwm_channel,
+                &mut self.pwm_timer,
+                &mut self.pwm_pin,
+                count,
+            ),
+            #[cfg(feature = "multi-probe")]
+            probes::SECOND_CHANNEL => crate::probe::trace(
+                &mut self.adc,
+                &mut self.second_probe_pin,
+                &mut self.pwm_channel,
+                &mut self.pwm_timer,
+                &mut self.pwm_pin,
+                count,
+            ),
+            _ => bail!("no probe on ADC1 channel {}", channel),
+        }
+        #[cfg(feature = "fake-sensor")]
+        {
+            let _ = channel;
+            Ok(vec![
+                crate::fake_sensor::read(crate::slow_clock_seconds());
+                count
+            ])
+        }
+    }
+
+    /// Reads the battery voltage in mV, if the `battery` feature is enabled.
+    pub fn read_battery(&mut self) -> Result<Option<u16>> {
+        #[cfg(feature = "battery")]
+        return crate::battery::read(&mut self.adc, &mut self.battery_pin).map(Some);
+        #[cfg(not(feature = "battery"))]
+        return Ok(None);
+    }
+
+    /// Reads the voltage of the ADC self-test in mV, if the `reference` feature is enabled.
+    pub fn read_reference(&mut self) -> Result<Option<u16>> {
+        #[cfg(feature = "reference")]
+        return crate::self_test::read(&mut self.adc, &mut self.reference_pin).map(Some);
+        #[cfg(not(feature = "reference"))]
+        return Ok(None);
+    }
+
+    /// Configures pin `pin` of the GPIO expander as an output, for extensions of the board.
+    #[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+    #[allow(dead_code)]
+    pub fn expander_output(&self, pin: u8) -> Result<ExpanderOutput> {
+        expander::output(&self.expander, pin)
+    }
+
+    /// Configures pin `pin` of the GPIO expander as an input, for extensions of the board.
+    #[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+    #[allow(dead_code)]
+    pub fn expander_input(&self, pin: u8) -> Result<ExpanderInput> {
+        expander::input(&self.expander, pin)
+    }
+
+    /// Hands out the modem, which can only be taken once per wake cycle.
+    pub fn take_modem(&mut self) -> Result<modem::Modem> {
+        self.modem.take().context("modem already taken")
+    }
+}
+
+impl Drop for Board {
+    fn drop(&mut self) {
+        let _ = self.led.set_low();
+        #[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+        let _ = self.expander.borrow_mut().reset();
+    }
+}
@@ -0,0 +1,57 @@
+//! Information about the running firmware, reported once after it has been flashed or updated.
+
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+const NVS_NAMESPACE: &str = "build_info";
+const NVS_KEY: &str = "reported";
+
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub build_timestamp: i64,
+    pub idf_version: String,
+    pub partition: String,
+    pub elf_sha256: [u8; 32],
+}
+
+impl BuildInfo {
+    pub fn current() -> BuildInfo {
+        let app_description = unsafe { &*esp_idf_sys::esp_ota_get_app_description() };
+        let partition = unsafe { esp_idf_sys::esp_ota_get_running_partition().as_ref() };
+
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+            idf_version: c_string(&app_description.idf_ver),
+            partition: partition.map_or_else(String::new, |p| c_string(&p.label)),
+            elf_sha256: app_description.app_elf_sha256,
+        }
+    }
+
+    /// Short hexadecimal identifier of the firmware image.
+    pub fn build_id(&self) -> String {
+        self.elf_sha256[..4]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Whether this build has already been reported since it was flashed.
+    pub fn is_reported(&self, partition: &EspDefaultNvsPartition) -> Result<bool> {
+        let namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+        Ok(namespace.get::<[u8; 32]>(NVS_KEY)? == Some(self.elf_sha256))
+    }
+
+    pub fn mark_reported(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, &self.elf_sha256)
+    }
+}
+
+fn c_string(chars: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(chars.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,153 @@
+//! Gestures of the user button. A single press starts maintenance mode, a double press records
+//! that the plant has been watered and a long press starts WiFi provisioning via the access point,
+//! if enabled.
+//!
+//! Gestures only work in powered mode, in which the button is polled all the time. The button is
+//! on GPIO9, which can't wake the ESP32-C3 from deep sleep, as only GPIO0 to GPIO5 can. On battery,
+//! a gesture is therefore only read if the button is already pressed when a cycle starts.
+
+use std::time::{Duration, Instant};
+
+/// How often the button is sampled while a gesture is being entered.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Maximum time between the presses of a double press, and time after which a press is held.
+const WINDOW: Duration = Duration::from_millis(400);
+const LONG_PRESS: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    /// Press, or hold shorter than a long press.
+    Press,
+    DoublePress,
+    LongPress,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// Button down for the first or second time.
+    Down {
+        since: Instant,
+        second: bool,
+    },
+    /// Button up after the first press.
+    Up {
+        since: Instant,
+    },
+    /// Gesture recognized, waiting for the button to be released.
+    Held,
+}
+
+/// Recognizes gestures from button samples.
+pub struct Detector {
+    state: State,
+}
+
+impl Default for Detector {
+    fn default() -> Detector {
+        Detector::new()
+    }
+}
+
+impl Detector {
+    pub fn new() -> Detector {
+        Detector { state: State::Idle }
+    }
+
+    /// Whether no gesture is being entered.
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    /// Updates the detector with the button sampled at `now`, returning the gesture completed by
+    /// the sample, if any.
+    pub fn update(&mut self, pressed: bool, now: Instant) -> Option<Gesture> {
+        let (state, gesture) = match (self.state, pressed) {
+            (State::Idle, true) => (
+                State::Down {
+                    since: now,
+                    second: false,
+                },
+                None,
+            ),
+            (State::Down { second: true, .. }, false) => (State::Idle, Some(Gesture::DoublePress)),
+            (
+                State::Down {
+                    since,
+                    second: false,
+                },
+                false,
+            ) if now - since >= WINDOW => (State::Idle, Some(Gesture::Press)),
+            (State::Down { second: false, .. }, false) => (State::Up { since: now }, None),
+            (
+                State::Down {
+                    since,
+                    second: false,
+                },
+                true,
+            ) if now - since >= LONG_PRESS => (State::Held, Some(Gesture::LongPress)),
+            (
+                State::Down {
+                    since,
+                    second: true,
+                },
+                true,
+            ) if now - since >= WINDOW => (State::Held, Some(Gesture::DoublePress)),
+            (State::Up { .. }, true) => (
+                State::Down {
+                    since: now,
+                    second: true,
+                },
+                None,
+            ),
+            (State::Up { since }, false) if now - since >= WINDOW => {
+                (State::Idle, Some(Gesture::Press))
+            }
+            (State::Held, false) => (State::Idle, None),
+            (state, _) => (state, None),
+        };
+        self.state = state;
+        gesture
+    }
+}
+
+#[test]
+pub fn test_update() {
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut detector = Detector::new();
+    assert_eq!(detector.update(false, at(0)), None);
+    assert!(detector.is_idle());
+
+    let samples = [(true, 0), (false, 100), (false, 300)];
+    for (pressed, ms) in samples {
+        assert_eq!(detector.update(pressed, at(ms)), None);
+    }
+    assert_eq!(detector.update(false, at(500)), Some(Gesture::Press));
+    assert!(detector.is_idle());
+
+    let samples = [(true, 1000), (false, 1100), (true, 1300)];
+    for (pressed, ms) in samples {
+        assert_eq!(detector.update(pressed, at(ms)), None);
+    }
+    assert_eq!(detector.update(false, at(1400)), Some(Gesture::DoublePress));
+    assert!(detector.is_idle());
+
+    assert_eq!(detector.update(true, at(2000)), None);
+    assert_eq!(detector.update(true, at(3000)), None);
+    assert_eq!(detector.update(false, at(3100)), Some(Gesture::Press));
+    assert!(detector.is_idle());
+
+    assert_eq!(detector.update(true, at(4000)), None);
+    assert_eq!(detector.update(true, at(9000)), Some(Gesture::LongPress));
+    assert_eq!(detector.update(true, at(9500)), None);
+    assert!(!detector.is_idle());
+    assert_eq!(detector.update(false, at(9600)), None);
+    assert!(detector.is_idle());
+
+    let samples = [(true, 10000), (false, 10100), (true, 10200)];
+    for (pressed, ms) in samples {
+        assert_eq!(detector.update(pressed, at(ms)), None);
+    }
+    assert_eq!(detector.update(true, at(10600)), Some(Gesture::DoublePress));
+}
@@ -0,0 +1,170 @@
+//! Calibration of the probes in the actual medium. Holding the button after a reset, while the LED
+//! greets, enters calibration mode once the greeting ends. The button can't already be held when
+//! reset is released, as it's on a strapping pin and the chip would enter download mode.
+//!
+//! Calibration mode captures a reading of every probe in dry medium, guided by slow blinking, and
+//! then in saturated medium, guided by fast blinking. Each capture is taken with a press of the
+//! button once the probes are in place. Measured calibrations take precedence over the preset of the
+//! configured medium, and readings of calibrated probes are uploaded with their estimated water
+//! content in percent.
+
+use crate::board::Board;
+use crate::button::{self, Detector, Gesture};
+use crate::soil::{self, Calibration};
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use std::time::{Duration, Instant};
+
+const NVS_NAMESPACE: &str = "calibration";
+const NVS_KEY: &str = "probes";
+
+/// Time the button must be held after the greeting to enter calibration mode.
+const HOLD: Duration = Duration::from_secs(2);
+/// Time to wait for each capture before calibration mode is left without changes.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Minimum difference in mV between the dry and the wet reading.
+const MIN_SPAN: u16 = 200;
+
+/// Calibrations of the probes, by ADC1 channel.
+pub struct Calibrations {
+    measured: Vec<(u8, Calibration)>,
+    preset: Option<Calibration>,
+}
+
+impl Calibrations {
+    /// Returns the calibration of the probe on `channel`, measured or from the medium preset.
+    pub fn get(&self, channel: u8) -> Option<Calibration> {
+        self.measured
+            .iter()
+            .find(|(c, _)| *c == channel)
+            .map(|(_, calibration)| *calibration)
+            .or(self.preset)
+    }
+}
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Calibrations> {
+    Ok(Calibrations {
+        measured: Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)?,
+        preset: soil::load(partition)?.map(soil::Medium::calibration),
+    })
+}
+
+/// Whether the button is held at the end of the greeting.
+pub fn is_requested(board: &mut Board) -> bool {
+    let start = Instant::now();
+    while board.is_button_pressed() || board.is_button_settling() {
+        if start.elapsed() >= HOLD {
+            return true;
+        }
+        FreeRtos::delay_ms(button::POLL_INTERVAL.as_millis() as u32);
+    }
+    false
+}
+
+/// Runs calibration mode for the probes on `channels` and stores the result.
+pub fn run(board: &mut Board, partition: &EspDefaultNvsPartition, channels: &[u8]) -> Result<()> {
+    println!("calibration mode, release the button");
+    while board.is_button_pressed() {
+        FreeRtos::delay_ms(button::POLL_INTERVAL.as_millis() as u32);
+    }
+
+    println!("place the probes in dry medium and press the button");
+    let dry = capture(board, channels, Duration::from_millis(1000))?;
+    println!("place the probes in saturated medium and press the button");
+    let wet = capture(board, channels, Duration::from_millis(200))?;
+
+    let mut measured = Vec::new();
+    for (channel, (dry, wet)) in channels.iter().zip(dry.into_iter().zip(wet)) {
+        let calibration = Calibration { dry, wet };
+        validate(&calibration)?;
+        println!(
+            "calibrated channel {}: dry {} mV, wet {} mV",
+            channel, dry, wet
+        );
+        measured.push((*channel, calibration));
+    }
+    Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, &measured)?;
+
+    board.led().set_high()?;
+    FreeRtos::delay_ms(2000);
+    board.led().set_low()?;
+    Ok(())
+}
+
+/// Blinks the LED with `period` until the button is pressed, then reads the probes on `channels`.
+fn capture(board: &mut Board, channels: &[u8], period: Duration) -> Result<Vec<u16>> {
+    let start = Instant::now();
+    let mut detector = Detector::new();
+    loop {
+        if start.elapsed() >= CAPTURE_TIMEOUT {
+            bail!("calibration timed out");
+        }
+        let lit = (start.elapsed().as_millis() / (period.as_millis() / 2)) % 2 == 0;
+        if lit {
+            board.led().set_high()?;
+        } else {
+            board.led().set_low()?;
+        }
+        if detector.update(board.is_button_pressed(), Instant::now()) == Some(Gesture::Press) {
+            break;
+        }
+        FreeRtos::delay_ms(button::POLL_INTERVAL.as_millis() as u32);
+    }
+    board.led().set_low()?;
+
+    let mut readings = Vec::new();
+    for &channel in channels {
+        readings.push(board.sample_probe_at(channel)?.value);
+    }
+    println!("captured {:?} mV", readings);
+    Ok(readings)
+}
+
+/// Checks that readings fall clearly from dry to wet medium.
+fn validate(calibration: &Calibration) -> Result<()> {
+    if calibration.wet.saturating_add(MIN_SPAN) > calibration.dry {
+        bail!(
+            "wet reading of {} mV not clearly below dry reading of {} mV",
+            calibration.wet,
+            calibration.dry
+        );
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_calibrations() {
+    validate(&Calibration {
+        dry: 2400,
+        wet: 1200,
+    })
+    .unwrap();
+    assert!(validate(&Calibration {
+        dry: 2400,
+        wet: 2300
+    })
+    .is_err());
+    assert!(validate(&Calibration {
+        dry: 1200,
+        wet: 2400
+    })
+    .is_err());
+
+    let measured = Calibration {
+        dry: 2500,
+        wet: 1300,
+    };
+    let calibrations = Calibrations {
+        measured: vec![(4, measured)],
+        preset: Some(soil::Medium::Clay.calibration()),
+    };
+    assert_eq!(calibrations.get(4), Some(measured));
+    assert_eq!(calibrations.get(3), Some(soil::Medium::Clay.calibration()));
+    let calibrations = Calibrations {
+        preset: None,
+        ..calibrations
+    };
+    assert_eq!(calibrations.get(3), None);
+}
@@ -0,0 +1,100 @@
+use crate::command::{self, Command};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Reads commands from the serial console until no line has been entered for `idle_timeout`.
+pub fn run(idle_timeout: Duration) -> Vec<Command> {
+    let (line_tx, line_rx) = channel();
+    let spawned = thread::Builder::new().stack_size(4096).spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if line_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    if let Err(e) = spawned {
+        println!("error starting console: {}", e);
+        return Vec::new();
+    }
+
+    println!("console open for {} s, commands:", idle_timeout.as_secs());
+    println!("  locate");
+    println!("  maintenance [<minutes>|off]");
+    println!("  snooze [<minutes>]");
+    println!("  profile standard|seedling");
+    println!("  soil potting_mix|coco_coir|clay|sand|rockwool|none");
+    println!("  tag <key> <value>");
+    println!("  untag <key>");
+    println!("  record on|off|dump");
+    println!("  spill dump");
+    println!("  trace capture [<channel>]");
+    println!("  dryrun on|off");
+    println!("  ratelimit <minutes> <uploads per day>");
+    println!("  time <unix seconds>");
+    println!("  mac factory|random|<address>");
+    #[cfg(feature = "powered")]
+    println!("  password <password of local interfaces>");
+    #[cfg(feature = "smartconfig")]
+    println!("  provision smartconfig");
+    #[cfg(feature = "dpp")]
+    println!("  provision dpp");
+    #[cfg(feature = "softap")]
+    println!("  provision softap");
+    println!("  config export");
+    println!(
+        "  {}, followed by the document and a line with \"end\"",
+        command::IMPORT_CONFIG
+    );
+    println!("  exit");
+
+    let mut commands = Vec::new();
+    // Lines of a configuration document being entered.
+    let mut document: Option<Vec<String>> = None;
+    let mut deadline = Instant::now() + idle_timeout;
+    loop {
+        prompt();
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let line = match line_rx.recv_timeout(remaining) {
+            Ok(Ok(line)) => line,
+            Ok(Err(e)) => {
+                println!("error reading console: {}", e);
+                break;
+            }
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        };
+        deadline = Instant::now() + idle_timeout;
+
+        match line.trim() {
+            "" => continue,
+            "exit" => break,
+            command::IMPORT_CONFIG => {
+                document.get_or_insert_with(Vec::new);
+                continue;
+            }
+            "end" if document.is_some() => {
+                let lines = document.take().unwrap_or_default();
+                commands.push(Command::ImportConfig(lines.join("\n")));
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(lines) = &mut document {
+            lines.push(line);
+            continue;
+        }
+        match line.parse() {
+            Ok(command) => commands.push(command),
+            Err(e) => println!("{}", e),
+        }
+    }
+    println!();
+
+    commands
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
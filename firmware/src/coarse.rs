@@ -0,0 +1,195 @@
+//! Coarse reporting for sinks that publish to shared dashboards. A coarse sink only gets the
+//! readings of the probes, as a band of `dry`, `ok` or `wet`, with timestamps rounded down to
+//! `resolution`. Other points, such as battery, diagnostics and crash reports, aren't sent to it.
+//! Sinks that aren't coarse, such as the owner's own store, get full resolution.
+
+use crate::line_protocol;
+use crate::points::{MeasurementBatch, Point, Value};
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+pub const NVS_NAMESPACE: &str = "coarse";
+const NVS_KEY: &str = "settings";
+const BAND_FIELD: &str = "band";
+
+/// Where points go: the upload over one of the transports, or StatsD in addition to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sink {
+    /// The server at `device.write_url`.
+    Influx,
+    Graphite,
+    Otlp,
+    Postgrest,
+    Mqtt,
+    Statsd,
+}
+
+impl Sink {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sink::Influx => "influx",
+            Sink::Graphite => "graphite",
+            Sink::Otlp => "otlp",
+            Sink::Postgrest => "postgrest",
+            Sink::Mqtt => "mqtt",
+            Sink::Statsd => "statsd",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Band {
+    Wet,
+    Ok,
+    Dry,
+}
+
+impl Band {
+    pub fn name(self) -> &'static str {
+        match self {
+            Band::Wet => "wet",
+            Band::Ok => "ok",
+            Band::Dry => "dry",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Sinks that get coarse points only.
+    pub sinks: Vec<Sink>,
+    /// Reading in mV at and above which the soil is reported as dry.
+    pub dry_above: u16,
+    /// Reading in mV below which the soil is reported as wet.
+    pub wet_below: u16,
+    /// Seconds to which timestamps are rounded down.
+    pub resolution: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            sinks: Vec::new(),
+            dry_above: 2000,
+            wet_below: 1500,
+            resolution: 3600,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Settings> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    pub fn is_coarse(&self, sink: Sink) -> bool {
+        self.sinks.contains(&sink)
+    }
+
+    pub fn band(&self, value: u16) -> Band {
+        if value >= self.dry_above {
+            Band::Dry
+        } else if value < self.wet_below {
+            Band::Wet
+        } else {
+            Band::Ok
+        }
+    }
+
+    /// Converts a batch to coarse points. Only points of the measurement of `line_prefix` with its
+    /// field are kept, as readings of the probes.
+    pub fn convert(&self, batch: &MeasurementBatch, line_prefix: &str) -> MeasurementBatch {
+        let prefix = line_protocol::parse_prefix(line_prefix);
+        let resolution = i64::from(self.resolution);
+        let mut coarse = MeasurementBatch::default();
+        for point in &batch.points {
+            if point.measurement != prefix.measurement {
+                continue;
+            }
+            let value = point
+                .fields
+                .iter()
+                .find(|(key, _)| *key == prefix.field)
+                .and_then(|(_, value)| match value {
+                    Value::Float(value) => Some(*value),
+                    Value::Integer(value) => Some(*value as f64),
+                    _ => None,
+                });
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            let band = self.band(value.round() as u16);
+            coarse.points.push(Point {
+                measurement: point.measurement.clone(),
+                tags: point.tags.clone(),
+                fields: vec![(BAND_FIELD.into(), Value::String(band.name().into()))],
+                seconds: point.seconds - point.seconds.rem_euclid(resolution),
+                sequence: 0,
+            });
+        }
+        coarse
+    }
+}
+
+pub fn validate(settings: &Settings) -> Result<()> {
+    if settings.wet_below > settings.dry_above {
+        bail!("coarse.wet_below must not be above coarse.dry_above");
+    }
+    if settings.resolution == 0 {
+        bail!("coarse.resolution must be at least 1 s");
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_convert() {
+    use crate::line_protocol::FieldValue;
+
+    let settings = Settings::default();
+    assert_eq!(settings.band(2000), Band::Dry);
+    assert_eq!(settings.band(1999), Band::Ok);
+    assert_eq!(settings.band(1499), Band::Wet);
+    validate(&settings).unwrap();
+    assert!(validate(&Settings {
+        wet_below: 2100,
+        ..settings.clone()
+    })
+    .is_err());
+
+    let prefix = "moisture,sensor=a value=";
+    let mut batch = MeasurementBatch::default();
+    let tags = [("wake", "timer")];
+    batch.push_value(prefix, &tags, FieldValue::Float(2100.0), 1_700_003_599, 1);
+    batch.push_value(prefix, &tags, FieldValue::Integer(1200), 1_700_003_600, 0);
+    batch.push_fields(
+        prefix,
+        "battery",
+        &[],
+        &[("voltage_mv", FieldValue::Integer(3000))],
+        1_700_003_600,
+    );
+    batch.push_fields(
+        prefix,
+        "moisture",
+        &[],
+        &[("note", FieldValue::String("x"))],
+        1_700_003_600,
+    );
+    assert_eq!(
+        settings.convert(&batch, prefix).to_line_protocol(),
+        "moisture,sensor=a,wake=timer band=\"dry\" 1700002800000000000\n\
+         moisture,sensor=a,wake=timer band=\"wet\" 1700002800000000000\n"
+    );
+    assert!(settings
+        .convert(&MeasurementBatch::default(), "moisture value=")
+        .is_empty());
+}
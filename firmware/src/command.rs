@@ -0,0 +1,265 @@
+use crate::http;
+use crate::probes;
+use crate::profile::Profile;
+use crate::soil::Medium;
+use crate::wifi_mac::MacMode;
+use anyhow::{bail, Result};
+use embedded_svc::http::Method;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_MAINTENANCE_DURATION: Duration = Duration::from_secs(2 * 3600);
+pub const DEFAULT_SNOOZE_DURATION: Duration = Duration::from_secs(12 * 3600);
+/// Command followed by a TOML document, which extends to the end of the input.
+pub const IMPORT_CONFIG: &str = "config import";
+/// Size of the response to a poll, which fits a configuration to import.
+const MAX_POLL_SIZE: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Locate,
+    /// Starts maintenance mode for the given duration, or ends it if the duration is zero.
+    Maintenance(Duration),
+    /// Drops the dryness alert and raises none for the given duration.
+    Snooze(Duration),
+    SetProfile(Profile),
+    /// Selects the calibration preset of the growing medium, or none.
+    SetSoil(Option<Medium>),
+    SetTag(String, String),
+    RemoveTag(String),
+    SetRecording(bool),
+    DumpRecording,
+    /// Prints the spilled measurements for decoding on a host.
+    DumpSpill,
+    /// Captures a raw ADC trace of the probe on the given ADC1 channel for upload.
+    CaptureTrace(u8),
+    ExportConfig,
+    ImportConfig(String),
+    SetDryRun(bool),
+    /// Sets the minimum interval between uploads and the maximum number of uploads per day.
+    SetRateLimit(Duration, u32),
+    /// Sets the time to the given Unix timestamp in seconds.
+    SetTime(i64),
+    SetMacMode(MacMode),
+    /// Sets the password of the local interfaces of powered mode.
+    #[cfg(feature = "powered")]
+    SetPassword(crate::local_auth::Password),
+    /// Requests WiFi provisioning with the given method.
+    #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+    Provision(crate::provisioning::Method),
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (verb, args) = match s.split_once(char::is_whitespace) {
+            Some((verb, args)) => (verb, args.trim()),
+            None => (s, ""),
+        };
+
+        let command = match (verb, args) {
+            ("locate", "") => Command::Locate,
+            ("maintenance", "") => Command::Maintenance(DEFAULT_MAINTENANCE_DURATION),
+            ("maintenance", "off") => Command::Maintenance(Duration::ZERO),
+            ("maintenance", minutes) => match minutes.parse::<u64>() {
+                Ok(minutes) => Command::Maintenance(Duration::from_secs(minutes * 60)),
+                Err(_) => bail!("invalid maintenance duration: {}", minutes),
+            },
+            ("snooze", "") => Command::Snooze(DEFAULT_SNOOZE_DURATION),
+            ("snooze", minutes) => match minutes.parse::<u64>() {
+                Ok(minutes) => Command::Snooze(Duration::from_secs(minutes * 60)),
+                Err(_) => bail!("invalid snooze duration: {}", minutes),
+            },
+            ("profile", profile) => Command::SetProfile(profile.parse()?),
+            ("soil", "none") => Command::SetSoil(None),
+            ("soil", medium) => Command::SetSoil(Some(medium.parse()?)),
+            ("tag", args) => match args.split_once(char::is_whitespace) {
+                Some((key, value)) if !key.contains('=') => {
+                    Command::SetTag(key.into(), value.trim().into())
+                }
+                _ => bail!("usage: tag <key> <value>"),
+            },
+            ("untag", key) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+                Command::RemoveTag(key.into())
+            }
+            ("untag", _) => bail!("usage: untag <key>"),
+            ("record", "on") => Command::SetRecording(true),
+            ("record", "off") => Command::SetRecording(false),
+            ("record", "dump") => Command::DumpRecording,
+            ("record", _) => bail!("usage: record on|off|dump"),
+            ("spill", "dump") => Command::DumpSpill,
+            ("spill", _) => bail!("usage: spill dump"),
+            ("trace", "capture") => Command::CaptureTrace(probes::PRIMARY_CHANNEL),
+            ("trace", args) => match args.strip_prefix("capture ").map(|c| c.trim().parse()) {
+                Some(Ok(channel)) => Command::CaptureTrace(channel),
+                _ => bail!("usage: trace capture [<channel>]"),
+            },
+            ("dryrun", "on") => Command::SetDryRun(true),
+            ("dryrun", "off") => Command::SetDryRun(false),
+            ("dryrun", _) => bail!("usage: dryrun on|off"),
+            ("ratelimit", args) => {
+                let parsed = args
+                    .split_once(char::is_whitespace)
+                    .and_then(|(minutes, count)| {
+                        Some((minutes.parse::<u64>().ok()?, count.trim().parse().ok()?))
+                    });
+                match parsed {
+                    Some((minutes, count)) => {
+                        Command::SetRateLimit(Duration::from_secs(minutes * 60), count)
+                    }
+                    None => bail!("usage: ratelimit <minutes> <uploads per day>"),
+                }
+            }
+            #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+            ("provision", method) => Command::Provision(method.parse()?),
+            ("time", seconds) => match seconds.parse() {
+                Ok(seconds) => Command::SetTime(seconds),
+                Err(_) => bail!("usage: time <unix seconds>"),
+            },
+            ("mac", mode) => Command::SetMacMode(mode.parse()?),
+            #[cfg(feature = "powered")]
+            ("password", password) => Command::SetPassword(password.parse()?),
+            ("config", "export") => Command::ExportConfig,
+            ("config", _) => bail!("usage: config export|import"),
+            _ => bail!("unknown command: {}", s),
+        };
+        Ok(command)
+    }
+}
+
+/// Fetches pending commands from `url`, one command per line of the response body.
+pub fn poll(url: &str, authorization: &str) -> Result<Vec<Command>> {
+    let headers = [("Authorization", authorization)];
+    let mut http_client = http::new_connection()?;
+    let body = http::request(
+        &mut http_client,
+        Method::Get,
+        url,
+        &headers,
+        &[],
+        MAX_POLL_SIZE,
+    )?;
+    Ok(parse_commands(&String::from_utf8_lossy(&body)))
+}
+
+/// Parses one command per line, skipping (and logging) commands this firmware doesn't know. A
+/// configuration import takes the rest of the text as its document.
+fn parse_commands(text: &str) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.trim() == IMPORT_CONFIG {
+            let document: Vec<_> = lines.collect();
+            commands.push(Command::ImportConfig(document.join("\n")));
+            break;
+        }
+        match line.parse() {
+            Ok(command) => commands.push(command),
+            Err(e) => println!("ignoring remote command: {}", e),
+        }
+    }
+    commands
+}
+
+#[test]
+pub fn test_parse_commands() {
+    assert_eq!(parse_commands(""), vec![]);
+    assert_eq!(
+        parse_commands("locate\n\n  locate \n"),
+        vec![Command::Locate, Command::Locate]
+    );
+    assert_eq!(parse_commands("explode\nlocate\n"), vec![Command::Locate]);
+    assert_eq!(
+        parse_commands("maintenance\nmaintenance 30\nmaintenance off\nmaintenance x\n"),
+        vec![
+            Command::Maintenance(DEFAULT_MAINTENANCE_DURATION),
+            Command::Maintenance(Duration::from_secs(1800)),
+            Command::Maintenance(Duration::ZERO),
+        ]
+    );
+    assert_eq!(
+        parse_commands(
+            "snooze
+snooze 90
+snooze off
+"
+        ),
+        vec![
+            Command::Snooze(DEFAULT_SNOOZE_DURATION),
+            Command::Snooze(Duration::from_secs(5400)),
+        ]
+    );
+    assert_eq!(
+        parse_commands("profile seedling\nprofile dome\nprofile standard\n"),
+        vec![
+            Command::SetProfile(Profile::Seedling),
+            Command::SetProfile(Profile::Standard),
+        ]
+    );
+    assert_eq!(
+        parse_commands(
+            "soil coco_coir
+soil peat
+soil none
+"
+        ),
+        vec![
+            Command::SetSoil(Some(Medium::CocoCoir)),
+            Command::SetSoil(None),
+        ]
+    );
+    assert_eq!(
+        parse_commands("tag site Green House\ntag a=b c\ntag row\nuntag site\nuntag\n"),
+        vec![
+            Command::SetTag("site".into(), "Green House".into()),
+            Command::RemoveTag("site".into()),
+        ]
+    );
+    assert_eq!(
+        parse_commands("record on\nrecord dump\nrecord\nrecord off\n"),
+        vec![
+            Command::SetRecording(true),
+            Command::DumpRecording,
+            Command::SetRecording(false),
+        ]
+    );
+    assert_eq!(
+        parse_commands("spill dump\nspill\n"),
+        vec![Command::DumpSpill]
+    );
+    assert_eq!(
+        parse_commands("trace capture\ntrace capture 3\ntrace\ntrace capture x\n"),
+        vec![Command::CaptureTrace(4), Command::CaptureTrace(3)]
+    );
+    assert_eq!(
+        parse_commands("dryrun on\ndryrun\ndryrun off\n"),
+        vec![Command::SetDryRun(true), Command::SetDryRun(false)]
+    );
+    assert_eq!(
+        parse_commands("ratelimit 15 48\nratelimit 15\nratelimit x 1\n"),
+        vec![Command::SetRateLimit(Duration::from_secs(900), 48)]
+    );
+    assert_eq!(
+        parse_commands("time 1700000000\ntime\ntime now\n"),
+        vec![Command::SetTime(1_700_000_000)]
+    );
+    assert_eq!(
+        parse_commands("mac random\nmac\nmac 02:00:00:00:00:01\n"),
+        vec![
+            Command::SetMacMode(MacMode::Random),
+            Command::SetMacMode(MacMode::Static([2, 0, 0, 0, 0, 1])),
+        ]
+    );
+    assert_eq!(
+        parse_commands("config export\nconfig\nconfig import\n[tags]\nsite = \"x\"\n"),
+        vec![
+            Command::ExportConfig,
+            Command::ImportConfig("[tags]\nsite = \"x\"".into()),
+        ]
+    );
+}
@@ -0,0 +1,39 @@
+//! Switches for turning off optional firmware components at runtime. They are part of the
+//! configuration, so a misbehaving component can be turned off across the fleet with a config
+//! import or the device shadow, without reflashing, until a fixed firmware is rolled out.
+
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+const NVS_NAMESPACE: &str = "components";
+const NVS_KEY: &str = "enabled";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Components {
+    /// Whether to check for firmware updates after uploads.
+    pub ota: bool,
+    /// Whether to upload the diagnostics recorded after flashing.
+    pub diagnostics: bool,
+}
+
+impl Default for Components {
+    fn default() -> Components {
+        Components {
+            ota: true,
+            diagnostics: true,
+        }
+    }
+}
+
+impl Components {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Components> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+}
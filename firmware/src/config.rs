@@ -0,0 +1,708 @@
+//! Export and import of the device configuration as TOML, for backups and for cloning a known-good
+//! setup onto new devices. Settings compiled into the firmware are exported for reference, with
+//! secrets redacted, and ignored on import.
+
+use crate::adaptive;
+use crate::alert::Policy;
+use crate::awake_limit;
+use crate::batch;
+use crate::coarse;
+use crate::components::Components;
+use crate::device_config::DeviceConfig;
+use crate::fleet;
+use crate::graphite::{self, Target};
+use crate::http;
+use crate::led;
+use crate::mqtt;
+use crate::notifier::{self, Channels};
+use crate::otlp;
+use crate::postgrest;
+use crate::probes::{self, Probe};
+use crate::profile;
+use crate::rate_limit::{self, Limits};
+use crate::schedule::{self, Band};
+use crate::soil::{self, Medium};
+use crate::statsd;
+use crate::timeouts::{self, Timeouts};
+use crate::upload_window::{self, Window};
+use crate::wifi_mac::{self, MacMode};
+use crate::{dry_run, recorder, tags, wifi_credentials};
+use anyhow::{bail, Result};
+use embedded_svc::http::Method;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(skip_deserializing)]
+    firmware: Firmware,
+    /// The authorization is exported redacted, and kept if imported redacted.
+    #[serde(default)]
+    device: DeviceConfig,
+    #[serde(default)]
+    recorder: Recorder,
+    #[serde(default)]
+    upload: Upload,
+    #[serde(default)]
+    upload_window: Window,
+    #[serde(default)]
+    awake_limit: awake_limit::Settings,
+    #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    schedule: Schedule,
+    /// Takes precedence over `schedule.bands` if enabled.
+    #[serde(default)]
+    adaptive: adaptive::Settings,
+    #[serde(default)]
+    fleet: Fleet,
+    #[serde(default)]
+    profile: profile::Settings,
+    #[serde(default)]
+    soil: Soil,
+    #[serde(default)]
+    probes: Probes,
+    #[serde(default)]
+    led: led::Settings,
+    #[serde(default)]
+    graphite: Graphite,
+    #[serde(default)]
+    statsd: Statsd,
+    #[serde(default)]
+    otlp: Otlp,
+    /// Uploads go to `device.write_url` if the URL is empty. The API key is exported redacted, and kept
+    /// if imported redacted.
+    #[serde(default)]
+    postgrest: postgrest::Target,
+    /// Uploads go to `device.write_url` if the URL is empty. The password is exported redacted, and
+    /// kept if imported redacted.
+    #[serde(default)]
+    mqtt: mqtt::Target,
+    /// Sinks publishing to shared dashboards, which only get moisture bands.
+    #[serde(default)]
+    coarse: coarse::Settings,
+    #[serde(default)]
+    alert: Policy,
+    /// The Discord webhook, the Telegram token and the ntfy topic URL and token are exported
+    /// redacted, and kept if imported redacted.
+    #[serde(default)]
+    notifier: Channels,
+    #[serde(default)]
+    wifi: Wifi,
+    #[serde(default)]
+    components: Components,
+    /// Missing sections are reset to their defaults on import, so that a device ends up with
+    /// exactly the imported configuration.
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+struct Firmware {
+    version: String,
+    wifi_ssid: String,
+    wifi_password: String,
+    command_url: Option<String>,
+    config_url: Option<String>,
+    ota_manifest_url: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Recorder {
+    enabled: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Upload {
+    dry_run: bool,
+    /// Minimum interval between uploads in seconds.
+    min_interval: u64,
+    max_per_day: u32,
+    /// Measurements per request, see [`batch`].
+    batch_size: u16,
+}
+
+/// Intervals between cycles by moisture band. Cycles are `device.measurement_interval` apart if
+/// there are no bands.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Schedule {
+    bands: Vec<Band>,
+}
+
+/// Coordination of cycles among sensors behind one access point, see [`fleet`].
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Fleet {
+    /// Number of sensors, 0 to not coordinate.
+    size: u16,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Soil {
+    /// Calibration preset of the growing medium, if any.
+    medium: Option<Medium>,
+}
+
+/// Probes in addition to or naming the on-board probe, see [`probes`].
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Probes {
+    channels: Vec<Probe>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Graphite {
+    /// Host and port of the plaintext listener. Uploads go to `device.write_url` if empty.
+    address: String,
+    /// Metric path, in which `{measurement}`, `{field}` and tag keys are replaced.
+    template: String,
+}
+
+/// Gauges sent in addition to the upload, without acknowledgement.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Statsd {
+    /// Host and port of the StatsD server. No gauges are sent if empty.
+    address: String,
+    prefix: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Otlp {
+    /// Metrics endpoint of an OpenTelemetry collector. Uploads go to `device.write_url` if
+    /// empty.
+    url: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Wifi {
+    /// `factory`, `random` or a static address.
+    mac: String,
+}
+
+impl Default for Wifi {
+    fn default() -> Wifi {
+        Wifi {
+            mac: MacMode::Factory.to_string(),
+        }
+    }
+}
+
+impl Default for Graphite {
+    fn default() -> Graphite {
+        Graphite {
+            address: String::new(),
+            template: graphite::DEFAULT_TEMPLATE.into(),
+        }
+    }
+}
+
+impl Default for Statsd {
+    fn default() -> Statsd {
+        Statsd {
+            address: String::new(),
+            prefix: statsd::DEFAULT_PREFIX.into(),
+        }
+    }
+}
+
+impl Default for Upload {
+    fn default() -> Upload {
+        Upload {
+            dry_run: false,
+            min_interval: rate_limit::DEFAULT_MIN_INTERVAL.as_secs(),
+            max_per_day: rate_limit::DEFAULT_MAX_PER_DAY,
+            batch_size: batch::DEFAULT_SIZE,
+        }
+    }
+}
+
+/// Returns the effective configuration.
+pub fn current(partition: &EspDefaultNvsPartition) -> Result<Config> {
+    let limits = Limits::load(partition)?;
+    let graphite = match graphite::load(partition)? {
+        Some(target) => Graphite {
+            address: target.address,
+            template: target.template,
+        },
+        None => Graphite::default(),
+    };
+    let statsd = match statsd::load(partition)? {
+        Some(target) => Statsd {
+            address: target.address,
+            prefix: target.prefix,
+        },
+        None => Statsd::default(),
+    };
+    let mut postgrest = postgrest::load(partition)?.unwrap_or_default();
+    redact(&mut postgrest.api_key);
+    let mut mqtt = mqtt::load(partition)?.unwrap_or_default();
+    redact(&mut mqtt.password);
+    let mut notifier = Channels::load(partition)?;
+    redact(&mut notifier.discord_webhook);
+    redact(&mut notifier.telegram_token);
+    redact(&mut notifier.ntfy_url);
+    redact(&mut notifier.ntfy_token);
+    let mut device = DeviceConfig::load(partition)?;
+    redact(&mut device.authorization);
+    let otlp = Otlp {
+        url: otlp::load(partition)?
+            .map(|target| target.url)
+            .unwrap_or_default(),
+    };
+    Ok(Config {
+        firmware: Firmware {
+            version: env!("CARGO_PKG_VERSION").into(),
+            wifi_ssid: wifi_credentials::load(partition)?.0,
+            wifi_password: REDACTED.into(),
+            command_url: crate::COMMAND_URL.map(Into::into),
+            config_url: crate::CONFIG_URL.map(Into::into),
+            ota_manifest_url: crate::OTA_MANIFEST_URL.map(Into::into),
+        },
+        device,
+        recorder: Recorder {
+            enabled: recorder::is_enabled(partition)?,
+        },
+        upload: Upload {
+            dry_run: dry_run::is_enabled(partition)?,
+            min_interval: limits.min_interval.as_secs(),
+            max_per_day: limits.max_per_day,
+            batch_size: batch::load(partition)?,
+        },
+        upload_window: Window::load(partition)?,
+        awake_limit: awake_limit::Settings::load(partition)?,
+        timeouts: Timeouts::load(partition)?,
+        schedule: Schedule {
+            bands: schedule::load(partition)?,
+        },
+        adaptive: adaptive::Settings::load(partition)?,
+        fleet: Fleet {
+            size: fleet::load(partition)?,
+        },
+        profile: profile::Settings::load(partition)?,
+        soil: Soil {
+            medium: soil::load(partition)?,
+        },
+        probes: Probes {
+            channels: probes::load(partition)?,
+        },
+        led: led::Settings::load(partition)?,
+        graphite,
+        statsd,
+        otlp,
+        postgrest,
+        mqtt,
+        coarse: coarse::Settings::load(partition)?,
+        alert: Policy::load(partition)?,
+        notifier,
+        wifi: Wifi {
+            mac: wifi_mac::load(partition)?.to_string(),
+        },
+        components: Components::load(partition)?,
+        tags: tags::load(partition)?.into_iter().collect(),
+    })
+}
+
+pub fn export(partition: &EspDefaultNvsPartition) -> Result<String> {
+    Ok(toml::to_string(&current(partition)?)?)
+}
+
+/// Replaces the configuration by the TOML document `text`. The document is validated as a whole
+/// before anything is written, and the previous configuration is restored if writing fails.
+pub fn import(partition: &EspDefaultNvsPartition, text: &str) -> Result<()> {
+    let config = parse(text)?;
+    let previous = current(partition)?;
+    if let Err(e) = apply(partition, &config) {
+        if let Err(e) = apply(partition, &previous) {
+            println!("error restoring configuration: {}", e);
+        }
+        return Err(e);
+    }
+    println!("configuration imported.");
+    Ok(())
+}
+
+/// Uploads the exported configuration to `url`, so that it can be backed up remotely.
+pub fn put(partition: &EspDefaultNvsPartition, url: &str, authorization: &str) -> Result<()> {
+    let data = export(partition)?;
+    let content_length = data.len().to_string();
+    let headers = [
+        ("Authorization", authorization),
+        ("Content-Type", "application/toml"),
+        ("Content-Length", &content_length),
+    ];
+    let mut http_client = http::new_connection()?;
+    http::submit(
+        &mut http_client,
+        Method::Put,
+        url,
+        &headers,
+        data.as_bytes(),
+    )?;
+    println!("configuration exported.");
+    Ok(())
+}
+
+impl Config {
+    /// Returns the settable part of the configuration as `section.key` pairs.
+    fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::new();
+        settings.insert("device.write_url".into(), self.device.write_url.clone());
+        settings.insert("device.line_prefix".into(), self.device.line_prefix.clone());
+        let interval = self.device.measurement_interval.to_string();
+        settings.insert("device.measurement_interval".into(), interval);
+        settings.insert("recorder.enabled".into(), self.recorder.enabled.to_string());
+        settings.insert("upload.dry_run".into(), self.upload.dry_run.to_string());
+        let min_interval = self.upload.min_interval.to_string();
+        settings.insert("upload.min_interval".into(), min_interval);
+        let max_per_day = self.upload.max_per_day.to_string();
+        settings.insert("upload.max_per_day".into(), max_per_day);
+        let batch_size = self.upload.batch_size.to_string();
+        settings.insert("upload.batch_size".into(), batch_size);
+        let window = &self.upload_window;
+        settings.insert("upload_window.start".into(), window.start.to_string());
+        settings.insert("upload_window.end".into(), window.end.to_string());
+        let utc_offset = window.utc_offset.to_string();
+        settings.insert("upload_window.utc_offset".into(), utc_offset);
+        let awake_limit = self.awake_limit.seconds.to_string();
+        settings.insert("awake_limit.seconds".into(), awake_limit);
+        let timeouts = &self.timeouts;
+        let wifi_start = timeouts.wifi_start.to_string();
+        settings.insert("timeouts.wifi_start".into(), wifi_start);
+        settings.insert("timeouts.connect".into(), timeouts.connect.to_string());
+        settings.insert("timeouts.dhcp".into(), timeouts.dhcp.to_string());
+        settings.insert("timeouts.sntp".into(), timeouts.sntp.to_string());
+        let bands: Vec<_> = self
+            .schedule
+            .bands
+            .iter()
+            .map(|band| format!("{}:{}", band.from, band.interval))
+            .collect();
+        settings.insert("schedule.bands".into(), bands.join(","));
+        let adaptive = &self.adaptive;
+        settings.insert("adaptive.enabled".into(), adaptive.enabled.to_string());
+        let min_interval = adaptive.min_interval.to_string();
+        settings.insert("adaptive.min_interval".into(), min_interval);
+        let max_interval = adaptive.max_interval.to_string();
+        settings.insert("adaptive.max_interval".into(), max_interval);
+        let rapid_rate = adaptive.rapid_rate.to_string();
+        settings.insert("adaptive.rapid_rate".into(), rapid_rate);
+        settings.insert("adaptive.flat_rate".into(), adaptive.flat_rate.to_string());
+        settings.insert("fleet.size".into(), self.fleet.size.to_string());
+        let profile = &self.profile;
+        settings.insert("profile.active".into(), profile.active.to_string());
+        let interval = profile.seedling_interval.to_string();
+        settings.insert("profile.seedling_interval".into(), interval);
+        let dry_above = profile.seedling_dry_above.to_string();
+        settings.insert("profile.seedling_dry_above".into(), dry_above);
+        let medium = self.soil.medium.map_or("", Medium::name);
+        settings.insert("soil.medium".into(), medium.into());
+        let channels: Vec<_> = self
+            .probes
+            .channels
+            .iter()
+            .map(|probe| format!("{}:{}", probe.channel, probe.name))
+            .collect();
+        settings.insert("probes.channels".into(), channels.join(","));
+        let led = &self.led;
+        settings.insert("led.brightness".into(), led.brightness.to_string());
+        let night_brightness = led.night_brightness.to_string();
+        settings.insert("led.night_brightness".into(), night_brightness);
+        settings.insert("led.night_start".into(), led.night_start.to_string());
+        settings.insert("led.night_end".into(), led.night_end.to_string());
+        settings.insert("led.utc_offset".into(), led.utc_offset.to_string());
+        settings.insert("graphite.address".into(), self.graphite.address.clone());
+        settings.insert("graphite.template".into(), self.graphite.template.clone());
+        settings.insert("statsd.address".into(), self.statsd.address.clone());
+        settings.insert("statsd.prefix".into(), self.statsd.prefix.clone());
+        settings.insert("otlp.url".into(), self.otlp.url.clone());
+        settings.insert("postgrest.url".into(), self.postgrest.url.clone());
+        settings.insert("mqtt.url".into(), self.mqtt.url.clone());
+        settings.insert("mqtt.topic".into(), self.mqtt.topic.clone());
+        settings.insert("mqtt.username".into(), self.mqtt.username.clone());
+        let coarse = &self.coarse;
+        let sinks: Vec<_> = coarse.sinks.iter().map(|sink| sink.name()).collect();
+        settings.insert("coarse.sinks".into(), sinks.join(","));
+        settings.insert("coarse.dry_above".into(), coarse.dry_above.to_string());
+        settings.insert("coarse.wet_below".into(), coarse.wet_below.to_string());
+        settings.insert("coarse.resolution".into(), coarse.resolution.to_string());
+        let dry_above = self.alert.dry_above.to_string();
+        settings.insert("alert.dry_above".into(), dry_above);
+        let critical_above = self.alert.critical_above.to_string();
+        settings.insert("alert.critical_above".into(), critical_above);
+        let chat_id = self.notifier.telegram_chat_id.clone();
+        settings.insert("notifier.telegram_chat_id".into(), chat_id);
+        for (measurement, table) in &self.postgrest.tables {
+            settings.insert(format!("postgrest.tables.{}", measurement), table.clone());
+        }
+        for (key, column) in &self.postgrest.columns {
+            settings.insert(format!("postgrest.columns.{}", key), column.clone());
+        }
+        settings.insert("wifi.mac".into(), self.wifi.mac.clone());
+        let components = &self.components;
+        settings.insert("components.ota".into(), components.ota.to_string());
+        let diagnostics = components.diagnostics.to_string();
+        settings.insert("components.diagnostics".into(), diagnostics);
+        for (key, value) in &self.tags {
+            settings.insert(format!("tags.{}", key), value.clone());
+        }
+        settings
+    }
+
+    /// Returns a hash of the settable part of the configuration, which is equal on devices that
+    /// have converged to the same configuration (32 bit FNV-1a, as hex).
+    pub fn hash(&self) -> String {
+        let mut hash: u32 = 0x811c_9dc5;
+        for (key, value) in self.settings() {
+            for byte in key
+                .bytes()
+                .chain([b'='])
+                .chain(value.bytes())
+                .chain([b'\n'])
+            {
+                hash ^= u32::from(byte);
+                hash = hash.wrapping_mul(0x0100_0193);
+            }
+        }
+        format!("{:08x}", hash)
+    }
+
+    /// Returns the keys of settings that were added, changed or removed in `other`.
+    pub fn changed_keys(&self, other: &Config) -> Vec<String> {
+        let before = self.settings();
+        let after = other.settings();
+        let mut keys: Vec<_> = before
+            .keys()
+            .chain(after.keys())
+            .filter(|key| before.get(*key) != after.get(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// Replaces a secret by a placeholder for export.
+fn redact(secret: &mut String) {
+    if !secret.is_empty() {
+        *secret = REDACTED.into();
+    }
+}
+
+/// Restores the `stored` secret if the imported one is the placeholder.
+fn unredact(secret: &mut String, stored: String) {
+    if secret == REDACTED {
+        *secret = stored;
+    }
+}
+
+fn parse(text: &str) -> Result<Config> {
+    let config: Config = toml::from_str(text)?;
+    for key in config.tags.keys() {
+        if key.is_empty() || key.contains(|c: char| c == '=' || c.is_whitespace()) {
+            bail!("invalid tag key: {:?}", key);
+        }
+    }
+    config.wifi.mac.parse::<MacMode>()?;
+    config.device.validate()?;
+    batch::validate(config.upload.batch_size)?;
+    upload_window::validate(&config.upload_window)?;
+    awake_limit::validate(&config.awake_limit)?;
+    timeouts::validate(&config.timeouts)?;
+    schedule::validate(&config.schedule.bands)?;
+    schedule::validate(&[Band {
+        from: 0,
+        interval: config.profile.seedling_interval,
+    }])?;
+    adaptive::validate(&config.adaptive)?;
+    fleet::validate(config.fleet.size)?;
+    probes::validate(&config.probes.channels)?;
+    led::validate(&config.led)?;
+    graphite::validate_template(&config.graphite.template)?;
+    statsd::validate_prefix(&config.statsd.prefix)?;
+    if !config.otlp.url.is_empty() {
+        otlp::validate_url(&config.otlp.url)?;
+    }
+    if !config.postgrest.url.is_empty() {
+        postgrest::validate(&config.postgrest)?;
+    }
+    if !config.mqtt.url.is_empty() {
+        mqtt::validate(&config.mqtt)?;
+    }
+    coarse::validate(&config.coarse)?;
+    let alert = &config.alert;
+    if alert.critical_above != 0
+        && (alert.dry_above == 0 || alert.critical_above <= alert.dry_above)
+    {
+        bail!("alert.critical_above must be above a non-zero alert.dry_above");
+    }
+    let ntfy_url = &config.notifier.ntfy_url;
+    if !ntfy_url.is_empty() && ntfy_url != REDACTED {
+        notifier::validate_ntfy_url(ntfy_url)?;
+    }
+    let sinks = [
+        &config.graphite.address,
+        &config.otlp.url,
+        &config.postgrest.url,
+        &config.mqtt.url,
+    ];
+    if sinks.iter().filter(|s| !s.is_empty()).count() > 1 {
+        bail!("uploads can go to only one of Graphite, OTLP, PostgREST and MQTT");
+    }
+    Ok(config)
+}
+
+fn apply(partition: &EspDefaultNvsPartition, config: &Config) -> Result<()> {
+    let mut device = config.device.clone();
+    unredact(
+        &mut device.authorization,
+        DeviceConfig::load(partition)?.authorization,
+    );
+    device.save(partition)?;
+    let tags: tags::Tags = config.tags.clone().into_iter().collect();
+    tags::replace(partition, &tags)?;
+    // Enabling the recorder clears the recording, so only write it if it changes.
+    if recorder::is_enabled(partition)? != config.recorder.enabled {
+        recorder::set_enabled(partition, config.recorder.enabled)?;
+    }
+    dry_run::set_enabled(partition, config.upload.dry_run)?;
+    let limits = Limits {
+        min_interval: Duration::from_secs(config.upload.min_interval),
+        max_per_day: config.upload.max_per_day,
+    };
+    limits.save(partition)?;
+    batch::save(partition, config.upload.batch_size)?;
+    config.upload_window.save(partition)?;
+    config.awake_limit.save(partition)?;
+    config.timeouts.save(partition)?;
+    schedule::save(partition, &config.schedule.bands)?;
+    config.adaptive.save(partition)?;
+    fleet::save(partition, config.fleet.size)?;
+    config.profile.save(partition)?;
+    soil::save(partition, config.soil.medium)?;
+    probes::save(partition, &config.probes.channels)?;
+    config.led.save(partition)?;
+    let target = Target {
+        address: config.graphite.address.clone(),
+        template: config.graphite.template.clone(),
+    };
+    graphite::save(partition, Some(&target).filter(|t| !t.address.is_empty()))?;
+    let target = statsd::Target {
+        address: config.statsd.address.clone(),
+        prefix: config.statsd.prefix.clone(),
+    };
+    statsd::save(partition, Some(&target).filter(|t| !t.address.is_empty()))?;
+    let target = otlp::Target {
+        url: config.otlp.url.clone(),
+    };
+    otlp::save(partition, Some(&target).filter(|t| !t.url.is_empty()))?;
+    let mut target = config.postgrest.clone();
+    let stored = postgrest::load(partition)?.unwrap_or_default();
+    unredact(&mut target.api_key, stored.api_key);
+    postgrest::save(partition, Some(&target).filter(|t| !t.url.is_empty()))?;
+    let mut target = config.mqtt.clone();
+    let stored = mqtt::load(partition)?.unwrap_or_default();
+    unredact(&mut target.password, stored.password);
+    mqtt::save(partition, Some(&target).filter(|t| !t.url.is_empty()))?;
+    config.coarse.save(partition)?;
+    config.alert.save(partition)?;
+    let mut channels = config.notifier.clone();
+    let stored = Channels::load(partition)?;
+    unredact(&mut channels.discord_webhook, stored.discord_webhook);
+    unredact(&mut channels.telegram_token, stored.telegram_token);
+    unredact(&mut channels.ntfy_url, stored.ntfy_url);
+    unredact(&mut channels.ntfy_token, stored.ntfy_token);
+    channels.save(partition)?;
+    wifi_mac::save(partition, config.wifi.mac.parse()?)?;
+    config.components.save(partition)?;
+    Ok(())
+}
+
+#[test]
+pub fn test_parse() {
+    let config = parse(
+        "[firmware]\n\
+         wifi_ssid = \"ignored\"\n\
+         \n\
+         [recorder]\n\
+         enabled = true\n\
+         \n\
+         [tags]\n\
+         site = \"Green House\"\n\
+         plant = \"basil\"\n",
+    )
+    .unwrap();
+    assert_eq!(config.firmware, Firmware::default());
+    assert!(config.recorder.enabled);
+    assert_eq!(config.upload, Upload::default());
+    assert_eq!(
+        config.tags.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("plant".into(), "basil".into()),
+            ("site".into(), "Green House".into())
+        ]
+    );
+
+    assert_eq!(parse("").unwrap(), Config::default());
+    assert!(parse("[tags]\n\"a=b\" = \"c\"\n").is_err());
+    assert!(parse("[recorder]\nenabled = \"yes\"\n").is_err());
+    assert!(parse("[wifi]\nmac = \"stable\"\n").is_err());
+    assert!(parse("[components]\nota = \"off\"\n").is_err());
+    assert!(parse("[device]\nline_prefix = \"moisture\"\n").is_err());
+    assert!(parse("[device]\nmeasurement_interval = 10\n").is_err());
+    assert!(parse("[upload]\nbatch_size = 0\n").is_err());
+    assert!(parse("[upload_window]\nstart = 8\nend = 24\n").is_err());
+    assert!(parse("[awake_limit]\nseconds = 5\n").is_err());
+    assert!(parse("[timeouts]\ndhcp = 0\n").is_err());
+    assert!(parse("[coarse]\nresolution = 0\n").is_err());
+    assert!(parse("[coarse]\nsinks = [\"display\"]\n").is_err());
+    assert!(parse("[graphite]\ntemplate = \"{sensor\"\n").is_err());
+    assert!(parse("[statsd]\nprefix = \"a:b\"\n").is_err());
+    assert!(parse("[otlp]\nurl = \"collector:4318\"\n").is_err());
+    assert!(parse("[postgrest]\nurl = \"http://db:3000\"\n").is_err());
+    assert!(parse("[mqtt]\nurl = \"mqtt://broker\"\ntopic = \"a/#\"\n").is_err());
+    assert!(parse("[schedule]\nbands = [{ from = 0, interval = 30 }]\n").is_err());
+    assert!(parse("[adaptive]\nmax_interval = 600\n").is_err());
+    assert!(parse("[fleet]\nsize = 1000\n").is_err());
+    assert!(parse("[profile]\nactive = \"dome\"\n").is_err());
+    assert!(parse("[soil]\nmedium = \"peat\"\n").is_err());
+    assert!(parse("[[probes.channels]]\nchannel = 0\nname = \"a\"\n").is_err());
+    assert!(parse("[led]\nnight_brightness = 150\n").is_err());
+    assert!(parse("[alert]\ncritical_above = 2400\n").is_err());
+    assert!(parse("[alert]\ndry_above = 2000\ncritical_above = 1800\n").is_err());
+    assert!(parse("[notifier]\nntfy_url = \"ntfy.sh/basil\"\n").is_err());
+    assert!(parse(
+        "[graphite]\naddress = \"graphite:2003\"\n\
+         [otlp]\nurl = \"http://collector:4318/v1/metrics\"\n"
+    )
+    .is_err());
+}
+
+#[test]
+pub fn test_changed_keys() {
+    let before = Config::default();
+    let mut after = Config::default();
+    assert!(before.changed_keys(&after).is_empty());
+    assert_eq!(before.hash(), after.hash());
+
+    after.recorder.enabled = true;
+    after.tags.insert("site".into(), "Green House".into());
+    assert_eq!(
+        before.changed_keys(&after),
+        vec!["recorder.enabled", "tags.site"]
+    );
+    assert_eq!(after.changed_keys(&before), before.changed_keys(&after));
+    assert_ne!(before.hash(), after.hash());
+    assert_eq!(before.hash().len(), 8);
+}
@@ -0,0 +1,100 @@
+//! Metrics of the WiFi connection of the current cycle, uploaded as a `connection` point along with
+//! the upload, so that failing uploads can be correlated with signal quality and slow networks.
+//! Durations are measured from starting WiFi, except for DHCP, which starts once associated.
+
+use crate::line_protocol::FieldValue;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Signal strength of the access point in dBm.
+    pub rssi: Option<i8>,
+    /// Time until associated with the access point.
+    pub associate_ms: Option<u32>,
+    /// Time from associating until an IP address was obtained.
+    pub dhcp_ms: Option<u32>,
+    /// Time until the time was synced by SNTP.
+    pub sntp_ms: Option<u32>,
+}
+
+impl Metrics {
+    pub fn fields(&self) -> Vec<(&'static str, FieldValue<'static>)> {
+        let mut fields = Vec::new();
+        if let Some(rssi) = self.rssi {
+            fields.push(("rssi", FieldValue::Integer(rssi.into())));
+        }
+        let durations = [
+            ("associate_ms", self.associate_ms),
+            ("dhcp_ms", self.dhcp_ms),
+            ("sntp_ms", self.sntp_ms),
+        ];
+        for (name, ms) in durations {
+            if let Some(ms) = ms {
+                fields.push((name, FieldValue::Integer(ms.into())));
+            }
+        }
+        fields
+    }
+}
+
+struct Connection {
+    started: Instant,
+    associated: Option<Instant>,
+    metrics: Metrics,
+}
+
+static CURRENT: Mutex<Option<Connection>> = Mutex::new(None);
+
+fn elapsed_ms(since: Instant) -> Option<u32> {
+    Some(since.elapsed().as_millis() as u32)
+}
+
+/// Starts measuring a connection as WiFi is started.
+pub fn start() {
+    *CURRENT.lock().unwrap() = Some(Connection {
+        started: Instant::now(),
+        associated: None,
+        metrics: Metrics::default(),
+    });
+}
+
+pub fn associated(rssi: Option<i8>) {
+    if let Some(connection) = CURRENT.lock().unwrap().as_mut() {
+        connection.associated = Some(Instant::now());
+        connection.metrics.rssi = rssi;
+        connection.metrics.associate_ms = elapsed_ms(connection.started);
+    }
+}
+
+pub fn ip_assigned() {
+    if let Some(connection) = CURRENT.lock().unwrap().as_mut() {
+        connection.metrics.dhcp_ms = connection.associated.and_then(elapsed_ms);
+    }
+}
+
+pub fn time_synced() {
+    if let Some(connection) = CURRENT.lock().unwrap().as_mut() {
+        connection.metrics.sntp_ms = elapsed_ms(connection.started);
+    }
+}
+
+/// Returns the metrics of the connection of the current cycle, if WiFi was started.
+pub fn metrics() -> Option<Metrics> {
+    CURRENT.lock().unwrap().as_ref().map(|c| c.metrics)
+}
+
+#[test]
+pub fn test_metrics() {
+    assert_eq!(metrics(), None);
+    start();
+    associated(Some(-67));
+    ip_assigned();
+    let metrics = metrics().unwrap();
+    assert_eq!(metrics.rssi, Some(-67));
+    assert!(metrics.dhcp_ms.is_some());
+    assert_eq!(metrics.sntp_ms, None);
+    let names: Vec<_> = metrics.fields().iter().map(|(name, _)| *name).collect();
+    assert_eq!(names, ["rssi", "associate_ms", "dhcp_ms"]);
+    assert!(Metrics::default().fields().is_empty());
+}
@@ -0,0 +1,178 @@
+//! Sampling of the probe in ADC continuous mode with the `continuous` feature. The ADC converts at
+//! a fixed rate into a DMA buffer while the probe is excited, and the CPU only collects whole frames
+//! instead of waiting for every one-shot conversion. The probe settles on the means of the frames,
+//! after which a capture of `CAPTURE_CONVERSIONS` samples is taken in about 13 ms.
+//!
+//! esp-idf-hal 0.39 has no driver for the continuous mode, so the ESP-IDF 4.4 API is used directly.
+
+use crate::settling::{self, Progress, Trace};
+use anyhow::{Context, Result};
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::{adc, gpio};
+use esp_idf_sys::{self as sys, esp};
+
+const SAMPLE_FREQ_HZ: u32 = 20_000;
+/// Conversion results in the output format of the ESP32-C3 take 4 bytes.
+const BYTES_PER_CONVERSION: usize = 4;
+/// Conversions per frame, one frame per settling poll.
+const FRAME_CONVERSIONS: usize = (SAMPLE_FREQ_HZ * settling::POLL_INTERVAL_MS / 1000) as usize;
+pub const CAPTURE_CONVERSIONS: usize = 256;
+const READ_TIMEOUT_MS: u32 = 50;
+
+/// Returns the samples in mV of the probe on ADC1 `channel`, which must be excited already.
+pub fn capture<P: gpio::ADCPin<Adc = adc::ADC1>>(
+    _adc: impl Peripheral<P = adc::ADC1>,
+    _adc_pin: impl Peripheral<P = P>,
+    channel: u8,
+) -> Result<Vec<u16>> {
+    let converter = Converter::start(channel)?;
+
+    let mut trace = Trace::new();
+    let mut frame = Vec::with_capacity(FRAME_CONVERSIONS);
+    loop {
+        frame.clear();
+        converter.read(&mut frame, FRAME_CONVERSIONS)?;
+        match trace.update(converter.to_mv(mean(&frame))) {
+            Progress::Settling => {}
+            Progress::Settled => break,
+            Progress::TimedOut => {
+                trace.record_timeout(crate::slow_clock_seconds());
+                break;
+            }
+        }
+    }
+
+    let mut samples = Vec::with_capacity(CAPTURE_CONVERSIONS);
+    converter.read(&mut samples, CAPTURE_CONVERSIONS)?;
+    Ok(samples
+        .into_iter()
+        .map(|raw| converter.to_mv(raw))
+        .collect())
+}
+
+/// The running ADC in continuous mode, stopped when dropped.
+struct Converter {
+    channel: u8,
+    characteristics: sys::esp_adc_cal_characteristics_t,
+}
+
+impl Converter {
+    fn start(channel: u8) -> Result<Converter> {
+        let init_config = sys::adc_digi_init_config_t {
+            max_store_buf_size: (2 * CAPTURE_CONVERSIONS * BYTES_PER_CONVERSION) as u32,
+            conv_num_each_intr: (FRAME_CONVERSIONS * BYTES_PER_CONVERSION) as u32,
+            adc1_chan_mask: 1 << channel,
+            adc2_chan_mask: 0,
+        };
+        esp!(unsafe { sys::adc_digi_initialize(&init_config) })
+            .context("initializing ADC continuous mode")?;
+        // From here on, the driver is deinitialized on errors.
+        let mut converter = Converter {
+            channel,
+            characteristics: Default::default(),
+        };
+
+        let mut pattern = sys::adc_digi_pattern_config_t {
+            atten: sys::adc_atten_t_ADC_ATTEN_DB_11 as u8,
+            channel,
+            unit: 0,
+            bit_width: 12,
+        };
+        let config = sys::adc_digi_configuration_t {
+            conv_limit_en: false,
+            conv_limit_num: 250,
+            pattern_num: 1,
+            adc_pattern: &mut pattern,
+            sample_freq_hz: SAMPLE_FREQ_HZ,
+            conv_mode: sys::adc_digi_convert_mode_t_ADC_CONV_SINGLE_UNIT_1,
+            format: sys::adc_digi_output_format_t_ADC_DIGI_OUTPUT_FORMAT_TYPE2,
+        };
+        esp!(unsafe { sys::adc_digi_controller_configure(&config) })?;
+        unsafe {
+            sys::esp_adc_cal_characterize(
+                sys::adc_unit_t_ADC_UNIT_1,
+                sys::adc_atten_t_ADC_ATTEN_DB_11,
+                sys::adc_bits_width_t_ADC_WIDTH_BIT_12,
+                0,
+                &mut converter.characteristics,
+            );
+        }
+        esp!(unsafe { sys::adc_digi_start() })?;
+        Ok(converter)
+    }
+
+    /// Appends `count` raw conversion results of the probe to `samples`.
+    fn read(&self, samples: &mut Vec<u16>, count: usize) -> Result<()> {
+        let mut buffer = [0; FRAME_CONVERSIONS * BYTES_PER_CONVERSION];
+        let target = samples.len() + count;
+        while samples.len() < target {
+            let mut length = 0;
+            esp!(unsafe {
+                sys::adc_digi_read_bytes(
+                    buffer.as_mut_ptr(),
+                    buffer.len() as u32,
+                    &mut length,
+                    READ_TIMEOUT_MS,
+                )
+            })
+            .context("reading ADC conversions")?;
+            decode(&buffer[..length as usize], self.channel, samples);
+        }
+        samples.truncate(target);
+        Ok(())
+    }
+
+    fn to_mv(&self, raw: u16) -> u16 {
+        unsafe { sys::esp_adc_cal_raw_to_voltage(raw.into(), &self.characteristics) as u16 }
+    }
+}
+
+impl Drop for Converter {
+    fn drop(&mut self) {
+        unsafe {
+            sys::adc_digi_stop();
+            sys::adc_digi_deinitialize();
+        }
+    }
+}
+
+/// Appends the raw results of ADC1 `channel` in `bytes` to `samples`. Each result is a
+/// little-endian word with the data in bits 0 to 11, the channel in bits 13 to 15 and the unit in
+/// bit 16.
+fn decode(bytes: &[u8], channel: u8, samples: &mut Vec<u16>) {
+    for chunk in bytes.chunks_exact(BYTES_PER_CONVERSION) {
+        let word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let unit = (word >> 16) & 0x1;
+        if unit == 0 && (word >> 13) & 0x7 == u32::from(channel) {
+            samples.push((word & 0xfff) as u16);
+        }
+    }
+}
+
+fn mean(samples: &[u16]) -> u16 {
+    let sum: u32 = samples.iter().map(|&s| u32::from(s)).sum();
+    (sum / samples.len().max(1) as u32) as u16
+}
+
+#[test]
+pub fn test_decode() {
+    let word =
+        |data: u32, channel: u32, unit: u32| (data | channel << 13 | unit << 16).to_le_bytes();
+    let bytes: Vec<u8> = [
+        word(1800, 4, 0),
+        word(4095, 3, 0),
+        word(1802, 4, 0),
+        word(100, 4, 1),
+    ]
+    .concat();
+    let mut samples = vec![];
+    decode(&bytes, 4, &mut samples);
+    decode(&bytes[..6], 4, &mut samples);
+    assert_eq!(samples, vec![1800, 1802, 1800]);
+    assert_eq!(mean(&samples), 1800);
+    assert_eq!(mean(&[]), 0);
+
+    let mut samples = vec![];
+    decode(&bytes, 3, &mut samples);
+    assert_eq!(samples, vec![4095]);
+}
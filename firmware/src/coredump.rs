@@ -0,0 +1,155 @@
+//! Crash reports from core dumps. ESP-IDF writes a core dump to the `coredump` partition when the
+//! firmware panics or faults. A summary of it is uploaded as a `crash` point with the next upload,
+//! after which the dump is erased. Until then, the full dump can be read over serial with
+//! `espcoredump.py`.
+//!
+//! The ESP32-C3 is a RISC-V chip, for which ESP-IDF doesn't unwind the stack on the device. The
+//! summary has the program counter and return address of the crash, and as backtrace the words on
+//! the stack of the crashed task that point into code, to be resolved with `addr2line`.
+
+use anyhow::{bail, Result};
+use esp_idf_sys::{self as sys, esp};
+use std::fmt;
+use std::ptr;
+
+/// Backtrace addresses uploaded at most.
+const MAX_BACKTRACE: usize = 8;
+/// Address ranges of code: ROM, internal RAM and flash.
+const CODE_RANGES: [(u32, u32); 3] = [
+    (0x4000_0000, 0x4006_0000),
+    (0x4037_c000, 0x403e_0000),
+    (0x4200_0000, 0x4280_0000),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub task: String,
+    pub pc: u32,
+    /// Return address at the time of the crash.
+    pub ra: u32,
+    /// Cause of the exception, as in the RISC-V `mcause` register.
+    pub cause: u32,
+    pub backtrace: Vec<u32>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "crash in task {} at {:#010x}, cause {}, backtrace {}",
+            self.task,
+            self.pc,
+            self.cause,
+            self.backtrace_string()
+        )
+    }
+}
+
+impl Summary {
+    pub fn backtrace_string(&self) -> String {
+        let addresses: Vec<_> = [self.ra]
+            .iter()
+            .chain(&self.backtrace)
+            .map(|address| format!("{:#010x}", address))
+            .collect();
+        addresses.join(" ")
+    }
+}
+
+/// Returns the summary of the stored core dump, if any.
+pub fn pending() -> Option<Summary> {
+    let (mut address, mut size) = (0, 0);
+    if unsafe { sys::esp_core_dump_image_get(&mut address, &mut size) } != sys::ESP_OK {
+        return None;
+    }
+    let mut summary: sys::esp_core_dump_summary_t = Default::default();
+    if let Err(e) = esp!(unsafe { sys::esp_core_dump_get_summary(&mut summary) }) {
+        println!("error reading core dump of {} bytes: {}", size, e);
+        return Some(Summary {
+            task: "unknown".into(),
+            pc: 0,
+            ra: 0,
+            cause: 0,
+            backtrace: vec![],
+        });
+    }
+    let task: Vec<u8> = summary.exc_task.iter().map(|&c| c as u8).collect();
+    let stack = &summary.exc_bt_info.stackdump;
+    let stack_words = (summary.exc_bt_info.stack_size as usize / 4).min(stack.len());
+    Some(Summary {
+        task: task_name(&task),
+        pc: summary.exc_pc,
+        ra: summary.ex_info.ra,
+        cause: summary.ex_info.mcause,
+        backtrace: code_addresses(&stack[..stack_words]),
+    })
+}
+
+/// Erases the stored core dump.
+pub fn clear() -> Result<()> {
+    let partition = unsafe {
+        sys::esp_partition_find_first(
+            sys::esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+            sys::esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_DATA_COREDUMP,
+            ptr::null(),
+        )
+    };
+    if partition.is_null() {
+        bail!("no core dump partition");
+    }
+    let size = unsafe { (*partition).size };
+    esp!(unsafe { sys::esp_partition_erase_range(partition, 0, size as _) })?;
+    Ok(())
+}
+
+fn task_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into()
+}
+
+/// Returns the words of `stack` that point into code, which are mostly return addresses.
+fn code_addresses(stack: &[u32]) -> Vec<u32> {
+    stack
+        .iter()
+        .copied()
+        .filter(|&word| {
+            CODE_RANGES
+                .iter()
+                .any(|&(start, end)| (start..end).contains(&word))
+        })
+        .take(MAX_BACKTRACE)
+        .collect()
+}
+
+#[test]
+pub fn test_summary() {
+    assert_eq!(task_name(b"main\0\0\0\0"), "main");
+    assert_eq!(task_name(b"sixteen_chars_ab"), "sixteen_chars_ab");
+
+    let stack = [
+        0x3fc8_a000,
+        0x4200_1a2c,
+        0,
+        0x4038_0f00,
+        0x4280_0000,
+        0x4000_1234,
+    ];
+    assert_eq!(
+        code_addresses(&stack),
+        vec![0x4200_1a2c, 0x4038_0f00, 0x4000_1234]
+    );
+    assert_eq!(code_addresses(&[0x4200_0000; 20]).len(), MAX_BACKTRACE);
+
+    let summary = Summary {
+        task: "main".into(),
+        pc: 0x4200_2000,
+        ra: 0x4200_1ffc,
+        cause: 7,
+        backtrace: vec![0x4200_1a2c],
+    };
+    assert_eq!(summary.backtrace_string(), "0x42001ffc 0x42001a2c");
+    assert_eq!(
+        summary.to_string(),
+        "crash in task main at 0x42002000, cause 7, backtrace 0x42001ffc 0x42001a2c"
+    );
+}
@@ -0,0 +1,83 @@
+//! Debouncing of the digital inputs of the board. Inputs are sampled periodically, and a change of
+//! the sampled level is only accepted once it has held for the settle time of the input, which
+//! rejects glitches and contact bounce. The user button is the only digital input of the current
+//! board; inputs such as a rain sensor, a float switch or the charge status of the charger would
+//! get their own variant of [`Input`] with a settle time suiting them.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Button,
+}
+
+impl Input {
+    /// Time a changed level must hold before it's accepted.
+    pub fn settle_time(self) -> Duration {
+        match self {
+            Input::Button => Duration::from_millis(30),
+        }
+    }
+}
+
+pub struct Debouncer {
+    settle_time: Duration,
+    level: bool,
+    /// Time since which the sampled level differs from the accepted one.
+    changed_since: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(input: Input, level: bool) -> Debouncer {
+        Debouncer {
+            settle_time: input.settle_time(),
+            level,
+            changed_since: None,
+        }
+    }
+
+    /// Updates the debouncer with the level sampled at `now`, returning the accepted level.
+    pub fn update(&mut self, sampled: bool, now: Instant) -> bool {
+        if sampled == self.level {
+            self.changed_since = None;
+        } else {
+            let since = *self.changed_since.get_or_insert(now);
+            if now - since >= self.settle_time {
+                self.level = sampled;
+                self.changed_since = None;
+            }
+        }
+        self.level
+    }
+
+    /// Whether a changed level hasn't been accepted yet.
+    pub fn is_settling(&self) -> bool {
+        self.changed_since.is_some()
+    }
+}
+
+#[test]
+pub fn test_update() {
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut debouncer = Debouncer::new(Input::Button, false);
+    assert!(!debouncer.update(false, at(0)));
+    assert!(!debouncer.is_settling());
+
+    // A glitch shorter than the settle time is rejected.
+    assert!(!debouncer.update(true, at(20)));
+    assert!(debouncer.is_settling());
+    assert!(!debouncer.update(false, at(40)));
+    assert!(!debouncer.is_settling());
+
+    // Bouncing restarts the settle time.
+    assert!(!debouncer.update(true, at(100)));
+    assert!(!debouncer.update(false, at(110)));
+    assert!(!debouncer.update(true, at(120)));
+    assert!(!debouncer.update(true, at(140)));
+    assert!(debouncer.update(true, at(150)));
+    assert!(!debouncer.is_settling());
+
+    assert!(debouncer.update(false, at(200)));
+    assert!(!debouncer.update(false, at(230)));
+}
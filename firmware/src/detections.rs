@@ -0,0 +1,56 @@
+//! Log of the events detected in the readings of the on-board probe, see [`crate::watering`], kept
+//! in RTC memory and uploaded with the next upload. They're uploaded as points of their own instead
+//! of marking the measurements as watered, which only the button does: the thresholds are checked
+//! against constructed series rather than recordings of real pots, so detections are guesses that
+//! shouldn't be mistaken for what a person recorded.
+
+use crate::rtc::LOGS;
+use crate::watering::Event;
+
+pub const MAX_DETECTIONS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Detection {
+    /// Slow clock time in seconds of the reading that completed the event.
+    pub time: u32,
+    pub event: Event,
+}
+
+pub fn record(time: u32, event: Event) {
+    println!("detected event: {}", event);
+    unsafe {
+        LOGS.detections
+            .overwriting_push_back(Detection { time, event });
+    }
+}
+
+/// Returns the detections not uploaded yet, oldest first.
+pub fn pending() -> Vec<Detection> {
+    unsafe { LOGS.detections.iter().copied().collect() }
+}
+
+pub fn clear() {
+    unsafe {
+        LOGS.detections.clear();
+    }
+}
+
+#[test]
+pub fn test_detections() {
+    for time in 0..MAX_DETECTIONS as u32 + 1 {
+        record(time, Event::Watered);
+    }
+    record(100, Event::DriedOut);
+    let detections = pending();
+    assert_eq!(detections.len(), MAX_DETECTIONS);
+    assert_eq!(detections[0].time, 2);
+    assert_eq!(
+        detections.last(),
+        Some(&Detection {
+            time: 100,
+            event: Event::DriedOut,
+        })
+    );
+    clear();
+    assert!(pending().is_empty());
+}
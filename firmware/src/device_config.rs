@@ -0,0 +1,95 @@
+//! Upload target and interval of the device, stored in NVS so that one image can be deployed to many
+//! devices. Until a configuration has been stored, the values given in environment variables at
+//! build time are used, if any.
+
+use crate::line_protocol;
+use crate::schedule::{self, Band};
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const NVS_NAMESPACE: &str = "device";
+const NVS_KEY: &str = "config";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    /// InfluxDB write endpoint.
+    pub write_url: String,
+    /// Value of the `Authorization` header of requests to the server.
+    pub authorization: String,
+    /// Series and field key of readings, see [`line_protocol`].
+    pub line_prefix: String,
+    /// Interval between cycles in seconds, unless the schedule has bands.
+    pub measurement_interval: u32,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> DeviceConfig {
+        DeviceConfig {
+            write_url: option_env!("WRITE_URL").unwrap_or_default().into(),
+            authorization: option_env!("AUTHORIZATION").unwrap_or_default().into(),
+            line_prefix: option_env!("LINE_PREFIX").unwrap_or_default().into(),
+            measurement_interval: crate::MEASUREMENT_INTERVAL.as_secs() as u32,
+        }
+    }
+}
+
+impl DeviceConfig {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<DeviceConfig> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        self.validate()?;
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    /// Checks the settings that have been given. The write URL and the line prefix may be empty on
+    /// devices that haven't been configured yet.
+    pub fn validate(&self) -> Result<()> {
+        if !self.write_url.is_empty() {
+            validate_write_url(&self.write_url)?;
+        }
+        if !self.line_prefix.is_empty() {
+            line_protocol::validate(&format!("{}0", self.line_prefix))?;
+        }
+        schedule::validate(&[Band {
+            from: 0,
+            interval: self.measurement_interval,
+        }])
+    }
+
+    pub fn measurement_interval(&self) -> Duration {
+        Duration::from_secs(self.measurement_interval.into())
+    }
+}
+
+pub fn validate_write_url(url: &str) -> Result<()> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        bail!("write URL must start with http:// or https://: {}", url);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_validate() {
+    let mut config = DeviceConfig {
+        write_url: "https://influx.example.com/api/v2/write?bucket=plants".into(),
+        authorization: "Token abc".into(),
+        line_prefix: "moisture,sensor=balcony value=".into(),
+        measurement_interval: 3600,
+    };
+    config.validate().unwrap();
+
+    config.line_prefix = "moisture".into();
+    assert!(config.validate().is_err());
+    config.line_prefix = String::new();
+    config.write_url = "influx.example.com".into();
+    assert!(config.validate().is_err());
+    config.write_url = String::new();
+    config.measurement_interval = 10;
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,110 @@
+//! Extended self-metrics recorded for the first cycles after new firmware has been flashed, so that
+//! firmware versions can be compared across the fleet from the uploaded data alone. They are
+//! uploaded tagged with the build ID.
+
+use crate::rtc::{LOGS, STATE};
+use std::hash::{Hash, Hasher};
+
+/// Number of cycles after flashing for which diagnostics are recorded.
+pub const DIAGNOSTIC_CYCLES: u32 = 48;
+
+/// Rough current draw of the board while awake, and additionally while the radio is on, used for
+/// the energy estimate.
+const AWAKE_CURRENT_MA: u32 = 25;
+const RADIO_CURRENT_MA: u32 = 55;
+const SUPPLY_VOLTAGE_MV: u32 = 3300;
+
+/// Diagnostics of the cycles since the last upload, at most one per cycle.
+pub const MAX_DIAGNOSTICS: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Diagnostic {
+    /// Slow clock time in seconds of the cycle.
+    pub time: u32,
+    /// Variance of the samples of the reading of the cycle.
+    pub sample_variance: Option<f32>,
+    /// Time from starting WiFi until an IP address was obtained.
+    pub connect_ms: Option<u32>,
+    pub awake_ms: u32,
+    /// Subsystems that weren't ready after startup, see [`crate::startup`].
+    pub not_ready: u16,
+}
+
+impl Hash for Diagnostic {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.time.hash(state);
+        self.sample_variance.map(f32::to_bits).hash(state);
+        self.connect_ms.hash(state);
+        self.awake_ms.hash(state);
+        self.not_ready.hash(state);
+    }
+}
+
+impl Diagnostic {
+    /// Estimated energy used during the cycle in millijoules.
+    pub fn energy_mj(&self) -> u32 {
+        let charge_uc = u64::from(self.awake_ms) * u64::from(AWAKE_CURRENT_MA)
+            + u64::from(self.connect_ms.unwrap_or(0)) * u64::from(RADIO_CURRENT_MA);
+        (charge_uc * u64::from(SUPPLY_VOLTAGE_MV) / 1_000_000) as u32
+    }
+}
+
+/// Starts recording the current cycle if it's one of the first `DIAGNOSTIC_CYCLES` boots since
+/// flashing.
+pub fn start(boots: u32) {
+    unsafe {
+        if boots == 1 {
+            // Diagnostics of the previous firmware, which aren't tagged with its build ID.
+            LOGS.diagnostics.clear();
+        }
+        STATE.diagnostic = (boots <= DIAGNOSTIC_CYCLES).then(Diagnostic::default);
+    }
+}
+
+/// Returns the diagnostic of the current cycle, if it's recorded.
+pub fn current() -> Option<&'static mut Diagnostic> {
+    unsafe { STATE.diagnostic.as_mut() }
+}
+
+/// Completes the diagnostic of the current cycle.
+pub fn finish(awake_ms: u32) {
+    unsafe {
+        if let Some(mut diagnostic) = STATE.diagnostic.take() {
+            diagnostic.awake_ms = awake_ms;
+            LOGS.diagnostics.overwriting_push_back(diagnostic);
+        }
+    }
+}
+
+/// Returns the diagnostics not uploaded yet, oldest first.
+pub fn pending() -> Vec<Diagnostic> {
+    unsafe { LOGS.diagnostics.iter().copied().collect() }
+}
+
+pub fn clear() {
+    unsafe {
+        LOGS.diagnostics.clear();
+    }
+}
+
+#[test]
+pub fn test_diagnostics() {
+    let diagnostic = Diagnostic {
+        connect_ms: Some(2000),
+        awake_ms: 4000,
+        ..Default::default()
+    };
+    // (4000 ms * 25 mA + 2000 ms * 55 mA) * 3.3 V
+    assert_eq!(diagnostic.energy_mj(), 693);
+
+    start(1);
+    current().unwrap().time = 10;
+    finish(500);
+    start(DIAGNOSTIC_CYCLES + 1);
+    assert!(current().is_none());
+    finish(600);
+    assert_eq!(pending().len(), 1);
+    assert_eq!(pending()[0].awake_ms, 500);
+    start(1);
+    assert!(pending().is_empty());
+}
@@ -0,0 +1,92 @@
+//! Provisioning of WiFi credentials with the Device Provisioning Protocol (WiFi Easy Connect),
+//! enabled with the `dpp` feature. The device prints its bootstrapping URI, which is shown as a QR
+//! code and scanned with a phone to send the credentials.
+
+use crate::provisioning::c_bytes;
+use anyhow::{anyhow, Result};
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_sys::esp;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Channels listened on for the configurator.
+const CHANNELS: &[u8] = b"1,6,11\0";
+
+enum Event {
+    UriReady(String),
+    ConfigReceived(String, String),
+    Failed(i32),
+}
+
+/// Events received by the callback, which runs in the supplicant task.
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+/// Waits for credentials sent from a configurator.
+pub fn receive(timeout: Duration) -> Result<(String, String)> {
+    unsafe {
+        esp!(esp_idf_sys::esp_supp_dpp_init(Some(handle_event)))?;
+    }
+    let result = run(timeout);
+    unsafe {
+        esp_idf_sys::esp_supp_dpp_deinit();
+    }
+    result
+}
+
+fn run(timeout: Duration) -> Result<(String, String)> {
+    unsafe {
+        esp!(esp_idf_sys::esp_supp_dpp_bootstrap_gen(
+            CHANNELS.as_ptr() as *const c_char,
+            esp_idf_sys::esp_supp_dpp_bootstrap_t_DPP_BOOTSTRAP_QR_CODE,
+            ptr::null(),
+            ptr::null(),
+        ))?;
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        let events: Vec<_> = EVENTS.lock().unwrap().drain(..).collect();
+        for event in events {
+            match event {
+                Event::UriReady(uri) => {
+                    println!("scan the QR code of this URI to provision WiFi:");
+                    println!("{}", uri);
+                    unsafe {
+                        esp!(esp_idf_sys::esp_supp_dpp_start_listen())?;
+                    }
+                }
+                Event::ConfigReceived(ssid, password) => return Ok((ssid, password)),
+                Event::Failed(code) => {
+                    println!("DPP failed with {}, listening again", code);
+                    unsafe {
+                        esp!(esp_idf_sys::esp_supp_dpp_start_listen())?;
+                    }
+                }
+            }
+        }
+        FreeRtos::delay_ms(100);
+    }
+
+    unsafe {
+        esp_idf_sys::esp_supp_dpp_stop_listen();
+    }
+    Err(anyhow!("no DPP configuration received"))
+}
+
+unsafe extern "C" fn handle_event(event: esp_idf_sys::esp_supp_dpp_event_t, data: *mut c_void) {
+    let event = match event {
+        esp_idf_sys::esp_supp_dpp_event_t_ESP_SUPP_DPP_URI_READY => {
+            let uri = CStr::from_ptr(data as *const c_char);
+            Event::UriReady(uri.to_string_lossy().into_owned())
+        }
+        esp_idf_sys::esp_supp_dpp_event_t_ESP_SUPP_DPP_CFG_RECVD => {
+            let config = &*(data as *const esp_idf_sys::wifi_config_t);
+            Event::ConfigReceived(c_bytes(&config.sta.ssid), c_bytes(&config.sta.password))
+        }
+        _ => Event::Failed(data as i32),
+    };
+    EVENTS.lock().unwrap().push(event);
+}
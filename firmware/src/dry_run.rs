@@ -0,0 +1,64 @@
+//! Upload dry run for verifying a configuration, in particular the line prefix, before deployment.
+//! Requests are built as for a real upload, but validated and printed instead of sent.
+
+use crate::line_protocol;
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const NVS_NAMESPACE: &str = "upload";
+const DRY_RUN_NVS_KEY: &str = "dry_run";
+
+pub fn is_enabled(partition: &EspDefaultNvsPartition) -> Result<bool> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(DRY_RUN_NVS_KEY)
+}
+
+pub fn set_enabled(partition: &EspDefaultNvsPartition, enabled: bool) -> Result<()> {
+    Namespace::open(partition, NVS_NAMESPACE)?.set(DRY_RUN_NVS_KEY, &enabled)
+}
+
+/// Validates the URL and the line protocol syntax of a request and prints it, with the
+/// authorization redacted.
+pub fn print_request(url: &str, headers: &[(&str, &str)], body: &str) -> Result<()> {
+    validate_url(url)?;
+    for line in body.lines() {
+        line_protocol::validate(line)?;
+    }
+
+    println!("dry run, not sending:");
+    println!("POST {}", url);
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("Authorization") {
+            println!("{}: <redacted>", name);
+        } else {
+            println!("{}: {}", name, value);
+        }
+    }
+    println!();
+    print!("{}", body);
+    Ok(())
+}
+
+fn validate_url(url: &str) -> Result<()> {
+    let rest = match url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+    {
+        Some(rest) => rest,
+        None => bail!("URL must start with http:// or https://: {}", url),
+    };
+    let host = rest.split(|c| c == '/' || c == '?').next().unwrap_or("");
+    if host.is_empty() || url.contains(char::is_whitespace) {
+        bail!("invalid URL: {}", url);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_validate_url() {
+    validate_url("https://example.com/api/v2/write?org=a&bucket=b").unwrap();
+    validate_url("http://192.168.1.2:8086").unwrap();
+    assert!(validate_url("example.com/write").is_err());
+    assert!(validate_url("https:///write").is_err());
+    assert!(validate_url("https://example.com/write?bucket=my plants").is_err());
+}
@@ -0,0 +1,163 @@
+//! Typed errors of the paths that fail in the field: connecting, uploading, reading the probe and
+//! storing settings. A [`FirmwareError`] is a failure of a subsystem with its [`ErrorCode`] and the
+//! context it occurred in, so that it's recorded, retried and shown by its code without searching a
+//! chain of messages. Errors of drivers and of modules using anyhow are attached to a subsystem
+//! with [`Classify`]. The other paths use anyhow, into which these errors convert with `?`, and
+//! [`code`] finds their code there as well. This is the only place where errors get codes, the
+//! codes themselves are numbered in [`crate::error_code`].
+
+use crate::error_code::ErrorCode;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum FirmwareError {
+    /// Reading a probe failed.
+    Sensor { code: ErrorCode, context: String },
+    /// Connecting to the access point failed.
+    Wifi { code: ErrorCode, context: String },
+    /// A request to a server failed, before its response or with an unsuccessful status.
+    Http {
+        code: ErrorCode,
+        context: String,
+        /// Pause the server asks for with `Retry-After`.
+        retry_after: Option<Duration>,
+    },
+    /// The time couldn't be synced.
+    Time { context: String },
+    /// Reading or writing NVS failed.
+    Storage { context: String },
+}
+
+impl FirmwareError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            FirmwareError::Sensor { code, .. }
+            | FirmwareError::Wifi { code, .. }
+            | FirmwareError::Http { code, .. } => *code,
+            FirmwareError::Time { .. } => ErrorCode::SntpTimeout,
+            FirmwareError::Storage { .. } => ErrorCode::Storage,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FirmwareError::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Returns the error with `outer` prepended to its context.
+    pub fn context(mut self, outer: impl fmt::Display) -> FirmwareError {
+        let (FirmwareError::Sensor { context, .. }
+        | FirmwareError::Wifi { context, .. }
+        | FirmwareError::Http { context, .. }
+        | FirmwareError::Time { context }
+        | FirmwareError::Storage { context }) = &mut self;
+        *context = format!("{}: {}", outer, context);
+        self
+    }
+
+    fn context_message(&self) -> &str {
+        match self {
+            FirmwareError::Sensor { context, .. }
+            | FirmwareError::Wifi { context, .. }
+            | FirmwareError::Http { context, .. }
+            | FirmwareError::Time { context }
+            | FirmwareError::Storage { context } => context,
+        }
+    }
+}
+
+impl fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.context_message())
+    }
+}
+
+impl std::error::Error for FirmwareError {}
+
+/// Returns the code of the [`FirmwareError`] that `error` wraps, or [`ErrorCode::Unknown`].
+pub fn code(error: &anyhow::Error) -> ErrorCode {
+    error
+        .downcast_ref::<FirmwareError>()
+        .map_or(ErrorCode::Unknown, FirmwareError::code)
+}
+
+/// Attaches errors to the subsystem that failed, keeping their message, with all causes of anyhow
+/// errors, as context.
+pub trait Classify<T> {
+    fn sensor(self, code: ErrorCode) -> Result<T, FirmwareError>;
+    fn wifi(self, code: ErrorCode) -> Result<T, FirmwareError>;
+    fn http(self, code: ErrorCode) -> Result<T, FirmwareError>;
+    fn time(self) -> Result<T, FirmwareError>;
+    fn storage(self) -> Result<T, FirmwareError>;
+}
+
+impl<T, E: fmt::Display> Classify<T> for Result<T, E> {
+    fn sensor(self, code: ErrorCode) -> Result<T, FirmwareError> {
+        self.map_err(|e| FirmwareError::Sensor {
+            code,
+            context: format!("{:#}", e),
+        })
+    }
+
+    fn wifi(self, code: ErrorCode) -> Result<T, FirmwareError> {
+        self.map_err(|e| FirmwareError::Wifi {
+            code,
+            context: format!("{:#}", e),
+        })
+    }
+
+    fn http(self, code: ErrorCode) -> Result<T, FirmwareError> {
+        self.map_err(|e| FirmwareError::Http {
+            code,
+            context: format!("{:#}", e),
+            retry_after: None,
+        })
+    }
+
+    fn time(self) -> Result<T, FirmwareError> {
+        self.map_err(|e| FirmwareError::Time {
+            context: format!("{:#}", e),
+        })
+    }
+
+    fn storage(self) -> Result<T, FirmwareError> {
+        self.map_err(|e| FirmwareError::Storage {
+            context: format!("{:#}", e),
+        })
+    }
+}
+
+#[test]
+pub fn test_firmware_error() {
+    let e = FirmwareError::Http {
+        code: ErrorCode::HttpRateLimited,
+        context: "HTTP status 429".into(),
+        retry_after: Some(Duration::from_secs(30)),
+    };
+    let e = e.context("inserting into moisture");
+    assert_eq!(e.code(), ErrorCode::HttpRateLimited);
+    assert_eq!(e.retry_after(), Some(Duration::from_secs(30)));
+    assert_eq!(
+        e.to_string(),
+        "rate limited: inserting into moisture: HTTP status 429"
+    );
+
+    let read: Result<(), _> = Err(anyhow::anyhow!("ADC timeout").context("sampling"));
+    let e = read.sensor(ErrorCode::SensorRead).unwrap_err();
+    assert_eq!(e.code(), ErrorCode::SensorRead);
+    assert_eq!(e.to_string(), "probe not readable: sampling: ADC timeout");
+    assert_eq!(
+        Err::<(), _>("full").storage().unwrap_err().code(),
+        ErrorCode::Storage
+    );
+    assert_eq!(e.retry_after(), None);
+
+    let e = anyhow::Error::from(FirmwareError::Time {
+        context: "timed out".into(),
+    });
+    assert_eq!(code(&e.context("syncing")), ErrorCode::SntpTimeout);
+    assert_eq!(code(&anyhow::anyhow!("invalid JSON")), ErrorCode::Unknown);
+}
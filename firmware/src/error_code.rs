@@ -0,0 +1,181 @@
+//! Stable numeric codes for failures, so that dashboards can aggregate them across firmware
+//! versions instead of matching on error messages. Errors carry their code as a
+//! [`crate::error::FirmwareError`], and the last failure is kept in RTC memory until it has been
+//! uploaded. A journal of the latest failures is kept with it and uploaded as `events`, so that
+//! intermittent failures between uploads show up as well.
+
+use crate::rtc::{LOGS, STATE};
+use std::fmt;
+
+/// Events kept in the journal, the oldest are dropped if there are more.
+pub const MAX_EVENTS: usize = 8;
+
+/// The first digit of a code is its category: 1 WiFi, 2 time, 3 HTTP, 4 sensor, 5 storage, 6
+/// memory, 7 wake cycle. Codes must never be renumbered or reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    // 101 is reserved for authentication failures, which the WiFi driver doesn't report separately.
+    WifiConnect = 102,
+    WifiStartTimeout = 103,
+    ConnectTimeout = 104,
+    DhcpTimeout = 105,
+    SntpTimeout = 201,
+    Http5xx = 301,
+    Http4xx = 302,
+    HttpConnect = 303,
+    HttpUnauthorized = 304,
+    HttpRateLimited = 305,
+    HttpResponseTooLarge = 306,
+    SensorOpen = 401,
+    SensorRead = 402,
+    ProbeConfig = 403,
+    Storage = 501,
+    HeapCorrupted = 601,
+    StackExhausted = 602,
+    AwakeLimit = 701,
+    Unknown = 999,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Failure {
+    pub code: ErrorCode,
+    /// Slow clock time in seconds of the last failure.
+    pub time: u32,
+    /// Number of failed cycles since the last upload.
+    pub count: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub code: ErrorCode,
+    /// Slow clock time in seconds.
+    pub time: u32,
+    /// Number of the wake cycle, see [`crate::wake::count`].
+    pub wake: u32,
+}
+
+impl ErrorCode {
+    pub fn for_http_status(status: u16) -> ErrorCode {
+        match status {
+            401 | 403 => ErrorCode::HttpUnauthorized,
+            429 => ErrorCode::HttpRateLimited,
+            500..=u16::MAX => ErrorCode::Http5xx,
+            _ => ErrorCode::Http4xx,
+        }
+    }
+
+    pub fn number(self) -> u16 {
+        self as u16
+    }
+
+    pub fn category(self) -> u16 {
+        self.number() / 100
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorCode::WifiConnect => "WiFi connection failed",
+            ErrorCode::WifiStartTimeout => "WiFi start timed out",
+            ErrorCode::ConnectTimeout => "WiFi connection timed out",
+            ErrorCode::DhcpTimeout => "DHCP timed out",
+            ErrorCode::SntpTimeout => "time sync timed out",
+            ErrorCode::Http5xx => "server error",
+            ErrorCode::Http4xx => "request rejected",
+            ErrorCode::HttpConnect => "server unreachable",
+            ErrorCode::HttpUnauthorized => "not authorized",
+            ErrorCode::HttpRateLimited => "rate limited",
+            ErrorCode::HttpResponseTooLarge => "response too large",
+            ErrorCode::SensorOpen => "probe disconnected",
+            ErrorCode::SensorRead => "probe not readable",
+            ErrorCode::ProbeConfig => "probe configuration invalid",
+            ErrorCode::Storage => "storage failed",
+            ErrorCode::HeapCorrupted => "heap corrupted",
+            ErrorCode::StackExhausted => "stack exhausted",
+            ErrorCode::AwakeLimit => "awake time limit exceeded",
+            ErrorCode::Unknown => "unknown error",
+        })
+    }
+}
+
+pub fn record(code: ErrorCode, time: u32) {
+    let count = pending().map_or(0, |failure| failure.count);
+    unsafe {
+        STATE.last_failure = Some(Failure {
+            code,
+            time,
+            count: count + 1,
+        });
+        LOGS.error_events.overwriting_push_back(Event {
+            code,
+            time,
+            wake: STATE.wakes,
+        });
+    }
+}
+
+/// Returns the last failure not uploaded yet.
+pub fn pending() -> Option<Failure> {
+    unsafe { STATE.last_failure }
+}
+
+/// Returns the journal of events not uploaded yet, oldest first.
+pub fn events() -> Vec<Event> {
+    unsafe { LOGS.error_events.iter().copied().collect() }
+}
+
+pub fn clear() {
+    unsafe {
+        STATE.last_failure = None;
+        LOGS.error_events.clear();
+    }
+}
+
+#[test]
+pub fn test_error_code() {
+    assert_eq!(ErrorCode::for_http_status(503), ErrorCode::Http5xx);
+    assert_eq!(ErrorCode::for_http_status(400), ErrorCode::Http4xx);
+    assert_eq!(ErrorCode::for_http_status(403), ErrorCode::HttpUnauthorized);
+    assert_eq!(ErrorCode::for_http_status(429), ErrorCode::HttpRateLimited);
+    assert_eq!(ErrorCode::SntpTimeout.number(), 201);
+    assert_eq!(ErrorCode::DhcpTimeout.category(), 1);
+    assert_eq!(ErrorCode::SensorOpen.category(), 4);
+    assert_eq!(ErrorCode::ProbeConfig.category(), 4);
+    assert_eq!(ErrorCode::HeapCorrupted.category(), 6);
+    assert_eq!(ErrorCode::AwakeLimit.category(), 7);
+
+    record(ErrorCode::WifiConnect, 10);
+    record(ErrorCode::Http5xx, 20);
+    assert_eq!(
+        pending(),
+        Some(Failure {
+            code: ErrorCode::Http5xx,
+            time: 20,
+            count: 2
+        })
+    );
+    assert_eq!(events()[0].code, ErrorCode::WifiConnect);
+
+    unsafe {
+        STATE.wakes = 7;
+    }
+    for time in 0..MAX_EVENTS as u32 {
+        record(ErrorCode::SensorRead, 100 + time);
+    }
+    let journal = events();
+    assert_eq!(journal.len(), MAX_EVENTS);
+    assert_eq!(journal[0].time, 100);
+    assert_eq!(
+        journal.last(),
+        Some(&Event {
+            code: ErrorCode::SensorRead,
+            time: 107,
+            wake: 7
+        })
+    );
+    clear();
+    assert_eq!(pending(), None);
+    assert!(events().is_empty());
+}
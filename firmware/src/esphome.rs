@@ -0,0 +1,354 @@
+//! Server for the ESPHome native API, so that Home Assistant can adopt the sensor directly. It
+//! needs the device to stay connected and is therefore only used in powered mode, see the
+//! `esphome` feature. Only the plaintext transport is implemented.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::mdns::EspMdns;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+pub const PORT: u16 = 6053;
+const API_VERSION: (u32, u32) = (1, 7);
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+const HELLO_REQUEST: u32 = 1;
+const HELLO_RESPONSE: u32 = 2;
+const CONNECT_REQUEST: u32 = 3;
+const CONNECT_RESPONSE: u32 = 4;
+const DISCONNECT_REQUEST: u32 = 5;
+const DISCONNECT_RESPONSE: u32 = 6;
+const PING_REQUEST: u32 = 7;
+const PING_RESPONSE: u32 = 8;
+const DEVICE_INFO_REQUEST: u32 = 9;
+const DEVICE_INFO_RESPONSE: u32 = 10;
+const LIST_ENTITIES_REQUEST: u32 = 11;
+const LIST_ENTITIES_SENSOR_RESPONSE: u32 = 16;
+const LIST_ENTITIES_DONE_RESPONSE: u32 = 19;
+const SUBSCRIBE_STATES_REQUEST: u32 = 20;
+const SENSOR_STATE_RESPONSE: u32 = 25;
+
+/// `state_class` of sensors, as in Home Assistant.
+const STATE_CLASS_MEASUREMENT: u32 = 1;
+
+pub struct Device<'a> {
+    pub name: &'a str,
+    pub mac_address: &'a str,
+    pub version: &'a str,
+}
+
+pub struct Sensor {
+    pub object_id: &'static str,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub device_class: &'static str,
+    pub icon: &'static str,
+}
+
+/// Entities exposed to Home Assistant. The board measures neither its battery nor temperature, so
+/// moisture is the only one.
+pub const SENSORS: [Sensor; 1] = [Sensor {
+    object_id: "moisture",
+    name: "Moisture",
+    unit: "",
+    device_class: "",
+    icon: "mdi:water-percent",
+}];
+
+pub struct Server<'a> {
+    device: &'a Device<'a>,
+    _mdns: EspMdns,
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+impl<'a> Server<'a> {
+    /// Starts listening and announces the API via mDNS.
+    pub fn start(device: &'a Device) -> Result<Server<'a>> {
+        let mut mdns = EspMdns::take()?;
+        mdns.set_hostname(device.name)?;
+        mdns.add_service(
+            Some(device.name),
+            "_esphomelib",
+            "_tcp",
+            PORT,
+            &[("version", device.version), ("mac", device.mac_address)],
+        )?;
+
+        let listener = TcpListener::bind(("0.0.0.0", PORT))?;
+        listener.set_nonblocking(true)?;
+        println!("serving ESPHome API on port {}", PORT);
+
+        Ok(Server {
+            device,
+            _mdns: mdns,
+            listener,
+            client: None,
+        })
+    }
+
+    /// Accepts a client and handles its requests without blocking. `states` are the current
+    /// sensor states in the order of [`SENSORS`], sent to the client if they `changed`.
+    pub fn poll(&mut self, states: &[f32; SENSORS.len()], changed: bool) -> Result<()> {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                println!("ESPHome API client {} connected", address);
+                stream.set_nonblocking(true)?;
+                // A single client is served, a new one replaces the previous.
+                self.client = Some(Client::new(stream));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Some(client) = &mut self.client {
+            if let Err(e) = client.poll(self.device, states, changed) {
+                println!("ESPHome API client disconnected: {}", e);
+                self.client = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    received: Vec<u8>,
+    subscribed: bool,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> Client {
+        Client {
+            stream,
+            received: Vec::new(),
+            subscribed: false,
+        }
+    }
+
+    /// Handles the messages received so far and sends the states if they `changed`.
+    fn poll(&mut self, device: &Device, states: &[f32], changed: bool) -> Result<()> {
+        let mut buffer = [0; 256];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => bail!("connection closed"),
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut out = Vec::new();
+        while let Some((message_type, size)) = parse_frame(&self.received)? {
+            self.received.drain(..size);
+            if message_type == SUBSCRIBE_STATES_REQUEST {
+                self.subscribed = true;
+            }
+            for (response_type, payload) in respond(message_type, device, states) {
+                write_frame(&mut out, response_type, &payload);
+            }
+            if message_type == DISCONNECT_REQUEST {
+                self.stream.write_all(&out)?;
+                bail!("disconnect requested");
+            }
+        }
+        if changed && self.subscribed {
+            for (response_type, payload) in state_responses(states) {
+                write_frame(&mut out, response_type, &payload);
+            }
+        }
+        self.stream.write_all(&out)?;
+        Ok(())
+    }
+}
+
+/// Returns the responses to a request, as message types and payloads.
+fn respond(message_type: u32, device: &Device, states: &[f32]) -> Vec<(u32, Vec<u8>)> {
+    match message_type {
+        HELLO_REQUEST => {
+            let mut payload = Vec::new();
+            write_uint32(&mut payload, 1, API_VERSION.0);
+            write_uint32(&mut payload, 2, API_VERSION.1);
+            write_string(
+                &mut payload,
+                3,
+                &format!("soil-moisture-sensor {}", device.version),
+            );
+            write_string(&mut payload, 4, device.name);
+            vec![(HELLO_RESPONSE, payload)]
+        }
+        // No password is configured, so `invalid_password` stays false.
+        CONNECT_REQUEST => vec![(CONNECT_RESPONSE, Vec::new())],
+        DISCONNECT_REQUEST => vec![(DISCONNECT_RESPONSE, Vec::new())],
+        PING_REQUEST => vec![(PING_RESPONSE, Vec::new())],
+        DEVICE_INFO_REQUEST => {
+            let mut payload = Vec::new();
+            write_string(&mut payload, 2, device.name);
+            write_string(&mut payload, 3, device.mac_address);
+            write_string(&mut payload, 4, device.version);
+            write_string(&mut payload, 6, "ESP32-C3");
+            write_bool(&mut payload, 7, true);
+            vec![(DEVICE_INFO_RESPONSE, payload)]
+        }
+        LIST_ENTITIES_REQUEST => {
+            let mut responses: Vec<_> = SENSORS
+                .iter()
+                .map(|sensor| {
+                    let mut payload = Vec::new();
+                    write_string(&mut payload, 1, sensor.object_id);
+                    write_fixed32(&mut payload, 2, key(sensor.object_id));
+                    write_string(&mut payload, 3, sensor.name);
+                    write_string(
+                        &mut payload,
+                        4,
+                        &format!("{}{}", device.name, sensor.object_id),
+                    );
+                    write_string(&mut payload, 5, sensor.icon);
+                    write_string(&mut payload, 6, sensor.unit);
+                    write_string(&mut payload, 9, sensor.device_class);
+                    write_uint32(&mut payload, 10, STATE_CLASS_MEASUREMENT);
+                    (LIST_ENTITIES_SENSOR_RESPONSE, payload)
+                })
+                .collect();
+            responses.push((LIST_ENTITIES_DONE_RESPONSE, Vec::new()));
+            responses
+        }
+        SUBSCRIBE_STATES_REQUEST => state_responses(states),
+        // Log, service and Home Assistant state subscriptions aren't supported and need no
+        // response.
+        _ => Vec::new(),
+    }
+}
+
+fn state_responses(states: &[f32]) -> Vec<(u32, Vec<u8>)> {
+    SENSORS
+        .iter()
+        .zip(states)
+        .map(|(sensor, state)| {
+            let mut payload = Vec::new();
+            write_fixed32(&mut payload, 1, key(sensor.object_id));
+            write_fixed32(&mut payload, 2, state.to_bits());
+            (SENSOR_STATE_RESPONSE, payload)
+        })
+        .collect()
+}
+
+/// Key of an entity, the FNV-1 hash of its object ID as computed by ESPHome.
+fn key(object_id: &str) -> u32 {
+    object_id.bytes().fold(2166136261, |hash: u32, b| {
+        hash.wrapping_mul(16777619) ^ u32::from(b)
+    })
+}
+
+/// Parses the header of a plaintext frame: a zero byte, the payload size and the message type as
+/// varints. Returns the message type and the size of the whole frame once it is complete.
+fn parse_frame(data: &[u8]) -> Result<Option<(u32, usize)>> {
+    match data.first() {
+        None => return Ok(None),
+        Some(0) => {}
+        Some(_) => bail!("unsupported frame"),
+    }
+    let (size, size_length) = match read_varint(&data[1..]) {
+        Some(varint) => varint,
+        None => return Ok(None),
+    };
+    if size as usize > MAX_MESSAGE_SIZE {
+        bail!("message too large: {} bytes", size);
+    }
+    let (message_type, type_length) = match read_varint(&data[1 + size_length..]) {
+        Some(varint) => varint,
+        None => return Ok(None),
+    };
+    let frame_size = 1 + size_length + type_length + size as usize;
+    Ok((data.len() >= frame_size).then_some((message_type, frame_size)))
+}
+
+fn write_frame(out: &mut Vec<u8>, message_type: u32, payload: &[u8]) {
+    out.push(0);
+    write_varint(out, payload.len() as u32);
+    write_varint(out, message_type);
+    out.extend_from_slice(payload);
+}
+
+/// Reads a varint, returning it and the number of bytes it takes, or `None` if it's incomplete.
+fn read_varint(data: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0;
+    for (i, b) in data.iter().take(5).enumerate() {
+        value |= u32::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Protobuf fields. Default values are omitted, as protobuf encoders do.
+
+fn write_uint32(out: &mut Vec<u8>, field: u32, value: u32) {
+    if value != 0 {
+        write_varint(out, field << 3);
+        write_varint(out, value);
+    }
+}
+
+fn write_bool(out: &mut Vec<u8>, field: u32, value: bool) {
+    write_uint32(out, field, value.into());
+}
+
+fn write_fixed32(out: &mut Vec<u8>, field: u32, value: u32) {
+    if value != 0 {
+        write_varint(out, field << 3 | 5);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        write_varint(out, field << 3 | 2);
+        write_varint(out, value.len() as u32);
+        out.extend_from_slice(value.as_bytes());
+    }
+}
+
+#[test]
+pub fn test_frames() {
+    let mut out = Vec::new();
+    write_frame(&mut out, PING_REQUEST, &[]);
+    write_frame(&mut out, LIST_ENTITIES_SENSOR_RESPONSE, &[1; 200]);
+    assert_eq!(out[..3], [0, 0, 7]);
+    assert_eq!(out[3..7], [0, 200, 1, 16]);
+    assert_eq!(parse_frame(&out).unwrap(), Some((PING_REQUEST, 3)));
+    assert_eq!(parse_frame(&out[3..]).unwrap(), Some((16, 204)));
+    assert_eq!(parse_frame(&out[3..100]).unwrap(), None);
+    assert_eq!(parse_frame(&out[3..4]).unwrap(), None);
+    assert!(parse_frame(&[1, 0, 7]).is_err());
+
+    assert_eq!(key("a"), 0x050c5d7e);
+
+    let device = Device {
+        name: "plant",
+        mac_address: "AA:BB:CC:DD:EE:FF",
+        version: "0.1.0",
+    };
+    let responses = respond(HELLO_REQUEST, &device, &[0.0]);
+    assert_eq!(responses[0].0, HELLO_RESPONSE);
+    assert_eq!(responses[0].1[..4], [8, 1, 16, 7]);
+    let responses = respond(SUBSCRIBE_STATES_REQUEST, &device, &[1.5]);
+    assert_eq!(
+        responses,
+        vec![(SENSOR_STATE_RESPONSE, {
+            let mut payload = vec![13];
+            payload.extend_from_slice(&key("moisture").to_le_bytes());
+            payload.push(21);
+            payload.extend_from_slice(&1.5f32.to_le_bytes());
+            payload
+        })]
+    );
+}
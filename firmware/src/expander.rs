@@ -0,0 +1,194 @@
+//! Support for an I2C GPIO expander, for relay banks, buttons or status LEDs beyond the few free pins
+//! of the ESP32-C3. The `mcp23017` feature selects an MCP23017 with 16 pins, the `pcf8574` feature a
+//! PCF8574 with 8 pins, either at `ADDRESS` on the I2C bus on GPIO6 (SDA) and GPIO8 (SCL). GPIO8 is
+//! a strapping pin that must be high at reset, which the pull-ups of the bus ensure.
+//!
+//! Expander pins implement the embedded-hal digital traits, as the native `PinDriver`s do. All pins
+//! are inputs at power-on and are turned back into inputs when the board is dropped, so relays don't
+//! stay switched during deep sleep.
+
+use anyhow::{bail, Error, Result};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::i2c::I2cDriver;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Address with all address pins tied low.
+pub const ADDRESS: u8 = 0x20;
+
+#[cfg(feature = "mcp23017")]
+const CHIP: Chip = Chip::Mcp23017;
+#[cfg(feature = "pcf8574")]
+const CHIP: Chip = Chip::Pcf8574;
+
+// Registers of the MCP23017 in the default bank mode, where port B follows port A.
+const MCP23017_IODIR: u8 = 0x00;
+const MCP23017_GPPU: u8 = 0x0c;
+const MCP23017_GPIO: u8 = 0x12;
+const MCP23017_OLAT: u8 = 0x14;
+
+// Only one chip is selected by the features.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Chip {
+    Mcp23017,
+    Pcf8574,
+}
+
+impl Chip {
+    fn pin_count(self) -> u8 {
+        match self {
+            Chip::Mcp23017 => 16,
+            Chip::Pcf8574 => 8,
+        }
+    }
+
+    /// Returns the I2C writes that configure the pins, with bit n of `inputs` set if pin n is an
+    /// input and bit n of `levels` the level of pin n if it's an output.
+    fn writes(self, inputs: u16, levels: u16) -> Vec<Vec<u8>> {
+        match self {
+            Chip::Mcp23017 => {
+                let [inputs_a, inputs_b] = inputs.to_le_bytes();
+                let [levels_a, levels_b] = levels.to_le_bytes();
+                // Latches first, so outputs don't glitch when their direction changes.
+                vec![
+                    vec![MCP23017_OLAT, levels_a, levels_b],
+                    vec![MCP23017_GPPU, inputs_a, inputs_b],
+                    vec![MCP23017_IODIR, inputs_a, inputs_b],
+                ]
+            }
+            // The pins are quasi-bidirectional, with inputs driven weakly high.
+            Chip::Pcf8574 => vec![vec![(levels | inputs) as u8]],
+        }
+    }
+}
+
+pub struct Expander {
+    i2c: I2cDriver<'static>,
+    inputs: u16,
+    levels: u16,
+}
+
+impl Expander {
+    /// Resets the expander on `i2c`, making all pins inputs.
+    pub fn new(i2c: I2cDriver<'static>) -> Result<Rc<RefCell<Expander>>> {
+        let mut expander = Expander {
+            i2c,
+            inputs: 0,
+            levels: 0,
+        };
+        expander.reset()?;
+        Ok(Rc::new(RefCell::new(expander)))
+    }
+
+    /// Makes all pins inputs.
+    pub fn reset(&mut self) -> Result<()> {
+        self.inputs = u16::MAX;
+        self.levels = 0;
+        self.flush()
+    }
+
+    fn configure(&mut self, pin: u8, input: bool, level: bool) -> Result<()> {
+        if pin >= CHIP.pin_count() {
+            bail!("the {:?} has no pin {}", CHIP, pin);
+        }
+        let mask = 1 << pin;
+        self.inputs = if input {
+            self.inputs | mask
+        } else {
+            self.inputs & !mask
+        };
+        self.levels = if level {
+            self.levels | mask
+        } else {
+            self.levels & !mask
+        };
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for bytes in CHIP.writes(self.inputs, self.levels) {
+            self.i2c.write(ADDRESS, &bytes, BLOCK)?;
+        }
+        Ok(())
+    }
+
+    fn read_levels(&mut self) -> Result<u16> {
+        let mut buffer = [0; 2];
+        match CHIP {
+            Chip::Mcp23017 => self
+                .i2c
+                .write_read(ADDRESS, &[MCP23017_GPIO], &mut buffer, BLOCK)?,
+            Chip::Pcf8574 => self.i2c.read(ADDRESS, &mut buffer[..1], BLOCK)?,
+        }
+        Ok(u16::from_le_bytes(buffer))
+    }
+}
+
+/// Pin `pin` of the expander as an output, initially low.
+pub fn output(expander: &Rc<RefCell<Expander>>, pin: u8) -> Result<ExpanderOutput> {
+    expander.borrow_mut().configure(pin, false, false)?;
+    Ok(ExpanderOutput {
+        expander: expander.clone(),
+        pin,
+    })
+}
+
+/// Pin `pin` of the expander as an input, pulled up.
+pub fn input(expander: &Rc<RefCell<Expander>>, pin: u8) -> Result<ExpanderInput> {
+    expander.borrow_mut().configure(pin, true, false)?;
+    Ok(ExpanderInput {
+        expander: expander.clone(),
+        pin,
+    })
+}
+
+pub struct ExpanderOutput {
+    expander: Rc<RefCell<Expander>>,
+    pin: u8,
+}
+
+impl OutputPin for ExpanderOutput {
+    type Error = Error;
+
+    fn set_low(&mut self) -> Result<()> {
+        self.expander.borrow_mut().configure(self.pin, false, false)
+    }
+
+    fn set_high(&mut self) -> Result<()> {
+        self.expander.borrow_mut().configure(self.pin, false, true)
+    }
+}
+
+pub struct ExpanderInput {
+    expander: Rc<RefCell<Expander>>,
+    pin: u8,
+}
+
+impl InputPin for ExpanderInput {
+    type Error = Error;
+
+    fn is_high(&self) -> Result<bool> {
+        let levels = self.expander.borrow_mut().read_levels()?;
+        Ok(levels & (1 << self.pin) != 0)
+    }
+
+    fn is_low(&self) -> Result<bool> {
+        Ok(!self.is_high()?)
+    }
+}
+
+#[test]
+pub fn test_writes() {
+    assert_eq!(
+        Chip::Mcp23017.writes(0xff0f, 0x0030),
+        vec![
+            vec![MCP23017_OLAT, 0x30, 0x00],
+            vec![MCP23017_GPPU, 0x0f, 0xff],
+            vec![MCP23017_IODIR, 0x0f, 0xff],
+        ]
+    );
+    assert_eq!(Chip::Pcf8574.writes(0x0f, 0x30), vec![vec![0x3f]]);
+    assert_eq!(Chip::Pcf8574.writes(u16::MAX, 0), vec![vec![0xff]]);
+}
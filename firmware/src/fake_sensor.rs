@@ -0,0 +1,90 @@
+//! Simulated sensor for demos and soak tests, enabled with the `fake-sensor` feature. Soil dries
+//! exponentially and gets watered at random once it is dry enough, readings are noisy.
+
+use std::hash::{Hash, Hasher};
+
+/// Reading of completely dry soil.
+const DRY_VALUE: f32 = 2400.0;
+/// Reading of saturated soil.
+const WET_VALUE: f32 = 1100.0;
+/// Time in seconds for the moisture to drop to 1/e.
+const DRYING_TIME_CONSTANT: f32 = 4.0 * 24.0 * 3600.0;
+/// Moisture below which the simulated gardener might water.
+const WATERING_THRESHOLD: f32 = 0.35;
+/// Probability of watering per reading below the threshold.
+const WATERING_PROBABILITY: f32 = 0.1;
+const NOISE_AMPLITUDE: f32 = 15.0;
+
+pub fn read(time: u32) -> u16 {
+    unsafe { crate::rtc::STATE.simulation.read(time) }
+}
+
+pub struct Simulation {
+    /// Moisture between 0 (dry) and 1 (saturated).
+    moisture: f32,
+    last_time: Option<u32>,
+    rng_state: u32,
+}
+
+impl Hash for Simulation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.moisture.to_bits(), self.last_time, self.rng_state).hash(state);
+    }
+}
+
+impl Simulation {
+    pub const fn new(seed: u32) -> Simulation {
+        Simulation {
+            moisture: 1.0,
+            last_time: None,
+            rng_state: seed,
+        }
+    }
+
+    fn read(&mut self, time: u32) -> u16 {
+        if let Some(last_time) = self.last_time {
+            let elapsed = time.saturating_sub(last_time) as f32;
+            self.moisture *= (-elapsed / DRYING_TIME_CONSTANT).exp();
+        }
+        self.last_time = Some(time);
+
+        if self.moisture < WATERING_THRESHOLD && self.random() < WATERING_PROBABILITY {
+            self.moisture = 1.0;
+        }
+
+        let noise = (self.random() * 2.0 - 1.0) * NOISE_AMPLITUDE;
+        (DRY_VALUE - self.moisture * (DRY_VALUE - WET_VALUE) + noise) as u16
+    }
+
+    /// Returns a pseudo-random number between 0 and 1 (xorshift32).
+    fn random(&mut self) -> f32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        x as f32 / u32::MAX as f32
+    }
+}
+
+#[test]
+pub fn test_simulation() {
+    let mut simulation = Simulation::new(1);
+    let mut previous = simulation.read(0);
+    assert!(
+        (WET_VALUE - NOISE_AMPLITUDE..=WET_VALUE + NOISE_AMPLITUDE).contains(&(previous as f32))
+    );
+
+    let mut waterings = 0;
+    for hour in 1..24 * 60 {
+        let value = simulation.read(hour * 3600);
+        assert!(
+            (WET_VALUE - NOISE_AMPLITUDE..=DRY_VALUE + NOISE_AMPLITUDE).contains(&(value as f32))
+        );
+        if value as f32 + 4.0 * NOISE_AMPLITUDE < previous as f32 {
+            waterings += 1;
+        }
+        previous = value;
+    }
+    assert!(waterings > 0);
+}
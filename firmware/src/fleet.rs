@@ -0,0 +1,86 @@
+//! Coordination of cycles among sensors behind one weak access point. With the size of the fleet
+//! configured, each sensor derives a slot from its factory MAC address and aligns its wakes to the
+//! slot's offset into the interval, spreading associations across the hour instead of sensors
+//! powered on together connecting at the same time. Slots are derived independently, so two sensors
+//! can share one, but a fleet stays spread without any exchange between them.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::esp;
+
+const NVS_NAMESPACE: &str = "fleet";
+const NVS_KEY: &str = "size";
+/// Most slots per interval, so that a slot still leaves time for a cycle with an upload.
+pub const MAX_SIZE: u16 = 240;
+
+/// Returns the number of sensors whose cycles are coordinated, 0 if they aren't.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<u16> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, size: u16) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    if size == 0 {
+        namespace.remove(NVS_KEY)
+    } else {
+        namespace.set(NVS_KEY, &size)
+    }
+}
+
+pub fn validate(size: u16) -> Result<()> {
+    if size > MAX_SIZE {
+        bail!("fleet size must be at most {}", MAX_SIZE);
+    }
+    Ok(())
+}
+
+/// Returns the slot of this sensor among a fleet of `size`, or `None` if cycles aren't
+/// coordinated. Wakes are aligned to it by [`crate::scheduler`].
+pub fn own_slot(size: u16) -> Result<Option<u16>> {
+    if size == 0 {
+        return Ok(None);
+    }
+    let slot = slot(&factory_mac()?, size);
+    println!("upload slot {} of {}", slot, size);
+    Ok(Some(slot))
+}
+
+/// Returns the seed of the jitter of wakes, which spreads the sensors of a fleet without slots.
+pub fn jitter_seed() -> Result<u32> {
+    Ok(hash(&factory_mac()?))
+}
+
+fn factory_mac() -> Result<[u8; 6]> {
+    let mut mac = [0; 6];
+    esp!(unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) })?;
+    Ok(mac)
+}
+
+/// Returns the slot of the sensor with `id` among `size`.
+fn slot(id: &[u8], size: u16) -> u16 {
+    (hash(id) % u32::from(size)) as u16
+}
+
+/// Returns the 32 bit FNV-1a hash of `id`.
+fn hash(id: &[u8]) -> u32 {
+    id.iter().fold(0x811c_9dc5, |hash: u32, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[test]
+pub fn test_slots() {
+    validate(0).unwrap();
+    validate(MAX_SIZE).unwrap();
+    assert!(validate(MAX_SIZE + 1).is_err());
+
+    let mac = [0x58, 0xcf, 0x79, 0x01, 0x02, 0x03];
+    assert_eq!(slot(&mac, 1), 0);
+    assert!(slot(&mac, 12) < 12);
+    assert_eq!(slot(&mac, 12), slot(&mac, 12));
+    let slots: std::collections::BTreeSet<_> = (0..=255)
+        .map(|last| slot(&[0x58, 0xcf, 0x79, 0x01, 0x02, last], 12))
+        .collect();
+    assert_eq!(slots.len(), 12);
+}
@@ -0,0 +1,187 @@
+//! Upload to Graphite or Go-Carbon via the Graphite plaintext protocol over TCP, instead of to an
+//! InfluxDB-compatible store. Every numeric field of a point becomes a line of its own. Metric
+//! paths follow a configured template, in which `{measurement}`, `{field}` and tag keys such as
+//! `{sensor}` are replaced.
+
+use crate::error::{Classify, FirmwareError};
+use crate::error_code::ErrorCode;
+use crate::points::{MeasurementBatch, Point, Value};
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const NVS_NAMESPACE: &str = "graphite";
+const NVS_KEY: &str = "target";
+pub const DEFAULT_TEMPLATE: &str = "{measurement}.{field}";
+/// Replaces placeholders of tags a point doesn't have.
+const MISSING_TAG: &str = "none";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    /// Host and port of the plaintext listener, usually port 2003.
+    pub address: String,
+    pub template: String,
+}
+
+/// Returns the Graphite target, if uploads go to Graphite.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<Target>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, target: Option<&Target>) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match target {
+        Some(target) => namespace.set(NVS_KEY, target),
+        None => namespace.remove(NVS_KEY),
+    }
+}
+
+impl Target {
+    /// Sends the points of `batch`. The plaintext protocol has no acknowledgement, so points count
+    /// as sent once the connection has been closed without error.
+    pub fn send(&self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        let lines = convert(batch, &self.template);
+        println!("{}", lines);
+
+        let address = self
+            .address
+            .to_socket_addrs()
+            .http(ErrorCode::HttpConnect)?
+            .next()
+            .ok_or_else(|| format!("unresolvable address {}", self.address))
+            .http(ErrorCode::HttpConnect)?;
+        let mut stream =
+            TcpStream::connect_timeout(&address, TIMEOUT).http(ErrorCode::HttpConnect)?;
+        stream
+            .set_write_timeout(Some(TIMEOUT))
+            .http(ErrorCode::HttpConnect)?;
+        stream
+            .write_all(lines.as_bytes())
+            .http(ErrorCode::HttpConnect)?;
+        stream.shutdown(Shutdown::Both).http(ErrorCode::HttpConnect)
+    }
+}
+
+/// Checks that the placeholders of `template` are well-formed.
+pub fn validate_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            bail!("unmatched }} in template {:?}", template);
+        }
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("unmatched {{ in template {:?}", template),
+        };
+        let name = &rest[start + 1..end];
+        if name.is_empty() || name.contains('{') {
+            bail!("invalid placeholder in template {:?}", template);
+        }
+        rest = &rest[end + 1..];
+    }
+    if template.is_empty() {
+        bail!("empty template");
+    }
+    Ok(())
+}
+
+/// Converts points to plaintext protocol lines, one per numeric field. String fields have no
+/// representation and are dropped. Graphite stores a single value per path and second, so points
+/// that differ only in their sequence number overwrite each other.
+pub fn convert(batch: &MeasurementBatch, template: &str) -> String {
+    let mut out = String::new();
+    for point in &batch.points {
+        for (field, value) in &point.fields {
+            if let Some(value) = numeric_value(value) {
+                let path = path(template, point, field);
+                out.push_str(&format!("{} {} {}\n", path, value, point.seconds));
+            }
+        }
+    }
+    out
+}
+
+fn numeric_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Boolean(value) => Some(u8::from(*value).to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::String(_) => None,
+    }
+}
+
+fn path(template: &str, point: &Point, field: &str) -> String {
+    let mut path = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').unwrap_or(rest.len() - start);
+        path.push_str(&rest[..start]);
+        let name = &rest[start + 1..end];
+        let value = match name {
+            "measurement" => point.measurement.as_str(),
+            "field" => field,
+            _ => point
+                .tags
+                .iter()
+                .find(|(key, _)| key == name)
+                .map_or(MISSING_TAG, |(_, value)| value.as_str()),
+        };
+        path.extend(value.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        }));
+        rest = rest.get(end + 1..).unwrap_or("");
+    }
+    path.push_str(rest);
+    path
+}
+
+#[test]
+pub fn test_convert() {
+    use crate::line_protocol::FieldValue;
+
+    let mut batch = MeasurementBatch::default();
+    let prefix = "moisture,sensor=Balcony\\ 1 value=";
+    batch.push_value(prefix, &[], FieldValue::Float(1234.0), 1000, 3);
+    batch.push_fields(
+        prefix,
+        "build",
+        &[],
+        &[
+            ("version", FieldValue::String("1.0")),
+            ("dirty", FieldValue::Boolean(false)),
+            ("variance", FieldValue::Float(2.5)),
+        ],
+        2000,
+    );
+    batch.push_fields(
+        "moisture value=",
+        "queue",
+        &[],
+        &[("depth", FieldValue::Integer(3))],
+        3000,
+    );
+    assert_eq!(
+        convert(&batch, "garden.{sensor}.{measurement}.{field}"),
+        "garden.Balcony_1.moisture.value 1234 1000\n\
+         garden.Balcony_1.build.dirty 0 2000\n\
+         garden.Balcony_1.build.variance 2.5 2000\n\
+         garden.none.queue.depth 3 3000\n"
+    );
+
+    validate_template(DEFAULT_TEMPLATE).unwrap();
+    validate_template("garden.{sensor}.{field}").unwrap();
+    assert!(validate_template("").is_err());
+    assert!(validate_template("{measurement").is_err());
+    assert!(validate_template("measurement}").is_err());
+    assert!(validate_template("{}.{field}").is_err());
+    assert!(validate_template("{a{b}}").is_err());
+}
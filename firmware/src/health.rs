@@ -0,0 +1,107 @@
+//! Fleet health counters persisted in NVS: boots and awake time since the firmware was flashed,
+//! and restarts that weren't wake-ups from deep sleep.
+
+use crate::rtc::STATE;
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_hal::reset::ResetReason;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+const NVS_NAMESPACE: &str = "health";
+const NVS_KEY: &str = "counters";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counters {
+    /// Build the counters belong to, they start over when another firmware is flashed.
+    build_id: String,
+    pub boots: u32,
+    pub awake_ms: u64,
+    /// Restarts since the last upload that weren't wake-ups from deep sleep or power-ons.
+    pub unexpected_restarts: u32,
+    pub last_unexpected_reason: Option<String>,
+}
+
+pub fn set_awake_time(awake_ms: u32) {
+    unsafe {
+        STATE.awake_ms = awake_ms;
+    }
+}
+
+/// Counts the current boot and returns the updated counters.
+pub fn count_boot(
+    partition: &EspDefaultNvsPartition,
+    build_id: &str,
+    reset_reason: ResetReason,
+) -> Result<Counters> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut counters: Counters = namespace.get_or_default(NVS_KEY)?;
+    let awake_ms = unsafe { std::mem::take(&mut STATE.awake_ms) };
+    let unexpected = !matches!(reset_reason, ResetReason::DeepSleep | ResetReason::PowerOn);
+    counters.count(
+        build_id,
+        awake_ms,
+        unexpected.then(|| format!("{:?}", reset_reason)),
+    );
+    namespace.set(NVS_KEY, &counters)?;
+    Ok(counters)
+}
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Counters> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+}
+
+/// Resets the unexpected restarts after they have been uploaded.
+pub fn mark_reported(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut counters: Counters = namespace.get_or_default(NVS_KEY)?;
+    counters.unexpected_restarts = 0;
+    counters.last_unexpected_reason = None;
+    namespace.set(NVS_KEY, &counters)
+}
+
+impl Counters {
+    pub fn restarted_unexpectedly(&self) -> bool {
+        self.unexpected_restarts > 0
+    }
+
+    fn count(&mut self, build_id: &str, awake_ms: u32, unexpected_reason: Option<String>) {
+        if self.build_id != build_id {
+            // The restart after flashing is expected, as is the awake time of the old firmware
+            // not being counted.
+            *self = Counters {
+                build_id: build_id.into(),
+                boots: 1,
+                ..Default::default()
+            };
+            return;
+        }
+        self.boots += 1;
+        self.awake_ms += u64::from(awake_ms);
+        if let Some(reason) = unexpected_reason {
+            self.unexpected_restarts += 1;
+            self.last_unexpected_reason = Some(reason);
+        }
+    }
+}
+
+#[test]
+pub fn test_count() {
+    let mut counters = Counters::default();
+    counters.count("aaaa", 1000, Some("Software".into()));
+    assert_eq!(counters.boots, 1);
+    assert_eq!(counters.awake_ms, 0);
+    assert!(!counters.restarted_unexpectedly());
+
+    counters.count("aaaa", 1500, None);
+    counters.count("aaaa", 2000, Some("Panic".into()));
+    assert_eq!(counters.boots, 3);
+    assert_eq!(counters.awake_ms, 3500);
+    assert_eq!(counters.unexpected_restarts, 1);
+    assert_eq!(counters.last_unexpected_reason.as_deref(), Some("Panic"));
+
+    counters.count("bbbb", 1000, None);
+    assert_eq!(counters.boots, 1);
+    assert_eq!(counters.awake_ms, 0);
+    assert!(!counters.restarted_unexpectedly());
+}
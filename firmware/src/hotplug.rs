@@ -0,0 +1,114 @@
+//! Detection of probes plugged in or removed while powered. A reading failing with
+//! [`ErrorCode::SensorOpen`] means that no probe is connected to the channel. Changes are reported
+//! once as an [`Event`], instead of failing every read of a missing probe. The on-board probe is
+//! checked with its regular reads, the other available channels are scanned every `SCAN_INTERVAL`.
+
+use crate::error::FirmwareError;
+use crate::error_code::ErrorCode;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Connected(u8),
+    Removed(u8),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Connected(channel) => write!(f, "probe connected to ADC1 channel {}", channel),
+            Event::Removed(channel) => write!(f, "probe removed from ADC1 channel {}", channel),
+        }
+    }
+}
+
+/// Whether `error` of a reading means that no probe is connected.
+pub fn is_open(error: &FirmwareError) -> bool {
+    error.code() == ErrorCode::SensorOpen
+}
+
+pub struct Presence {
+    /// Channels with whether a probe is connected.
+    channels: Vec<(u8, bool)>,
+    scanned_at: Option<Instant>,
+}
+
+impl Presence {
+    /// Tracks `channels`, with the on-board probe assumed connected, as it has been read when
+    /// powered mode starts, and the others not.
+    pub fn new(channels: &[u8], primary: u8) -> Presence {
+        Presence {
+            channels: channels.iter().map(|&c| (c, c == primary)).collect(),
+            scanned_at: None,
+        }
+    }
+
+    /// Records whether a probe is connected to `channel`, returning the change if there is one.
+    pub fn update(&mut self, channel: u8, connected: bool) -> Option<Event> {
+        let (_, was_connected) = self.channels.iter_mut().find(|(c, _)| *c == channel)?;
+        if *was_connected == connected {
+            return None;
+        }
+        *was_connected = connected;
+        Some(if connected {
+            Event::Connected(channel)
+        } else {
+            Event::Removed(channel)
+        })
+    }
+
+    /// Returns whether the channels other than `primary` are due to be scanned at `now`, and if so
+    /// takes the scan as done.
+    pub fn scan_due(&mut self, now: Instant) -> bool {
+        if self
+            .scanned_at
+            .map_or(false, |at| now.duration_since(at) < SCAN_INTERVAL)
+        {
+            return false;
+        }
+        self.scanned_at = Some(now);
+        true
+    }
+
+    pub fn connected(&self) -> usize {
+        self.channels.iter().filter(|(_, c)| *c).count()
+    }
+}
+
+#[test]
+pub fn test_presence() {
+    let mut presence = Presence::new(&[4, 3], 4);
+    assert_eq!(presence.connected(), 1);
+    assert_eq!(presence.update(4, true), None);
+    assert_eq!(presence.update(3, false), None);
+    assert_eq!(presence.update(3, true), Some(Event::Connected(3)));
+    assert_eq!(presence.update(3, true), None);
+    assert_eq!(presence.connected(), 2);
+    assert_eq!(presence.update(4, false), Some(Event::Removed(4)));
+    assert_eq!(presence.update(4, false), None);
+    assert_eq!(presence.update(2, true), None);
+    assert_eq!(presence.connected(), 1);
+    assert_eq!(
+        Event::Removed(4).to_string(),
+        "probe removed from ADC1 channel 4"
+    );
+
+    let start = Instant::now();
+    assert!(presence.scan_due(start));
+    assert!(!presence.scan_due(start + SCAN_INTERVAL / 2));
+    assert!(presence.scan_due(start + SCAN_INTERVAL));
+
+    let open = FirmwareError::Sensor {
+        code: ErrorCode::SensorOpen,
+        context: "reading of 3000 mV".into(),
+    };
+    assert!(is_open(&open.context("error measuring")));
+    let failed = FirmwareError::Sensor {
+        code: ErrorCode::SensorRead,
+        context: "ADC timeout".into(),
+    };
+    assert!(!is_open(&failed));
+}
@@ -0,0 +1,119 @@
+//! Requests to HTTP servers, shared by the transports, the notifier, the pollers and the updates.
+//! Failures are [`FirmwareError::Http`]. Errors before the response arrives or while reading it
+//! have the [`ErrorCode`] `HttpConnect`, unsuccessful responses the code of their status and the
+//! pause the server asks for, so that [`crate::retry`] can tell which ones to retry. Bodies are
+//! read up to a limit given by the caller, so that a misbehaving server can't exhaust the heap.
+
+use crate::error::{Classify, FirmwareError};
+use crate::error_code::ErrorCode;
+use crate::retry;
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+
+/// Bytes of an error response kept for the error message. The rest is drained.
+const MAX_ERROR_BODY: usize = 512;
+
+/// Returns a new connection, which verifies servers with the certificate bundle.
+pub fn new_connection() -> Result<EspHttpConnection, FirmwareError> {
+    let http_client_config = Configuration {
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    };
+    EspHttpConnection::new(&http_client_config).http(ErrorCode::HttpConnect)
+}
+
+/// Sends a request with `body` over `http_client`, leaving the body of a successful response to be
+/// read. The body of an unsuccessful response is drained, so that the connection can be reused.
+pub fn send(
+    http_client: &mut EspHttpConnection,
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), FirmwareError> {
+    http_client
+        .initiate_request(method, url, headers)
+        .http(ErrorCode::HttpConnect)?;
+    if !body.is_empty() {
+        http_client.write_all(body).http(ErrorCode::HttpConnect)?;
+    }
+    http_client
+        .initiate_response()
+        .http(ErrorCode::HttpConnect)?;
+    let status = http_client.status();
+    if !(200..300).contains(&status) {
+        return Err(response_error(http_client));
+    }
+    Ok(())
+}
+
+/// Sends a request like [`send`] and drains the body of the response, for requests that only
+/// matter by their status.
+pub fn submit(
+    http_client: &mut EspHttpConnection,
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), FirmwareError> {
+    send(http_client, method, url, headers, body)?;
+    read_body(http_client, 0)?;
+    Ok(())
+}
+
+/// Sends a request like [`send`] and returns the body of the response, which fails with
+/// `HttpResponseTooLarge` if it's longer than `limit` bytes.
+pub fn request(
+    http_client: &mut EspHttpConnection,
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, FirmwareError> {
+    send(http_client, method, url, headers, body)?;
+    let body = read_body(http_client, limit.saturating_add(1))?;
+    if body.len() > limit {
+        return Err(FirmwareError::Http {
+            code: ErrorCode::HttpResponseTooLarge,
+            context: format!("response exceeds {} bytes", limit),
+            retry_after: None,
+        });
+    }
+    Ok(body)
+}
+
+/// Returns the error for the unsuccessful response of `http_client`, with the start of its body,
+/// the [`ErrorCode`] of its status and the pause the server asks for, if any.
+fn response_error(http_client: &mut EspHttpConnection) -> FirmwareError {
+    let status = http_client.status();
+    let retry_after = http_client
+        .header("Retry-After")
+        .and_then(retry::parse_retry_after);
+    let body = read_body(http_client, MAX_ERROR_BODY).unwrap_or_default();
+    let body = String::from_utf8_lossy(&body);
+    let context = match body.trim() {
+        "" => format!("HTTP status {}", status),
+        body => format!("HTTP status {}: {}", status, body),
+    };
+    FirmwareError::Http {
+        code: ErrorCode::for_http_status(status),
+        context,
+        retry_after,
+    }
+}
+
+/// Reads the response body of `http_client` to its end, returning at most `limit` bytes of it.
+fn read_body(http_client: &mut EspHttpConnection, limit: usize) -> Result<Vec<u8>, FirmwareError> {
+    let mut body = Vec::new();
+    let mut buffer = [0; 256];
+    loop {
+        let read = http_client.read(&mut buffer).http(ErrorCode::HttpConnect)?;
+        if read == 0 {
+            return Ok(body);
+        }
+        let kept = read.min(limit - body.len());
+        body.extend_from_slice(&buffer[..kept]);
+    }
+}
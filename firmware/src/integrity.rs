@@ -0,0 +1,87 @@
+//! Detection of memory corruption before it reaches the RTC state. Before the state is committed,
+//! the heap is checked for damaged blocks and the main task for a nearly exhausted stack. Either
+//! restarts the chip instead of committing, so the next cycle resumes from the last snapshot taken
+//! before the damage, and records the failure with its error code to be uploaded.
+//!
+//! The RTC data section is reloaded at every reset other than a wake from deep sleep, so both the
+//! snapshots, see [`crate::rtc`], and the failure are kept in RTC memory that isn't initialized.
+//! Light heap poisoning in `sdkconfig.defaults` gives every block the canaries checked here, and
+//! debug builds poison comprehensively and trap stack overflows with a watchpoint, see
+//! `sdkconfig.defaults.debug`. An overflow the checks here don't catch in time still panics, and
+//! its core dump is uploaded.
+
+use crate::error_code::{self, ErrorCode};
+use esp_idf_sys as sys;
+use std::ptr;
+
+/// Free stack of the main task in bytes below which it's considered exhausted.
+const MIN_FREE_STACK: u32 = 512;
+/// Marks a failure detected before the last restart, as uninitialized memory holds any value.
+const MAGIC: u32 = 0x6865_6170;
+
+#[derive(Clone, Copy)]
+struct Detected {
+    magic: u32,
+    code: u16,
+}
+
+#[link_section = ".rtc_noinit.integrity"]
+static mut DETECTED: Detected = Detected { magic: 0, code: 0 };
+
+/// Checks the heap and the stack of the calling task, restarting the chip if either is damaged.
+pub fn verify() {
+    let code = if !unsafe { sys::heap_caps_check_integrity_all(true) } {
+        ErrorCode::HeapCorrupted
+    } else if free_stack() < MIN_FREE_STACK {
+        ErrorCode::StackExhausted
+    } else {
+        return;
+    };
+    println!("{}, restarting without committing the RTC state", code);
+    unsafe {
+        ptr::write_volatile(
+            &mut DETECTED,
+            Detected {
+                magic: MAGIC,
+                code: code.number(),
+            },
+        );
+        sys::esp_restart();
+    }
+}
+
+/// Records the failure detected before the last restart, if any, at slow clock `time`.
+pub fn record_detected(time: u32) {
+    let detected = unsafe { ptr::replace(&mut DETECTED, Detected { magic: 0, code: 0 }) };
+    if let Some(code) = decode(detected) {
+        println!("restarted after {}", code);
+        error_code::record(code, time);
+    }
+}
+
+fn decode(detected: Detected) -> Option<ErrorCode> {
+    if detected.magic != MAGIC {
+        return None;
+    }
+    [ErrorCode::HeapCorrupted, ErrorCode::StackExhausted]
+        .into_iter()
+        .find(|code| code.number() == detected.code)
+}
+
+/// Returns the least free stack of the calling task so far in bytes, as stack words are bytes in
+/// ESP-IDF.
+fn free_stack() -> u32 {
+    unsafe { sys::uxTaskGetStackHighWaterMark(ptr::null_mut()) }
+}
+
+#[test]
+pub fn test_decode() {
+    let detected = |magic, code| Detected { magic, code };
+    assert_eq!(decode(detected(MAGIC, 601)), Some(ErrorCode::HeapCorrupted));
+    assert_eq!(
+        decode(detected(MAGIC, 602)),
+        Some(ErrorCode::StackExhausted)
+    );
+    assert_eq!(decode(detected(MAGIC, 501)), None);
+    assert_eq!(decode(detected(0x1234_5678, 601)), None);
+}
@@ -0,0 +1,159 @@
+//! The status LED, driven by LEDC so that its brightness can be set, and dimmed at night so that it
+//! doesn't disturb in a bedroom or grow tent. Night is given in local hours, with the UTC offset of
+//! the location configured, as the firmware has no time zone database. The time of the previous
+//! cycle's sync is used, so the LED isn't dimmed before the first sync after power-on. The board has
+//! no light sensor and no free ADC pin for one.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_hal::units::FromValueType;
+use esp_idf_hal::{gpio, ledc};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+const NVS_NAMESPACE: &str = "led";
+const NVS_KEY: &str = "settings";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Brightness in percent.
+    pub brightness: u8,
+    /// Brightness in percent from `night_start` to `night_end`, not dimming if equal to
+    /// `brightness`.
+    pub night_brightness: u8,
+    /// Local hour at which the night starts.
+    pub night_start: u8,
+    /// Local hour at which the night ends.
+    pub night_end: u8,
+    /// Offset of local time from UTC in minutes.
+    pub utc_offset: i16,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            brightness: 100,
+            night_brightness: 100,
+            night_start: 22,
+            night_end: 7,
+            utc_offset: 0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Settings> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    /// Returns the brightness in percent at Unix time `now`, if known.
+    pub fn brightness(&self, now: Option<i64>) -> u8 {
+        let local = match now {
+            Some(now) => now + i64::from(self.utc_offset) * 60,
+            None => return self.brightness,
+        };
+        let hour = local.rem_euclid(24 * 60 * 60) / (60 * 60);
+        let (start, end) = (i64::from(self.night_start), i64::from(self.night_end));
+        let night = if start <= end {
+            (start..end).contains(&hour)
+        } else {
+            hour >= start || hour < end
+        };
+        if night {
+            self.night_brightness
+        } else {
+            self.brightness
+        }
+    }
+}
+
+pub fn validate(settings: &Settings) -> Result<()> {
+    if settings.brightness > 100 || settings.night_brightness > 100 {
+        bail!("LED brightness must be at most 100 percent");
+    }
+    if settings.night_start > 23 || settings.night_end > 23 {
+        bail!("LED night hours must be from 0 to 23");
+    }
+    if settings.utc_offset.abs() > 14 * 60 {
+        bail!("invalid UTC offset of {} minutes", settings.utc_offset);
+    }
+    Ok(())
+}
+
+pub struct Led {
+    driver: ledc::LedcDriver<'static>,
+    /// Duty cycle while lit.
+    duty: u32,
+}
+
+impl Led {
+    pub fn new(channel: ledc::CHANNEL1, timer: ledc::TIMER1, pin: gpio::Gpio7) -> Result<Led> {
+        let config = ledc::config::TimerConfig::new().frequency(1.kHz().into());
+        let driver = ledc::LedcDriver::new(
+            channel,
+            ledc::LedcTimerDriver::new(timer, &config)?,
+            pin,
+            &config,
+        )?;
+        let duty = driver.get_max_duty();
+        Ok(Led { driver, duty })
+    }
+
+    /// Sets the brightness in percent, which takes effect when the LED is lit next.
+    pub fn set_brightness(&mut self, percent: u8) {
+        self.duty = self.driver.get_max_duty() * u32::from(percent.min(100)) / 100;
+    }
+
+    pub fn set_high(&mut self) -> Result<()> {
+        Ok(self.driver.set_duty(self.duty)?)
+    }
+
+    pub fn set_low(&mut self) -> Result<()> {
+        Ok(self.driver.set_duty(0)?)
+    }
+}
+
+#[test]
+pub fn test_brightness() {
+    let settings = Settings {
+        night_brightness: 10,
+        utc_offset: 120,
+        ..Default::default()
+    };
+    validate(&settings).unwrap();
+    let at = |hour: i64, minute: i64| Some(19_000 * 24 * 60 * 60 + hour * 60 * 60 + minute * 60);
+    assert_eq!(settings.brightness(None), 100);
+    assert_eq!(settings.brightness(at(12, 0)), 100);
+    assert_eq!(settings.brightness(at(19, 59)), 100);
+    assert_eq!(settings.brightness(at(20, 0)), 10);
+    assert_eq!(settings.brightness(at(23, 30)), 10);
+    assert_eq!(settings.brightness(at(4, 59)), 10);
+    assert_eq!(settings.brightness(at(5, 0)), 100);
+
+    let settings = Settings {
+        night_brightness: 0,
+        night_start: 1,
+        night_end: 6,
+        utc_offset: -300,
+        ..Default::default()
+    };
+    assert_eq!(settings.brightness(at(6, 0)), 0);
+    assert_eq!(settings.brightness(at(11, 0)), 100);
+    assert_eq!(Settings::default().brightness(at(23, 0)), 100);
+
+    assert!(validate(&Settings {
+        brightness: 101,
+        ..Default::default()
+    })
+    .is_err());
+    assert!(validate(&Settings {
+        night_start: 24,
+        ..Default::default()
+    })
+    .is_err());
+}
@@ -0,0 +1,351 @@
+//! Helpers for the InfluxDB line protocol.
+//!
+//! A line prefix such as `moisture,sensor=balcony value=` consists of the series (measurement
+//! and tags) followed by a space and the field key. Extra tags are inserted in front of that
+//! space. Lines for other measurements reuse the tags of the prefix, so that all data of a
+//! device can be selected the same way.
+
+use crate::points::Point;
+use anyhow::{bail, Result};
+use std::fmt::{Display, Write};
+
+pub enum FieldValue<'a> {
+    Boolean(bool),
+    Float(f64),
+    Integer(i64),
+    String(&'a str),
+}
+
+impl Display for FieldValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FieldValue::Boolean(value) => write!(f, "{}", value),
+            FieldValue::Float(value) => write!(f, "{}", value),
+            FieldValue::Integer(value) => write!(f, "{}i", value),
+            FieldValue::String(value) => {
+                f.write_char('"')?;
+                for c in value.chars() {
+                    if matches!(c, '"' | '\\') {
+                        f.write_char('\\')?;
+                    }
+                    f.write_char(c)?;
+                }
+                f.write_char('"')
+            }
+        }
+    }
+}
+
+/// Numbers points that fall into the same second, so that they get distinct timestamps instead of
+/// overwriting each other in the database.
+#[derive(Default)]
+pub struct Sequence {
+    second: Option<i64>,
+    next: u32,
+}
+
+impl Sequence {
+    /// Returns the sequence number of a point at `seconds`. Points must be given in order.
+    pub fn next(&mut self, seconds: i64) -> u32 {
+        if self.second != Some(seconds) {
+            self.second = Some(seconds);
+            self.next = 0;
+        }
+        self.next += 1;
+        self.next - 1
+    }
+}
+
+/// Writes the line of `point`.
+pub fn write_point(out: &mut String, point: &Point) {
+    out.push_str(&escape(&point.measurement, &[',', ' ']));
+    for (key, value) in &point.tags {
+        let _ = write!(out, ",{}={}", escape_key(key), escape_key(value));
+    }
+    for (i, (key, value)) in point.fields.iter().enumerate() {
+        let separator = if i == 0 { ' ' } else { ',' };
+        let _ = write!(out, "{}{}={}", separator, escape_key(key), value.as_field());
+    }
+    let _ = writeln!(out, " {}{:09}", point.seconds, point.sequence);
+}
+
+/// A line prefix, unescaped.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Prefix {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub field: String,
+}
+
+pub fn parse_prefix(prefix: &str) -> Prefix {
+    let (series, field) = split_prefix(prefix);
+    let series = split_all(series, ',', false);
+    let tags = series[1..]
+        .iter()
+        .map(|tag| {
+            let (key, value) = split_unescaped(tag, '=');
+            (unescape(key), unescape(value))
+        })
+        .collect();
+    Prefix {
+        measurement: unescape(series[0]),
+        tags,
+        field: unescape(field.trim_end_matches('=')),
+    }
+}
+
+/// A parsed line, with tag keys and values and field keys unescaped. Field values are kept as
+/// written.
+#[derive(Debug, PartialEq, Eq)]
+struct Line<'a> {
+    measurement: String,
+    tags: Vec<(String, String)>,
+    fields: Vec<(String, &'a str)>,
+    /// In nanoseconds.
+    timestamp: Option<i64>,
+}
+
+/// Checks the syntax of a line: series, fields and an optional timestamp, separated by spaces.
+pub fn validate(line: &str) -> Result<()> {
+    parse(line).map(|_| ())
+}
+
+fn parse(line: &str) -> Result<Line> {
+    let parts = split_all(line, ' ', true);
+    let (series, fields, timestamp) = match parts.as_slice() {
+        [series, fields] => (series, fields, None),
+        [series, fields, timestamp] => (series, fields, Some(timestamp)),
+        _ => bail!("expected series, fields and timestamp: {}", line),
+    };
+
+    let series = split_all(series, ',', false);
+    if series[0].is_empty() {
+        bail!("missing measurement: {}", line);
+    }
+    let mut tags = Vec::new();
+    for tag in &series[1..] {
+        match split_unescaped(tag, '=') {
+            (key, value) if !key.is_empty() && !value.is_empty() => {
+                tags.push((unescape(key), unescape(value)))
+            }
+            _ => bail!("invalid tag {}: {}", tag, line),
+        }
+    }
+
+    let mut parsed_fields = Vec::new();
+    for field in split_all(fields, ',', true) {
+        let (key, value) = split_unescaped(field, '=');
+        if key.is_empty() || !is_field_value(value) {
+            bail!("invalid field {}: {}", field, line);
+        }
+        parsed_fields.push((unescape(key), value));
+    }
+
+    let timestamp = match timestamp {
+        Some(timestamp) => match timestamp.parse::<i64>() {
+            Ok(timestamp) => Some(timestamp),
+            Err(_) => bail!("invalid timestamp {}: {}", timestamp, line),
+        },
+        None => None,
+    };
+    Ok(Line {
+        measurement: unescape(series[0]),
+        tags,
+        fields: parsed_fields,
+        timestamp,
+    })
+}
+
+fn is_field_value(value: &str) -> bool {
+    if let Some(integer) = value.strip_suffix('i') {
+        integer.parse::<i64>().is_ok()
+    } else if let Some(integer) = value.strip_suffix('u') {
+        integer.parse::<u64>().is_ok()
+    } else if value.starts_with('"') {
+        value.len() >= 2 && value.ends_with('"')
+    } else {
+        matches!(
+            value,
+            "t" | "T" | "true" | "True" | "TRUE" | "f" | "F" | "false" | "False" | "FALSE"
+        ) || value.parse::<f64>().is_ok()
+    }
+}
+
+/// Splits `s` at every occurrence of `separator` not escaped by a backslash and, if `quotes` is
+/// set, not within a quoted string.
+fn split_all(s: &str, separator: char, quotes: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if quotes => quoted = !quoted,
+            _ if c == separator && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Splits a line prefix into series and field key.
+fn split_prefix(prefix: &str) -> (&str, &str) {
+    split_unescaped(prefix, ' ')
+}
+
+/// Splits `s` at the first occurrence of `separator` not escaped by a backslash.
+fn split_unescaped(s: &str, separator: char) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == separator => return (&s[..i], &s[i + 1..]),
+            _ => {}
+        }
+    }
+    (s, "")
+}
+
+/// Escapes a tag key or value, or a field key.
+fn escape_key(s: &str) -> String {
+    escape(s, &[',', '=', ' '])
+}
+
+/// Escapes backslashes and the `special` characters of `s`.
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut escaped = false;
+    for c in s.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+        escaped = false;
+        unescaped.push(c);
+    }
+    unescaped
+}
+
+#[test]
+pub fn test_write_point() {
+    use crate::points::MeasurementBatch;
+
+    let mut batch = MeasurementBatch::default();
+    batch.push_value(
+        "moisture,sensor=a value=",
+        &[],
+        FieldValue::Float(123.0),
+        1000,
+        0,
+    );
+    batch.push_value(
+        "moisture,sensor=a\\ b value=",
+        &[("maintenance", "true"), ("pot", "big, red")],
+        FieldValue::Float(456.0),
+        2000,
+        1,
+    );
+    batch.push_fields(
+        "moisture value=",
+        "queue",
+        &[],
+        &[("depth", FieldValue::Integer(3))],
+        1000,
+    );
+    batch.push_fields(
+        "moisture,sensor=a\\,b value=",
+        "build",
+        &[("site", "x")],
+        &[
+            ("schema", FieldValue::Integer(-1)),
+            ("dirty", FieldValue::Boolean(false)),
+            ("variance", FieldValue::Float(2.5)),
+            ("version", FieldValue::String("say \"hi\" \\o/")),
+        ],
+        2000,
+    );
+    assert_eq!(
+        batch.to_line_protocol(),
+        "moisture,sensor=a value=123 1000000000000\n\
+         moisture,sensor=a\\ b,maintenance=true,pot=big\\,\\ red value=456 2000000000001\n\
+         queue depth=3i 1000000000000\n\
+         build,sensor=a\\,b,site=x schema=-1i,dirty=false,variance=2.5,version=\"say \\\"hi\\\" \\\\o/\" 2000000000000\n"
+    );
+
+    let mut sequence = Sequence::default();
+    let numbers: Vec<_> = [5, 5, 5, 6, 7, 7].map(|s| sequence.next(s)).into();
+    assert_eq!(numbers, vec![0, 1, 2, 0, 0, 1]);
+}
+
+#[test]
+pub fn test_parse_prefix() {
+    assert_eq!(
+        parse_prefix("soil\\ moisture,sensor=a\\,b,site=x value="),
+        Prefix {
+            measurement: "soil moisture".into(),
+            tags: vec![("sensor".into(), "a,b".into()), ("site".into(), "x".into())],
+            field: "value".into(),
+        }
+    );
+}
+
+#[test]
+pub fn test_validate() {
+    use crate::points::MeasurementBatch;
+
+    let mut batch = MeasurementBatch::default();
+    batch.push_value(
+        "moisture,sensor=a\\ b value=",
+        &[("pot", "x=y")],
+        FieldValue::Float(456.0),
+        2000,
+        3,
+    );
+    batch.push_fields(
+        "moisture value=",
+        "build",
+        &[],
+        &[("version", FieldValue::String("a, \"b c\""))],
+        1000,
+    );
+    for line in batch.to_line_protocol().lines() {
+        validate(line).unwrap();
+    }
+    validate("m v=1.5,w=true,x=3u").unwrap();
+    assert_eq!(
+        parse("m,t=a\\ b v=1.5,w\\,x=\"c d\" 1000").unwrap(),
+        Line {
+            measurement: "m".into(),
+            tags: vec![("t".into(), "a b".into())],
+            fields: vec![("v".into(), "1.5"), ("w,x".into(), "\"c d\"")],
+            timestamp: Some(1000),
+        }
+    );
+
+    assert!(validate("m").is_err());
+    assert!(validate("m v=1 2 3").is_err());
+    assert!(validate(",t=a v=1").is_err());
+    assert!(validate("m,t= v=1").is_err());
+    assert!(validate("m v=").is_err());
+    assert!(validate("m v=1x").is_err());
+    assert!(validate("m v=\"open 1").is_err());
+    assert!(validate("m v=1 soon").is_err());
+}
@@ -0,0 +1,60 @@
+//! WebSocket endpoint `/live` of powered mode, streaming every new reading as JSON. While a client
+//! is connected, the sensor is read more often, so that a web page can show the values in real
+//! time while the probe is being placed. Browsers can't send headers with WebSockets, so a client
+//! sends the password as its first message and only receives readings after that.
+
+use crate::local_auth::Verifier;
+use anyhow::Result;
+use embedded_svc::ws::FrameType;
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+use esp_idf_svc::http::server::EspHttpServer;
+use std::sync::{Arc, Mutex};
+
+pub struct Live {
+    senders: Arc<Mutex<Vec<EspHttpWsDetachedSender>>>,
+}
+
+impl Live {
+    /// Adds the endpoint to `server`.
+    pub fn register(server: &mut EspHttpServer, verifier: Verifier) -> Result<Live> {
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let handler_senders = senders.clone();
+        server.ws_handler("/live", move |connection| {
+            if connection.is_new() {
+                println!("live client {} connected", connection.session());
+                return anyhow::Ok(());
+            }
+            if connection.is_closed() {
+                // Closed connections are dropped when sending.
+                return Ok(());
+            }
+
+            let mut buffer = [0; 128];
+            let (_, len) = connection.recv(&mut buffer)?;
+            let password = String::from_utf8_lossy(&buffer[..len]);
+            if verifier.verify(password.trim_end_matches('\0')) {
+                let sender = connection.create_detached_sender()?;
+                handler_senders.lock().unwrap().push(sender);
+            } else {
+                println!("live client {} not authorized", connection.session());
+            }
+            Ok(())
+        })?;
+        println!("serving WebSocket /live");
+        Ok(Live { senders })
+    }
+
+    pub fn has_clients(&self) -> bool {
+        !self.senders.lock().unwrap().is_empty()
+    }
+
+    /// Sends a reading to all clients.
+    pub fn send(&self, value: u16) {
+        let message = serde_json::json!({ "value": value }).to_string();
+        self.senders.lock().unwrap().retain_mut(|sender| {
+            sender
+                .send(FrameType::Text(false), message.as_bytes())
+                .is_ok()
+        });
+    }
+}
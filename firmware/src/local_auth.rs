@@ -0,0 +1,140 @@
+//! Password protecting the local interfaces of powered mode, so that a sensor on a shared network
+//! can't be read or reconfigured by anyone who finds it. Only a salted hash is stored in NVS. As
+//! long as no password is set, the local interfaces refuse all requests.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+const NVS_NAMESPACE: &str = "local_auth";
+const NVS_KEY: &str = "password";
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Slows down guessing passwords from a leaked hash, while verifying still takes only a few ms.
+const HASH_ROUNDS: u32 = 1000;
+
+/// Password in plain text, as given with a command. It isn't printed, so that it doesn't end up
+/// in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Password(String);
+
+impl FromStr for Password {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.chars().count() < MIN_PASSWORD_LENGTH {
+            bail!(
+                "password must have at least {} characters",
+                MIN_PASSWORD_LENGTH
+            );
+        }
+        Ok(Password(s.into()))
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredHash {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+pub fn set_password(partition: &EspDefaultNvsPartition, password: &Password) -> Result<()> {
+    let mut salt = [0; 16];
+    unsafe {
+        esp_idf_sys::esp_fill_random(salt.as_mut_ptr() as _, salt.len() as _);
+    }
+    let stored = StoredHash {
+        salt,
+        hash: hash(&salt, &password.0),
+    };
+    Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, &stored)?;
+    println!("local password set.");
+    Ok(())
+}
+
+/// Checks the credentials of requests to the local interfaces.
+#[derive(Clone)]
+pub struct Verifier {
+    stored: Option<StoredHash>,
+}
+
+impl Verifier {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Verifier> {
+        let stored = Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)?;
+        if stored.is_none() {
+            println!("no local password set, local interfaces refuse all requests");
+        }
+        Ok(Verifier { stored })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        match &self.stored {
+            Some(stored) => constant_time_eq(&hash(&stored.salt, password), &stored.hash),
+            None => false,
+        }
+    }
+
+    /// Checks an Authorization header carrying the password as bearer token.
+    pub fn verify_header(&self, header: Option<&str>) -> bool {
+        header
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map_or(false, |password| self.verify(password))
+    }
+}
+
+fn hash(salt: &[u8], password: &str) -> [u8; 32] {
+    let mut hash: [u8; 32] = Sha256::new()
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize()
+        .into();
+    for _ in 1..HASH_ROUNDS {
+        hash = Sha256::digest(hash).into();
+    }
+    hash
+}
+
+/// Compares in constant time, so that a match can't be guessed byte by byte from response times.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[test]
+pub fn test_verify() {
+    let salt = [7; 16];
+    let verifier = Verifier {
+        stored: Some(StoredHash {
+            salt,
+            hash: hash(&salt, "correct horse"),
+        }),
+    };
+    assert!(verifier.verify("correct horse"));
+    assert!(!verifier.verify("correct horsf"));
+    assert!(verifier.verify_header(Some("Bearer correct horse")));
+    assert!(!verifier.verify_header(Some("correct horse")));
+    assert!(!verifier.verify_header(None));
+    assert_ne!(
+        hash(&[8; 16], "correct horse"),
+        hash(&salt, "correct horse")
+    );
+
+    assert!(!Verifier { stored: None }.verify(""));
+    assert!("short".parse::<Password>().is_err());
+    assert_eq!(
+        format!("{:?}", "correct horse".parse::<Password>().unwrap()),
+        "<redacted>"
+    );
+}
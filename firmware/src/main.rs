@@ -1,243 +1,2044 @@
+mod adaptive;
+mod adc_trace;
+mod alert;
 mod arr_deque;
+mod awake_limit;
+mod batch;
+#[cfg(feature = "battery")]
+mod battery;
+mod board;
+mod build_info;
+mod button;
+mod calibration;
+mod cli;
+mod coarse;
+mod command;
+mod components;
+mod config;
+mod connection;
+#[cfg(feature = "continuous")]
+mod continuous;
+mod coredump;
+mod debounce;
+mod detections;
+mod device_config;
+mod diagnostics;
+#[cfg(feature = "dpp")]
+mod dpp;
+mod dry_run;
+mod error;
+mod error_code;
+#[cfg(feature = "esphome")]
+mod esphome;
+#[cfg(any(feature = "mcp23017", feature = "pcf8574"))]
+mod expander;
+#[cfg(feature = "fake-sensor")]
+mod fake_sensor;
+mod fleet;
+mod graphite;
+mod health;
+mod hotplug;
+mod http;
+mod integrity;
+mod led;
+mod line_protocol;
+#[cfg(feature = "powered")]
+mod live;
+#[cfg(feature = "powered")]
+mod local_auth;
+mod maintenance;
+mod manual_time;
+#[cfg(feature = "powered")]
+mod measure_endpoint;
+#[cfg(feature = "powered")]
+mod metrics;
+mod mqtt;
+mod notifier;
+mod ota;
+mod otlp;
+mod packing;
+#[cfg(feature = "peer-time")]
+mod peer_message;
+#[cfg(feature = "peer-time")]
+mod peer_time;
+mod points;
+mod postgrest;
+mod power;
+#[cfg(not(feature = "fake-sensor"))]
+mod probe;
+mod probes;
+mod profile;
+#[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+mod provisioning;
+mod rate_limit;
+mod recorder;
+mod retry;
+mod rtc;
+mod safe_mode;
+mod sampling;
+mod schedule;
+mod scheduler;
+mod self_test;
+mod series;
+mod session;
+mod settling;
+mod shadow;
+mod skips;
+#[cfg(feature = "smartconfig")]
+mod smartconfig;
+#[cfg(feature = "softap")]
+mod softap;
+mod soil;
+mod spill;
+mod spread;
+mod startup;
+mod statsd;
+mod storage;
+mod tags;
+mod timeouts;
+mod transport;
+mod upload_window;
+mod uptime;
+mod wake;
+mod watering;
+#[cfg(feature = "powered")]
+mod web_ui;
+mod wifi_credentials;
+mod wifi_mac;
 
-use crate::arr_deque::ArrDeque;
+use crate::board::Board;
+use crate::build_info::BuildInfo;
+use crate::calibration::Calibrations;
+use crate::command::Command;
+use crate::components::Components;
+use crate::detections::Detection;
+use crate::device_config::DeviceConfig;
+use crate::diagnostics::Diagnostic;
+use crate::error::{Classify, FirmwareError};
+use crate::error_code::{ErrorCode, Event, Failure};
+use crate::health::Counters;
+use crate::led::Led;
+use crate::line_protocol::{FieldValue, Sequence};
+use crate::points::MeasurementBatch;
+use crate::probes::Probe;
+use crate::rate_limit::Limits;
+use crate::schedule::RtcClock;
+use crate::scheduler::Deferral;
+use crate::session::Session;
+use crate::skips::{Skip, SkipReason};
+use crate::spread::Stats;
+use crate::startup::{Startup, Subsystem};
+use crate::tags::Tags;
+use crate::timeouts::Timeouts;
+use crate::transport::Transport;
+use crate::wake::WakeCause;
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::Utc;
 use embedded_svc;
 use embedded_svc::http::Method;
-use embedded_svc::io::Write;
-use esp_idf_hal::units::FromValueType;
-use esp_idf_hal::{adc, gpio, ledc, reset};
-use esp_idf_hal::{delay::FreeRtos, peripherals};
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_hal::{modem, reset};
+use esp_idf_svc::http::client::EspHttpConnection;
 use esp_idf_svc::netif::IpEvent;
 use esp_idf_svc::wifi::{EspWifi, WifiEvent};
 use esp_idf_svc::{eventloop, nvs, sntp};
-use std::sync::mpsc::channel;
-use std::time::Duration;
+#[cfg(feature = "powered")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+#[cfg(feature = "powered")]
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(all(feature = "battery", feature = "reference"))]
+compile_error!("the battery and reference features both use GPIO3");
+#[cfg(all(
+    feature = "multi-probe",
+    any(feature = "battery", feature = "reference")
+))]
+compile_error!("the second probe of the multi-probe feature uses GPIO3");
+#[cfg(all(feature = "mcp23017", feature = "pcf8574"))]
+compile_error!("only one GPIO expander is supported");
 
 const WIFI_SSID: &str = env!("WIFI_SSID");
 const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
 
-const WRITE_URL: &str = env!("WRITE_URL");
-const AUTHORIZATION: &str = env!("AUTHORIZATION");
-const LINE_PREFIX: &str = env!("LINE_PREFIX");
+const COMMAND_URL: Option<&str> = option_env!("COMMAND_URL");
+/// Where the configuration is uploaded to when an export is requested remotely.
+const CONFIG_URL: Option<&str> = option_env!("CONFIG_URL");
+/// Desired configuration the device converges to, see [`shadow`].
+const SHADOW_URL: Option<&str> = option_env!("SHADOW_URL");
+/// Manifest of the latest firmware, checked after each upload, see [`ota`].
+const OTA_MANIFEST_URL: Option<&str> = option_env!("OTA_MANIFEST_URL");
+/// Version of the layout of the configuration stored in NVS.
+const CONFIG_SCHEMA_VERSION: i64 = 1;
+const QUEUE_MEASUREMENT: &str = "queue";
+const BUILD_MEASUREMENT: &str = "build";
+const CONFIG_MEASUREMENT: &str = "config";
+const CONNECTION_MEASUREMENT: &str = "connection";
+const ERROR_MEASUREMENT: &str = "error";
+const EVENTS_MEASUREMENT: &str = "events";
+const HEALTH_MEASUREMENT: &str = "health";
+const SKIP_MEASUREMENT: &str = "skip";
+const DETECTION_MEASUREMENT: &str = "detection";
+const DIAGNOSTICS_MEASUREMENT: &str = "diagnostics";
+const BATTERY_MEASUREMENT: &str = "battery";
+const SAMPLING_MEASUREMENT: &str = "sampling";
+const SETTLING_TIMEOUT_MEASUREMENT: &str = "settling_timeout";
+const SHADOW_MEASUREMENT: &str = "shadow";
+const CRASH_MEASUREMENT: &str = "crash";
+const TRACE_MEASUREMENT: &str = "trace";
+const UPTIME_MEASUREMENT: &str = "uptime";
+const MOISTURE_PERCENT_MEASUREMENT: &str = "moisture_percent";
 
+/// Default interval between cycles, until a device configuration has been stored.
 const MEASUREMENT_INTERVAL: Duration = Duration::from_secs(3600);
 const MIN_RECORDED_MEASUREMENTS: usize = 6;
-const MAX_RECORDED_MEASUREMENTS: usize = 1000;
+/// A month of hourly readings, as many as fit into the RTC memory budget with the other state.
+const MAX_RECORDED_MEASUREMENTS: usize = 800;
+const HTTP_CONNECT_STACK_SIZE: usize = 10 * 1024;
+const SESSION_BUDGET: Duration = Duration::from_secs(60);
+const CONSOLE_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCATE_DURATION: Duration = Duration::from_secs(60);
+/// Awake time expected of a cycle woken from deep sleep that only measures and doesn't connect.
+/// It's a diagnostic target, not a budget: the cycle still reads its settings from NVS and stores
+/// the record of the previous cycle, and exceeding the target is only logged, so that slow cycles
+/// show up in the console instead of being cut short.
+const OFFLINE_CYCLE_TARGET: Duration = Duration::from_secs(1);
+/// How long to wait for time sync if the time has been set manually or received from another
+/// sensor.
+const FALLBACK_TIME_SYNC_WAIT: Duration = Duration::from_secs(10);
+/// How often the sensor is read while staying online in powered mode.
+#[cfg(feature = "powered")]
+const POWERED_READ_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the sensor is read while readings are streamed live.
+#[cfg(feature = "powered")]
+const LIVE_READ_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+const PROVISIONING_TIMEOUT: Duration = Duration::from_secs(120);
 
+/// Flags, the wake cause and the probe channel are packed into a byte, so that a measurement with
+/// the battery voltage fits into 8 bytes of RTC memory, see [`packing`].
 #[derive(Clone)]
-struct Measurement {
+pub struct Measurement {
     value: u16,
+    /// Packed [`packing::Info`].
+    info: u8,
+    /// Packed battery voltage, see [`packing::pack_battery`].
+    battery: u8,
     time: u32,
 }
 
-#[link_section = ".rtc.data.rtc_memory"]
-static mut MEASUREMENTS: ArrDeque<Measurement, MAX_RECORDED_MEASUREMENTS> = ArrDeque::new();
+impl Measurement {
+    fn new(
+        value: u16,
+        time: u32,
+        wake_cause: WakeCause,
+        maintenance: bool,
+        watered: bool,
+    ) -> Measurement {
+        let info = packing::Info {
+            maintenance,
+            watered,
+            wake_cause,
+            channel: probes::PRIMARY_CHANNEL,
+        };
+        Measurement {
+            value,
+            info: info.pack(),
+            battery: 0,
+            time,
+        }
+    }
+
+    fn with_battery(mut self, battery_mv: Option<u16>) -> Measurement {
+        self.battery = packing::pack_battery(battery_mv);
+        self
+    }
+
+    fn with_channel(mut self, channel: u8) -> Measurement {
+        self.info = packing::Info {
+            channel,
+            ..self.unpacked()
+        }
+        .pack();
+        self
+    }
+
+    fn unpacked(&self) -> packing::Info {
+        packing::Info::unpack(self.info)
+    }
+
+    fn maintenance(&self) -> bool {
+        self.unpacked().maintenance
+    }
+
+    fn watered(&self) -> bool {
+        self.unpacked().watered
+    }
+
+    /// Cause of the wake of the cycle in which the reading was taken.
+    fn wake_cause(&self) -> WakeCause {
+        self.unpacked().wake_cause
+    }
+
+    /// ADC1 channel of the probe.
+    fn channel(&self) -> u8 {
+        self.unpacked().channel
+    }
+
+    fn battery_mv(&self) -> Option<u16> {
+        packing::unpack_battery(self.battery)
+    }
+}
+
+/// Phases of a wake cycle. The state is committed to RTC memory when a phase starts, so that a
+/// cycle interrupted by a reset, including a panic or a watchdog reset, is resumed from the start
+/// of the phase instead of started over. Phases that need the connection resume by connecting
+/// again, which continues at the interrupted phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Sample,
+    Decide,
+    Connect,
+    Sync,
+    ConfigPoll,
+    Upload,
+    Sleep,
+}
+
+/// Source of the offset of the slow clock to UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeSource {
+    Sntp,
+    Manual,
+    /// Broadcast by another sensor, see `peer_time`.
+    Peer,
+}
+
+impl TimeSource {
+    fn name(self) -> &'static str {
+        match self {
+            TimeSource::Sntp => "sntp",
+            TimeSource::Manual => "manual",
+            TimeSource::Peer => "peer",
+        }
+    }
+}
 
 fn main() -> Result<()> {
     esp_idf_sys::link_patches();
+    rtc::restore();
+    safe_mode::count_boot();
+    integrity::record_detected(slow_clock_seconds());
+    awake_limit::record_forced(slow_clock_seconds());
 
-    if let Err(e) = run() {
-        println!("error: {}", e);
+    match Board::take() {
+        Ok(mut board) => match run(&mut board) {
+            // An updated image that doesn't get here is rolled back at the next wake.
+            Ok(()) => {
+                if let Err(e) = ota::confirm() {
+                    println!("error confirming updated firmware: {}", e);
+                }
+            }
+            Err(e) => {
+                let phase = unsafe { rtc::STATE.phase };
+                let code = record_error(e);
+                if matches!(
+                    phase,
+                    Phase::Connect | Phase::Sync | Phase::ConfigPoll | Phase::Upload
+                ) {
+                    retry::record_failure();
+                }
+                if let Err(e) = show_error(board.led(), code) {
+                    println!("error showing error: {}", e);
+                }
+            }
+        },
+        Err(e) => {
+            record_error(e);
+        }
     }
+    awake_limit::disarm();
 
     unsafe {
-        go_to_sleep();
+        rtc::STATE.phase = Phase::Sleep;
+        rtc::STATE.resumed = false;
     }
-}
 
-fn run() -> Result<()> {
-    let peripherals = peripherals::Peripherals::take().context("peripherals already taken")?;
-    let mut led_driver = gpio::PinDriver::output(peripherals.pins.gpio7)?;
+    let awake_ms = (unsafe { esp_idf_sys::esp_timer_get_time() } / 1000) as _;
+    recorder::current().awake_ms = awake_ms;
+    health::set_awake_time(awake_ms);
+    uptime::add_awake_time(awake_ms);
+    diagnostics::finish(awake_ms);
+    integrity::verify();
+    rtc::commit();
 
-    let mut power_mode_driver = gpio::PinDriver::output(peripherals.pins.gpio10)?;
-    power_mode_driver.set_high()?;
+    unsafe {
+        go_to_sleep(Duration::from_millis(awake_ms.into()));
+    }
+}
 
-    let mut adc_driver = adc::AdcDriver::new(
-        peripherals.adc1,
-        &adc::config::Config::new().calibration(true),
-    )?;
-    let mut adc_channel_driver: adc::AdcChannelDriver<gpio::Gpio4, adc::Atten11dB<_>> =
-        adc::AdcChannelDriver::new(peripherals.pins.gpio4)?;
-
-    let pwm_config = ledc::config::TimerConfig::new().frequency(50.kHz().into());
-    let mut sensor_pwm_driver = ledc::LedcDriver::new(
-        peripherals.ledc.channel0,
-        ledc::LedcTimerDriver::new(peripherals.ledc.timer0, &pwm_config)?,
-        peripherals.pins.gpio5,
-        &pwm_config,
-    )?;
+fn run(board: &mut Board) -> Result<()> {
+    let nvs_partition = nvs::EspDefaultNvsPartition::take()?;
+    let mut startup = Startup::default();
+    startup.init(Subsystem::AwakeLimit, || awake_limit::arm(&nvs_partition));
+    startup.init(Subsystem::Recorder, || {
+        recorder::store_previous(&nvs_partition)
+    });
+    let reset_reason = reset::ResetReason::get();
+    let wake_cause = wake::current();
+    println!("woken by {}, wake {}", wake_cause.name(), wake::count());
+    let build_id = BuildInfo::current().build_id();
+    let counters = startup.init(Subsystem::Health, || {
+        health::count_boot(&nvs_partition, &build_id, reset_reason)
+    });
+    if let Some(counters) = &counters {
+        println!("boot {}, reset reason {:?}", counters.boots, reset_reason);
+    }
+    startup.init(Subsystem::Diagnostics, || {
+        diagnostics::start(counters.as_ref().context("boot not counted")?.boots);
+        Ok(())
+    });
+    if reset_reason != reset::ResetReason::DeepSleep {
+        if let Some(summary) = coredump::pending() {
+            println!("core dump found: {}", summary);
+        }
+        startup.init(Subsystem::SpillRestore, || spill::restore(&nvs_partition));
+    }
+    startup.init(Subsystem::SpillHook, || spill::install(&nvs_partition));
+    unsafe {
+        rtc::STATE.not_ready |= startup.not_ready();
+    }
+    if let Some(diagnostic) = diagnostics::current() {
+        diagnostic.not_ready = startup.not_ready();
+    }
+
+    #[cfg(feature = "softap")]
+    if provisioning::pending().is_none() && wifi_credentials::load(&nvs_partition)?.0.is_empty() {
+        println!("no WiFi credentials stored");
+        provisioning::request(provisioning::Method::SoftAp);
+    }
 
     let clock_source = unsafe { esp_idf_sys::rtc_clk_slow_freq_get() };
     if clock_source != esp_idf_sys::rtc_slow_freq_t_RTC_SLOW_FREQ_32K_XTAL {
         bail!("wrong slow clock source");
     }
 
-    if reset::ResetReason::get() != reset::ResetReason::DeepSleep {
-        greeting(&mut led_driver)?;
-    } else {
-        led_driver.set_high()?;
+    let safe = safe_mode::enter();
+    let interrupted = unsafe { rtc::STATE.phase };
+    let mut phase = Phase::Sample;
+    if safe {
+        for command in awake_limit::paused(|| cli::run(CONSOLE_IDLE_TIMEOUT)) {
+            apply_command(command, &nvs_partition);
+        }
+        phase = Phase::Connect;
+    } else if interrupted != Phase::Sleep {
+        if unsafe { rtc::STATE.resumed } {
+            println!("resumed cycle interrupted again in {:?}", interrupted);
+            phase = Phase::Sleep;
+        } else {
+            println!("resuming cycle interrupted in {:?}", interrupted);
+            unsafe {
+                rtc::STATE.resumed = true;
+            }
+            phase = match interrupted {
+                Phase::Sample | Phase::Decide => Phase::Sample,
+                _ => Phase::Connect,
+            };
+        }
     }
+    let polled = interrupted == Phase::Upload;
+
+    let mut _wifi = None;
+    let mut sntp = None;
+    let mut http_client = None;
+    let mut session = None;
 
-    sensor_pwm_driver.set_duty(sensor_pwm_driver.get_max_duty() / 100)?;
-    FreeRtos::delay_ms(20); // TODO: good value?
+    loop {
+        unsafe {
+            rtc::STATE.phase = phase;
+        }
+        integrity::verify();
+        rtc::commit();
 
-    match adc_driver.read(&mut adc_channel_driver) {
-        Ok(value) => {
-            let time = slow_clock_seconds();
-            println!("recorded value: {} at {}", value, time);
+        phase = match phase {
+            Phase::Sample => {
+                // The time of the previous cycle, as it's synced again below.
+                let now = unsafe { rtc::STATE.time_offset }
+                    .map(|offset| slow_clock_seconds() as i64 + offset);
+                let brightness = led::Settings::load(&nvs_partition)?.brightness(now);
+                board.led().set_brightness(brightness);
+                unsafe {
+                    rtc::STATE.time_offset = None;
+                }
 
-            unsafe {
-                MEASUREMENTS.overwriting_push_back(Measurement { value, time });
-                if MEASUREMENTS.len() < MIN_RECORDED_MEASUREMENTS {
-                    return Ok(());
+                if reset::ResetReason::get() != reset::ResetReason::DeepSleep {
+                    greeting(board.led())?;
+                    if let Err(e) = probes::check(&nvs_partition) {
+                        println!("ignoring probe configuration: {:#}", e);
+                        error_code::record(error::code(&e), slow_clock_seconds());
+                    }
+                    if calibration::is_requested(board) {
+                        let channels = probes::channels(&probes::load_valid(&nvs_partition)?);
+                        awake_limit::paused(|| calibration::run(board, &nvs_partition, &channels))?;
+                    }
+                    for command in awake_limit::paused(|| cli::run(CONSOLE_IDLE_TIMEOUT)) {
+                        apply_command(command, &nvs_partition);
+                    }
+                } else {
+                    board.led().set_high()?;
+                }
+
+                let mut watered = false;
+                match read_gesture(board) {
+                    Some(button::Gesture::Press) => maintenance::start(
+                        slow_clock_seconds(),
+                        command::DEFAULT_MAINTENANCE_DURATION,
+                    ),
+                    Some(button::Gesture::DoublePress) => watered = true,
+                    Some(button::Gesture::LongPress) => request_access_point(),
+                    None => {}
+                }
+
+                if unsafe { rtc::STATE.locate_pending } {
+                    unsafe {
+                        rtc::STATE.locate_pending = false;
+                    }
+                    awake_limit::paused(|| locate(board.led()))?;
+                }
+
+                let reading = board.probe_samples();
+
+                let time = slow_clock_seconds();
+                recorder::current().time = time;
+
+                let mut samples = match reading {
+                    Ok(samples) => samples,
+                    Err(e) => return Err(e.context("error measuring").into()),
+                };
+                recorder::current().set_samples(&samples);
+                let sampled = sampling::filter(&mut samples);
+                let value = sampled.value;
+                recorder::current().value = Some(value);
+                spread::record(time, sampled.variance);
+                if let Some(diagnostic) = diagnostics::current() {
+                    diagnostic.time = time;
+                    diagnostic.sample_variance = Some(sampled.variance);
+                }
+                let battery_mv = match board.read_battery() {
+                    Ok(battery_mv) => battery_mv,
+                    Err(e) => {
+                        println!("error reading battery voltage: {}", e);
+                        None
+                    }
+                };
+                match board.read_reference() {
+                    Ok(Some(reading_mv)) => self_test::record(reading_mv),
+                    Ok(None) => {}
+                    Err(e) => println!("error reading ADC reference: {}", e),
+                }
+                let maintenance = maintenance::is_active(time);
+                // Readings while the probe is handled during maintenance would look like waterings.
+                if !maintenance {
+                    let detector = unsafe { &mut rtc::STATE.watering };
+                    let event = detector.update(&watering::Thresholds::default(), time, value);
+                    if let Some(event) = event {
+                        detections::record(time, event);
+                    }
+                }
+                println!("recorded value: {} at {}", value, time);
+                if let Some(battery_mv) = battery_mv {
+                    println!("battery voltage: {} mV", battery_mv);
+                }
+
+                record_measurement(
+                    Measurement::new(value, time, wake_cause, maintenance, watered)
+                        .with_battery(battery_mv),
+                );
+                safe_mode::measurement_stored();
+                let channels = probes::channels(&probes::load_valid(&nvs_partition)?);
+                for &channel in &channels[1..] {
+                    match board.sample_probe_at(channel) {
+                        Ok(sampled) => {
+                            println!("recorded value: {} on channel {}", sampled.value, channel);
+                            record_measurement(
+                                Measurement::new(
+                                    sampled.value,
+                                    time,
+                                    wake_cause,
+                                    maintenance,
+                                    watered,
+                                )
+                                .with_channel(channel),
+                            );
+                        }
+                        Err(e) => println!("error measuring channel {}: {}", channel, e),
+                    }
+                }
+                if let Err(e) = spill::update(&nvs_partition) {
+                    println!("error spilling measurements: {}", e);
+                }
+                if let Some(channel) = adc_trace::requested(&nvs_partition)? {
+                    match board.trace_probe_at(channel, adc_trace::SAMPLES) {
+                        Ok(samples) => {
+                            if let Err(e) =
+                                adc_trace::store(&nvs_partition, time, channel, &samples)
+                            {
+                                println!("error storing trace: {}", e);
+                            }
+                        }
+                        Err(e) => println!("error tracing channel {}: {:#}", channel, e),
+                    }
+                }
+                if watered {
+                    record_watered(time);
+                }
+                let profile = profile::Settings::load(&nvs_partition)?;
+                let policy = profile.alert_policy(alert::Policy::load(&nvs_partition)?);
+                alert::evaluate(&policy, value, time, maintenance);
+                let plan = scheduler::Plan {
+                    bands: profile.bands(schedule::load(&nvs_partition)?),
+                    // The seedling profile keeps its fixed interval.
+                    adaptive: match profile.active {
+                        profile::Profile::Standard => adaptive::Settings::load(&nvs_partition)?,
+                        profile::Profile::Seedling => adaptive::Settings::default(),
+                    },
+                    default: DeviceConfig::load(&nvs_partition)?.measurement_interval(),
+                    seed: fleet::jitter_seed()?,
+                };
+                let interval = unsafe { rtc::STATE.scheduler.sampled(&plan, value, &RtcClock) };
+                println!("next cycle in {} s", interval.as_secs());
+
+                Phase::Decide
+            }
+            Phase::Decide => {
+                let now = slow_clock_seconds();
+                // Each probe records a measurement per cycle.
+                let channels = probes::channels(&probes::load_valid(&nvs_partition)?);
+                let min_recorded = MIN_RECORDED_MEASUREMENTS * channels.len();
+                let provisioning = provisioning_pending();
+                let skip = if !provisioning && dry_run::is_enabled(&nvs_partition)? {
+                    Some(SkipReason::DryRun)
+                } else {
+                    let plan = scheduler::UploadPlan {
+                        window: upload_window::Window::load(&nvs_partition)?,
+                        limits: Limits::load(&nvs_partition)?,
+                    };
+                    let urgent = provisioning || alert::pending().is_some();
+                    let buffering = unsafe { rtc::LOGS.measurements.len() } < min_recorded;
+                    let deferral = unsafe {
+                        rtc::STATE
+                            .scheduler
+                            .defers_upload(&plan, urgent, buffering, &RtcClock)
+                    };
+                    deferral.map(|deferral| match deferral {
+                        Deferral::Window => SkipReason::Window,
+                        Deferral::Buffering => SkipReason::Buffering,
+                        Deferral::RateLimit => SkipReason::RateLimit,
+                        Deferral::Backoff => SkipReason::Backoff,
+                    })
+                };
+
+                match skip {
+                    Some(reason) => {
+                        skips::record(now, reason, wake_cause);
+                        if reason == SkipReason::DryRun {
+                            // Time isn't synced without network, timestamps are slow clock seconds.
+                            let device = DeviceConfig::load(&nvs_partition)?;
+                            upload(
+                                &nvs_partition,
+                                &mut transport::DryRun { device },
+                                unsafe { rtc::STATE.time_offset }.unwrap_or(0),
+                                &MeasurementBatch::default(),
+                            )?;
+                        }
+                        Phase::Sleep
+                    }
+                    None => Phase::Connect,
+                }
+            }
+            Phase::Connect => {
+                let modem = board.take_modem()?;
+                let sync_time = unsafe { rtc::STATE.time_offset }.is_none();
+                let connect_started = Instant::now();
+                let (wifi, time_sync) = connect_wifi(modem, nvs_partition.clone(), sync_time)?;
+                if let Some(diagnostic) = diagnostics::current() {
+                    diagnostic.connect_ms = Some(connect_started.elapsed().as_millis() as _);
+                }
+                _wifi = Some(wifi);
+                sntp = time_sync;
+                session = Some(Session::new(SESSION_BUDGET));
+
+                if sync_time {
+                    Phase::Sync
+                } else if polled && !safe {
+                    Phase::Upload
+                } else {
+                    Phase::ConfigPoll
                 }
             }
+            Phase::Sync => {
+                // Connecting to the server doesn't depend on the time, only the timestamps do.
+                let device = DeviceConfig::load(&nvs_partition)?;
+                let connecting = thread::Builder::new()
+                    .stack_size(HTTP_CONNECT_STACK_SIZE)
+                    .spawn(move || connect_http(&device))?;
+
+                let session = session.as_ref().context("not connected")?;
+                let sntp = sntp.as_ref().context("time sync not started")?;
+                #[cfg(feature = "peer-time")]
+                let peer = peer_time::Listener::start()?;
+                let manual_offset = manual_time::offset();
+                // Time received from another sensor is preferred over the manually set time.
+                let fallback_time = || {
+                    #[cfg(feature = "peer-time")]
+                    if let Some(offset) = peer.offset() {
+                        return Some((offset, TimeSource::Peer));
+                    }
+                    manual_offset.map(|offset| (offset, TimeSource::Manual))
+                };
+                let timeout = Timeouts::load(&nvs_partition)?.sntp();
+                let started = Instant::now();
+                let fallback = loop {
+                    if sntp.get_sync_status() == sntp::SyncStatus::Completed {
+                        connection::time_synced();
+                        break None;
+                    }
+                    let waited = started.elapsed() >= FALLBACK_TIME_SYNC_WAIT;
+                    let timed_out = session.remaining().is_zero() || started.elapsed() >= timeout;
+                    let fallback = fallback_time();
+                    if timed_out || (fallback.is_some() && waited) {
+                        if fallback.is_some() {
+                            break fallback;
+                        }
+                        let context = "time sync didn't complete in time".into();
+                        return Err(FirmwareError::Time { context }.into());
+                    }
+                    FreeRtos::delay_ms(100);
+                };
+
+                match connecting.join() {
+                    Ok(Ok(client)) => http_client = Some(client),
+                    Ok(Err(e)) => println!("error connecting to server: {}", e),
+                    Err(_) => println!("error connecting to server: thread panicked"),
+                }
+
+                let (time_offset, time_source) = match fallback {
+                    Some((offset, source)) => {
+                        println!("time sync didn't complete, using {} time.", source.name());
+                        (offset, source)
+                    }
+                    None => {
+                        println!("time synced.");
+                        let offset = Utc::now().timestamp() - slow_clock_seconds() as i64;
+                        (offset, TimeSource::Sntp)
+                    }
+                };
+                unsafe {
+                    rtc::STATE.time_offset = Some(time_offset);
+                    rtc::STATE.time_source = time_source;
+                    rtc::STATE.synced_offset = Some(time_offset);
+                }
+
+                Phase::ConfigPoll
+            }
+            Phase::Upload => {
+                let session = session.as_ref().context("not connected")?;
+                let time_offset = unsafe { rtc::STATE.time_offset }.context("time not synced")?;
+                let latest = unsafe { rtc::LOGS.measurements.back() }.map(|m| m.value);
+                if let Some(metrics) = connection::metrics() {
+                    session.queue(format_connection(
+                        &metrics,
+                        &tags::load(&nvs_partition)?,
+                        &DeviceConfig::load(&nvs_partition)?.line_prefix,
+                        time_offset,
+                    ));
+                }
+                session.queue(format_uptime(
+                    &tags::load(&nvs_partition)?,
+                    &DeviceConfig::load(&nvs_partition)?.line_prefix,
+                    time_offset,
+                ));
+                let queued = session.take_queued();
+                retry::run(session, "upload", || {
+                    let mut transport = transport::configured(&nvs_partition, http_client.take())?;
+                    uptime::count_upload_attempt();
+                    Ok(upload(
+                        &nvs_partition,
+                        transport.as_mut(),
+                        time_offset,
+                        &queued,
+                    )?)
+                })?;
+                uptime::count_upload_success();
+                retry::record_success();
+                if let (Some(target), Some(value)) = (statsd::load(&nvs_partition)?, latest) {
+                    send_gauge(&target, &coarse::Settings::load(&nvs_partition)?, value);
+                }
+                check_for_update(session, &nvs_partition)?;
+
+                Phase::Sleep
+            }
+            Phase::ConfigPoll => {
+                let session = session.as_ref().context("not connected")?;
+                if let Some(command_url) = COMMAND_URL {
+                    let authorization = DeviceConfig::load(&nvs_partition)?.authorization;
+                    let polled = session.run("command poll", || {
+                        command::poll(command_url, &authorization)
+                    });
+                    match polled {
+                        Ok(commands) => {
+                            if let Err(e) = apply_remote_commands(session, &nvs_partition, commands)
+                            {
+                                println!("error applying remote commands: {}", e);
+                            }
+                        }
+                        Err(e) => println!("error polling commands: {}", e),
+                    }
+                }
+                if let Some(shadow_url) = SHADOW_URL {
+                    if let Err(e) = sync_shadow(session, &nvs_partition, shadow_url) {
+                        println!("error syncing shadow: {:#}", e);
+                    }
+                }
+                if alert::is_active() {
+                    if let Err(e) = session.run("ntfy poll", || poll_ntfy(&nvs_partition)) {
+                        println!("error polling ntfy: {:#}", e);
+                    }
+                }
+                if let Some(alert) = alert::pending() {
+                    match session.run("notify", || notify(&nvs_partition, &alert)) {
+                        Ok(()) => alert::mark_notified(),
+                        Err(e) => println!("error notifying alert: {:#}", e),
+                    }
+                }
+
+                if safe {
+                    check_for_update(session, &nvs_partition)?;
+                    Phase::Sleep
+                } else {
+                    Phase::Upload
+                }
+            }
+            Phase::Sleep => break,
+        };
+    }
+
+    // The modem is only taken when connecting, so a cycle that doesn't connect leaves the radio,
+    // the event loop and the network interfaces uninitialized. NVS is still used for settings.
+    if _wifi.is_none() && reset_reason == reset::ResetReason::DeepSleep {
+        let awake = Duration::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as _);
+        println!("measured without connecting in {} ms", awake.as_millis());
+        if awake > OFFLINE_CYCLE_TARGET {
+            println!(
+                "exceeded the target of {} ms for cycles without connecting",
+                OFFLINE_CYCLE_TARGET.as_millis()
+            );
         }
-        Err(e) => {
-            bail!("error measuring: {}", e);
+    }
+
+    // Powered sensors stay connected, there's no association to spread.
+    #[cfg(not(feature = "powered"))]
+    {
+        let size = fleet::load(&nvs_partition)?;
+        if let Some(slot) = fleet::own_slot(size)? {
+            unsafe {
+                rtc::STATE
+                    .scheduler
+                    .align(&RtcClock, slot, size, MEASUREMENT_INTERVAL);
+            }
+        }
+    }
+
+    // The sensors are sampled while serving, so safe mode goes to sleep instead.
+    #[cfg(feature = "powered")]
+    if !safe {
+        awake_limit::disarm();
+        serve_powered(board, &nvs_partition, _wifi)?;
+    }
+
+    Ok(())
+}
+
+/// Checks for a firmware update, unless updates are switched off.
+fn check_for_update(session: &Session, nvs_partition: &nvs::EspDefaultNvsPartition) -> Result<()> {
+    let ota_enabled = Components::load(nvs_partition)?.ota;
+    if let Some(manifest_url) = OTA_MANIFEST_URL.filter(|_| ota_enabled) {
+        let authorization = DeviceConfig::load(nvs_partition)?.authorization;
+        let checked = session.run("update check", || {
+            ota::check(nvs_partition, manifest_url, &authorization)
+        });
+        if let Err(e) = checked {
+            println!("error checking for updates: {:#}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Buffers `measurement` for upload, dropping the oldest one if the buffer is full.
+fn record_measurement(measurement: Measurement) {
+    unsafe {
+        let overwritten = rtc::LOGS.measurements.overwriting_push_back(measurement);
+        if overwritten.is_some() {
+            rtc::STATE.overwritten_measurements += 1;
+        }
+    }
+}
+
+/// Acts on the plant having been watered at slow clock time `time`, as recorded with the button.
+/// The dryness alert is dropped and snoozed, as it takes a while for the water to reach the probe.
+fn record_watered(time: u32) {
+    println!("watered at {}", time);
+    alert::snooze(time, command::DEFAULT_SNOOZE_DURATION);
+}
+
+/// Requests provisioning via the access point, which starts when WiFi is started next.
+fn request_access_point() {
+    #[cfg(feature = "softap")]
+    provisioning::request(provisioning::Method::SoftAp);
+    #[cfg(not(feature = "softap"))]
+    println!("provisioning via access point not enabled, ignoring long press");
+}
+
+/// Returns the gesture being entered with the button, if it's pressed.
+fn read_gesture(board: &mut Board) -> Option<button::Gesture> {
+    let mut detector = button::Detector::new();
+    loop {
+        let gesture = detector.update(board.is_button_pressed(), Instant::now());
+        if gesture.is_some() || (detector.is_idle() && !board.is_button_settling()) {
+            return gesture;
         }
+        FreeRtos::delay_ms(button::POLL_INTERVAL.as_millis() as u32);
+    }
+}
+
+/// Keeps the sensor online in powered mode until the next cycle is due, answering read requests
+/// and serving the ESPHome API if enabled.
+#[cfg(feature = "powered")]
+fn serve_powered(
+    board: &mut Board,
+    nvs_partition: &nvs::EspDefaultNvsPartition,
+    wifi: Option<EspWifi<'static>>,
+) -> Result<()> {
+    let _wifi = match wifi {
+        Some(wifi) => wifi,
+        None => connect_wifi(board.take_modem()?, nvs_partition.clone(), false)?.0,
     };
 
-    let sysloop = eventloop::EspSystemEventLoop::take()?;
-    let nvs_partition = nvs::EspDefaultNvsPartition::take()?;
+    let mut value = board.read_probe()?;
+    let calibration = calibration::load(nvs_partition)?.get(probes::PRIMARY_CHANNEL);
+    let metrics = Arc::new(Mutex::new(metrics::Metrics {
+        moisture: Some(value),
+        moisture_percent: calibration.map(|calibration| calibration.percent(value)),
+        queue_depth: unsafe { rtc::LOGS.measurements.len() },
+        probes_connected: 1,
+        health: health::load(nvs_partition)?,
+        ..Default::default()
+    }));
 
-    let mut esp_wifi = EspWifi::new(peripherals.modem, sysloop.clone(), Some(nvs_partition))?;
-    esp_wifi.set_configuration(&embedded_svc::wifi::Configuration::Client(
-        embedded_svc::wifi::ClientConfiguration {
-            ssid: WIFI_SSID.into(),
-            password: WIFI_PASSWORD.into(),
-            channel: None,
-            ..Default::default()
-        },
-    ))?;
+    let verifier = local_auth::Verifier::load(nvs_partition)?;
+    let (read_tx, read_rx) = channel();
+    let mut http_server = measure_endpoint::start(verifier.clone(), read_tx)?;
+    metrics::register(&mut http_server, metrics.clone(), verifier.clone())?;
+    let live = live::Live::register(&mut http_server, verifier.clone())?;
+    web_ui::register(
+        &mut http_server,
+        nvs_partition.clone(),
+        unsafe { rtc::LOGS.measurements.iter().cloned().collect() },
+        verifier,
+    )?;
 
-    let (wifi_started_tx, wifi_started_rx) = channel();
-    let (wifi_connected_tx, wifi_connected_rx) = channel();
-    let _wifi_subscription = sysloop.subscribe(move |event: &WifiEvent| match event {
-        WifiEvent::StaStarted => {
-            let _ = wifi_started_tx.send(());
+    #[cfg(feature = "esphome")]
+    let (name, mac_address) = esphome_identity()?;
+    #[cfg(feature = "esphome")]
+    let device = esphome::Device {
+        name: &name,
+        mac_address: &mac_address,
+        version: BuildInfo::current().version,
+    };
+    #[cfg(feature = "esphome")]
+    let mut esphome_server = esphome::Server::start(&device)?;
+
+    let awake = Duration::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as _);
+    let interval = schedule::next_interval(MEASUREMENT_INTERVAL);
+    let until = Instant::now() + interval.saturating_sub(awake);
+    let mut statsd = statsd::load(nvs_partition)?;
+    let mut coarse = coarse::Settings::load(nvs_partition)?;
+    // Imports via the web UI apply to the gauges right away.
+    let reload = Arc::new(AtomicBool::new(false));
+    for namespace in [statsd::NVS_NAMESPACE, coarse::NVS_NAMESPACE] {
+        let reload = reload.clone();
+        storage::subscribe(namespace, move |_| reload.store(true, Ordering::Relaxed));
+    }
+    let mut read_at = Instant::now();
+    let wake_cause = wake::current();
+    let mut detector = button::Detector::new();
+    let mut presence =
+        hotplug::Presence::new(probes::available_channels(), probes::PRIMARY_CHANNEL);
+    #[cfg(feature = "peer-time")]
+    let mut broadcaster = peer_time::Broadcaster::start()?;
+    while Instant::now() < until {
+        let mut changed = false;
+        match detector.update(board.is_button_pressed(), Instant::now()) {
+            Some(button::Gesture::Press) => {
+                maintenance::start(slow_clock_seconds(), command::DEFAULT_MAINTENANCE_DURATION)
+            }
+            Some(button::Gesture::DoublePress) => {
+                value = board.read_probe()?;
+                read_at = Instant::now();
+                changed = true;
+                let time = slow_clock_seconds();
+                record_measurement(Measurement::new(
+                    value,
+                    time,
+                    wake_cause,
+                    maintenance::is_active(time),
+                    true,
+                ));
+                record_watered(time);
+            }
+            Some(button::Gesture::LongPress) => request_access_point(),
+            None => {}
         }
-        WifiEvent::StaConnected => {
-            let _ = wifi_connected_tx.send(Ok(()));
+        let read_interval = if live.has_clients() {
+            LIVE_READ_INTERVAL
+        } else {
+            POWERED_READ_INTERVAL
+        };
+        let mut events = Vec::new();
+        if read_at.elapsed() >= read_interval {
+            read_at = Instant::now();
+            match board.read_probe() {
+                Ok(new_value) => {
+                    value = new_value;
+                    changed = true;
+                    events.extend(presence.update(probes::PRIMARY_CHANNEL, true));
+                }
+                Err(e) if hotplug::is_open(&e) => {
+                    events.extend(presence.update(probes::PRIMARY_CHANNEL, false));
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
-        WifiEvent::StaDisconnected => {
-            let _ = wifi_connected_tx.send(Err(anyhow!("WiFi disconnected")));
+        if presence.scan_due(Instant::now()) {
+            for &channel in probes::available_channels() {
+                if channel == probes::PRIMARY_CHANNEL {
+                    continue;
+                }
+                let connected = match board.sample_probe_at(channel) {
+                    Ok(_) => true,
+                    Err(e) if hotplug::is_open(&e) => false,
+                    Err(e) => {
+                        println!("error scanning channel {}: {}", channel, e);
+                        continue;
+                    }
+                };
+                events.extend(presence.update(channel, connected));
+            }
+        }
+        if !events.is_empty() {
+            for event in &events {
+                println!("{}", event);
+            }
+            let mut metrics = metrics.lock().unwrap();
+            metrics.probes_connected = presence.connected();
+            if events.contains(&hotplug::Event::Removed(probes::PRIMARY_CHANNEL)) {
+                metrics.moisture = None;
+                metrics.moisture_percent = None;
+            }
+        }
+        while let Ok(reply) = read_rx.try_recv() {
+            let reading = board.read_probe();
+            if let Ok(new_value) = reading {
+                value = new_value;
+                read_at = Instant::now();
+                changed = true;
+            }
+            let _ = reply.send(reading.map_err(Into::into));
+        }
+        if reload.swap(false, Ordering::Relaxed) {
+            statsd = statsd::load(nvs_partition)?;
+            coarse = coarse::Settings::load(nvs_partition)?;
+        }
+        if changed {
+            {
+                let mut metrics = metrics.lock().unwrap();
+                metrics.moisture = Some(value);
+                metrics.moisture_percent = calibration.map(|c| c.percent(value));
+            }
+            live.send(value);
+            if let Some(target) = &statsd {
+                send_gauge(target, &coarse, value);
+            }
         }
-        _ => {}
-    })?;
 
-    let (ip_assigned_tx, ip_assigned_rx) = channel();
-    let _netif_subscription = sysloop.subscribe(move |event: &IpEvent| match event {
-        IpEvent::DhcpIpAssigned(_) | IpEvent::DhcpIp6Assigned(_) => {
-            let _ = ip_assigned_tx.send(());
+        #[cfg(feature = "esphome")]
+        esphome_server.poll(&[f32::from(value)], changed)?;
+        #[cfg(feature = "peer-time")]
+        if let Err(e) = broadcaster.poll() {
+            println!("error broadcasting time: {}", e);
         }
-        _ => {}
+        FreeRtos::delay_ms(100);
+    }
+
+    Ok(())
+}
+
+/// Name and MAC address the sensor is known by in Home Assistant.
+#[cfg(feature = "esphome")]
+fn esphome_identity() -> Result<(String, String)> {
+    let mut mac = [0; 6];
+    esp_idf_sys::esp!(unsafe {
+        esp_idf_sys::esp_wifi_get_mac(esp_idf_sys::wifi_interface_t_WIFI_IF_STA, mac.as_mut_ptr())
     })?;
+    let mac: Vec<_> = mac.iter().map(|b| format!("{:02X}", b)).collect();
+    let name = format!("soil-moisture-{}", mac[3..].concat().to_lowercase());
+    Ok((name, mac.join(":")))
+}
+
+/// Connects to the WiFi network. If `sync_time` is set, time synchronization is started as soon as
+/// the network interface exists, so that it proceeds in the background while connecting.
+fn connect_wifi(
+    modem: modem::Modem,
+    nvs_partition: nvs::EspDefaultNvsPartition,
+    sync_time: bool,
+) -> Result<(EspWifi<'static>, Option<sntp::EspSntp>), FirmwareError> {
+    let sysloop = eventloop::EspSystemEventLoop::take().wifi(ErrorCode::WifiConnect)?;
 
-    esp_wifi.start()?;
+    let (ssid, password) = wifi_credentials::load(&nvs_partition).storage()?;
+    let timeouts = Timeouts::load(&nvs_partition).storage()?;
+    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), Some(nvs_partition.clone()))
+        .wifi(ErrorCode::WifiConnect)?;
+    esp_wifi
+        .set_configuration(&client_configuration(&ssid, &password))
+        .wifi(ErrorCode::WifiConnect)?;
+    let mac_mode = wifi_mac::load(&nvs_partition).storage()?;
+    wifi_mac::apply(mac_mode).wifi(ErrorCode::WifiConnect)?;
 
-    wifi_started_rx.recv()?;
+    let (wifi_started_tx, wifi_started_rx) = channel();
+    let (wifi_connected_tx, wifi_connected_rx) = channel();
+    let _wifi_subscription = sysloop
+        .subscribe(move |event: &WifiEvent| match event {
+            WifiEvent::StaStarted => {
+                let _ = wifi_started_tx.send(());
+            }
+            WifiEvent::StaConnected => {
+                let _ = wifi_connected_tx.send(Ok(()));
+            }
+            WifiEvent::StaDisconnected => {
+                let _ = wifi_connected_tx.send(Err(anyhow!("WiFi disconnected")));
+            }
+            _ => {}
+        })
+        .wifi(ErrorCode::WifiConnect)?;
+
+    let (ip_assigned_tx, ip_assigned_rx) = channel();
+    let _netif_subscription = sysloop
+        .subscribe(move |event: &IpEvent| match event {
+            IpEvent::DhcpIpAssigned(_) | IpEvent::DhcpIp6Assigned(_) => {
+                let _ = ip_assigned_tx.send(());
+            }
+            _ => {}
+        })
+        .wifi(ErrorCode::WifiConnect)?;
+
+    connection::start();
+    esp_wifi.start().wifi(ErrorCode::WifiConnect)?;
+    let sntp = if sync_time {
+        Some(sntp::EspSntp::new_default().time()?)
+    } else {
+        None
+    };
+
+    wait_for(&wifi_started_rx, timeouts.wifi_start(), "WiFi start")
+        .wifi(ErrorCode::WifiStartTimeout)?;
+    #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+    if let Some(method) = provisioning::pending() {
+        let (ssid, password) = awake_limit::paused(|| {
+            provisioning::receive(method, &mut esp_wifi, &nvs_partition, PROVISIONING_TIMEOUT)
+        })
+        .wifi(ErrorCode::WifiConnect)?;
+        wifi_credentials::save(&nvs_partition, &ssid, &password).storage()?;
+        esp_wifi
+            .set_configuration(&client_configuration(&ssid, &password))
+            .wifi(ErrorCode::WifiConnect)?;
+    }
     println!("connecting WiFi...");
-    esp_wifi.connect()?;
+    esp_wifi.connect().wifi(ErrorCode::WifiConnect)?;
 
-    wifi_connected_rx.recv()??;
+    wait_for(&wifi_connected_rx, timeouts.connect(), "WiFi connection")
+        .wifi(ErrorCode::ConnectTimeout)?
+        .wifi(ErrorCode::WifiConnect)?;
     println!("WiFi connected.");
 
-    ip_assigned_rx.recv()?;
-    println!("IP address obtained, syncing time....");
+    let mut ap_info = esp_idf_sys::wifi_ap_record_t::default();
+    let rssi = esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) })
+        .ok()
+        .map(|()| ap_info.rssi);
+    recorder::current().rssi = rssi;
+    connection::associated(rssi);
 
-    let sntp = sntp::EspSntp::new_default()?;
-    while sntp.get_sync_status() != sntp::SyncStatus::Completed {
-        FreeRtos::delay_ms(100);
+    wait_for(&ip_assigned_rx, timeouts.dhcp(), "DHCP").wifi(ErrorCode::DhcpTimeout)?;
+    connection::ip_assigned();
+    println!("IP address obtained.");
+
+    Ok((esp_wifi, sntp))
+}
+
+/// Waits for the event of a stage of connecting for at most `timeout`.
+fn wait_for<T>(receiver: &Receiver<T>, timeout: Duration, stage: &str) -> Result<T> {
+    receiver
+        .recv_timeout(timeout)
+        .map_err(|_| anyhow!("{} timed out after {} s", stage, timeout.as_secs()))
+}
+
+fn client_configuration(ssid: &str, password: &str) -> embedded_svc::wifi::Configuration {
+    embedded_svc::wifi::Configuration::Client(embedded_svc::wifi::ClientConfiguration {
+        ssid: ssid.into(),
+        password: password.into(),
+        channel: None,
+        ..Default::default()
+    })
+}
+
+/// Whether WiFi provisioning has been requested, which needs WiFi to be started in this cycle.
+fn provisioning_pending() -> bool {
+    #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+    return provisioning::pending().is_some();
+    #[cfg(not(any(feature = "smartconfig", feature = "dpp", feature = "softap")))]
+    return false;
+}
+
+/// Notifies `alert` via the configured channels, naming the plant by its `plant` tag.
+fn notify(nvs_partition: &nvs::EspDefaultNvsPartition, alert: &alert::Alert) -> Result<()> {
+    let channels = notifier::Channels::load(nvs_partition)?;
+    if channels.is_empty() {
+        println!("no notification channels configured, dropping alert");
+        return Ok(());
+    }
+    let tags = tags::load(nvs_partition)?;
+    let plant = tags
+        .iter()
+        .find(|(key, _)| key == "plant")
+        .map_or("Your plant", |(_, value)| value.as_str());
+    channels.send(&alert.message(plant), alert.severity)
+}
+
+/// Applies snoozes requested with the button of ntfy notifications. Other commands aren't accepted
+/// from ntfy, as anyone who knows the topic can publish to it.
+fn poll_ntfy(nvs_partition: &nvs::EspDefaultNvsPartition) -> Result<()> {
+    let channels = notifier::Channels::load(nvs_partition)?;
+    if channels.ntfy_url.is_empty() {
+        return Ok(());
+    }
+    let time_offset = unsafe { rtc::STATE.time_offset }.context("time not synced")?;
+    let now = slow_clock_seconds() as i64 + time_offset;
+    for message in channels.poll_ntfy(nvs_partition, now)? {
+        match message.parse() {
+            Ok(Command::Snooze(duration)) => alert::snooze(slow_clock_seconds(), duration),
+            _ => println!("ignoring ntfy command: {}", message),
+        }
+    }
+    Ok(())
+}
+
+/// Sends the reading `value` as StatsD gauge, or its band as 0 for wet, 1 for ok and 2 for dry if
+/// StatsD is a coarse sink. Errors are only logged, as the gauge is sent in addition to the upload.
+fn send_gauge(target: &statsd::Target, coarse: &coarse::Settings, value: u16) {
+    let gauge = if coarse.is_coarse(coarse::Sink::Statsd) {
+        ("moisture_band", f64::from(coarse.band(value) as u8))
+    } else {
+        ("moisture", value.into())
+    };
+    if let Err(e) = target.send(&[gauge]) {
+        println!("error sending StatsD gauge: {}", e);
+    }
+}
+
+/// Uploads the buffered measurements in chunks. The measurements of each acknowledged chunk are
+/// removed from the buffer in RTC memory, so an interrupted upload continues with the first chunk
+/// that wasn't acknowledged. `queued` points of other tasks are sent with the last chunk, which is
+/// sent even without measurements.
+fn upload(
+    nvs_partition: &nvs::EspDefaultNvsPartition,
+    transport: &mut dyn Transport,
+    time_offset: i64,
+    queued: &MeasurementBatch,
+) -> Result<(), FirmwareError> {
+    let device = DeviceConfig::load(nvs_partition).storage()?;
+    let mut tags = tags::load(nvs_partition).storage()?;
+    if let Some(medium) = soil::load(nvs_partition).storage()? {
+        if !tags.iter().any(|(key, _)| key == "soil") {
+            tags.push(("soil".into(), medium.name().into()));
+        }
+    }
+    let build_info = BuildInfo::current();
+    let failure = error_code::pending();
+    let events = error_code::events();
+    let health = health::load(nvs_partition).storage()?;
+    let not_ready = unsafe { rtc::STATE.not_ready };
+    let skips = skips::pending();
+    let detections = detections::pending();
+    let sampling = spread::pending();
+    let settling_timeout = settling::pending();
+    let crash = coredump::pending();
+    let trace_chunks = adc_trace::pending(nvs_partition).storage()?;
+    let diagnostics = if Components::load(nvs_partition).storage()?.diagnostics {
+        diagnostics::pending()
+    } else {
+        Vec::new()
+    };
+    let probes = probes::load_valid(nvs_partition).sensor(ErrorCode::ProbeConfig)?;
+    let calibrations = calibration::load(nvs_partition).storage()?;
+    let report_build = !build_info.is_reported(nvs_partition).storage()?;
+    let batch_size = usize::from(batch::load(nvs_partition).storage()?);
+
+    // Chunks are formatted from the buffer in place, as a copy of it doesn't fit in RAM when full.
+    let depth = unsafe { rtc::LOGS.measurements.len() };
+    let chunk_count = depth.div_ceil(batch_size).max(1);
+    let queue_stats = QueueStats {
+        depth,
+        oldest_age: unsafe { rtc::LOGS.measurements.front() }
+            .map_or(0, |m| slow_clock_seconds().saturating_sub(m.time)),
+        overwritten: unsafe { rtc::STATE.overwritten_measurements },
+        chunks: chunk_count as _,
+        time_source: unsafe { rtc::STATE.time_source },
+    };
+
+    let labels = Labels {
+        probes: &probes,
+        calibrations: &calibrations,
+        tags: &tags,
+        line_prefix: &device.line_prefix,
+        time_offset,
+    };
+    let report = Report {
+        queue_stats: Some(&queue_stats),
+        build_info: report_build.then_some(&build_info),
+        failure: failure.as_ref(),
+        events: &events,
+        health: Some(&health),
+        not_ready,
+        skips: &skips,
+        detections: &detections,
+        sampling: &sampling,
+        settling_timeout: settling_timeout.as_ref(),
+        crash: crash.as_ref(),
+        diagnostics: &diagnostics,
+    };
+
+    let restored = spill::restored(nvs_partition).storage()?;
+    for segment in &restored {
+        let mut sequence = Sequence::default();
+        let segment_labels = Labels {
+            time_offset: segment.offset,
+            ..labels
+        };
+        for chunk in segment.measurements.chunks(batch_size) {
+            let data = format_measurements(chunk, &mut sequence, &segment_labels);
+            transport.send(&data)?;
+        }
+    }
+    if !restored.is_empty() && transport.acknowledges() {
+        println!("uploaded spilled measurements.");
+        spill::clear_restored(nvs_partition).storage()?;
+    }
+
+    let mut sequence = Sequence::default();
+    // Sent measurements stay in the buffer if the transport doesn't acknowledge them.
+    let mut unacknowledged = 0;
+    for i in 0..chunk_count {
+        let last = i + 1 == chunk_count;
+        let chunk = unsafe { rtc::LOGS.measurements.iter().skip(unacknowledged) };
+        let chunk_len = batch_size.min(depth - i * batch_size);
+        let mut data = format_measurements(chunk.take(chunk_len), &mut sequence, &labels);
+        if last {
+            data.append(&mut format_report(&report, &labels));
+            data.append(&mut format_trace(
+                &trace_chunks,
+                &tags,
+                &device.line_prefix,
+                time_offset,
+            ));
+            data.points.extend_from_slice(&queued.points);
+        }
+        transport.send(&data)?;
+        if !transport.acknowledges() {
+            unacknowledged += chunk_len;
+            continue;
+        }
+        println!("successfully sent chunk {}/{}.", i + 1, chunk_count);
+
+        unsafe {
+            rtc::LOGS
+                .measurements
+                .drain()
+                .take(chunk_len)
+                .for_each(drop)
+        };
+        integrity::verify();
+        rtc::commit();
     }
-    println!("time synced, sending data..");
 
-    let time_offset = Utc::now().timestamp() - slow_clock_seconds() as i64;
+    if !transport.acknowledges() {
+        return Ok(());
+    }
 
-    let measurements: Vec<_> = unsafe { MEASUREMENTS.iter().cloned().collect() };
-    send_values(measurements.as_slice(), time_offset)?;
-    println!("successfully sent data.");
+    if report_build {
+        build_info.mark_reported(nvs_partition).storage()?;
+    }
+    error_code::clear();
+    skips::clear();
+    detections::clear();
+    unsafe {
+        rtc::STATE.not_ready = 0;
+    }
+    spread::clear();
+    settling::clear();
+    diagnostics::clear();
+    if crash.is_some() {
+        coredump::clear().storage()?;
+    }
+    if !trace_chunks.is_empty() {
+        adc_trace::mark_uploaded(nvs_partition).storage()?;
+    }
+    if health.restarted_unexpectedly() {
+        health::mark_reported(nvs_partition).storage()?;
+    }
+    spill::clear(nvs_partition).storage()?;
 
     unsafe {
-        MEASUREMENTS = ArrDeque::new();
+        rtc::STATE.overwritten_measurements = 0;
+    }
+
+    Ok(())
+}
+
+/// Applies a command received via the console or remotely after an upload. Commands only change
+/// persisted state, so they behave the same regardless of where they came from.
+fn apply_command(command: Command, nvs_partition: &nvs::EspDefaultNvsPartition) {
+    println!("received command: {:?}", command);
+    let result = match command {
+        Command::Locate => {
+            unsafe {
+                rtc::STATE.locate_pending = true;
+            }
+            Ok(())
+        }
+        Command::Maintenance(duration) if duration.is_zero() => {
+            maintenance::stop();
+            Ok(())
+        }
+        Command::Maintenance(duration) => {
+            maintenance::start(slow_clock_seconds(), duration);
+            Ok(())
+        }
+        Command::Snooze(duration) => {
+            alert::snooze(slow_clock_seconds(), duration);
+            Ok(())
+        }
+        Command::SetProfile(profile) => profile::set_active(nvs_partition, profile),
+        Command::SetSoil(medium) => soil::save(nvs_partition, medium),
+        Command::SetTag(key, value) => tags::set(nvs_partition, &key, &value),
+        Command::RemoveTag(key) => tags::remove(nvs_partition, &key),
+        Command::SetRecording(enabled) => recorder::set_enabled(nvs_partition, enabled),
+        Command::DumpRecording => {
+            recorder::load(nvs_partition).map(|records| recorder::dump(&records))
+        }
+        Command::DumpSpill => spill::dump(nvs_partition),
+        Command::CaptureTrace(channel) => adc_trace::request(nvs_partition, channel),
+        Command::SetDryRun(enabled) => dry_run::set_enabled(nvs_partition, enabled),
+        Command::SetRateLimit(min_interval, max_per_day) => Limits {
+            min_interval,
+            max_per_day,
+        }
+        .save(nvs_partition),
+        #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+        Command::Provision(method) => {
+            provisioning::request(method);
+            Ok(())
+        }
+        Command::SetTime(seconds) => manual_time::set(seconds, slow_clock_seconds()),
+        Command::SetMacMode(mode) => wifi_mac::save(nvs_partition, mode),
+        #[cfg(feature = "powered")]
+        Command::SetPassword(password) => local_auth::set_password(nvs_partition, &password),
+        Command::ExportConfig => config::export(nvs_partition).map(|text| println!("{}", text)),
+        Command::ImportConfig(text) => config::import(nvs_partition, &text),
+    };
+    if let Err(e) = result {
+        println!("error applying command: {}", e);
+    }
+}
+
+/// Applies commands received remotely. If they change the configuration, a point listing the
+/// changed keys and the hash of the new configuration is queued for the upload, so that fleet
+/// tooling can verify that devices converge to the same configuration.
+fn apply_remote_commands(
+    session: &Session,
+    nvs_partition: &nvs::EspDefaultNvsPartition,
+    commands: Vec<Command>,
+) -> Result<()> {
+    let before = config::current(nvs_partition)?;
+    let authorization = DeviceConfig::load(nvs_partition)?.authorization;
+    for command in commands {
+        match (command, CONFIG_URL) {
+            (Command::ExportConfig, Some(config_url)) => {
+                let exported = session.run("config export", || {
+                    config::put(nvs_partition, config_url, &authorization)
+                });
+                if let Err(e) = exported {
+                    println!("error exporting configuration: {}", e);
+                }
+            }
+            (command, _) => apply_command(command, nvs_partition),
+        }
     }
+    let after = config::current(nvs_partition)?;
 
+    let changed = before.changed_keys(&after);
+    if changed.is_empty() {
+        return Ok(());
+    }
+    let time_offset = unsafe { rtc::STATE.time_offset }.context("time not synced")?;
+    session.queue(format_config_report(
+        &changed,
+        &after.hash(),
+        &tags::load(nvs_partition)?,
+        &DeviceConfig::load(nvs_partition)?.line_prefix,
+        time_offset,
+    ));
+    Ok(())
+}
+
+/// Converges the configuration to the desired one at `url` and queues the reported state.
+fn sync_shadow(
+    session: &Session,
+    nvs_partition: &nvs::EspDefaultNvsPartition,
+    url: &str,
+) -> Result<()> {
+    let authorization = DeviceConfig::load(nvs_partition)?.authorization;
+    let reported = session.run("shadow sync", || {
+        shadow::sync(nvs_partition, url, &authorization)
+    })?;
+    let time_offset = unsafe { rtc::STATE.time_offset }.context("time not synced")?;
+    let tags = tags::load(nvs_partition)?;
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let mut data = MeasurementBatch::default();
+    data.push_fields(
+        &DeviceConfig::load(nvs_partition)?.line_prefix,
+        SHADOW_MEASUREMENT,
+        &tags,
+        &reported.fields(),
+        slow_clock_seconds() as i64 + time_offset,
+    );
+    session.queue(data);
     Ok(())
 }
 
+/// Logs an error that ended the cycle and keeps its code for the next upload.
+fn record_error(e: anyhow::Error) -> ErrorCode {
+    let code = error::code(&e);
+    println!("error {}: {:#}", code.number(), e);
+    error_code::record(code, slow_clock_seconds());
+    code
+}
+
 fn slow_clock_seconds() -> u32 {
     let rtc_time = unsafe { esp_idf_sys::rtc_time_get() };
     (rtc_time / u64::from(esp_idf_sys::RTC_SLOW_CLK_FREQ_32K)) as _
 }
 
-fn greeting<T: gpio::Pin, MODE: gpio::OutputMode>(
-    led_pin: &mut gpio::PinDriver<T, MODE>,
-) -> Result<()> {
+fn greeting(led: &mut Led) -> Result<()> {
     for _ in 0..4 {
-        led_pin.set_low()?;
+        led.set_low()?;
         FreeRtos::delay_ms(20);
-        led_pin.set_high()?;
+        led.set_high()?;
         FreeRtos::delay_ms(100);
     }
     FreeRtos::delay_ms(400);
-    led_pin.set_low()?;
+    led.set_low()?;
     FreeRtos::delay_ms(1000);
-    led_pin.set_high()?;
+    led.set_high()?;
     FreeRtos::delay_ms(500);
 
     Ok(())
 }
 
-unsafe fn go_to_sleep() -> ! {
-    let delay = MEASUREMENT_INTERVAL.as_micros() as _;
+/// Blinks the LED for `LOCATE_DURATION`, which is about as long as the awake time limit, so it's
+/// run with the limit paused.
+fn locate(led: &mut Led) -> Result<()> {
+    println!("locating...");
+    let start = Instant::now();
+    while start.elapsed() < LOCATE_DURATION {
+        led.set_low()?;
+        FreeRtos::delay_ms(250);
+        led.set_high()?;
+        FreeRtos::delay_ms(250);
+    }
+
+    Ok(())
+}
+
+/// Blinks the category of `code` (its first digit) twice, long blinks separated by a pause.
+fn show_error(led: &mut Led, code: ErrorCode) -> Result<()> {
+    for _ in 0..2 {
+        led.set_low()?;
+        FreeRtos::delay_ms(1000);
+        for _ in 0..code.category() {
+            led.set_high()?;
+            FreeRtos::delay_ms(400);
+            led.set_low()?;
+            FreeRtos::delay_ms(400);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sleeps until the next cycle is due. In powered mode, the sensor has been awake for most of the
+/// interval already.
+unsafe fn go_to_sleep(awake: Duration) -> ! {
+    let interval = schedule::next_interval(MEASUREMENT_INTERVAL);
+    let delay = if cfg!(feature = "powered") {
+        interval.saturating_sub(awake).max(Duration::from_secs(1))
+    } else {
+        interval
+    };
+    let delay = delay.as_micros() as _;
     esp_idf_sys::esp_sleep_enable_timer_wakeup(delay);
+    power::hold_in_deep_sleep();
     esp_idf_sys::esp_deep_sleep_start();
     unreachable!();
 }
 
-fn send_values(measurements: &[Measurement], time_offset: i64) -> anyhow::Result<()> {
-    let http_client_config = esp_idf_svc::http::client::Configuration {
-        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
-        ..Default::default()
-    };
+/// State of the measurement buffer at the time of an upload.
+struct QueueStats {
+    depth: usize,
+    /// Age of the oldest unsent measurement in seconds.
+    oldest_age: u32,
+    overwritten: u32,
+    /// Number of requests the upload is split into.
+    chunks: u32,
+    time_source: TimeSource,
+}
 
-    let data: String = measurements
-        .iter()
-        .map(|m| {
-            format!(
-                "{}{} {}000000000\n",
-                LINE_PREFIX,
-                m.value,
-                m.time as i64 + time_offset
-            )
-        })
-        .collect();
-
-    println!("{}", data);
-
-    let content_length = data.len().to_string();
-    let headers = vec![
-        ("Authorization", AUTHORIZATION),
-        ("Content-Length", &content_length),
-    ];
-
-    let mut http_client = esp_idf_svc::http::client::EspHttpConnection::new(&http_client_config)?;
-    http_client.initiate_request(Method::Post, WRITE_URL, &headers)?;
-    http_client.write_all(data.as_bytes())?;
-    http_client.initiate_response()?;
-
-    let status = http_client.status();
-    if status < 200 || status >= 300 {
-        let mut response = vec![0; 1000];
-        http_client.read(&mut response)?;
-        bail!(
-            "HTTP status {}: {}",
-            status,
-            String::from_utf8_lossy(&response)
+/// Opens a connection to the server, including the TLS handshake, by sending a HEAD request. The
+/// connection is kept alive and reused by subsequent requests of the same client.
+fn connect_http(device: &DeviceConfig) -> Result<EspHttpConnection, FirmwareError> {
+    let mut http_client = http::new_connection()?;
+    let headers = [("Authorization", device.authorization.as_str())];
+    http_client
+        .initiate_request(Method::Head, &device.write_url, &headers)
+        .http(ErrorCode::HttpConnect)?;
+    http_client
+        .initiate_response()
+        .http(ErrorCode::HttpConnect)?;
+    println!("connected to server, status {}.", http_client.status());
+    Ok(http_client)
+}
+
+/// Points reported along with the measurements of an upload, with its last chunk.
+struct Report<'a> {
+    queue_stats: Option<&'a QueueStats>,
+    build_info: Option<&'a BuildInfo>,
+    failure: Option<&'a Failure>,
+    events: &'a [Event],
+    health: Option<&'a Counters>,
+    /// Subsystems that weren't ready since the last upload, reported with the health point.
+    not_ready: u16,
+    skips: &'a [Skip],
+    /// Events detected in the readings, uploaded apart from the waterings recorded with the button.
+    detections: &'a [Detection],
+    sampling: &'a [Stats],
+    settling_timeout: Option<&'a settling::Timeout>,
+    crash: Option<&'a coredump::Summary>,
+    diagnostics: &'a [Diagnostic],
+}
+
+/// What points are labeled with.
+struct Labels<'a> {
+    probes: &'a [Probe],
+    calibrations: &'a Calibrations,
+    tags: &'a Tags,
+    line_prefix: &'a str,
+    time_offset: i64,
+}
+
+fn format_measurements<'a>(
+    measurements: impl IntoIterator<Item = &'a Measurement>,
+    sequence: &mut Sequence,
+    labels: &Labels,
+) -> MeasurementBatch {
+    let Labels {
+        probes,
+        calibrations,
+        tags,
+        line_prefix,
+        time_offset,
+    } = *labels;
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+    let mut data = MeasurementBatch::default();
+    for m in measurements {
+        let seconds = m.time as i64 + time_offset;
+        let mut measurement_tags = tags.clone();
+        measurement_tags.push(("wake", m.wake_cause().name()));
+        let channel = m.channel().to_string();
+        if !probes.is_empty() {
+            measurement_tags.push(("channel", &channel));
+            if let Some(probe) = probes.iter().find(|probe| probe.channel == m.channel()) {
+                measurement_tags.push(("probe", &probe.name));
+            }
+        }
+        if m.maintenance() {
+            measurement_tags.push(("maintenance", "true"));
+        }
+        if m.watered() {
+            measurement_tags.push(("watered", "true"));
+        }
+        data.push_value(
+            line_prefix,
+            &measurement_tags,
+            FieldValue::Float(m.value.into()),
+            seconds,
+            sequence.next(seconds),
         );
+        if let Some(calibration) = calibrations.get(m.channel()) {
+            let percent = calibration.percent(m.value);
+            data.push_fields(
+                line_prefix,
+                MOISTURE_PERCENT_MEASUREMENT,
+                &measurement_tags,
+                &[("percent", FieldValue::Integer(percent.into()))],
+                seconds,
+            );
+        }
+        if let Some(battery_mv) = m.battery_mv() {
+            data.push_fields(
+                line_prefix,
+                BATTERY_MEASUREMENT,
+                &tags,
+                &[("voltage_mv", FieldValue::Integer(battery_mv.into()))],
+                seconds,
+            );
+        }
     }
 
-    Ok(())
+    data
+}
+
+fn format_report(report: &Report, labels: &Labels) -> MeasurementBatch {
+    let Report {
+        queue_stats,
+        build_info,
+        failure,
+        events,
+        health,
+        not_ready,
+        skips,
+        detections,
+        sampling,
+        settling_timeout,
+        crash,
+        diagnostics,
+    } = *report;
+    let Labels {
+        tags,
+        line_prefix,
+        time_offset,
+        ..
+    } = *labels;
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+    let mut data = MeasurementBatch::default();
+    if let Some(queue_stats) = queue_stats {
+        data.push_fields(
+            line_prefix,
+            QUEUE_MEASUREMENT,
+            &tags,
+            &[
+                ("depth", FieldValue::Integer(queue_stats.depth as i64)),
+                (
+                    "oldest_age",
+                    FieldValue::Integer(queue_stats.oldest_age.into()),
+                ),
+                (
+                    "overwritten",
+                    FieldValue::Integer(queue_stats.overwritten.into()),
+                ),
+                ("chunks", FieldValue::Integer(queue_stats.chunks.into())),
+                (
+                    "time_source",
+                    FieldValue::String(queue_stats.time_source.name()),
+                ),
+            ],
+            slow_clock_seconds() as i64 + time_offset,
+        );
+    }
+    if let Some(build_info) = build_info {
+        data.push_fields(
+            line_prefix,
+            BUILD_MEASUREMENT,
+            &tags,
+            &[
+                ("version", FieldValue::String(build_info.version)),
+                ("build_id", FieldValue::String(&build_info.build_id())),
+                (
+                    "build_timestamp",
+                    FieldValue::Integer(build_info.build_timestamp),
+                ),
+                ("idf_version", FieldValue::String(&build_info.idf_version)),
+                ("partition", FieldValue::String(&build_info.partition)),
+                ("config_schema", FieldValue::Integer(CONFIG_SCHEMA_VERSION)),
+            ],
+            slow_clock_seconds() as i64 + time_offset,
+        );
+    }
+
+    if let Some(failure) = failure {
+        data.push_fields(
+            line_prefix,
+            ERROR_MEASUREMENT,
+            &tags,
+            &[
+                ("code", FieldValue::Integer(failure.code.number().into())),
+                ("count", FieldValue::Integer(failure.count.into())),
+            ],
+            failure.time as i64 + time_offset,
+        );
+    }
+    for event in events {
+        data.push_fields(
+            line_prefix,
+            EVENTS_MEASUREMENT,
+            &tags,
+            &[
+                ("code", FieldValue::Integer(event.code.number().into())),
+                ("message", FieldValue::String(&event.code.to_string())),
+                ("wake", FieldValue::Integer(event.wake.into())),
+            ],
+            event.time as i64 + time_offset,
+        );
+    }
+    if let Some(health) = health {
+        let mut fields = vec![
+            ("boots", FieldValue::Integer(health.boots.into())),
+            ("awake_ms", FieldValue::Integer(health.awake_ms as i64)),
+            (
+                "unexpected_restarts",
+                FieldValue::Integer(health.unexpected_restarts.into()),
+            ),
+            (
+                "restarted_unexpectedly",
+                FieldValue::Boolean(health.restarted_unexpectedly()),
+            ),
+        ];
+        if let Some(reason) = &health.last_unexpected_reason {
+            fields.push(("last_unexpected_reason", FieldValue::String(reason)));
+        }
+        if let Some((reading_mv, passed)) = self_test::latest() {
+            fields.push(("reference_mv", FieldValue::Integer(reading_mv.into())));
+            fields.push(("adc_degraded", FieldValue::Boolean(!passed)));
+        }
+        let not_ready = startup::names(not_ready);
+        if !not_ready.is_empty() {
+            fields.push(("not_ready", FieldValue::String(&not_ready)));
+        }
+        data.push_fields(
+            line_prefix,
+            HEALTH_MEASUREMENT,
+            &tags,
+            &fields,
+            slow_clock_seconds() as i64 + time_offset,
+        );
+    }
+    for skip in skips {
+        let skip_tags: Vec<_> = tags
+            .iter()
+            .cloned()
+            .chain([("wake", skip.wake_cause.name())])
+            .collect();
+        data.push_fields(
+            line_prefix,
+            SKIP_MEASUREMENT,
+            &skip_tags,
+            &[("reason", FieldValue::String(skip.reason.name()))],
+            skip.time as i64 + time_offset,
+        );
+    }
+    for detection in detections {
+        data.push_fields(
+            line_prefix,
+            DETECTION_MEASUREMENT,
+            &tags,
+            &[("event", FieldValue::String(detection.event.name()))],
+            detection.time as i64 + time_offset,
+        );
+    }
+    for stats in sampling {
+        data.push_fields(
+            line_prefix,
+            SAMPLING_MEASUREMENT,
+            &tags,
+            &[("variance", FieldValue::Float(stats.variance.into()))],
+            stats.time as i64 + time_offset,
+        );
+    }
+    if let Some(timeout) = settling_timeout {
+        data.push_fields(
+            line_prefix,
+            SETTLING_TIMEOUT_MEASUREMENT,
+            &tags,
+            &[
+                ("first_mv", FieldValue::Integer(timeout.first.into())),
+                ("last_mv", FieldValue::Integer(timeout.last.into())),
+                ("variance", FieldValue::Float(timeout.variance.into())),
+                ("count", FieldValue::Integer(timeout.count.into())),
+            ],
+            timeout.time as i64 + time_offset,
+        );
+    }
+    if let Some(crash) = crash {
+        let backtrace = crash.backtrace_string();
+        let pc = format!("{:#010x}", crash.pc);
+        data.push_fields(
+            line_prefix,
+            CRASH_MEASUREMENT,
+            &tags,
+            &[
+                ("task", FieldValue::String(&crash.task)),
+                ("pc", FieldValue::String(&pc)),
+                ("cause", FieldValue::Integer(crash.cause.into())),
+                ("backtrace", FieldValue::String(&backtrace)),
+            ],
+            slow_clock_seconds() as i64 + time_offset,
+        );
+    }
+    if !diagnostics.is_empty() {
+        let build_id = BuildInfo::current().build_id();
+        let diagnostic_tags: Vec<_> = tags
+            .iter()
+            .cloned()
+            .chain([("build_id", build_id.as_str())])
+            .collect();
+        for diagnostic in diagnostics {
+            let mut fields = vec![
+                ("awake_ms", FieldValue::Integer(diagnostic.awake_ms.into())),
+                (
+                    "energy_mj",
+                    FieldValue::Integer(diagnostic.energy_mj().into()),
+                ),
+                ("rtc_utilization", FieldValue::Float(rtc::utilization())),
+            ];
+            if let Some(variance) = diagnostic.sample_variance {
+                fields.push(("sample_variance", FieldValue::Float(variance.into())));
+            }
+            if let Some(connect_ms) = diagnostic.connect_ms {
+                fields.push(("connect_ms", FieldValue::Integer(connect_ms.into())));
+            }
+            let not_ready = startup::names(diagnostic.not_ready);
+            if !not_ready.is_empty() {
+                fields.push(("not_ready", FieldValue::String(&not_ready)));
+            }
+            data.push_fields(
+                line_prefix,
+                DIAGNOSTICS_MEASUREMENT,
+                &diagnostic_tags,
+                &fields,
+                diagnostic.time as i64 + time_offset,
+            );
+        }
+    }
+
+    data
+}
+
+fn format_config_report(
+    changed: &[String],
+    hash: &str,
+    tags: &Tags,
+    line_prefix: &str,
+    time_offset: i64,
+) -> MeasurementBatch {
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let mut data = MeasurementBatch::default();
+    data.push_fields(
+        line_prefix,
+        CONFIG_MEASUREMENT,
+        &tags,
+        &[
+            ("changed", FieldValue::String(&changed.join(","))),
+            ("hash", FieldValue::String(hash)),
+        ],
+        slow_clock_seconds() as i64 + time_offset,
+    );
+    data
+}
+
+/// Formats a point per chunk of an ADC trace, tagged with the chunk, as all chunks of a trace have
+/// the time of its capture.
+fn format_trace(
+    chunks: &[adc_trace::Chunk],
+    tags: &Tags,
+    line_prefix: &str,
+    time_offset: i64,
+) -> MeasurementBatch {
+    let mut data = MeasurementBatch::default();
+    for chunk in chunks {
+        let index = chunk.index.to_string();
+        let mut tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        tags.push(("chunk", &index));
+        data.push_fields(
+            line_prefix,
+            TRACE_MEASUREMENT,
+            &tags,
+            &[
+                ("channel", FieldValue::Integer(chunk.capture.channel.into())),
+                ("chunks", FieldValue::Integer(chunk.capture.chunks.into())),
+                (
+                    "samples",
+                    FieldValue::String(&adc_trace::format_samples(&chunk.samples)),
+                ),
+            ],
+            chunk.capture.time as i64 + time_offset,
+        );
+    }
+    data
+}
+
+/// Formats the uptime counters as of the start of the upload, see [`uptime`].
+fn format_uptime(tags: &Tags, line_prefix: &str, time_offset: i64) -> MeasurementBatch {
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let (counters, wakes) = uptime::current();
+    let mut data = MeasurementBatch::default();
+    data.push_fields(
+        line_prefix,
+        UPTIME_MEASUREMENT,
+        &tags,
+        &counters.fields(wakes),
+        slow_clock_seconds() as i64 + time_offset,
+    );
+    data
+}
+
+fn format_connection(
+    metrics: &connection::Metrics,
+    tags: &Tags,
+    line_prefix: &str,
+    time_offset: i64,
+) -> MeasurementBatch {
+    let tags: Vec<_> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let mut data = MeasurementBatch::default();
+    let fields = metrics.fields();
+    if !fields.is_empty() {
+        data.push_fields(
+            line_prefix,
+            CONNECTION_MEASUREMENT,
+            &tags,
+            &fields,
+            slow_clock_seconds() as i64 + time_offset,
+        );
+    }
+    data
 }
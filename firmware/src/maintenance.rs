@@ -0,0 +1,25 @@
+//! Maintenance mode, used while the plant is being repotted or the probe cleaned. Readings taken
+//! during maintenance are tagged, and anything acting on readings (alerts, actuators) has to
+//! check [`is_active`] first.
+
+use crate::rtc::STATE;
+use std::time::Duration;
+
+pub fn start(now: u32, duration: Duration) {
+    let until = now.saturating_add(duration.as_secs().try_into().unwrap_or(u32::MAX));
+    unsafe {
+        STATE.maintenance_until = until;
+    }
+    println!("maintenance mode active for {} s", duration.as_secs());
+}
+
+pub fn stop() {
+    unsafe {
+        STATE.maintenance_until = 0;
+    }
+    println!("maintenance mode ended");
+}
+
+pub fn is_active(now: u32) -> bool {
+    now < unsafe { STATE.maintenance_until }
+}
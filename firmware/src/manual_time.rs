@@ -0,0 +1,49 @@
+//! Wall-clock time entered via the console, as a fallback for timestamping buffered measurements
+//! when SNTP is unavailable. Like the synced offset, it stays valid across deep sleep
+//! as long as the slow clock keeps running.
+
+use crate::rtc::STATE;
+use anyhow::{bail, Result};
+
+/// Latest plausible time after the firmware was built.
+const MAX_FIRMWARE_AGE: i64 = 20 * 365 * 24 * 3600;
+
+/// Sets the time to `unix_time` at slow clock time `slow_clock`, if it is plausible.
+pub fn set(unix_time: i64, slow_clock: u32) -> Result<()> {
+    check(unix_time)?;
+    unsafe {
+        STATE.manual_time_offset = Some(unix_time - slow_clock as i64);
+    }
+    println!("time set manually.");
+    Ok(())
+}
+
+pub fn offset() -> Option<i64> {
+    unsafe { STATE.manual_time_offset }
+}
+
+/// Checks that `unix_time` is plausible for this firmware.
+pub fn check(unix_time: i64) -> Result<()> {
+    let build_timestamp = env!("BUILD_TIMESTAMP").parse().unwrap_or(0);
+    check_plausible(unix_time, build_timestamp)
+}
+
+fn check_plausible(unix_time: i64, build_timestamp: i64) -> Result<()> {
+    if unix_time < build_timestamp {
+        bail!("time {} is before the firmware was built", unix_time);
+    }
+    if unix_time - build_timestamp > MAX_FIRMWARE_AGE {
+        bail!("time {} is too far in the future", unix_time);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_check_plausible() {
+    let build_timestamp = 1_700_000_000;
+    check_plausible(build_timestamp, build_timestamp).unwrap();
+    check_plausible(1_760_000_000, build_timestamp).unwrap();
+    assert!(check_plausible(0, build_timestamp).is_err());
+    assert!(check_plausible(1_699_999_999, build_timestamp).is_err());
+    assert!(check_plausible(build_timestamp + MAX_FIRMWARE_AGE + 1, build_timestamp).is_err());
+}
@@ -0,0 +1,49 @@
+//! HTTP endpoint `POST /measure` of powered mode, which reads the sensor immediately and returns
+//! the value, e.g. for automations or checking the placement of the probe.
+
+use crate::local_auth::Verifier;
+use anyhow::{anyhow, Result};
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use std::sync::mpsc::{sync_channel, Sender, SyncSender};
+use std::time::Duration;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A read requested by a client. The board is only accessible from the main task, which answers
+/// on the contained channel.
+pub type ReadRequest = SyncSender<Result<u16>>;
+
+/// Starts the HTTP server. It runs until the returned server is dropped.
+pub fn start(verifier: Verifier, requests: Sender<ReadRequest>) -> Result<EspHttpServer> {
+    let mut server = EspHttpServer::new(&Configuration::default())?;
+    server.fn_handler("/measure", Method::Post, move |request| {
+        if !verifier.verify_header(request.header("Authorization")) {
+            request.into_status_response(401)?;
+            return Ok(());
+        }
+
+        let (reply_tx, reply_rx) = sync_channel(1);
+        requests.send(reply_tx)?;
+        let reading = reply_rx
+            .recv_timeout(READ_TIMEOUT)
+            .map_err(|_| anyhow!("no reading within {} s", READ_TIMEOUT.as_secs()))
+            .and_then(|reading| reading);
+        match reading {
+            Ok(value) => {
+                println!("measured on request: {}", value);
+                let mut response =
+                    request.into_response(200, None, &[("Content-Type", "application/json")])?;
+                response.write_all(serde_json::json!({ "value": value }).to_string().as_bytes())?;
+            }
+            Err(e) => {
+                println!("error measuring on request: {}", e);
+                request.into_status_response(500)?;
+            }
+        }
+        Ok(())
+    })?;
+    println!("serving POST /measure");
+    Ok(server)
+}
@@ -0,0 +1,171 @@
+//! Prometheus exporter of powered mode, serving `GET /metrics` in the text exposition format, so
+//! that the sensor can be scraped directly on the LAN instead of pushing to a server. Scrapers pass
+//! the local password as bearer token.
+
+use crate::health::Counters;
+use crate::local_auth::Verifier;
+use anyhow::Result;
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::server::EspHttpServer;
+use std::fmt::{Display, Write as _};
+use std::sync::{Arc, Mutex};
+
+const PREFIX: &str = "soil_moisture_sensor";
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    /// Latest reading of the probe.
+    pub moisture: Option<u16>,
+    /// Water content estimated from the latest reading with the calibration preset of the medium.
+    pub moisture_percent: Option<u8>,
+    pub rssi: Option<i8>,
+    pub free_heap: u32,
+    pub minimum_free_heap: u32,
+    /// Measurements buffered for the next upload.
+    pub queue_depth: usize,
+    /// Probes that answered their latest read, see `hotplug`.
+    pub probes_connected: usize,
+    pub health: Counters,
+}
+
+/// Adds the endpoint to `server`. The radio and heap values are taken at the time of the request,
+/// everything else from `metrics`, which the main task keeps up to date.
+pub fn register(
+    server: &mut EspHttpServer,
+    metrics: Arc<Mutex<Metrics>>,
+    verifier: Verifier,
+) -> Result<()> {
+    server.fn_handler("/metrics", Method::Get, move |request| {
+        if !verifier.verify_header(request.header("Authorization")) {
+            request.into_status_response(401)?;
+            return Ok(());
+        }
+        let mut current = metrics.lock().unwrap().clone();
+        let mut ap_info = esp_idf_sys::wifi_ap_record_t::default();
+        if esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) }).is_ok()
+        {
+            current.rssi = Some(ap_info.rssi);
+        }
+        current.free_heap = unsafe { esp_idf_sys::esp_get_free_heap_size() };
+        current.minimum_free_heap = unsafe { esp_idf_sys::esp_get_minimum_free_heap_size() };
+
+        let mut response =
+            request.into_response(200, None, &[("Content-Type", "text/plain; version=0.0.4")])?;
+        response.write_all(format(&current).as_bytes())?;
+        Ok(())
+    })?;
+    println!("serving GET /metrics");
+    Ok(())
+}
+
+fn format(metrics: &Metrics) -> String {
+    let mut out = String::new();
+    if let Some(moisture) = metrics.moisture {
+        write_metric(
+            &mut out,
+            "moisture",
+            "gauge",
+            "Latest raw reading of the probe.",
+            moisture,
+        );
+    }
+    if let Some(percent) = metrics.moisture_percent {
+        write_metric(
+            &mut out,
+            "moisture_percent",
+            "gauge",
+            "Estimated water content in percent of saturation.",
+            percent,
+        );
+    }
+    if let Some(rssi) = metrics.rssi {
+        write_metric(
+            &mut out,
+            "wifi_rssi_dbm",
+            "gauge",
+            "Signal strength of the access point.",
+            rssi,
+        );
+    }
+    write_metric(
+        &mut out,
+        "free_heap_bytes",
+        "gauge",
+        "Free heap.",
+        metrics.free_heap,
+    );
+    write_metric(
+        &mut out,
+        "minimum_free_heap_bytes",
+        "gauge",
+        "Lowest free heap since boot.",
+        metrics.minimum_free_heap,
+    );
+    write_metric(
+        &mut out,
+        "queue_depth",
+        "gauge",
+        "Measurements buffered for upload.",
+        metrics.queue_depth,
+    );
+    write_metric(
+        &mut out,
+        "probes_connected",
+        "gauge",
+        "Probes connected to the sensor.",
+        metrics.probes_connected,
+    );
+    write_metric(
+        &mut out,
+        "boots_total",
+        "counter",
+        "Boots since the firmware was flashed.",
+        metrics.health.boots,
+    );
+    write_metric(
+        &mut out,
+        "awake_seconds_total",
+        "counter",
+        "Time awake since the firmware was flashed.",
+        metrics.health.awake_ms as f64 / 1000.0,
+    );
+    write_metric(
+        &mut out,
+        "unexpected_restarts",
+        "gauge",
+        "Unexpected restarts since the last upload.",
+        metrics.health.unexpected_restarts,
+    );
+    out
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+    let _ = writeln!(out, "{}_{} {}", PREFIX, name, value);
+}
+
+#[test]
+pub fn test_format() {
+    let mut metrics = Metrics {
+        moisture: Some(1234),
+        free_heap: 100000,
+        ..Default::default()
+    };
+    metrics.health.awake_ms = 1500;
+    let text = format(&metrics);
+    assert!(text.starts_with(
+        "# HELP soil_moisture_sensor_moisture Latest raw reading of the probe.\n\
+         # TYPE soil_moisture_sensor_moisture gauge\n\
+         soil_moisture_sensor_moisture 1234\n"
+    ));
+    assert!(!text.contains("rssi"));
+    assert!(!text.contains("moisture_percent"));
+    assert!(text.contains("\nsoil_moisture_sensor_free_heap_bytes 100000\n"));
+    assert!(text.contains("\nsoil_moisture_sensor_awake_seconds_total 1.5\n"));
+    assert!(text.contains("\nsoil_moisture_sensor_probes_connected 0\n"));
+
+    metrics.moisture_percent = Some(42);
+    assert!(format(&metrics).contains("\nsoil_moisture_sensor_moisture_percent 42\n"));
+}
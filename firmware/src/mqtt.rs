@@ -0,0 +1,223 @@
+//! Upload to an MQTT broker such as Mosquitto, for Home Assistant and other setups without an
+//! InfluxDB-compatible store. Every point of the upload is published with QoS 1 as a JSON object
+//! with the time and its tags and fields, to a configured topic in which `{measurement}` is
+//! replaced. A chunk counts as sent once the broker has acknowledged all of its messages.
+
+use crate::error::{Classify, FirmwareError};
+use crate::error_code::ErrorCode;
+use crate::points::MeasurementBatch;
+use crate::postgrest::field_value;
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use embedded_svc::mqtt::client::{Event, Publish, QoS};
+use esp_idf_svc::mqtt::client::{EspMqttClient, MqttClientConfiguration};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::sync::mpsc;
+use std::time::Duration;
+
+const NVS_NAMESPACE: &str = "mqtt";
+const NVS_KEY: &str = "target";
+pub const DEFAULT_TOPIC: &str = "soil-moisture-sensor/{measurement}";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Target {
+    /// Broker URL, e.g. `mqtt://homeassistant.local:1883` or `mqtts://` for TLS.
+    pub url: String,
+    /// Topic, in which `{measurement}` is replaced.
+    pub topic: String,
+    /// Sent if not empty.
+    pub username: String,
+    pub password: String,
+}
+
+impl Default for Target {
+    fn default() -> Target {
+        Target {
+            url: String::new(),
+            topic: DEFAULT_TOPIC.into(),
+            username: String::new(),
+            password: String::new(),
+        }
+    }
+}
+
+/// Returns the MQTT target, if uploads go to an MQTT broker.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<Target>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, target: Option<&Target>) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match target {
+        Some(target) => namespace.set(NVS_KEY, target),
+        None => namespace.remove(NVS_KEY),
+    }
+}
+
+/// Progress of the client, forwarded from its event callback.
+enum Status {
+    Connected,
+    Published(u32),
+    Disconnected,
+    Failed(String),
+}
+
+impl Target {
+    /// Publishes the points of `batch` and waits for their acknowledgement.
+    pub fn send(&self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        let messages = messages(batch, &self.topic);
+
+        let config = MqttClientConfiguration {
+            username: Some(self.username.as_str()).filter(|u| !u.is_empty()),
+            password: Some(self.password.as_str()).filter(|p| !p.is_empty()),
+            crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        let mut client = EspMqttClient::new(&self.url, &config, move |event| {
+            let status = match event {
+                Ok(Event::Connected(_)) => Status::Connected,
+                Ok(Event::Published(id)) => Status::Published(*id),
+                Ok(Event::Disconnected) => Status::Disconnected,
+                Ok(_) => return,
+                Err(e) => Status::Failed(e.to_string()),
+            };
+            let _ = sender.send(status);
+        })
+        .http(ErrorCode::HttpConnect)?;
+        let next = || {
+            receiver
+                .recv_timeout(TIMEOUT)
+                .map_err(|_| "MQTT broker timed out")
+                .http(ErrorCode::HttpConnect)
+        };
+
+        loop {
+            match next()? {
+                Status::Connected => break,
+                Status::Failed(e) => return Err(e).http(ErrorCode::HttpConnect),
+                _ => {}
+            }
+        }
+        let mut unacknowledged = BTreeSet::new();
+        for (topic, payload) in &messages {
+            println!("{} {}", topic, payload);
+            let id = client
+                .publish(topic.as_str(), QoS::AtLeastOnce, false, payload.as_bytes())
+                .http(ErrorCode::HttpConnect)?;
+            unacknowledged.insert(id);
+        }
+        while !unacknowledged.is_empty() {
+            match next()? {
+                Status::Published(id) => {
+                    unacknowledged.remove(&id);
+                }
+                Status::Disconnected => {
+                    return Err("disconnected from MQTT broker").http(ErrorCode::HttpConnect)
+                }
+                Status::Failed(e) => {
+                    return Err(format!("MQTT error: {}", e)).http(ErrorCode::HttpConnect)
+                }
+                Status::Connected => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that uploads to `target` can succeed.
+pub fn validate(target: &Target) -> Result<()> {
+    let schemes = ["mqtt://", "mqtts://", "ws://", "wss://"];
+    if !schemes.iter().any(|scheme| target.url.starts_with(scheme)) {
+        bail!(
+            "MQTT URL must start with mqtt://, mqtts://, ws:// or wss://: {}",
+            target.url
+        );
+    }
+    let topic = target.topic.replace("{measurement}", "");
+    if target.topic.is_empty() || topic.contains(['{', '}', '+', '#']) {
+        bail!("invalid MQTT topic {:?}", target.topic);
+    }
+    Ok(())
+}
+
+/// Converts points to topics and JSON payloads, one per point.
+fn messages(batch: &MeasurementBatch, topic: &str) -> Vec<(String, String)> {
+    let mut messages = Vec::new();
+    for point in &batch.points {
+        let mut object = Map::new();
+        let time = Utc.timestamp_nanos(point.nanos());
+        object.insert(
+            "time".into(),
+            time.to_rfc3339_opts(SecondsFormat::AutoSi, true).into(),
+        );
+        for (key, value) in &point.tags {
+            object.insert(key.clone(), value.as_str().into());
+        }
+        for (key, value) in &point.fields {
+            object.insert(key.clone(), field_value(value));
+        }
+        let topic = topic.replace("{measurement}", &point.measurement);
+        messages.push((topic, Value::Object(object).to_string()));
+    }
+    messages
+}
+
+#[test]
+pub fn test_messages() {
+    use crate::line_protocol::FieldValue;
+
+    let mut batch = MeasurementBatch::default();
+    let prefix = "moisture,sensor=a value=";
+    batch.push_value(prefix, &[], FieldValue::Float(1234.0), 1000, 0);
+    batch.push_fields(
+        prefix,
+        "queue",
+        &[],
+        &[
+            ("depth", FieldValue::Integer(3)),
+            ("age", FieldValue::Float(2.5)),
+        ],
+        1000,
+    );
+    assert_eq!(
+        messages(&batch, DEFAULT_TOPIC),
+        vec![
+            (
+                "soil-moisture-sensor/moisture".into(),
+                r#"{"sensor":"a","time":"1970-01-01T00:16:40Z","value":1234.0}"#.into()
+            ),
+            (
+                "soil-moisture-sensor/queue".into(),
+                r#"{"age":2.5,"depth":3,"sensor":"a","time":"1970-01-01T00:16:40Z"}"#.into()
+            ),
+        ]
+    );
+
+    let target = Target {
+        url: "mqtt://homeassistant.local:1883".into(),
+        ..Default::default()
+    };
+    validate(&target).unwrap();
+    assert!(validate(&Target {
+        url: "homeassistant.local".into(),
+        ..target.clone()
+    })
+    .is_err());
+    assert!(validate(&Target {
+        topic: "sensors/+/moisture".into(),
+        ..target.clone()
+    })
+    .is_err());
+    assert!(validate(&Target {
+        topic: "sensors/{sensor}".into(),
+        ..target
+    })
+    .is_err());
+}
@@ -0,0 +1,270 @@
+//! Notifications to Discord via a webhook, to Telegram via the bot API and to ntfy, for those who'd
+//! rather get a message than watch a dashboard. Messages are sent to every configured channel.
+//!
+//! ntfy notifications have the priority of the alert severity and a "Watered it" button, which
+//! publishes `snooze` to the command topic, the notification topic with `-commands` appended. The
+//! device polls the command topic while an alert is active.
+
+use crate::alert::Severity;
+use crate::http;
+use crate::storage::Namespace;
+use anyhow::{Context, Result};
+use embedded_svc::http::Method;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const NVS_NAMESPACE: &str = "notifier";
+const NVS_KEY: &str = "channels";
+/// Key of the ntfy command topic URL and the message ID or Unix time to poll commands since.
+const NVS_KEY_NTFY_SINCE: &str = "ntfy_since";
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+const NTFY_COMMAND_SUFFIX: &str = "-commands";
+/// Size of the response to a poll for commands.
+const MAX_POLL_SIZE: usize = 8192;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Channels {
+    pub discord_webhook: String,
+    pub telegram_token: String,
+    pub telegram_chat_id: String,
+    /// Topic URL, e.g. `https://ntfy.sh/<topic>`.
+    pub ntfy_url: String,
+    /// Access token, if the topics are protected.
+    pub ntfy_token: String,
+}
+
+impl Channels {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Channels> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.discord_webhook.is_empty()
+            && self.telegram_token.is_empty()
+            && self.ntfy_url.is_empty()
+    }
+
+    /// Sends `message` to all channels, failing if any of them fails.
+    pub fn send(&self, message: &str, severity: Severity) -> Result<()> {
+        let mut result = Ok(());
+        if !self.discord_webhook.is_empty() {
+            let sent = post_json(&self.discord_webhook, &json!({ "content": message }), None);
+            result = result.and(sent.context("error notifying via Discord"));
+        }
+        if !self.telegram_token.is_empty() {
+            let url = format!(
+                "{}/bot{}/sendMessage",
+                TELEGRAM_API_URL, self.telegram_token
+            );
+            let body = json!({ "chat_id": self.telegram_chat_id, "text": message });
+            let sent = post_json(&url, &body, None);
+            result = result.and(sent.context("error notifying via Telegram"));
+        }
+        if !self.ntfy_url.is_empty() {
+            let sent = self.publish_ntfy(message, severity);
+            result = result.and(sent.context("error notifying via ntfy"));
+        }
+        result
+    }
+
+    /// Returns the messages published to the ntfy command topic since the previous poll. The first
+    /// poll of a topic returns those published from Unix time `now` on.
+    pub fn poll_ntfy(&self, partition: &EspDefaultNvsPartition, now: i64) -> Result<Vec<String>> {
+        let url = command_topic_url(&self.ntfy_url)?;
+        let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+        let since = match namespace.get::<(String, String)>(NVS_KEY_NTFY_SINCE)? {
+            Some((polled_url, since)) if polled_url == url => since,
+            _ => now.to_string(),
+        };
+
+        let body = get(
+            &format!("{}/json?poll=1&since={}", url, since),
+            self.ntfy_authorization().as_deref(),
+        )?;
+        let (messages, last_id) = parse_ntfy_messages(&body);
+        namespace.set(NVS_KEY_NTFY_SINCE, &(url, last_id.unwrap_or(since)))?;
+        Ok(messages)
+    }
+
+    fn publish_ntfy(&self, message: &str, severity: Severity) -> Result<()> {
+        let (url, body) = ntfy_message(&self.ntfy_url, &self.ntfy_token, message, severity)?;
+        post_json(&url, &body, self.ntfy_authorization().as_deref())
+    }
+
+    fn ntfy_authorization(&self) -> Option<String> {
+        Some(&self.ntfy_token)
+            .filter(|token| !token.is_empty())
+            .map(|token| format!("Bearer {}", token))
+    }
+}
+
+/// Checks that `url` is the URL of an ntfy topic.
+pub fn validate_ntfy_url(url: &str) -> Result<()> {
+    split_ntfy_url(url).map(|_| ())
+}
+
+/// Splits an ntfy topic URL into server URL and topic.
+fn split_ntfy_url(url: &str) -> Result<(&str, &str)> {
+    url.trim_end_matches('/')
+        .rsplit_once('/')
+        .filter(|(server, topic)| server.contains("://") && !topic.is_empty())
+        .with_context(|| format!("invalid ntfy topic URL: {}", url))
+}
+
+fn command_topic_url(topic_url: &str) -> Result<String> {
+    let (server, topic) = split_ntfy_url(topic_url)?;
+    Ok(format!("{}/{}{}", server, topic, NTFY_COMMAND_SUFFIX))
+}
+
+/// Returns the URL to publish an ntfy notification to and its JSON body.
+fn ntfy_message(
+    topic_url: &str,
+    token: &str,
+    message: &str,
+    severity: Severity,
+) -> Result<(String, Value)> {
+    let (server, topic) = split_ntfy_url(topic_url)?;
+    let (priority, tag) = match severity {
+        Severity::Warning => (4, "seedling"),
+        Severity::Critical => (5, "wilted_flower"),
+    };
+    let mut action = json!({
+        "action": "http",
+        "label": "Watered it",
+        "url": command_topic_url(topic_url)?,
+        "method": "POST",
+        "body": "snooze",
+        "clear": true,
+    });
+    if !token.is_empty() {
+        action["headers"] = json!({ "Authorization": format!("Bearer {}", token) });
+    }
+    let body = json!({
+        "topic": topic,
+        "message": message,
+        "priority": priority,
+        "tags": [tag],
+        "actions": [action],
+    });
+    Ok((server.into(), body))
+}
+
+/// Returns the text of the messages in an ntfy JSON stream and the ID of the last one.
+fn parse_ntfy_messages(text: &str) -> (Vec<String>, Option<String>) {
+    let mut messages = Vec::new();
+    let mut last_id = None;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let event: Value = match serde_json::from_str(line) {
+            Ok(event) => event,
+            Err(e) => {
+                println!("ignoring ntfy event: {}", e);
+                continue;
+            }
+        };
+        if let (Some("message"), Some(id)) = (event["event"].as_str(), event["id"].as_str()) {
+            last_id = Some(id.to_string());
+            messages.push(event["message"].as_str().unwrap_or_default().to_string());
+        }
+    }
+    (messages, last_id)
+}
+
+fn post_json(url: &str, body: &Value, authorization: Option<&str>) -> Result<()> {
+    let body = body.to_string();
+    let content_length = body.len().to_string();
+    let mut headers = vec![
+        ("Content-Type", "application/json"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    if let Some(authorization) = authorization {
+        headers.push(("Authorization", authorization));
+    }
+    let mut http_client = http::new_connection()?;
+    http::submit(
+        &mut http_client,
+        Method::Post,
+        url,
+        &headers,
+        body.as_bytes(),
+    )?;
+    Ok(())
+}
+
+fn get(url: &str, authorization: Option<&str>) -> Result<String> {
+    let headers: Vec<_> = authorization
+        .map(|authorization| ("Authorization", authorization))
+        .into_iter()
+        .collect();
+    let mut http_client = http::new_connection()?;
+    let body = http::request(
+        &mut http_client,
+        Method::Get,
+        url,
+        &headers,
+        &[],
+        MAX_POLL_SIZE,
+    )?;
+    Ok(String::from_utf8_lossy(&body).into())
+}
+
+#[test]
+pub fn test_ntfy() {
+    let (url, body) = ntfy_message(
+        "https://ntfy.sh/basil",
+        "",
+        "Basil needs water",
+        Severity::Critical,
+    )
+    .unwrap();
+    assert_eq!(url, "https://ntfy.sh");
+    assert_eq!(
+        body,
+        json!({
+            "topic": "basil",
+            "message": "Basil needs water",
+            "priority": 5,
+            "tags": ["wilted_flower"],
+            "actions": [{
+                "action": "http",
+                "label": "Watered it",
+                "url": "https://ntfy.sh/basil-commands",
+                "method": "POST",
+                "body": "snooze",
+                "clear": true,
+            }],
+        })
+    );
+    let (_, body) = ntfy_message(
+        "https://ntfy.example.com/basil/",
+        "tk_a",
+        "",
+        Severity::Warning,
+    )
+    .unwrap();
+    assert_eq!(body["priority"], 4);
+    assert_eq!(
+        body["actions"][0]["url"],
+        "https://ntfy.example.com/basil-commands"
+    );
+    assert_eq!(
+        body["actions"][0]["headers"]["Authorization"],
+        "Bearer tk_a"
+    );
+    assert!(ntfy_message("basil", "", "", Severity::Warning).is_err());
+
+    let stream = "{\"id\":\"a1\",\"event\":\"open\"}\n\
+                  {\"id\":\"b2\",\"event\":\"message\",\"message\":\"snooze\"}\n\
+                  \n\
+                  {\"id\":\"c3\",\"event\":\"message\",\"message\":\"snooze 60\"}\n";
+    assert_eq!(
+        parse_ntfy_messages(stream),
+        (vec!["snooze".into(), "snooze 60".into()], Some("c3".into()))
+    );
+    assert_eq!(parse_ntfy_messages(""), (vec![], None));
+}
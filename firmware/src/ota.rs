@@ -0,0 +1,164 @@
+//! Over-the-air firmware updates. After a successful upload, the manifest at `OTA_MANIFEST_URL` is
+//! fetched, a JSON object with the `version` and the `url` of the latest image. A newer image is
+//! written to the inactive OTA partition and booted at the next wake. It must confirm itself by
+//! completing its first cycle, otherwise the bootloader rolls back to the previous image at the
+//! wake after. A version that was rolled back isn't tried again.
+//!
+//! Requires the partition table with OTA slots in `partitions.csv`, which has to be flashed once
+//! over serial, and the rollback support enabled in `sdkconfig.defaults`.
+
+use crate::http;
+use crate::storage::Namespace;
+use anyhow::{bail, Context, Result};
+use embedded_svc::http::Method;
+use esp_idf_svc::http::client::EspHttpConnection;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::{self as sys, esp};
+use serde::Deserialize;
+use std::ffi::c_void;
+use std::ptr;
+
+const NVS_NAMESPACE: &str = "ota";
+/// Version of the latest image that was flashed, kept to detect rollbacks.
+const NVS_KEY: &str = "flashed";
+/// Size of the manifest, which only holds the version and the URL of the image.
+const MAX_MANIFEST_SIZE: usize = 1024;
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+struct Manifest {
+    version: String,
+    url: String,
+}
+
+/// Checks for a newer version and flashes it, returning whether it will be booted at the next
+/// wake.
+pub fn check(
+    partition: &EspDefaultNvsPartition,
+    manifest_url: &str,
+    authorization: &str,
+) -> Result<bool> {
+    let headers = [("Authorization", authorization)];
+    let mut http_client = http::new_connection()?;
+    let body = http::request(
+        &mut http_client,
+        Method::Get,
+        manifest_url,
+        &headers,
+        &[],
+        MAX_MANIFEST_SIZE,
+    )?;
+    let manifest: Manifest = serde_json::from_slice(&body).context("invalid OTA manifest")?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(&manifest.version, current)? {
+        return Ok(false);
+    }
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    if namespace.get::<String>(NVS_KEY)?.as_ref() == Some(&manifest.version) {
+        println!(
+            "not updating to {}, which has been rolled back",
+            manifest.version
+        );
+        return Ok(false);
+    }
+
+    println!("updating from {} to {}", current, manifest.version);
+    // The download may take longer than a cycle is allowed to.
+    crate::awake_limit::paused(|| flash(&manifest.url, authorization))?;
+    namespace.set(NVS_KEY, &manifest.version)?;
+    println!(
+        "updated to {}, booting it at the next wake",
+        manifest.version
+    );
+    Ok(true)
+}
+
+/// Confirms the running image if it's booted for the first time after an update, which cancels
+/// the rollback.
+pub fn confirm() -> Result<()> {
+    let mut state = 0;
+    let running = unsafe { sys::esp_ota_get_running_partition() };
+    if unsafe { sys::esp_ota_get_state_partition(running, &mut state) } != sys::ESP_OK
+        || state != sys::esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY
+    {
+        return Ok(());
+    }
+    esp!(unsafe { sys::esp_ota_mark_app_valid_cancel_rollback() })?;
+    println!("confirmed updated firmware");
+    Ok(())
+}
+
+/// Requests `url`, returning the connection to read the body of the response from.
+fn connect(url: &str, authorization: &str) -> Result<EspHttpConnection> {
+    let headers = [("Authorization", authorization)];
+    let mut http_client = http::new_connection()?;
+    http::send(&mut http_client, Method::Get, url, &headers, &[])
+        .with_context(|| format!("requesting {}", url))?;
+    Ok(http_client)
+}
+
+/// Writes the image at `url` to the inactive OTA partition and makes it the boot partition. The
+/// image is verified by ESP-IDF before it's accepted.
+fn flash(url: &str, authorization: &str) -> Result<()> {
+    let update_partition = unsafe { sys::esp_ota_get_next_update_partition(ptr::null()) };
+    if update_partition.is_null() {
+        bail!("no OTA partition, the partition table has to be flashed over serial");
+    }
+    let mut http_client = connect(url, authorization)?;
+
+    let mut handle = 0;
+    let size = sys::OTA_SIZE_UNKNOWN as _;
+    esp!(unsafe { sys::esp_ota_begin(update_partition, size, &mut handle) })?;
+    let written = (|| -> Result<usize> {
+        let mut buffer = [0; 4096];
+        let mut written = 0;
+        loop {
+            let len = http_client.read(&mut buffer)?;
+            if len == 0 {
+                return Ok(written);
+            }
+            esp!(unsafe {
+                sys::esp_ota_write(handle, buffer.as_ptr() as *const c_void, len as _)
+            })?;
+            written += len;
+        }
+    })();
+    match written {
+        Ok(written) => println!("downloaded {} bytes", written),
+        Err(e) => {
+            unsafe { sys::esp_ota_abort(handle) };
+            return Err(e.context("error downloading image"));
+        }
+    }
+    esp!(unsafe { sys::esp_ota_end(handle) }).context("invalid image")?;
+    esp!(unsafe { sys::esp_ota_set_boot_partition(update_partition) })?;
+    Ok(())
+}
+
+/// Whether `candidate` is a newer version than `current`, both as dot-separated numbers.
+fn is_newer(candidate: &str, current: &str) -> Result<bool> {
+    let parse = |version: &str| {
+        version
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid version: {}", version))
+    };
+    Ok(parse(candidate)? > parse(current)?)
+}
+
+#[test]
+pub fn test_is_newer() {
+    assert!(is_newer("0.2.0", "0.1.0").unwrap());
+    assert!(is_newer("0.10.0", "0.9.3").unwrap());
+    assert!(is_newer("1.0.0.1", "1.0.0").unwrap());
+    assert!(!is_newer("0.1.0", "0.1.0").unwrap());
+    assert!(!is_newer("0.1.0", "0.2.0").unwrap());
+    assert!(is_newer("0.2.0-beta", "0.1.0").is_err());
+    assert!(is_newer("", "0.1.0").is_err());
+
+    let manifest: Manifest =
+        serde_json::from_str(r#"{"version": "0.2.0", "url": "https://example.com/fw.bin"}"#)
+            .unwrap();
+    assert_eq!(manifest.version, "0.2.0");
+}
@@ -0,0 +1,212 @@
+//! Upload to OpenTelemetry collectors via OTLP/HTTP with JSON encoding, instead of to an
+//! InfluxDB-compatible store. Every numeric field of the points of an upload becomes a gauge data
+//! point named `<measurement>.<field>`, with the tags as attributes. The device is
+//! identified by resource attributes.
+
+use crate::error::FirmwareError;
+use crate::http;
+use crate::points::{self, MeasurementBatch};
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use embedded_svc::http::Method;
+use esp_idf_svc::http::client::EspHttpConnection;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const NVS_NAMESPACE: &str = "otlp";
+const NVS_KEY: &str = "target";
+const SERVICE_NAME: &str = "soil-moisture-sensor";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    /// Metrics endpoint of the collector, usually `http://<host>:4318/v1/metrics`.
+    pub url: String,
+}
+
+/// Returns the OTLP target, if uploads go to an OpenTelemetry collector.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<Target>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, target: Option<&Target>) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match target {
+        Some(target) => namespace.set(NVS_KEY, target),
+        None => namespace.remove(NVS_KEY),
+    }
+}
+
+/// Identity of the device, given as resource attributes.
+pub struct Resource {
+    pub version: &'static str,
+    /// Factory MAC address, which stays the same across firmware updates.
+    pub instance_id: String,
+}
+
+impl Target {
+    /// Exports the points of `batch`.
+    pub fn send(
+        &self,
+        http_client: &mut EspHttpConnection,
+        batch: &MeasurementBatch,
+        resource: &Resource,
+    ) -> Result<(), FirmwareError> {
+        let body = convert(batch, resource).to_string();
+        println!("{}", body);
+
+        let content_length = body.len().to_string();
+        let headers = [
+            ("Content-Type", "application/json"),
+            ("Content-Length", content_length.as_str()),
+        ];
+        http::submit(
+            http_client,
+            Method::Post,
+            &self.url,
+            &headers,
+            body.as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Checks that `url` can receive exports.
+pub fn validate_url(url: &str) -> Result<()> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        bail!("OTLP URL must start with http:// or https://: {}", url);
+    }
+    Ok(())
+}
+
+/// Converts points to an OTLP metrics export request. String fields have no gauge representation
+/// and are dropped.
+pub fn convert(batch: &MeasurementBatch, resource: &Resource) -> Value {
+    let mut metrics: Vec<(String, Vec<Value>)> = Vec::new();
+    for point in &batch.points {
+        let time = point.nanos().to_string();
+        let attributes: Vec<_> = point
+            .tags
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect();
+        for (field, value) in &point.fields {
+            let mut data_point = match number(value) {
+                Some(number) => number,
+                None => continue,
+            };
+            data_point["timeUnixNano"] = json!(time);
+            data_point["attributes"] = json!(attributes);
+
+            let name = format!("{}.{}", point.measurement, field);
+            match metrics.iter_mut().find(|(existing, _)| *existing == name) {
+                Some((_, data_points)) => data_points.push(data_point),
+                None => metrics.push((name, vec![data_point])),
+            }
+        }
+    }
+
+    let metrics: Vec<_> = metrics
+        .into_iter()
+        .map(|(name, data_points)| json!({ "name": name, "gauge": { "dataPoints": data_points } }))
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", SERVICE_NAME),
+                    attribute("service.version", resource.version),
+                    attribute("service.instance.id", &resource.instance_id),
+                ],
+            },
+            "scopeMetrics": [{
+                "scope": { "name": SERVICE_NAME },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// Returns a data point holding the field `value`, if it's numeric. 64 bit integers are encoded as
+/// strings in OTLP JSON.
+fn number(value: &points::Value) -> Option<Value> {
+    match value {
+        points::Value::Boolean(value) => Some(json!({ "asInt": u8::from(*value).to_string() })),
+        points::Value::Float(value) => Some(json!({ "asDouble": value })),
+        points::Value::Integer(value) => Some(json!({ "asInt": value.to_string() })),
+        points::Value::String(_) => None,
+    }
+}
+
+#[test]
+pub fn test_convert() {
+    use crate::line_protocol::FieldValue;
+
+    let resource = Resource {
+        version: "1.0.0",
+        instance_id: "a0b1c2d3e4f5".into(),
+    };
+    let mut batch = MeasurementBatch::default();
+    let prefix = "moisture,sensor=a value=";
+    batch.push_value(prefix, &[], FieldValue::Integer(1234), 1000, 0);
+    batch.push_value(prefix, &[], FieldValue::Integer(1240), 1000, 1);
+    batch.push_fields(
+        prefix,
+        "diagnostics",
+        &[],
+        &[
+            ("build_id", FieldValue::String("ab")),
+            ("sample_variance", FieldValue::Float(2.5)),
+        ],
+        2000,
+    );
+    assert_eq!(
+        convert(&batch, &resource),
+        json!({
+            "resourceMetrics": [{
+                "resource": {
+                    "attributes": [
+                        { "key": "service.name", "value": { "stringValue": SERVICE_NAME } },
+                        { "key": "service.version", "value": { "stringValue": "1.0.0" } },
+                        { "key": "service.instance.id", "value": { "stringValue": "a0b1c2d3e4f5" } },
+                    ],
+                },
+                "scopeMetrics": [{
+                    "scope": { "name": SERVICE_NAME },
+                    "metrics": [
+                        {
+                            "name": "moisture.value",
+                            "gauge": { "dataPoints": [
+                                {
+                                    "asInt": "1234",
+                                    "timeUnixNano": "1000000000000",
+                                    "attributes": [{ "key": "sensor", "value": { "stringValue": "a" } }],
+                                },
+                                {
+                                    "asInt": "1240",
+                                    "timeUnixNano": "1000000000001",
+                                    "attributes": [{ "key": "sensor", "value": { "stringValue": "a" } }],
+                                },
+                            ] },
+                        },
+                        {
+                            "name": "diagnostics.sample_variance",
+                            "gauge": { "dataPoints": [{
+                                "asDouble": 2.5,
+                                "timeUnixNano": "2000000000000",
+                                "attributes": [{ "key": "sensor", "value": { "stringValue": "a" } }],
+                            }] },
+                        },
+                    ],
+                }],
+            }],
+        })
+    );
+
+    validate_url("http://collector:4318/v1/metrics").unwrap();
+    assert!(validate_url("collector:4318").is_err());
+}
@@ -0,0 +1,133 @@
+//! Layout of the info and battery bytes of a measurement, which pack its flags, the cause of the
+//! wake, the probe channel and the battery voltage so that a measurement fits into 8 bytes of RTC
+//! memory. Flags are in the low bits of the info byte, above them the wake cause, and in the high
+//! bits the ADC1 channel of the probe.
+//!
+//! The module doesn't use any types of the firmware, as the host crate `soil-codec` includes it.
+
+/// Taken during maintenance.
+const MAINTENANCE: u8 = 1 << 0;
+/// Taken right after the plant has been watered, as recorded with the button.
+const WATERED: u8 = 1 << 1;
+const WAKE_CAUSE_SHIFT: u8 = 2;
+const WAKE_CAUSE_MASK: u8 = 0b111;
+const CHANNEL_SHIFT: u8 = 5;
+/// Resolution of the battery voltage, which is uploaded as stored.
+const BATTERY_STEP_MV: u16 = 20;
+
+/// Representation 1 was a wake by the button, which can't wake the ESP32-C3 from deep sleep, and is
+/// read as `Other` now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum WakeCause {
+    Timer = 0,
+    /// Wake from deep sleep without a known cause.
+    Other = 2,
+    PowerOn = 3,
+    BrownOut = 4,
+    /// Reset other than a power-on or brown-out, e.g. by the reset pin, a panic or a watchdog.
+    Reset = 5,
+}
+
+impl WakeCause {
+    const ALL: [WakeCause; 5] = [
+        WakeCause::Timer,
+        WakeCause::Other,
+        WakeCause::PowerOn,
+        WakeCause::BrownOut,
+        WakeCause::Reset,
+    ];
+
+    /// Returns the cause represented by `repr`, or `Other` if there is none.
+    pub fn from_repr(repr: u8) -> WakeCause {
+        WakeCause::ALL
+            .into_iter()
+            .find(|&cause| cause as u8 == repr)
+            .unwrap_or(WakeCause::Other)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WakeCause::Timer => "timer",
+            WakeCause::Other => "other",
+            WakeCause::PowerOn => "power_on",
+            WakeCause::BrownOut => "brown_out",
+            WakeCause::Reset => "reset",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Info {
+    pub maintenance: bool,
+    pub watered: bool,
+    /// Cause of the wake of the cycle in which the reading was taken.
+    pub wake_cause: WakeCause,
+    /// ADC1 channel of the probe.
+    pub channel: u8,
+}
+
+impl Info {
+    pub fn pack(self) -> u8 {
+        let mut info =
+            ((self.wake_cause as u8) << WAKE_CAUSE_SHIFT) | (self.channel << CHANNEL_SHIFT);
+        if self.maintenance {
+            info |= MAINTENANCE;
+        }
+        if self.watered {
+            info |= WATERED;
+        }
+        info
+    }
+
+    pub fn unpack(info: u8) -> Info {
+        Info {
+            maintenance: info & MAINTENANCE != 0,
+            watered: info & WATERED != 0,
+            wake_cause: WakeCause::from_repr((info >> WAKE_CAUSE_SHIFT) & WAKE_CAUSE_MASK),
+            channel: info >> CHANNEL_SHIFT,
+        }
+    }
+}
+
+/// Packs the battery voltage in units of `BATTERY_STEP_MV`, 0 if not measured.
+pub fn pack_battery(battery_mv: Option<u16>) -> u8 {
+    let step = BATTERY_STEP_MV;
+    battery_mv.map_or(0, |mv| ((mv + step / 2) / step).clamp(1, 255) as u8)
+}
+
+pub fn unpack_battery(battery: u8) -> Option<u16> {
+    (battery != 0).then(|| u16::from(battery) * BATTERY_STEP_MV)
+}
+
+#[test]
+pub fn test_packing() {
+    for cause in WakeCause::ALL {
+        assert_eq!(WakeCause::from_repr(cause as u8), cause);
+    }
+    assert_eq!(WakeCause::from_repr(1), WakeCause::Other);
+    assert_eq!(WakeCause::from_repr(6), WakeCause::Other);
+
+    let info = Info {
+        maintenance: false,
+        watered: true,
+        wake_cause: WakeCause::Reset,
+        channel: 4,
+    };
+    assert_eq!(info.pack(), 0b1001_0110);
+    assert_eq!(Info::unpack(info.pack()), info);
+    let info = Info {
+        maintenance: true,
+        watered: false,
+        wake_cause: WakeCause::Timer,
+        channel: 7,
+    };
+    assert_eq!(Info::unpack(info.pack()), info);
+
+    assert_eq!(pack_battery(None), 0);
+    assert_eq!(pack_battery(Some(3009)), 150);
+    assert_eq!(unpack_battery(150), Some(3000));
+    assert_eq!(pack_battery(Some(5)), 1);
+    assert_eq!(pack_battery(Some(9000)), 255);
+    assert_eq!(unpack_battery(0), None);
+}
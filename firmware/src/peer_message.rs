@@ -0,0 +1,37 @@
+//! Message of the time broadcast between sensors over ESP-NOW, see `peer_time`: the magic `SMT`,
+//! a version byte and the Unix time in milliseconds.
+//!
+//! The module doesn't use any types of the firmware, as the host crate `soil-codec` includes it.
+
+const MAGIC: &[u8; 3] = b"SMT";
+const VERSION: u8 = 1;
+pub const SIZE: usize = 12;
+
+pub fn encode(unix_millis: i64) -> [u8; SIZE] {
+    let mut message = [0; SIZE];
+    message[..3].copy_from_slice(MAGIC);
+    message[3] = VERSION;
+    message[4..].copy_from_slice(&unix_millis.to_le_bytes());
+    message
+}
+
+/// Returns the Unix time in milliseconds of a broadcast.
+pub fn decode(message: &[u8]) -> Option<i64> {
+    if message.len() != SIZE || &message[..3] != MAGIC || message[3] != VERSION {
+        return None;
+    }
+    Some(i64::from_le_bytes(message[4..].try_into().ok()?))
+}
+
+#[test]
+pub fn test_messages() {
+    let message = encode(1_700_000_000_123);
+    assert_eq!(&message[..4], b"SMT\x01");
+    assert_eq!(decode(&message), Some(1_700_000_000_123));
+    assert_eq!(decode(&message[..11]), None);
+    let mut other = message;
+    other[3] = 2;
+    assert_eq!(decode(&other), None);
+    other[0] = b'X';
+    assert_eq!(decode(&other), None);
+}
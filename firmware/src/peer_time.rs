@@ -0,0 +1,86 @@
+//! Sharing of time between sensors over ESP-NOW, enabled with the `peer-time` feature. Sensors in
+//! powered mode whose time is synced by SNTP broadcast it every `BROADCAST_INTERVAL` on the channel
+//! of their access point, and sensors whose time sync doesn't complete adopt a received broadcast,
+//! so that their buffered measurements still get absolute timestamps. Uploads report the time
+//! source, so that points timestamped with peer time can be told apart.
+//!
+//! Broadcasts aren't authenticated. They're only adopted if SNTP fails and the time is plausible.
+
+use crate::peer_message;
+use crate::rtc::STATE;
+use crate::{manual_time, slow_clock_seconds, TimeSource};
+use anyhow::Result;
+use chrono::Utc;
+use esp_idf_svc::espnow::{EspNow, PeerInfo, BROADCAST};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const BROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Offset of the slow clock to UTC in seconds from the latest broadcast received.
+static RECEIVED_OFFSET: Mutex<Option<i64>> = Mutex::new(None);
+
+/// Receives broadcasts while in scope.
+pub struct Listener {
+    _espnow: EspNow,
+}
+
+impl Listener {
+    pub fn start() -> Result<Listener> {
+        *RECEIVED_OFFSET.lock().unwrap() = None;
+        let espnow = EspNow::take()?;
+        espnow.register_recv_cb(|_sender: &[u8], data: &[u8]| {
+            if let Some(unix_millis) = peer_message::decode(data) {
+                let unix_time = unix_millis.div_euclid(1000);
+                if manual_time::check(unix_time).is_ok() {
+                    *RECEIVED_OFFSET.lock().unwrap() =
+                        Some(unix_time - slow_clock_seconds() as i64);
+                }
+            }
+        })?;
+        Ok(Listener { _espnow: espnow })
+    }
+
+    /// Returns the offset of the slow clock to UTC in seconds from the latest broadcast, if any.
+    pub fn offset(&self) -> Option<i64> {
+        *RECEIVED_OFFSET.lock().unwrap()
+    }
+}
+
+/// Broadcasts the time while it's synced by SNTP.
+pub struct Broadcaster {
+    espnow: EspNow,
+    sent_at: Option<Instant>,
+}
+
+impl Broadcaster {
+    pub fn start() -> Result<Broadcaster> {
+        let espnow = EspNow::take()?;
+        espnow.add_peer(PeerInfo {
+            peer_addr: BROADCAST,
+            ifidx: esp_idf_sys::wifi_interface_t_WIFI_IF_STA,
+            ..Default::default()
+        })?;
+        Ok(Broadcaster {
+            espnow,
+            sent_at: None,
+        })
+    }
+
+    /// Broadcasts the time if `BROADCAST_INTERVAL` has passed since the last broadcast.
+    pub fn poll(&mut self) -> Result<()> {
+        let synced =
+            unsafe { STATE.time_offset.is_some() && STATE.time_source == TimeSource::Sntp };
+        let due = self
+            .sent_at
+            .map_or(true, |sent_at| sent_at.elapsed() >= BROADCAST_INTERVAL);
+        if synced && due {
+            self.espnow.send(
+                BROADCAST,
+                &peer_message::encode(Utc::now().timestamp_millis()),
+            )?;
+            self.sent_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
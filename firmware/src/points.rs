@@ -0,0 +1,179 @@
+//! Batches of points that uploads send over the transports. Tags and fields are kept as typed
+//! values, so that every transport formats points its own way, the server at the write URL as line
+//! protocol, without parsing what another format made of them.
+//!
+//! Points are added with a line prefix, see [`line_protocol`], whose measurement and tags all
+//! points of a device share.
+
+use crate::line_protocol::{self, FieldValue};
+
+/// An owned [`FieldValue`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Float(f64),
+    Integer(i64),
+    String(String),
+}
+
+impl Value {
+    pub fn as_field(&self) -> FieldValue {
+        match self {
+            Value::Boolean(value) => FieldValue::Boolean(*value),
+            Value::Float(value) => FieldValue::Float(*value),
+            Value::Integer(value) => FieldValue::Integer(*value),
+            Value::String(value) => FieldValue::String(value),
+        }
+    }
+}
+
+impl From<&FieldValue<'_>> for Value {
+    fn from(value: &FieldValue) -> Value {
+        match *value {
+            FieldValue::Boolean(value) => Value::Boolean(value),
+            FieldValue::Float(value) => Value::Float(value),
+            FieldValue::Integer(value) => Value::Integer(value),
+            FieldValue::String(value) => Value::String(value.into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, Value)>,
+    /// UTC time in seconds.
+    pub seconds: i64,
+    /// Added to the nanoseconds of the timestamp to tell apart points within the same second, see
+    /// [`line_protocol::Sequence`].
+    pub sequence: u32,
+}
+
+impl Point {
+    /// Returns the timestamp in nanoseconds.
+    pub fn nanos(&self) -> i64 {
+        self.seconds * 1_000_000_000 + i64::from(self.sequence)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeasurementBatch {
+    pub points: Vec<Point>,
+}
+
+impl MeasurementBatch {
+    /// Adds a point for the field of `prefix`, with extra `tags`.
+    pub fn push_value(
+        &mut self,
+        prefix: &str,
+        tags: &[(&str, &str)],
+        value: FieldValue,
+        seconds: i64,
+        sequence: u32,
+    ) {
+        let prefix = line_protocol::parse_prefix(prefix);
+        self.points.push(Point {
+            measurement: prefix.measurement,
+            tags: with_tags(prefix.tags, tags),
+            fields: vec![(prefix.field, (&value).into())],
+            seconds,
+            sequence,
+        });
+    }
+
+    /// Adds a point of `measurement` with the tags of `prefix`, extra `tags` and the given fields.
+    pub fn push_fields(
+        &mut self,
+        prefix: &str,
+        measurement: &str,
+        tags: &[(&str, &str)],
+        fields: &[(&str, FieldValue)],
+        seconds: i64,
+    ) {
+        let prefix = line_protocol::parse_prefix(prefix);
+        self.points.push(Point {
+            measurement: measurement.into(),
+            tags: with_tags(prefix.tags, tags),
+            fields: fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.into()))
+                .collect(),
+            seconds,
+            sequence: 0,
+        });
+    }
+
+    /// Moves the points of `other` to the end of the batch.
+    pub fn append(&mut self, other: &mut MeasurementBatch) {
+        self.points.append(&mut other.points);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn to_line_protocol(&self) -> String {
+        let mut out = String::new();
+        for point in &self.points {
+            line_protocol::write_point(&mut out, point);
+        }
+        out
+    }
+}
+
+fn with_tags(mut tags: Vec<(String, String)>, extra: &[(&str, &str)]) -> Vec<(String, String)> {
+    tags.extend(
+        extra
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+    );
+    tags
+}
+
+#[test]
+pub fn test_batch() {
+    let mut batch = MeasurementBatch::default();
+    batch.push_value(
+        "soil\\ moisture,sensor=a\\ b value=",
+        &[("pot", "big, red")],
+        FieldValue::Float(456.0),
+        2000,
+        1,
+    );
+    batch.push_fields(
+        "moisture,sensor=a value=",
+        "build",
+        &[],
+        &[("version", FieldValue::String("1.0"))],
+        1000,
+    );
+    assert_eq!(
+        batch.points,
+        vec![
+            Point {
+                measurement: "soil moisture".into(),
+                tags: vec![
+                    ("sensor".into(), "a b".into()),
+                    ("pot".into(), "big, red".into())
+                ],
+                fields: vec![("value".into(), Value::Float(456.0))],
+                seconds: 2000,
+                sequence: 1,
+            },
+            Point {
+                measurement: "build".into(),
+                tags: vec![("sensor".into(), "a".into())],
+                fields: vec![("version".into(), Value::String("1.0".into()))],
+                seconds: 1000,
+                sequence: 0,
+            },
+        ]
+    );
+    assert_eq!(batch.points[0].nanos(), 2_000_000_000_001);
+
+    let mut other = MeasurementBatch::default();
+    other.append(&mut batch);
+    assert!(batch.is_empty());
+    assert_eq!(other.points.len(), 2);
+}
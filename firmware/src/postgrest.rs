@@ -0,0 +1,215 @@
+//! Upload to PostgREST-compatible endpoints such as Supabase, for time series kept in Postgres or
+//! Timescale instead of an InfluxDB-compatible store. Points of mapped measurements are inserted as
+//! rows into their table, with a column for the time and for every tag and field.
+
+use crate::error::FirmwareError;
+use crate::http;
+use crate::points::{self, MeasurementBatch};
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use embedded_svc::http::Method;
+use esp_idf_svc::http::client::EspHttpConnection;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+const NVS_NAMESPACE: &str = "postgrest";
+const NVS_KEY: &str = "target";
+/// Column of the point time, unless mapped to a different name.
+const TIME_COLUMN: &str = "time";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Target {
+    /// Base URL of the API, e.g. `https://<project>.supabase.co/rest/v1`.
+    pub url: String,
+    /// Sent as `apikey` header and bearer token, if not empty.
+    pub api_key: String,
+    /// Tables by measurement. Points of other measurements aren't sent.
+    pub tables: BTreeMap<String, String>,
+    /// Columns by tag or field key, for those whose name differs.
+    pub columns: BTreeMap<String, String>,
+}
+
+/// Returns the PostgREST target, if uploads go to PostgREST.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<Target>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, target: Option<&Target>) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match target {
+        Some(target) => namespace.set(NVS_KEY, target),
+        None => namespace.remove(NVS_KEY),
+    }
+}
+
+impl Target {
+    /// Inserts the points of `batch`, with a request per table.
+    pub fn send(
+        &self,
+        http_client: &mut EspHttpConnection,
+        batch: &MeasurementBatch,
+    ) -> Result<(), FirmwareError> {
+        let authorization = format!("Bearer {}", self.api_key);
+        for (table, rows) in self.rows(batch) {
+            // Rows without a tag that others have, such as `maintenance`, get the column default.
+            let columns: BTreeSet<_> = rows
+                .iter()
+                .filter_map(Value::as_object)
+                .flat_map(Map::keys)
+                .cloned()
+                .collect();
+            let columns: Vec<_> = columns.into_iter().collect();
+            let url = format!(
+                "{}/{}?columns={}",
+                self.url.trim_end_matches('/'),
+                table,
+                columns.join(",")
+            );
+            let body = Value::Array(rows).to_string();
+            println!("{}", body);
+
+            let content_length = body.len().to_string();
+            let mut headers = vec![
+                ("Content-Type", "application/json"),
+                ("Content-Length", content_length.as_str()),
+                ("Prefer", "return=minimal, missing=default"),
+            ];
+            if !self.api_key.is_empty() {
+                headers.push(("apikey", self.api_key.as_str()));
+                headers.push(("Authorization", authorization.as_str()));
+            }
+            http::submit(http_client, Method::Post, &url, &headers, body.as_bytes())
+                .map_err(|e| e.context(format!("inserting into {}", table)))?;
+        }
+        Ok(())
+    }
+
+    /// Converts points to rows by table, in the order the tables first appear.
+    fn rows(&self, batch: &MeasurementBatch) -> Vec<(String, Vec<Value>)> {
+        let mut tables: Vec<(String, Vec<Value>)> = Vec::new();
+        for point in &batch.points {
+            let table = match self.tables.get(&point.measurement) {
+                Some(table) => table,
+                None => continue,
+            };
+
+            let mut row = Map::new();
+            let time = Utc.timestamp_nanos(point.nanos());
+            row.insert(
+                self.column(TIME_COLUMN),
+                time.to_rfc3339_opts(SecondsFormat::AutoSi, true).into(),
+            );
+            for (key, value) in &point.tags {
+                row.insert(self.column(key), value.as_str().into());
+            }
+            for (key, value) in &point.fields {
+                row.insert(self.column(key), field_value(value));
+            }
+
+            match tables.iter_mut().find(|(existing, _)| existing == table) {
+                Some((_, rows)) => rows.push(Value::Object(row)),
+                None => tables.push((table.clone(), vec![Value::Object(row)])),
+            }
+        }
+        tables
+    }
+
+    fn column(&self, key: &str) -> String {
+        self.columns.get(key).map_or(key, String::as_str).into()
+    }
+}
+
+/// Checks that uploads to `target` can succeed.
+pub fn validate(target: &Target) -> Result<()> {
+    if !target.url.starts_with("http://") && !target.url.starts_with("https://") {
+        bail!(
+            "PostgREST URL must start with http:// or https://: {}",
+            target.url
+        );
+    }
+    if target.tables.is_empty() {
+        bail!("no PostgREST tables mapped");
+    }
+    Ok(())
+}
+
+/// Converts a field value to JSON.
+pub fn field_value(value: &points::Value) -> Value {
+    match value {
+        points::Value::Boolean(value) => (*value).into(),
+        points::Value::Float(value) => (*value).into(),
+        points::Value::Integer(value) => (*value).into(),
+        points::Value::String(value) => value.as_str().into(),
+    }
+}
+
+#[test]
+pub fn test_rows() {
+    use crate::line_protocol::FieldValue;
+
+    let target = Target {
+        url: "https://example.supabase.co/rest/v1".into(),
+        tables: BTreeMap::from([("moisture".to_string(), "readings".to_string())]),
+        columns: BTreeMap::from([("value".to_string(), "moisture".to_string())]),
+        ..Default::default()
+    };
+    validate(&target).unwrap();
+    let mut batch = MeasurementBatch::default();
+    let prefix = "moisture,sensor=a value=";
+    batch.push_value(prefix, &[], FieldValue::Integer(1234), 1000, 0);
+    batch.push_fields(
+        prefix,
+        "queue",
+        &[],
+        &[("depth", FieldValue::Integer(3))],
+        1000,
+    );
+    batch.push_value(
+        prefix,
+        &[("maintenance", "true")],
+        FieldValue::Integer(1240),
+        1000,
+        1,
+    );
+    assert_eq!(
+        target.rows(&batch),
+        vec![(
+            "readings".into(),
+            vec![
+                serde_json::json!({
+                    "time": "1970-01-01T00:16:40Z",
+                    "sensor": "a",
+                    "moisture": 1234,
+                }),
+                serde_json::json!({
+                    "time": "1970-01-01T00:16:40.000000001Z",
+                    "sensor": "a",
+                    "maintenance": "true",
+                    "moisture": 1240,
+                }),
+            ]
+        )]
+    );
+
+    assert_eq!(field_value(&points::Value::Float(2.5)), 2.5);
+    assert_eq!(field_value(&points::Value::Boolean(false)), false);
+    assert_eq!(
+        field_value(&points::Value::String("say \"hi\"".into())),
+        "say \"hi\""
+    );
+
+    assert!(validate(&Target {
+        tables: BTreeMap::new(),
+        ..target.clone()
+    })
+    .is_err());
+    assert!(validate(&Target {
+        url: "example.com".into(),
+        ..target
+    })
+    .is_err());
+}
@@ -0,0 +1,54 @@
+//! The mode of the TPS631000 regulator, selected by GPIO10 (PWR_MODE). While awake, the regulator
+//! runs in forced PWM mode for a clean supply of the radio and the probe. Before deep sleep it's
+//! switched to power save mode, and the pin is held low: the digital GPIOs are powered down in deep
+//! sleep, and a floating mode pin lets the regulator leak current. The hold outlasts the wake until
+//! the next [`Regulator::new`] releases it.
+
+use anyhow::Result;
+use esp_idf_hal::gpio::{self, Pin, PinDriver};
+use esp_idf_sys::esp;
+
+/// GPIO of [`Regulator`], for [`force_power_save`].
+const MODE_PIN: i32 = 10;
+
+pub struct Regulator {
+    mode: PinDriver<'static, gpio::Gpio10, gpio::Output>,
+}
+
+impl Regulator {
+    /// Releases the hold of the previous sleep and switches to forced PWM mode.
+    pub fn new(pin: gpio::Gpio10) -> Result<Regulator> {
+        unsafe {
+            esp_idf_sys::gpio_deep_sleep_hold_dis();
+            esp!(esp_idf_sys::gpio_hold_dis(pin.pin()))?;
+        }
+        let mut mode = PinDriver::output(pin)?;
+        mode.set_high()?;
+        Ok(Regulator { mode })
+    }
+}
+
+impl Drop for Regulator {
+    /// Switches to power save mode and holds the pin, so that resetting the driver doesn't change
+    /// its level.
+    fn drop(&mut self) {
+        let _ = self.mode.set_low();
+        if let Err(e) = esp!(unsafe { esp_idf_sys::gpio_hold_en(self.mode.pin()) }) {
+            println!("error holding regulator mode pin: {}", e);
+        }
+    }
+}
+
+/// Keeps held pins at their level in deep sleep. Called right before sleeping.
+pub fn hold_in_deep_sleep() {
+    unsafe { esp_idf_sys::gpio_deep_sleep_hold_en() };
+}
+
+/// Switches to power save mode and holds the pin without the driver, for a deep sleep forced while
+/// the [`Regulator`] is still in use.
+pub fn force_power_save() {
+    unsafe {
+        esp_idf_sys::gpio_set_level(MODE_PIN, 0);
+        esp_idf_sys::gpio_hold_en(MODE_PIN);
+    }
+}
@@ -0,0 +1,128 @@
+//! The capacitive probe, excited by a PWM signal and read via the peak voltage detector. Several
+//! samples are taken per excitation once the probe has settled, to be filtered by
+//! [`crate::sampling`]: `SAMPLES` one-shot reads, or a DMA capture with the `continuous` feature.
+
+use crate::error::{Classify, FirmwareError};
+use crate::error_code::ErrorCode;
+#[cfg(not(feature = "continuous"))]
+use crate::sampling::SAMPLES;
+#[cfg(not(feature = "continuous"))]
+use crate::settling::{self, Progress, Trace};
+use anyhow::Result;
+#[cfg(not(feature = "continuous"))]
+use esp_idf_hal::delay::Ets;
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::units::FromValueType;
+use esp_idf_hal::{adc, gpio, ledc};
+
+/// Readings close to the supply voltage mean that there is no capacitance at all, i.e. the probe
+/// is broken off or not connected.
+const OPEN_CIRCUIT_VALUE: u16 = 2900; // TODO: good value?
+
+/// Reads the probe wired to `adc_pin`, which is on ADC1 `channel`.
+pub fn read<P: gpio::ADCPin<Adc = adc::ADC1>>(
+    adc: impl Peripheral<P = adc::ADC1>,
+    adc_pin: impl Peripheral<P = P>,
+    channel: u8,
+    pwm_channel: impl Peripheral<P = ledc::CHANNEL0>,
+    pwm_timer: impl Peripheral<P = ledc::TIMER0>,
+    pwm_pin: impl Peripheral<P = gpio::Gpio5>,
+) -> Result<Vec<u16>, FirmwareError> {
+    let samples = measure(adc, adc_pin, channel, pwm_channel, pwm_timer, pwm_pin)
+        .sensor(ErrorCode::SensorRead)?;
+    // Single samples close to the supply voltage may be noise, most of them aren't.
+    let open = samples.iter().filter(|&&s| s >= OPEN_CIRCUIT_VALUE).count();
+    if open > samples.len() / 2 {
+        let highest = samples.iter().copied().max().unwrap_or(0);
+        return Err(FirmwareError::Sensor {
+            code: ErrorCode::SensorOpen,
+            context: format!("reading of {} mV", highest),
+        });
+    }
+    Ok(samples)
+}
+
+fn measure<P: gpio::ADCPin<Adc = adc::ADC1>>(
+    adc: impl Peripheral<P = adc::ADC1>,
+    adc_pin: impl Peripheral<P = P>,
+    channel: u8,
+    pwm_channel: impl Peripheral<P = ledc::CHANNEL0>,
+    pwm_timer: impl Peripheral<P = ledc::TIMER0>,
+    pwm_pin: impl Peripheral<P = gpio::Gpio5>,
+) -> Result<Vec<u16>> {
+    let _excitation = excite(pwm_channel, pwm_timer, pwm_pin)?;
+    #[cfg(not(feature = "continuous"))]
+    return sample_one_shot(adc, adc_pin);
+    #[cfg(feature = "continuous")]
+    return crate::continuous::capture(adc, adc_pin, channel);
+}
+
+/// Reads `count` raw samples of the probe wired to `adc_pin` from the start of the excitation, for
+/// [`crate::adc_trace`]. Samples are read one-shot without waiting for the probe to settle, so that
+/// the trace shows the settling as well.
+pub fn trace<P: gpio::ADCPin<Adc = adc::ADC1>>(
+    adc: impl Peripheral<P = adc::ADC1>,
+    adc_pin: impl Peripheral<P = P>,
+    pwm_channel: impl Peripheral<P = ledc::CHANNEL0>,
+    pwm_timer: impl Peripheral<P = ledc::TIMER0>,
+    pwm_pin: impl Peripheral<P = gpio::Gpio5>,
+    count: usize,
+) -> Result<Vec<u16>> {
+    let mut adc_driver = adc::AdcDriver::new(adc, &adc::config::Config::new().calibration(true))?;
+    let mut adc_channel_driver: adc::AdcChannelDriver<P, adc::Atten11dB<_>> =
+        adc::AdcChannelDriver::new(adc_pin)?;
+
+    let _excitation = excite(pwm_channel, pwm_timer, pwm_pin)?;
+    let mut samples = Vec::with_capacity(count);
+    for _ in 0..count {
+        samples.push(adc_driver.read(&mut adc_channel_driver)?);
+    }
+    Ok(samples)
+}
+
+/// Starts the PWM signal exciting the probe, which stops when the returned driver is dropped.
+fn excite<'d>(
+    pwm_channel: impl Peripheral<P = ledc::CHANNEL0> + 'd,
+    pwm_timer: impl Peripheral<P = ledc::TIMER0> + 'd,
+    pwm_pin: impl Peripheral<P = gpio::Gpio5> + 'd,
+) -> Result<ledc::LedcDriver<'d>> {
+    let pwm_config = ledc::config::TimerConfig::new().frequency(50.kHz().into());
+    let mut sensor_pwm_driver = ledc::LedcDriver::new(
+        pwm_channel,
+        ledc::LedcTimerDriver::new(pwm_timer, &pwm_config)?,
+        pwm_pin,
+        &pwm_config,
+    )?;
+
+    sensor_pwm_driver.set_duty(sensor_pwm_driver.get_max_duty() / 100)?;
+    Ok(sensor_pwm_driver)
+}
+
+#[cfg(not(feature = "continuous"))]
+fn sample_one_shot<P: gpio::ADCPin<Adc = adc::ADC1>>(
+    adc: impl Peripheral<P = adc::ADC1>,
+    adc_pin: impl Peripheral<P = P>,
+) -> Result<Vec<u16>> {
+    let mut adc_driver = adc::AdcDriver::new(adc, &adc::config::Config::new().calibration(true))?;
+    let mut adc_channel_driver: adc::AdcChannelDriver<P, adc::Atten11dB<_>> =
+        adc::AdcChannelDriver::new(adc_pin)?;
+
+    let mut trace = Trace::new();
+    loop {
+        Ets::delay_ms(settling::POLL_INTERVAL_MS);
+        match trace.update(adc_driver.read(&mut adc_channel_driver)?) {
+            Progress::Settling => {}
+            Progress::Settled => break,
+            Progress::TimedOut => {
+                trace.record_timeout(crate::slow_clock_seconds());
+                break;
+            }
+        }
+    }
+
+    let mut samples = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        samples.push(adc_driver.read(&mut adc_channel_driver)?);
+    }
+    Ok(samples)
+}
@@ -0,0 +1,128 @@
+//! Probes sampled each wake, identified by the ADC1 channel they're wired to. The on-board probe on
+//! GPIO4 is always sampled and its readings drive alerts and the schedule. With the `multi-probe`
+//! feature, a second probe can be wired to GPIO3 and excited by the same PWM signal. GPIO0 and
+//! GPIO1 carry the 32 kHz crystal and GPIO2 is a strapping pin, so there is no room for more.
+//!
+//! Once probes are configured, readings are tagged with their `channel` and with the `probe` name.
+
+use crate::error::Classify;
+use crate::error_code::ErrorCode;
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+const NVS_NAMESPACE: &str = "probes";
+const NVS_KEY: &str = "probes";
+
+/// ADC1 channel of the on-board probe on GPIO4.
+pub const PRIMARY_CHANNEL: u8 = 4;
+/// ADC1 channel of the second probe on GPIO3.
+pub const SECOND_CHANNEL: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probe {
+    /// ADC1 channel, which is the GPIO number on the ESP32-C3.
+    pub channel: u8,
+    /// Value of the `probe` tag of its readings.
+    pub name: String,
+}
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Vec<Probe>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, probes: &[Probe]) -> Result<()> {
+    validate(probes)?;
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    if probes.is_empty() {
+        namespace.remove(NVS_KEY)
+    } else {
+        namespace.set(NVS_KEY, &probes)
+    }
+}
+
+/// Loads the probes to sample. A stored configuration that doesn't fit this build, e.g. one saved
+/// by a build with `multi-probe`, is ignored, and only the on-board probe is sampled.
+pub fn load_valid(partition: &EspDefaultNvsPartition) -> Result<Vec<Probe>> {
+    let probes = load(partition)?;
+    Ok(if validate(&probes).is_ok() {
+        probes
+    } else {
+        Vec::new()
+    })
+}
+
+/// Checks the stored configuration, so that one ignored by [`load_valid`] can be reported.
+pub fn check(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let probes = load(partition)?;
+    Ok(validate(&probes).sensor(ErrorCode::ProbeConfig)?)
+}
+
+/// Returns the ADC1 channels that probes can be wired to.
+pub fn available_channels() -> &'static [u8] {
+    if cfg!(feature = "multi-probe") {
+        &[PRIMARY_CHANNEL, SECOND_CHANNEL]
+    } else {
+        &[PRIMARY_CHANNEL]
+    }
+}
+
+/// Checks that `probes` are on available channels, each at most once, and named.
+pub fn validate(probes: &[Probe]) -> Result<()> {
+    for (i, probe) in probes.iter().enumerate() {
+        if !available_channels().contains(&probe.channel) {
+            bail!(
+                "no probe can be wired to ADC1 channel {}, available are {:?}",
+                probe.channel,
+                available_channels()
+            );
+        }
+        if probes[..i].iter().any(|p| p.channel == probe.channel) {
+            bail!("ADC1 channel {} is configured twice", probe.channel);
+        }
+        if probe.name.is_empty() {
+            bail!("probe on ADC1 channel {} has no name", probe.channel);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the channels sampled each wake, the on-board probe first.
+pub fn channels(probes: &[Probe]) -> Vec<u8> {
+    let mut channels = vec![PRIMARY_CHANNEL];
+    channels.extend(
+        probes
+            .iter()
+            .map(|probe| probe.channel)
+            .filter(|&channel| channel != PRIMARY_CHANNEL),
+    );
+    channels
+}
+
+#[test]
+pub fn test_validate() {
+    let probe = |channel, name: &str| Probe {
+        channel,
+        name: name.into(),
+    };
+    validate(&[]).unwrap();
+    validate(&[probe(PRIMARY_CHANNEL, "front")]).unwrap();
+    assert!(validate(&[probe(PRIMARY_CHANNEL, "")]).is_err());
+    assert!(validate(&[probe(0, "crystal")]).is_err());
+    assert!(validate(&[probe(PRIMARY_CHANNEL, "a"), probe(PRIMARY_CHANNEL, "b")]).is_err());
+    let result = validate(&[
+        probe(PRIMARY_CHANNEL, "front"),
+        probe(SECOND_CHANNEL, "back"),
+    ]);
+    assert_eq!(result.is_ok(), cfg!(feature = "multi-probe"));
+
+    assert_eq!(channels(&[]), vec![PRIMARY_CHANNEL]);
+    assert_eq!(
+        channels(&[
+            probe(SECOND_CHANNEL, "back"),
+            probe(PRIMARY_CHANNEL, "front")
+        ]),
+        vec![PRIMARY_CHANNEL, SECOND_CHANNEL]
+    );
+}
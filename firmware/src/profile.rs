@@ -0,0 +1,137 @@
+//! Operating profiles, switchable at runtime. The standard profile uses the configured schedule
+//! and alert policy. The seedling profile is meant for propagation trays under a humidity dome,
+//! whose substrate must never dry out: it samples at a short fixed interval and alerts as soon as
+//! readings leave the wet range, overriding the schedule bands and the alert policy.
+
+use crate::alert::Policy;
+use crate::schedule::Band;
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+const NVS_NAMESPACE: &str = "profile";
+const NVS_KEY: &str = "settings";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    #[default]
+    Standard,
+    Seedling,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Standard => write!(f, "standard"),
+            Profile::Seedling => write!(f, "seedling"),
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Profile> {
+        match s {
+            "standard" => Ok(Profile::Standard),
+            "seedling" => Ok(Profile::Seedling),
+            _ => bail!("unknown profile, expected standard or seedling: {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub active: Profile,
+    /// Interval between cycles in seconds in the seedling profile.
+    pub seedling_interval: u32,
+    /// Reading in mV at and above which the substrate is too dry in the seedling profile. Alerts
+    /// are disabled if 0.
+    pub seedling_dry_above: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            active: Profile::Standard,
+            seedling_interval: 600,
+            seedling_dry_above: 0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Settings> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    /// Returns the schedule bands of the active profile.
+    pub fn bands(&self, configured: Vec<Band>) -> Vec<Band> {
+        match self.active {
+            Profile::Standard => configured,
+            Profile::Seedling => vec![Band {
+                from: 0,
+                interval: self.seedling_interval,
+            }],
+        }
+    }
+
+    /// Returns the alert policy of the active profile.
+    pub fn alert_policy(&self, configured: Policy) -> Policy {
+        match self.active {
+            Profile::Standard => configured,
+            Profile::Seedling => Policy {
+                dry_above: self.seedling_dry_above,
+                critical_above: 0,
+            },
+        }
+    }
+}
+
+pub fn set_active(partition: &EspDefaultNvsPartition, profile: Profile) -> Result<()> {
+    let mut settings = Settings::load(partition)?;
+    settings.active = profile;
+    settings.save(partition)?;
+    println!("profile {} active", profile);
+    Ok(())
+}
+
+#[test]
+pub fn test_settings() {
+    let configured = Policy {
+        dry_above: 2000,
+        critical_above: 2400,
+    };
+    let mut settings = Settings::default();
+    assert_eq!(settings.alert_policy(configured.clone()), configured);
+    assert_eq!(settings.bands(vec![]), vec![]);
+
+    settings.active = "seedling".parse().unwrap();
+    settings.seedling_dry_above = 1300;
+    assert_eq!(
+        settings.alert_policy(configured),
+        Policy {
+            dry_above: 1300,
+            critical_above: 0,
+        }
+    );
+    assert_eq!(
+        settings.bands(vec![]),
+        vec![Band {
+            from: 0,
+            interval: 600,
+        }]
+    );
+
+    assert_eq!(Profile::Seedling.to_string(), "seedling");
+    assert!("dome".parse::<Profile>().is_err());
+}
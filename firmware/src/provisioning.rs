@@ -0,0 +1,73 @@
+//! Provisioning of WiFi credentials at runtime, with the methods enabled by features. Provisioning
+//! is requested with a command and runs the next time WiFi is started.
+
+use crate::rtc::STATE;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::wifi::EspWifi;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    #[cfg(feature = "smartconfig")]
+    SmartConfig,
+    #[cfg(feature = "dpp")]
+    Dpp,
+    #[cfg(feature = "softap")]
+    SoftAp,
+}
+
+impl FromStr for Method {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            #[cfg(feature = "smartconfig")]
+            "smartconfig" => Ok(Method::SmartConfig),
+            #[cfg(feature = "dpp")]
+            "dpp" => Ok(Method::Dpp),
+            #[cfg(feature = "softap")]
+            "softap" => Ok(Method::SoftAp),
+            _ => bail!("unknown provisioning method: {}", s),
+        }
+    }
+}
+
+pub fn request(method: Method) {
+    unsafe {
+        STATE.provisioning = Some(method);
+    }
+}
+
+pub fn pending() -> Option<Method> {
+    unsafe { STATE.provisioning }
+}
+
+/// Runs the pending provisioning and returns the received SSID and password. WiFi has to be
+/// started, but not connected.
+#[allow(unused_variables)]
+pub fn receive(
+    method: Method,
+    wifi: &mut EspWifi<'static>,
+    partition: &EspDefaultNvsPartition,
+    timeout: Duration,
+) -> Result<(String, String)> {
+    unsafe {
+        STATE.provisioning = None;
+    }
+    match method {
+        #[cfg(feature = "smartconfig")]
+        Method::SmartConfig => crate::smartconfig::receive(timeout),
+        #[cfg(feature = "dpp")]
+        Method::Dpp => crate::dpp::receive(timeout),
+        #[cfg(feature = "softap")]
+        Method::SoftAp => crate::softap::receive(wifi, partition, timeout),
+    }
+}
+
+/// Converts a zero padded byte array as used by the WiFi driver.
+pub fn c_bytes(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
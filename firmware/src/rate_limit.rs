@@ -0,0 +1,38 @@
+//! Protects shared ingest endpoints by limiting how often the sensor uploads, whatever triggers a
+//! cycle (button presses, resets, ...). Measurements that can't be uploaded stay buffered. The
+//! limits are stored here, uploads are counted against them by [`crate::scheduler`].
+
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use std::time::Duration;
+
+pub use crate::scheduler::UploadLimits as Limits;
+pub use crate::scheduler::{
+    DEFAULT_MAX_UPLOADS_PER_DAY as DEFAULT_MAX_PER_DAY,
+    DEFAULT_MIN_UPLOAD_INTERVAL as DEFAULT_MIN_INTERVAL,
+};
+
+const NVS_NAMESPACE: &str = "rate_limit";
+const MIN_INTERVAL_NVS_KEY: &str = "min_interval";
+const MAX_PER_DAY_NVS_KEY: &str = "max_per_day";
+
+impl Limits {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Limits> {
+        let namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+        Ok(Limits {
+            min_interval: namespace
+                .get(MIN_INTERVAL_NVS_KEY)?
+                .map_or(DEFAULT_MIN_INTERVAL, Duration::from_secs),
+            max_per_day: namespace
+                .get(MAX_PER_DAY_NVS_KEY)?
+                .unwrap_or(DEFAULT_MAX_PER_DAY),
+        })
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+        namespace.set(MIN_INTERVAL_NVS_KEY, &self.min_interval.as_secs())?;
+        namespace.set(MAX_PER_DAY_NVS_KEY, &self.max_per_day)
+    }
+}
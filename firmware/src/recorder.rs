@@ -0,0 +1,171 @@
+//! Debug facility recording the inputs of each wake cycle to NVS, so that anomalies observed in
+//! the field can be reproduced. The record of a cycle is collected in RTC memory and written to
+//! flash at the start of the following cycle, which also covers cycles that ended in an error.
+//! The records are printed as CSV by `record dump`, which `soil-codec replay` runs through the
+//! filtering of the samples and the watering detection on a host.
+
+use crate::rtc::STATE;
+use crate::sampling;
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const NVS_NAMESPACE: &str = "recorder";
+const ENABLED_NVS_KEY: &str = "enabled";
+const RECORDS_NVS_KEY: &str = "cycles";
+const MAX_RECORDS: usize = 64;
+/// Unfiltered samples recorded per cycle. Of the larger captures of ADC continuous mode, only the
+/// first are recorded.
+const RECORDED_SAMPLES: usize = sampling::SAMPLES;
+const RECORD_SIZE: usize = 12 + 2 * RECORDED_SAMPLES;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CycleRecord {
+    /// Slow clock time in seconds at which the sensor was read.
+    pub time: u32,
+    /// Raw sensor reading, if successful.
+    pub value: Option<u16>,
+    /// Unfiltered samples of the reading, of which the first `sample_count` are valid.
+    samples: [u16; RECORDED_SAMPLES],
+    sample_count: u8,
+    /// WiFi signal strength in dBm, if connected.
+    pub rssi: Option<i8>,
+    pub awake_ms: u32,
+}
+
+impl CycleRecord {
+    pub fn samples(&self) -> &[u16] {
+        &self.samples[..usize::from(self.sample_count)]
+    }
+
+    pub fn set_samples(&mut self, samples: &[u16]) {
+        let count = samples.len().min(RECORDED_SAMPLES);
+        self.samples[..count].copy_from_slice(&samples[..count]);
+        self.sample_count = count as u8;
+    }
+}
+
+/// Returns the record of the current cycle.
+pub fn current() -> &'static mut CycleRecord {
+    unsafe { STATE.cycle_record.get_or_insert_with(CycleRecord::default) }
+}
+
+/// Stores the record of the previous cycle if recording is enabled.
+pub fn store_previous(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let record = match unsafe { STATE.cycle_record.take() } {
+        Some(record) => record,
+        None => return Ok(()),
+    };
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    if !namespace.get_or_default::<bool>(ENABLED_NVS_KEY)? {
+        return Ok(());
+    }
+
+    let mut records = load_from(&namespace)?;
+    if records.len() >= MAX_RECORDS {
+        records.remove(0);
+    }
+    records.push(record);
+    save(&mut namespace, &records)
+}
+
+pub fn is_enabled(partition: &EspDefaultNvsPartition) -> Result<bool> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(ENABLED_NVS_KEY)
+}
+
+pub fn set_enabled(partition: &EspDefaultNvsPartition, enabled: bool) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    namespace.set(ENABLED_NVS_KEY, &enabled)?;
+    if enabled {
+        save(&mut namespace, &[])?;
+    }
+    Ok(())
+}
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Vec<CycleRecord>> {
+    load_from(&Namespace::open(partition, NVS_NAMESPACE)?)
+}
+
+/// Records are stored in a compact binary format rather than JSON to fit many of them.
+fn load_from(namespace: &Namespace) -> Result<Vec<CycleRecord>> {
+    Ok(match namespace.get_bytes(RECORDS_NVS_KEY)? {
+        Some(data) => data.chunks_exact(RECORD_SIZE).map(decode).collect(),
+        None => Vec::new(),
+    })
+}
+
+fn save(namespace: &mut Namespace, records: &[CycleRecord]) -> Result<()> {
+    let data: Vec<u8> = records.iter().flat_map(encode).collect();
+    namespace.set_bytes(RECORDS_NVS_KEY, &data)
+}
+
+/// Prints the recorded cycles as CSV, with the samples separated by spaces.
+pub fn dump(records: &[CycleRecord]) {
+    println!("time,value,rssi,awake_ms,samples");
+    for record in records {
+        let samples: Vec<_> = record.samples().iter().map(u16::to_string).collect();
+        println!(
+            "{},{},{},{},{}",
+            record.time,
+            record.value.map_or(String::new(), |v| v.to_string()),
+            record.rssi.map_or(String::new(), |v| v.to_string()),
+            record.awake_ms,
+            samples.join(" ")
+        );
+    }
+}
+
+fn encode(record: &CycleRecord) -> [u8; RECORD_SIZE] {
+    let mut data = [0; RECORD_SIZE];
+    data[0..4].copy_from_slice(&record.time.to_le_bytes());
+    data[4..6].copy_from_slice(&record.value.unwrap_or(u16::MAX).to_le_bytes());
+    data[6] = record.rssi.unwrap_or(i8::MIN) as u8;
+    data[7..11].copy_from_slice(&record.awake_ms.to_le_bytes());
+    data[11] = record.sample_count;
+    for (i, sample) in record.samples.iter().enumerate() {
+        data[12 + 2 * i..14 + 2 * i].copy_from_slice(&sample.to_le_bytes());
+    }
+    data
+}
+
+fn decode(data: &[u8]) -> CycleRecord {
+    let value = u16::from_le_bytes([data[4], data[5]]);
+    let rssi = data[6] as i8;
+    let mut samples = [0; RECORDED_SAMPLES];
+    for (i, sample) in samples.iter_mut().enumerate() {
+        *sample = u16::from_le_bytes([data[12 + 2 * i], data[13 + 2 * i]]);
+    }
+    CycleRecord {
+        time: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        value: (value != u16::MAX).then_some(value),
+        rssi: (rssi != i8::MIN).then_some(rssi),
+        awake_ms: u32::from_le_bytes([data[7], data[8], data[9], data[10]]),
+        samples,
+        sample_count: data[11].min(RECORDED_SAMPLES as u8),
+    }
+}
+
+#[test]
+pub fn test_encode_decode() {
+    let mut recorded = CycleRecord {
+        time: 123456,
+        value: Some(2048),
+        rssi: Some(-67),
+        awake_ms: 4321,
+        ..Default::default()
+    };
+    recorded.set_samples(&[2040, 2050, 2048, 2047, 2049, 2046, 2051, 2048, 2048, 2900]);
+    assert_eq!(recorded.samples().len(), RECORDED_SAMPLES);
+    assert_eq!(recorded.samples()[8], 2048);
+    let records = [
+        recorded,
+        CycleRecord {
+            time: 1,
+            ..Default::default()
+        },
+    ];
+    for record in records {
+        assert_eq!(decode(&encode(&record)), record);
+    }
+    assert!(records[1].samples().is_empty());
+}
@@ -0,0 +1,114 @@
+//! Retry policy for the network part of a cycle. An upload is attempted up to `MAX_ATTEMPTS` times
+//! within the session, with exponentially growing pauses in between. If the network part of a
+//! cycle fails nonetheless, the next `SKIPPED_WAKES` cycles only buffer their measurements instead
+//! of bringing up WiFi, which saves the battery while WiFi or the server are down.
+//!
+//! Failures are classified by their [`ErrorCode`]. Rejected and unauthorized requests, and
+//! responses too large to read, aren't retried, as they would fail again. Rate limited requests are
+//! retried after the pause the server asks for with `Retry-After`, if it fits into the session.
+//! Server errors and network-level errors are retried with the growing pauses.
+
+use crate::error::{self, FirmwareError};
+use crate::error_code::ErrorCode;
+use crate::rtc::STATE;
+use crate::session::Session;
+use anyhow::Result;
+use esp_idf_hal::delay::FreeRtos;
+use std::time::Duration;
+
+pub use crate::scheduler::SKIPPED_WAKES;
+
+pub const MAX_ATTEMPTS: u32 = 3;
+/// Pause before the second attempt, doubled for each further one.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Runs `task` within `session`, retrying it after errors that may be transient.
+pub fn run<T>(session: &Session, name: &str, mut task: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        let e = match session.run(name, &mut task) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let retry_after = e
+            .downcast_ref::<FirmwareError>()
+            .and_then(FirmwareError::retry_after);
+        let pause = match pause(error::code(&e), retry_after, attempt) {
+            Some(pause) if session.remaining() > pause => pause,
+            _ => return Err(e),
+        };
+        println!(
+            "{} failed, retrying in {} s: {:#}",
+            name,
+            pause.as_secs(),
+            e
+        );
+        FreeRtos::delay_ms(pause.as_millis() as u32);
+        attempt += 1;
+    }
+}
+
+/// Returns the pause before retrying after the failed attempt number `attempt` with `code`, or
+/// `None` if it isn't retried.
+fn pause(code: ErrorCode, retry_after: Option<Duration>, attempt: u32) -> Option<Duration> {
+    if attempt >= MAX_ATTEMPTS {
+        return None;
+    }
+    match code {
+        ErrorCode::Http4xx | ErrorCode::HttpUnauthorized | ErrorCode::HttpResponseTooLarge => None,
+        ErrorCode::HttpRateLimited => Some(retry_after.unwrap_or_else(|| backoff(attempt))),
+        _ => Some(backoff(attempt)),
+    }
+}
+
+/// Parses the value of a `Retry-After` header. Only delays in seconds are supported, as dates would
+/// need the time to be synced.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Returns the pause after the failed attempt number `attempt`, counted from 1.
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF * 2u32.pow(attempt - 1)
+}
+
+/// Records that the network part of the current cycle failed.
+pub fn record_failure() {
+    let failures = unsafe { STATE.scheduler.network_failed() };
+    println!(
+        "network failed in {} consecutive cycles, skipping the next {}",
+        failures, SKIPPED_WAKES
+    );
+}
+
+pub fn record_success() {
+    unsafe {
+        STATE.scheduler.network_succeeded();
+    }
+}
+
+#[test]
+pub fn test_pause() {
+    assert_eq!(backoff(1), Duration::from_secs(2));
+    assert_eq!(backoff(2), Duration::from_secs(4));
+    assert_eq!(backoff(3), Duration::from_secs(8));
+
+    let retry_after = Some(Duration::from_secs(30));
+    assert_eq!(pause(ErrorCode::Http5xx, None, 1), Some(backoff(1)));
+    assert_eq!(
+        pause(ErrorCode::HttpConnect, retry_after, 2),
+        Some(backoff(2))
+    );
+    assert_eq!(pause(ErrorCode::Http5xx, None, MAX_ATTEMPTS), None);
+    assert_eq!(pause(ErrorCode::Http4xx, None, 1), None);
+    assert_eq!(pause(ErrorCode::HttpUnauthorized, retry_after, 1), None);
+    assert_eq!(pause(ErrorCode::HttpResponseTooLarge, None, 1), None);
+    assert_eq!(
+        pause(ErrorCode::HttpRateLimited, retry_after, 1),
+        retry_after
+    );
+    assert_eq!(pause(ErrorCode::HttpRateLimited, None, 2), Some(backoff(2)));
+    assert_eq!(parse_retry_after(" 120"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    assert_eq!(parse_retry_after("-1"), None);
+}
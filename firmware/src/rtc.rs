@@ -0,0 +1,340 @@
+//! All state kept in RTC memory across deep sleep. Modules work on a copy of the control state in
+//! regular memory, which is committed to one of two snapshots in RTC memory at consistent points of
+//! the cycle: the inactive snapshot is written and checksummed before it's made the active one, so
+//! that a reset at any instant leaves a consistent snapshot to resume from.
+//!
+//! The snapshots are kept in RTC memory that isn't initialized, as the RTC data section is
+//! reloaded at every reset other than a wake from deep sleep, which would lose them to a panic or
+//! a watchdog reset. After powering on, the memory holds arbitrary bytes, which the version and the
+//! checksum reject, so the cycle starts with an empty state.
+//!
+//! The logs appended to every cycle, most of all the measurements, don't fit into RTC memory twice.
+//! They are kept in place in RTC memory, and the snapshots only record which of their elements are
+//! valid. Elements appended after a commit go to free slots, so a reset leaves the recorded ones
+//! intact, unless a full log overwrote its oldest elements.
+
+use crate::alert;
+use crate::arr_deque::{ArrDeque, Span};
+use crate::detections::{self, Detection};
+use crate::diagnostics::{self, Diagnostic};
+use crate::error_code::{self, Failure};
+use crate::recorder::CycleRecord;
+use crate::scheduler;
+use crate::settling;
+use crate::skips::{self, Skip};
+use crate::spread::{self, Stats};
+use crate::uptime;
+use crate::watering;
+use crate::{Measurement, Phase, TimeSource, MAX_RECORDED_MEASUREMENTS};
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::atomic::{fence, Ordering};
+
+/// RTC fast memory of the ESP32-C3, which holds the RTC data and the uninitialized RTC sections.
+pub const BUDGET: usize = 8 * 1024;
+
+/// Version of the layout of `RtcState` and `Logs`. Increment when changing it, so that a snapshot
+/// written by previous firmware isn't resumed from.
+const VERSION: u32 = 29;
+
+const _: () = assert!(
+    size_of::<Logs>() + size_of::<[Snapshot; 2]>() + size_of::<usize>() <= BUDGET,
+    "RTC state exceeds the RTC memory budget"
+);
+
+#[derive(Hash)]
+pub struct RtcState {
+    /// Number of unsent measurements dropped from the full buffer since the last successful
+    /// upload.
+    pub overwritten_measurements: u32,
+    pub locate_pending: bool,
+    /// Phase of the wake cycle in progress.
+    pub phase: Phase,
+    /// Whether the current cycle resumes an interrupted one. A resumed cycle that is interrupted
+    /// again isn't resumed a second time.
+    pub resumed: bool,
+    /// Offset of the slow clock to UTC in seconds, once synced in the current cycle.
+    pub time_offset: Option<i64>,
+    pub time_source: TimeSource,
+    /// Offset of the slow clock to UTC in seconds as of the latest sync, kept across cycles.
+    pub synced_offset: Option<i64>,
+    /// Offset of the slow clock to UTC in seconds as set manually.
+    pub manual_time_offset: Option<i64>,
+    /// Slow clock time in seconds at which maintenance mode ends.
+    pub maintenance_until: u32,
+    /// Interval until the next cycle, retry backoff and uploads of the day, see `scheduler`.
+    pub scheduler: scheduler::State,
+    /// Latest reading of the ADC self-test in mV.
+    pub reference_mv: Option<u16>,
+    pub last_failure: Option<Failure>,
+    /// Wake cycles since RTC memory was lost, counting the current one.
+    pub wakes: u32,
+    pub uptime: uptime::Counters,
+    pub watering: watering::Detector,
+    pub settling_timeout: Option<settling::Timeout>,
+    /// Awake time of the previous cycle, added to the health counters at the next boot.
+    pub awake_ms: u32,
+    pub cycle_record: Option<CycleRecord>,
+    /// Diagnostic of the current cycle, if diagnostics are recorded.
+    pub diagnostic: Option<Diagnostic>,
+    pub alert: alert::State,
+    /// Boots in a row that didn't store a measurement, see `safe_mode`.
+    pub failed_boots: u8,
+    /// Subsystems that weren't ready in the cycles since the last upload, as bit set of
+    /// `startup::Subsystem`.
+    pub not_ready: u16,
+    #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+    pub provisioning: Option<crate::provisioning::Method>,
+    #[cfg(feature = "fake-sensor")]
+    pub simulation: crate::fake_sensor::Simulation,
+}
+
+impl RtcState {
+    const fn new() -> RtcState {
+        RtcState {
+            overwritten_measurements: 0,
+            locate_pending: false,
+            phase: Phase::Sleep,
+            resumed: false,
+            time_offset: None,
+            time_source: TimeSource::Sntp,
+            synced_offset: None,
+            manual_time_offset: None,
+            maintenance_until: 0,
+            scheduler: scheduler::State::new(),
+            reference_mv: None,
+            last_failure: None,
+            wakes: 0,
+            uptime: uptime::Counters::new(),
+            watering: watering::Detector::new(),
+            settling_timeout: None,
+            awake_ms: 0,
+            cycle_record: None,
+            diagnostic: None,
+            alert: alert::State::new(),
+            failed_boots: 0,
+            not_ready: 0,
+            #[cfg(any(feature = "smartconfig", feature = "dpp", feature = "softap"))]
+            provisioning: None,
+            #[cfg(feature = "fake-sensor")]
+            simulation: crate::fake_sensor::Simulation::new(0x2545_f491),
+        }
+    }
+}
+
+/// Working copy of the state.
+pub static mut STATE: RtcState = RtcState::new();
+
+const LOG_COUNT: usize = 6;
+
+/// Logs of the cycles, the oldest elements are dropped if there are more than fit.
+pub struct Logs {
+    pub measurements: ArrDeque<Measurement, MAX_RECORDED_MEASUREMENTS>,
+    /// Journal of failures since the last upload.
+    pub error_events: ArrDeque<error_code::Event, { error_code::MAX_EVENTS }>,
+    pub skips: ArrDeque<Skip, { skips::MAX_SKIPS }>,
+    /// Sampling statistics.
+    pub sampling: ArrDeque<Stats, { spread::MAX_STATS }>,
+    pub diagnostics: ArrDeque<Diagnostic, { diagnostics::MAX_DIAGNOSTICS }>,
+    /// Events detected in the readings since the last upload.
+    pub detections: ArrDeque<Detection, { detections::MAX_DETECTIONS }>,
+}
+
+impl Logs {
+    const fn new() -> Logs {
+        Logs {
+            measurements: ArrDeque::new(),
+            error_events: ArrDeque::new(),
+            skips: ArrDeque::new(),
+            sampling: ArrDeque::new(),
+            diagnostics: ArrDeque::new(),
+            detections: ArrDeque::new(),
+        }
+    }
+
+    fn spans(&self) -> [Span; LOG_COUNT] {
+        [
+            self.measurements.span(),
+            self.error_events.span(),
+            self.skips.span(),
+            self.sampling.span(),
+            self.diagnostics.span(),
+            self.detections.span(),
+        ]
+    }
+
+    /// Makes the logs hold the elements at `spans`, which have to be returned by `spans` before.
+    unsafe fn set_spans(&mut self, spans: [Span; LOG_COUNT]) {
+        let [measurements, error_events, skips, sampling, diagnostics, detections] = spans;
+        self.measurements.set_span(measurements);
+        self.error_events.set_span(error_events);
+        self.skips.set_span(skips);
+        self.sampling.set_span(sampling);
+        self.diagnostics.set_span(diagnostics);
+        self.detections.set_span(detections);
+    }
+}
+
+/// The logs, kept in place.
+#[link_section = ".rtc_noinit.rtc_memory"]
+pub static mut LOGS: Logs = Logs::new();
+
+struct Snapshot {
+    version: u32,
+    checksum: u32,
+    /// Spans of the valid elements of `LOGS`.
+    spans: [Span; LOG_COUNT],
+    state: RtcState,
+}
+
+impl Snapshot {
+    const fn new() -> Snapshot {
+        Snapshot {
+            version: 0,
+            checksum: 0,
+            spans: [Span::EMPTY; LOG_COUNT],
+            state: RtcState::new(),
+        }
+    }
+
+    /// Whether the snapshot was committed by this firmware. The version is checked first, so that
+    /// the fields of arbitrary bytes aren't read.
+    fn is_valid(&self) -> bool {
+        self.version == VERSION && self.checksum == self.checksum()
+    }
+
+    /// 32 bit FNV-1a over the fields, so that neither padding nor uninitialized memory is read.
+    fn checksum(&self) -> u32 {
+        let mut hasher = Fnv(0x811c_9dc5);
+        self.spans.hash(&mut hasher);
+        self.state.hash(&mut hasher);
+        hasher.0
+    }
+}
+
+struct Fnv(u32);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0.into()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u32::from(byte)).wrapping_mul(0x0100_0193);
+        }
+    }
+}
+
+#[link_section = ".rtc_noinit.rtc_memory"]
+static mut SNAPSHOTS: [Snapshot; 2] = [Snapshot::new(), Snapshot::new()];
+
+/// Index of the snapshot to resume from.
+#[link_section = ".rtc_noinit.rtc_memory"]
+static mut ACTIVE: usize = 0;
+
+/// Loads the state from the active snapshot, or from the other one if the active one is invalid.
+/// The state and the logs are empty if neither is valid, as after powering on.
+pub fn restore() {
+    unsafe {
+        let active = ACTIVE % 2;
+        for i in [active, 1 - active] {
+            if SNAPSHOTS[i].is_valid() {
+                if i != active {
+                    println!("active RTC state snapshot invalid, restoring the previous one");
+                }
+                std::ptr::copy_nonoverlapping(&SNAPSHOTS[i].state, &mut STATE, 1);
+                LOGS.set_spans(SNAPSHOTS[i].spans);
+                ACTIVE = i;
+                return;
+            }
+        }
+        println!("no valid RTC state snapshot, starting with an empty state");
+        LOGS.set_spans([Span::EMPTY; LOG_COUNT]);
+    }
+}
+
+/// Writes the state and the spans of the logs to the inactive snapshot and makes it the active one.
+pub fn commit() {
+    unsafe {
+        let inactive = 1 - ACTIVE % 2;
+        let snapshot = &mut SNAPSHOTS[inactive];
+        snapshot.version = VERSION;
+        snapshot.spans = LOGS.spans();
+        std::ptr::copy_nonoverlapping(&STATE, &mut snapshot.state, 1);
+        snapshot.checksum = snapshot.checksum();
+        fence(Ordering::SeqCst);
+        std::ptr::write_volatile(&mut ACTIVE, inactive);
+    }
+}
+
+/// Share of the RTC memory budget used by the logs and the snapshots.
+pub fn utilization() -> f64 {
+    (size_of::<Logs>() + size_of::<[Snapshot; 2]>() + size_of::<usize>()) as f64 / BUDGET as f64
+}
+
+#[test]
+pub fn test_commit() {
+    unsafe {
+        // Powering on, with arbitrary bytes in the uninitialized RTC memory.
+        std::ptr::write_bytes(
+            std::ptr::addr_of_mut!(SNAPSHOTS).cast::<u8>(),
+            0xa5,
+            size_of::<[Snapshot; 2]>(),
+        );
+        std::ptr::write_bytes(
+            std::ptr::addr_of_mut!(LOGS).cast::<u8>(),
+            0xa5,
+            size_of::<Logs>(),
+        );
+        std::ptr::write_bytes(std::ptr::addr_of_mut!(ACTIVE), 0xa5, 1);
+        restore();
+        assert_eq!(STATE.maintenance_until, 0);
+        assert!(LOGS.measurements.is_empty());
+        assert!(LOGS.sampling.is_empty());
+
+        STATE.maintenance_until = 1;
+        commit();
+        STATE.maintenance_until = 2;
+        commit();
+        STATE.maintenance_until = 3;
+        restore();
+        assert_eq!(STATE.maintenance_until, 2);
+
+        // Reset while writing the active snapshot.
+        SNAPSHOTS[ACTIVE].state.maintenance_until = 4;
+        restore();
+        assert_eq!(STATE.maintenance_until, 1);
+
+        // Reset while writing the inactive snapshot.
+        STATE.maintenance_until = 5;
+        commit();
+        SNAPSHOTS[1 - ACTIVE].checksum ^= 1;
+        restore();
+        assert_eq!(STATE.maintenance_until, 5);
+
+        // Logs are restored to the elements committed last.
+        LOGS.sampling.overwriting_push_back(Stats {
+            time: 6,
+            variance: 0.5,
+        });
+        commit();
+        LOGS.sampling.overwriting_push_back(Stats {
+            time: 7,
+            variance: 0.5,
+        });
+        restore();
+        assert_eq!(LOGS.sampling.len(), 1);
+        assert_eq!(LOGS.sampling.back().map(|stats| stats.time), Some(6));
+
+        // A watchdog reset during an upload loses the working copy, but not the snapshots, so the
+        // cycle resumes at the upload after connecting again.
+        STATE.phase = Phase::Upload;
+        commit();
+        std::ptr::write(std::ptr::addr_of_mut!(STATE), RtcState::new());
+        assert_eq!(STATE.phase, Phase::Sleep);
+        restore();
+        assert_eq!(STATE.phase, Phase::Upload);
+        assert!(!STATE.resumed);
+    }
+}
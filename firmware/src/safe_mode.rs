@@ -0,0 +1,66 @@
+//! Safe mode after repeated failed boots. A boot counts as failed until the measurement of its
+//! cycle is stored, so that panics, watchdog resets and errors caused by a sensor driver or by the
+//! stored configuration are all counted. The count is committed to the RTC snapshots right away,
+//! which a reset doesn't initialize, see [`crate::rtc`], so only power loss starts it over. After
+//! `MAX_FAILED_BOOTS` failed boots in a row, a cycle runs in safe mode: the sensors aren't sampled
+//! and nothing is uploaded, only the console, provisioning, remote commands and the update check
+//! run, so that a remote device can still be fixed. The count starts over with safe mode, so the
+//! next cycle tries to measure again.
+
+use crate::rtc::{self, STATE};
+
+const MAX_FAILED_BOOTS: u8 = 3;
+
+/// Counts the boot as failed until [`measurement_stored`]. Called right after the RTC state is
+/// restored, and committed at once so that a crash later in the boot is counted.
+pub fn count_boot() {
+    unsafe {
+        STATE.failed_boots = STATE.failed_boots.saturating_add(1);
+    }
+    rtc::commit();
+}
+
+pub fn measurement_stored() {
+    unsafe {
+        STATE.failed_boots = 0;
+    }
+}
+
+/// Returns whether this boot runs in safe mode, starting the count over if it does.
+pub fn enter() -> bool {
+    // The count includes this boot.
+    let failed = unsafe { STATE.failed_boots }.saturating_sub(1);
+    if failed < MAX_FAILED_BOOTS {
+        return false;
+    }
+    println!("{} failed boots in a row, starting in safe mode", failed);
+    unsafe {
+        STATE.failed_boots = 0;
+    }
+    true
+}
+
+#[test]
+pub fn test_safe_mode() {
+    for _ in 0..MAX_FAILED_BOOTS {
+        count_boot();
+        assert!(!enter());
+    }
+    count_boot();
+    assert!(enter());
+    count_boot();
+    assert!(!enter());
+    measurement_stored();
+    // A panic loses the working copy, the committed count is restored.
+    count_boot();
+    unsafe {
+        STATE.failed_boots = 0;
+    }
+    rtc::restore();
+    assert_eq!(unsafe { STATE.failed_boots }, 1);
+    measurement_stored();
+    for _ in 0..MAX_FAILED_BOOTS {
+        count_boot();
+        assert!(!enter());
+    }
+}
@@ -0,0 +1,66 @@
+//! Filtering of probe readings. Each reading of the probe is made up of ADC samples taken while the
+//! probe is excited, see the `probe` module, of which the lowest and highest `TRIMMED` are
+//! discarded as outliers and the rest averaged. The variance of all samples is kept as well, see
+//! the `spread` module.
+//!
+//! The module doesn't use any types of the firmware, as the host crate `soil-codec` includes it
+//! to replay recorded cycles.
+
+pub const SAMPLES: usize = 9;
+/// Number of samples discarded at either end.
+const TRIMMED: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampled {
+    /// Trimmed mean in mV.
+    pub value: u16,
+    /// Variance of all samples in mV².
+    pub variance: f32,
+}
+
+/// Returns the trimmed mean and the variance of `samples`, which must not be empty.
+pub fn filter(samples: &mut [u16]) -> Sampled {
+    let variance = variance(samples);
+    samples.sort_unstable();
+    let trimmed = TRIMMED.min((samples.len() - 1) / 2);
+    let kept = &samples[trimmed..samples.len() - trimmed];
+    let count = kept.len() as u32;
+    let sum: u32 = kept.iter().map(|&s| u32::from(s)).sum();
+    Sampled {
+        value: ((sum + count / 2) / count) as u16,
+        variance,
+    }
+}
+
+pub fn variance(samples: &[u16]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mean = samples.iter().map(|&s| f64::from(s)).sum::<f64>() / samples.len() as f64;
+    (samples
+        .iter()
+        .map(|&s| (f64::from(s) - mean).powi(2))
+        .sum::<f64>()
+        / samples.len() as f64) as f32
+}
+
+#[test]
+pub fn test_filter() {
+    assert_eq!(variance(&[]), 0.0);
+    assert_eq!(variance(&[1000, 1002, 1000, 1002]), 1.0);
+
+    let mut samples = [1500, 1502, 2900, 1498, 1501, 0, 1499, 1503, 1500];
+    let sampled = filter(&mut samples);
+    assert_eq!(sampled.value, 1500);
+    assert!(sampled.variance > 100_000.0);
+
+    assert_eq!(filter(&mut [1200]).value, 1200);
+    assert_eq!(filter(&mut [1200, 1300]).value, 1250);
+    assert_eq!(
+        filter(&mut [1000, 1002, 1000, 1002]),
+        Sampled {
+            value: 1001,
+            variance: 1.0,
+        }
+    );
+}
@@ -0,0 +1,90 @@
+//! Interval ladder: the time until the next cycle depends on the moisture band of the latest
+//! reading, e.g. sampling more often when the soil nears the dryness threshold and rarely when it's
+//! saturated. Without bands, cycles are the measurement interval of the device configuration apart.
+//! The bands are stored here, the interval is selected by [`crate::scheduler`] with the state kept
+//! in RTC memory.
+
+use crate::rtc::STATE;
+use crate::scheduler::Clock;
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use std::time::Duration;
+
+pub use crate::scheduler::{Band, MIN_INTERVAL};
+
+const NVS_NAMESPACE: &str = "schedule";
+const NVS_KEY: &str = "bands";
+
+/// The clocks of the device, with the time of the latest sync carried forward, or set manually.
+pub struct RtcClock;
+
+impl Clock for RtcClock {
+    fn slow_clock(&self) -> u32 {
+        crate::slow_clock_seconds()
+    }
+
+    fn offset(&self) -> Option<i64> {
+        unsafe { STATE.synced_offset.or(STATE.manual_time_offset) }
+    }
+}
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Vec<Band>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, bands: &[Band]) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    if bands.is_empty() {
+        namespace.remove(NVS_KEY)
+    } else {
+        namespace.set(NVS_KEY, &bands)
+    }
+}
+
+/// Checks that `bands` are ordered by reading and have feasible intervals.
+pub fn validate(bands: &[Band]) -> Result<()> {
+    for band in bands {
+        if band.interval < MIN_INTERVAL {
+            bail!(
+                "interval of band from {} mV is shorter than {} s",
+                band.from,
+                MIN_INTERVAL
+            );
+        }
+    }
+    if bands.windows(2).any(|pair| pair[0].from >= pair[1].from) {
+        bail!("bands must be in increasing order of readings");
+    }
+    Ok(())
+}
+
+/// Returns the interval until the next cycle, or `default` if none has been selected.
+pub fn next_interval(default: Duration) -> Duration {
+    unsafe { STATE.scheduler.next_interval(default) }
+}
+
+#[test]
+pub fn test_validate() {
+    let bands = [
+        Band {
+            from: 1000,
+            interval: 21600,
+        },
+        Band {
+            from: 1500,
+            interval: 3600,
+        },
+        Band {
+            from: 1900,
+            interval: 900,
+        },
+    ];
+    validate(&bands).unwrap();
+    assert!(validate(&[bands[1].clone(), bands[0].clone()]).is_err());
+    assert!(validate(&[Band {
+        from: 0,
+        interval: 10
+    }])
+    .is_err());
+}
@@ -0,0 +1,749 @@
+//! Sequencing of the wake cycles: when the sensor wakes next, from the interval ladder, the
+//! adaptive interval, the jitter and the alignment to the fleet slot, and whether a cycle uploads,
+//! from the upload window, the rate limit and the backoff after failed cycles. The firmware makes
+//! these decisions only through [`State`], phase by phase. The time is read from a [`Clock`], and
+//! the module doesn't use any types of the firmware, so that the decisions of a series of cycles
+//! can be simulated on the host and checked against golden schedules. The settings are stored by
+//! `schedule`, `adaptive`, `fleet`, `upload_window` and `rate_limit`, and the state is kept in RTC
+//! memory.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Shortest interval of a band in seconds, which leaves time for a cycle with an upload.
+pub const MIN_INTERVAL: u32 = 60;
+/// Number of cycles after one whose network part failed that only buffer their measurements.
+pub const SKIPPED_WAKES: u32 = 3;
+/// Longest jitter added to an interval, as a fraction of it.
+pub const JITTER_FRACTION: u32 = 20;
+pub const DEFAULT_MIN_UPLOAD_INTERVAL: Duration = Duration::from_secs(15 * 60);
+pub const DEFAULT_MAX_UPLOADS_PER_DAY: u32 = 48;
+
+const DAY: u32 = 24 * 3600;
+
+/// Source of the time of a cycle.
+pub trait Clock {
+    /// Seconds since power-on, which keep counting in deep sleep.
+    fn slow_clock(&self) -> u32;
+    /// Offset of the slow clock from Unix time, if the time is known.
+    fn offset(&self) -> Option<i64>;
+}
+
+/// Band of the interval ladder: the time until the next cycle depends on the moisture band of the
+/// latest reading.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Band {
+    /// Lowest reading of the band in mV. The band extends to the next one, the first band also
+    /// covers lower readings.
+    pub from: u16,
+    /// Interval between cycles in seconds while readings are in the band.
+    pub interval: u32,
+}
+
+/// Settings of the adaptive interval, see [`crate::adaptive`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSettings {
+    pub enabled: bool,
+    /// Shortest interval between cycles in seconds.
+    pub min_interval: u32,
+    /// Longest interval between cycles in seconds.
+    pub max_interval: u32,
+    /// Change of readings in mV per hour from which the interval drops to the minimum.
+    pub rapid_rate: u16,
+    /// Change of readings in mV per hour up to which the interval is doubled.
+    pub flat_rate: u16,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> AdaptiveSettings {
+        AdaptiveSettings {
+            enabled: false,
+            min_interval: 15 * 60,
+            max_interval: 6 * 3600,
+            rapid_rate: 100,
+            flat_rate: 20,
+        }
+    }
+}
+
+/// Settings of the schedule in effect for a cycle.
+pub struct Plan {
+    pub bands: Vec<Band>,
+    /// Takes precedence over `bands` if enabled.
+    pub adaptive: AdaptiveSettings,
+    /// Interval if there are no bands, and the initial adaptive interval.
+    pub default: Duration,
+    /// Seed of the jitter, which differs between sensors.
+    pub seed: u32,
+}
+
+/// Hours of the day in which the sensor uploads, see [`crate::upload_window`]. Uploads are allowed
+/// all day if `start` and `end` are equal, as by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadWindow {
+    /// Local hour at which the window opens.
+    pub start: u8,
+    /// Local hour at which the window closes.
+    pub end: u8,
+    /// Offset of local time from UTC in minutes.
+    pub utc_offset: i16,
+}
+
+impl UploadWindow {
+    /// Returns whether uploads are allowed at Unix time `now`, if known.
+    pub fn allows(&self, now: Option<i64>) -> bool {
+        let local = match now {
+            Some(now) if self.start != self.end => now + i64::from(self.utc_offset) * 60,
+            _ => return true,
+        };
+        let hour = local.rem_euclid(24 * 60 * 60) / (60 * 60);
+        let (start, end) = (i64::from(self.start), i64::from(self.end));
+        if start < end {
+            (start..end).contains(&hour)
+        } else {
+            hour >= start || hour < end
+        }
+    }
+}
+
+/// Limits of how often the sensor uploads, see [`crate::rate_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UploadLimits {
+    pub min_interval: Duration,
+    pub max_per_day: u32,
+}
+
+impl Default for UploadLimits {
+    fn default() -> UploadLimits {
+        UploadLimits {
+            min_interval: DEFAULT_MIN_UPLOAD_INTERVAL,
+            max_per_day: DEFAULT_MAX_UPLOADS_PER_DAY,
+        }
+    }
+}
+
+/// Settings of the uploads in effect for a cycle.
+pub struct UploadPlan {
+    pub window: UploadWindow,
+    pub limits: UploadLimits,
+}
+
+/// Reason for a cycle not to upload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deferral {
+    /// Outside the upload window.
+    Window,
+    /// Too few measurements buffered.
+    Buffering,
+    /// Uploaded too recently or too often today.
+    RateLimit,
+    /// Skipping the network after a failed cycle.
+    Backoff,
+}
+
+/// Returns the interval of the band of `value`, if there are bands.
+fn band_interval(bands: &[Band], value: u16) -> Option<u32> {
+    let band = bands
+        .iter()
+        .rev()
+        .find(|band| band.from <= value)
+        .or(bands.first())?;
+    Some(band.interval)
+}
+
+/// State of the adaptive interval, see [`crate::adaptive`].
+#[derive(Hash)]
+struct Adaptive {
+    /// Slow clock time in seconds and value of the previous reading.
+    previous: Option<(u32, u16)>,
+    /// Interval until the next cycle in seconds, 0 if not adapted yet.
+    interval: u32,
+}
+
+impl Adaptive {
+    const fn new() -> Adaptive {
+        Adaptive {
+            previous: None,
+            interval: 0,
+        }
+    }
+
+    /// Adapts the interval to the reading `value` at slow clock time `time`, starting at `default`.
+    fn update(&mut self, settings: &AdaptiveSettings, value: u16, time: u32, default: u32) -> u32 {
+        let interval = if self.interval == 0 {
+            default
+        } else {
+            self.interval
+        };
+        let interval = match self.previous {
+            // The slow clock starts over after a power loss.
+            Some((previous_time, previous_value)) if previous_time < time => {
+                let change = u64::from(value.abs_diff(previous_value));
+                let rate = change * 3600 / u64::from(time - previous_time);
+                if rate >= u64::from(settings.rapid_rate) {
+                    settings.min_interval
+                } else if rate <= u64::from(settings.flat_rate) {
+                    interval.saturating_mul(2)
+                } else {
+                    interval
+                }
+            }
+            _ => interval,
+        };
+        self.previous = Some((time, value));
+        self.interval = interval.clamp(settings.min_interval, settings.max_interval);
+        self.interval
+    }
+}
+
+/// Returns the jitter added to `interval` at slow clock time `time`, up to `JITTER_FRACTION` of
+/// the interval. It's derived from `seed` and the time by an integer hash instead of a random
+/// number generator, so that it differs between sensors and cycles but schedules are reproducible.
+fn jitter(interval: u32, seed: u32, time: u32) -> u32 {
+    let mut hash = seed ^ time.wrapping_mul(0x9e37_79b9);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x846c_a68b);
+    hash ^= hash >> 16;
+    let range = interval / JITTER_FRACTION;
+    hash % (range + 1)
+}
+
+/// Uploads of the current day, limited by [`UploadLimits`].
+#[derive(Hash)]
+struct History {
+    /// Slow clock time in seconds of the last upload.
+    last: Option<u32>,
+    /// Start of the current day long window and the number of uploads in it.
+    day_start: u32,
+    count: u32,
+}
+
+impl History {
+    const fn new() -> History {
+        History {
+            last: None,
+            day_start: 0,
+            count: 0,
+        }
+    }
+
+    /// Returns whether an upload at slow clock time `now` is within `limits`.
+    fn allows(&self, now: u32, limits: &UploadLimits) -> bool {
+        let last = match self.last {
+            // The slow clock starts over after a power loss.
+            Some(last) if last <= now => last,
+            _ => return true,
+        };
+        let spaced = u64::from(now - last) >= limits.min_interval.as_secs();
+        let new_day = now - self.day_start >= DAY;
+        spaced && (new_day || self.count < limits.max_per_day)
+    }
+
+    fn record(&mut self, now: u32) {
+        if self.last.map_or(true, |last| last > now) || now - self.day_start >= DAY {
+            self.day_start = now;
+            self.count = 0;
+        }
+        self.last = Some(now);
+        self.count += 1;
+    }
+}
+
+/// Returns the time from Unix time `now` until the start of `slot` in the next interval. Wakes
+/// are at least half an interval apart, so that aligning doesn't add a cycle.
+fn delay(now: i64, interval: Duration, slot: u16, size: u16) -> Duration {
+    let interval = interval.as_secs().max(1) as i64;
+    let offset = interval * i64::from(slot) / i64::from(size);
+    let mut delay = (offset - now).rem_euclid(interval);
+    if delay < interval / 2 {
+        delay += interval;
+    }
+    Duration::from_secs(delay as u64)
+}
+
+/// State of the schedule across cycles.
+#[derive(Hash)]
+pub struct State {
+    adaptive: Adaptive,
+    /// Interval until the next cycle in seconds as selected by the latest reading.
+    next_interval: Option<u32>,
+    /// Jitter in seconds added to `next_interval`, none once aligned.
+    jitter: u16,
+    /// Consecutive cycles whose network part failed.
+    failures: u32,
+    /// Number of upcoming cycles that don't try the network.
+    skipped_wakes: u32,
+    uploads: History,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> State {
+        State {
+            adaptive: Adaptive::new(),
+            next_interval: None,
+            jitter: 0,
+            failures: 0,
+            skipped_wakes: 0,
+            uploads: History::new(),
+        }
+    }
+
+    /// Selects the interval until the next cycle after the reading `value` of the current one,
+    /// with jitter, so that sensors powered on together don't keep waking at the same time.
+    pub fn sampled(&mut self, plan: &Plan, value: u16, clock: &impl Clock) -> Duration {
+        let time = clock.slow_clock();
+        let interval = if plan.adaptive.enabled {
+            let default = plan.default.as_secs() as u32;
+            self.adaptive.update(&plan.adaptive, value, time, default)
+        } else {
+            self.adaptive = Adaptive::new();
+            band_interval(&plan.bands, value).unwrap_or(plan.default.as_secs() as u32)
+        };
+        self.next_interval = Some(interval);
+        self.jitter = jitter(interval, plan.seed, time).min(u16::MAX.into()) as u16;
+        self.next_interval(plan.default)
+    }
+
+    /// Returns why the current cycle doesn't upload, if it doesn't, given whether the buffer holds
+    /// too few measurements. Urgent uploads, e.g. of alerts, aren't deferred. An upload is recorded
+    /// against the rate limit once the cycle tries it.
+    pub fn defers_upload(
+        &mut self,
+        plan: &UploadPlan,
+        urgent: bool,
+        buffering: bool,
+        clock: &impl Clock,
+    ) -> Option<Deferral> {
+        let now = clock.slow_clock();
+        let unix_time = clock.offset().map(|offset| i64::from(now) + offset);
+        if urgent {
+            self.uploads.record(now);
+            None
+        } else if !plan.window.allows(unix_time) {
+            Some(Deferral::Window)
+        } else if buffering {
+            Some(Deferral::Buffering)
+        } else if !self.uploads.allows(now, &plan.limits) {
+            Some(Deferral::RateLimit)
+        } else if !self.tries_network() {
+            Some(Deferral::Backoff)
+        } else {
+            self.uploads.record(now);
+            None
+        }
+    }
+
+    /// Returns whether the current cycle tries the network, counting down the cycles that skip it
+    /// after a failed one. Only asked once nothing else keeps the cycle from uploading.
+    fn tries_network(&mut self) -> bool {
+        let skipped = self.skipped_wakes > 0;
+        self.skipped_wakes = self.skipped_wakes.saturating_sub(1);
+        !skipped
+    }
+
+    /// Records that the network part of the current cycle failed, returning the number of
+    /// consecutive cycles it failed in.
+    pub fn network_failed(&mut self) -> u32 {
+        self.failures += 1;
+        self.skipped_wakes = SKIPPED_WAKES;
+        self.failures
+    }
+
+    pub fn network_succeeded(&mut self) {
+        self.failures = 0;
+        self.skipped_wakes = 0;
+    }
+
+    /// Aligns the next wake to the start of `slot` of a fleet of `size`, at the end of the cycle,
+    /// if the time is known. Aligned wakes are spread by their slots instead of jitter.
+    pub fn align(&mut self, clock: &impl Clock, slot: u16, size: u16, default: Duration) {
+        let Some(offset) = clock.offset() else {
+            return;
+        };
+        let now = i64::from(clock.slow_clock()) + offset;
+        let interval = self
+            .next_interval
+            .map_or(default, |interval| Duration::from_secs(interval.into()));
+        let delay = delay(now, interval, slot, size);
+        self.next_interval = Some(delay.as_secs() as u32);
+        self.jitter = 0;
+    }
+
+    /// Returns the interval until the next cycle, or `default` if none has been selected.
+    pub fn next_interval(&self, default: Duration) -> Duration {
+        self.next_interval.map_or(default, |interval| {
+            Duration::from_secs(u64::from(interval) + u64::from(self.jitter))
+        })
+    }
+}
+
+#[test]
+pub fn test_band_interval() {
+    let bands = [
+        Band {
+            from: 1000,
+            interval: 21600,
+        },
+        Band {
+            from: 1500,
+            interval: 3600,
+        },
+        Band {
+            from: 1900,
+            interval: 900,
+        },
+    ];
+    assert_eq!(band_interval(&bands, 800), Some(21600));
+    assert_eq!(band_interval(&bands, 1499), Some(21600));
+    assert_eq!(band_interval(&bands, 1500), Some(3600));
+    assert_eq!(band_interval(&bands, 2500), Some(900));
+    assert_eq!(band_interval(&[], 2500), None);
+}
+
+#[test]
+pub fn test_adaptive() {
+    let settings = AdaptiveSettings {
+        enabled: true,
+        ..Default::default()
+    };
+    let mut adaptive = Adaptive::new();
+    assert_eq!(adaptive.update(&settings, 2000, 1000, 3600), 3600);
+    // 5 mV in an hour is flat.
+    assert_eq!(adaptive.update(&settings, 2005, 4600, 3600), 7200);
+    assert_eq!(adaptive.update(&settings, 2000, 11800, 3600), 14400);
+    assert_eq!(adaptive.update(&settings, 2000, 26200, 3600), 21600);
+    assert_eq!(adaptive.update(&settings, 2000, 47800, 3600), 21600);
+    // Watering drops the reading by 600 mV.
+    assert_eq!(adaptive.update(&settings, 1400, 69400, 3600), 900);
+    // 50 mV per hour is in between.
+    assert_eq!(adaptive.update(&settings, 1388, 70300, 3600), 900);
+    assert_eq!(adaptive.update(&settings, 1388, 71200, 3600), 1800);
+    // A restarted slow clock keeps the interval.
+    assert_eq!(adaptive.update(&settings, 1800, 10, 3600), 1800);
+}
+
+#[test]
+pub fn test_delay() {
+    let hour = Duration::from_secs(3600);
+    let at = |minute: i64, second: i64| 1_700_000_000 / 3600 * 3600 + minute * 60 + second;
+    // Slot 3 of 12 starts at minute 15.
+    assert_eq!(
+        delay(at(0, 0), hour, 3, 12),
+        Duration::from_secs(15 * 60 + 3600)
+    );
+    assert_eq!(delay(at(40, 0), hour, 3, 12), Duration::from_secs(35 * 60));
+    assert_eq!(delay(at(14, 59), hour, 3, 12), Duration::from_secs(3601));
+    assert_eq!(delay(at(15, 0), hour, 3, 12), hour);
+    assert_eq!(delay(at(20, 0), hour, 0, 1), Duration::from_secs(40 * 60));
+    assert_eq!(
+        delay(at(1, 0), Duration::from_secs(900), 1, 3),
+        Duration::from_secs(900 + 4 * 60)
+    );
+}
+
+#[test]
+pub fn test_network() {
+    let mut state = State::new();
+    assert!(state.tries_network());
+    assert_eq!(state.network_failed(), 1);
+    for _ in 0..SKIPPED_WAKES {
+        assert!(!state.tries_network());
+    }
+    assert!(state.tries_network());
+    assert_eq!(state.network_failed(), 2);
+    assert!(!state.tries_network());
+    state.network_succeeded();
+    assert!(state.tries_network());
+    assert_eq!(state.network_failed(), 1);
+}
+
+#[test]
+pub fn test_jitter() {
+    assert_eq!(jitter(3600, 7, 1000), jitter(3600, 7, 1000));
+    let jitters: Vec<_> = (0..100).map(|seed| jitter(3600, seed, 1000)).collect();
+    assert!(jitters.iter().all(|&jitter| jitter <= 180));
+    // Sensors powered on together are spread over the range.
+    assert!(jitters.iter().any(|&jitter| jitter < 60));
+    assert!(jitters.iter().any(|&jitter| jitter > 120));
+    assert_ne!(jitter(3600, 7, 1000), jitter(3600, 7, 4600));
+    assert_eq!(jitter(10, 7, 1000), 0);
+}
+
+#[test]
+pub fn test_upload_window() {
+    let midnight = 1_700_000_000 / 86400 * 86400;
+    let at = |hour: i64, minute: i64| Some(midnight + hour * 3600 + minute * 60);
+    let day = UploadWindow {
+        start: 8,
+        end: 20,
+        utc_offset: 0,
+    };
+    assert!(!day.allows(at(7, 59)));
+    assert!(day.allows(at(8, 0)));
+    assert!(day.allows(at(19, 59)));
+    assert!(!day.allows(at(20, 0)));
+    assert!(day.allows(None));
+
+    // 08:00 in UTC+2 is 06:00 UTC.
+    let east = UploadWindow {
+        utc_offset: 120,
+        ..day
+    };
+    assert!(east.allows(at(6, 0)));
+    assert!(!east.allows(at(18, 0)));
+
+    let night = UploadWindow {
+        start: 22,
+        end: 6,
+        utc_offset: 0,
+    };
+    assert!(night.allows(at(23, 0)));
+    assert!(night.allows(at(5, 0)));
+    assert!(!night.allows(at(12, 0)));
+
+    assert!(UploadWindow::default().allows(at(3, 0)));
+}
+
+#[test]
+pub fn test_history() {
+    let limits = UploadLimits {
+        min_interval: Duration::from_secs(600),
+        max_per_day: 3,
+    };
+    let mut history = History::new();
+    assert!(history.allows(1000, &limits));
+    history.record(1000);
+    assert!(!history.allows(1599, &limits));
+    assert!(history.allows(1600, &limits));
+    history.record(1600);
+    history.record(2200);
+    assert!(!history.allows(5000, &limits));
+    assert!(history.allows(1000 + DAY, &limits));
+    history.record(1000 + DAY);
+    assert_eq!(history.count, 1);
+    assert!(history.allows(10, &limits));
+}
+
+#[test]
+pub fn test_schedules() {
+    struct FakeClock {
+        slow_clock: std::cell::Cell<u32>,
+        offset: Option<i64>,
+    }
+
+    impl Clock for FakeClock {
+        fn slow_clock(&self) -> u32 {
+            self.slow_clock.get()
+        }
+
+        fn offset(&self) -> Option<i64> {
+            self.offset
+        }
+    }
+
+    /// Runs a cycle per reading of `values` through the phases of the firmware, each awake for
+    /// 10 s, returning the slow clock time of each wake, the sleep after it and why the cycle
+    /// didn't upload, if it didn't. The buffer holds enough measurements to upload every
+    /// `batch` cycles, and the network part of the cycles at `failures` fails.
+    fn simulate(
+        plan: &Plan,
+        uploads: &UploadPlan,
+        fleet: Option<(u16, u16)>,
+        offset: Option<i64>,
+        values: &[u16],
+        batch: usize,
+        failures: &[usize],
+    ) -> Vec<(u32, u64, Option<Deferral>)> {
+        let clock = FakeClock {
+            slow_clock: 1000.into(),
+            offset,
+        };
+        let mut state = State::new();
+        let mut buffered = 0;
+        let mut schedule = Vec::new();
+        for (i, &value) in values.iter().enumerate() {
+            let woken = clock.slow_clock();
+            state.sampled(plan, value, &clock);
+            buffered += 1;
+            let deferral = state.defers_upload(uploads, false, buffered < batch, &clock);
+            if deferral.is_none() && failures.contains(&i) {
+                state.network_failed();
+            } else if deferral.is_none() {
+                state.network_succeeded();
+                buffered = 0;
+            }
+            clock.slow_clock.set(woken + 10);
+            if let Some((slot, size)) = fleet {
+                state.align(&clock, slot, size, plan.default);
+            }
+            let sleep = state.next_interval(plan.default).as_secs();
+            schedule.push((woken, sleep, deferral));
+            clock.slow_clock.set(woken + 10 + sleep as u32);
+        }
+        schedule
+    }
+
+    let hour = Duration::from_secs(3600);
+    let ladder = Plan {
+        bands: vec![
+            Band {
+                from: 0,
+                interval: 7200,
+            },
+            Band {
+                from: 2000,
+                interval: 900,
+            },
+        ],
+        adaptive: AdaptiveSettings::default(),
+        default: hour,
+        seed: 7,
+    };
+    let unlimited = UploadPlan {
+        window: UploadWindow::default(),
+        limits: UploadLimits {
+            min_interval: Duration::ZERO,
+            max_per_day: u32::MAX,
+        },
+    };
+    // Drying out into the top band, then watered. Intervals have up to 5 % of jitter.
+    let values = [1800, 1950, 2000, 2100, 1200, 1200];
+    assert_eq!(
+        simulate(&ladder, &unlimited, None, None, &values, 1, &[]),
+        vec![
+            (1000, 7520, None),
+            (8530, 7339, None),
+            (15879, 918, None),
+            (16807, 917, None),
+            (17734, 7207, None),
+            (24951, 7380, None),
+        ]
+    );
+
+    // A failed upload skips the network in the next 3 cycles.
+    let values = [1800; 6];
+    let schedule = simulate(&ladder, &unlimited, None, None, &values, 1, &[1]);
+    let deferrals: Vec<_> = schedule.iter().map(|&(_, _, deferral)| deferral).collect();
+    let backoff = Some(Deferral::Backoff);
+    assert_eq!(deferrals, vec![None, None, backoff, backoff, backoff, None]);
+
+    let adaptive = Plan {
+        adaptive: AdaptiveSettings {
+            enabled: true,
+            ..Default::default()
+        },
+        ..ladder
+    };
+    // Flat readings double the interval up to the maximum, a rapid change drops it to the minimum.
+    let values = [2000, 2000, 2000, 2000, 2000, 1300, 1300];
+    assert_eq!(
+        simulate(&adaptive, &unlimited, None, None, &values, 1, &[]),
+        vec![
+            (1000, 3625, None),
+            (4635, 7530, None),
+            (12175, 14555, None),
+            (26740, 22663, None),
+            (49413, 21854, None),
+            (71277, 926, None),
+            (72213, 1833, None),
+        ]
+    );
+
+    // Slot 3 of 12 starts 15 minutes into the hour. Wakes are aligned once the time is known,
+    // without jitter.
+    let plan = Plan {
+        bands: vec![],
+        adaptive: AdaptiveSettings::default(),
+        default: hour,
+        seed: 7,
+    };
+    let offset = 1_700_000_000 / 3600 * 3600 - 1010;
+    assert_eq!(
+        simulate(
+            &plan,
+            &unlimited,
+            Some((3, 12)),
+            Some(offset),
+            &[1800; 3],
+            1,
+            &[]
+        ),
+        vec![(1000, 4500, None), (5510, 3590, None), (9110, 3590, None)]
+    );
+    assert_eq!(
+        simulate(&plan, &unlimited, Some((3, 12)), None, &[1800; 2], 1, &[]),
+        vec![(1000, 3625, None), (4635, 3663, None)]
+    );
+
+    // Hourly cycles from midnight with uploads from 08:00 to 20:00 and at most 3 a day, at least
+    // 3 hours apart. Buffering keeps the first cycles of the window from uploading.
+    let offset = 1_700_000_000 / 86400 * 86400 - 1000;
+    let uploads = UploadPlan {
+        window: UploadWindow {
+            start: 8,
+            end: 20,
+            utc_offset: 0,
+        },
+        limits: UploadLimits {
+            min_interval: 3 * hour,
+            max_per_day: 3,
+        },
+    };
+    let schedule = simulate(&plan, &uploads, None, Some(offset), &[1800; 24], 2, &[]);
+    let uploaded: Vec<_> = schedule
+        .iter()
+        .filter(|&&(_, _, deferral)| deferral.is_none())
+        .map(|&(woken, _, _)| woken)
+        .collect();
+    // At 08:27, 11:30 and 14:36.
+    assert_eq!(uploaded, vec![30461, 41421, 52587]);
+    let deferrals: Vec<_> = schedule.iter().map(|&(_, _, deferral)| deferral).collect();
+    let (window, buffering, rate_limit) = (
+        Some(Deferral::Window),
+        Some(Deferral::Buffering),
+        Some(Deferral::RateLimit),
+    );
+    assert_eq!(deferrals[..8], [window; 8]);
+    assert_eq!(
+        deferrals[8..20],
+        [
+            None, buffering, rate_limit, None, buffering, rate_limit, None, buffering, rate_limit,
+            rate_limit, rate_limit, rate_limit,
+        ]
+    );
+    assert_eq!(deferrals[20..], [window; 4]);
+    // Without the time, the window doesn't defer uploads.
+    let schedule = simulate(&plan, &uploads, None, None, &[1800; 5], 1, &[]);
+    let deferrals: Vec<_> = schedule.iter().map(|&(_, _, deferral)| deferral).collect();
+    assert_eq!(
+        deferrals,
+        vec![None, rate_limit, rate_limit, None, rate_limit]
+    );
+
+    // An alert is uploaded outside the window and counts against the rate limit.
+    let clock = FakeClock {
+        slow_clock: 1000.into(),
+        offset: Some(offset),
+    };
+    let mut state = State::new();
+    assert_eq!(state.defers_upload(&uploads, true, true, &clock), None);
+    clock.slow_clock.set(1000 + 8 * 3600);
+    assert_eq!(state.defers_upload(&uploads, false, false, &clock), None);
+    clock.slow_clock.set(1000 + 9 * 3600);
+    assert_eq!(state.defers_upload(&uploads, true, false, &clock), None);
+    clock.slow_clock.set(1000 + 10 * 3600);
+    assert_eq!(
+        state.defers_upload(&uploads, false, false, &clock),
+        rate_limit
+    );
+}
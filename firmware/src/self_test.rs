@@ -0,0 +1,56 @@
+//! Self-test of the ADC with a reference network wired to GPIO3, read on every wake with the
+//! `reference` feature. A reading that deviates from the known voltage of the network by more than
+//! the tolerance means that the ADC or its driver degraded, which is reported with the health
+//! counters. GPIO3 is the only spare ADC channel, so the feature excludes the `battery` feature.
+
+use crate::rtc::STATE;
+#[cfg(feature = "reference")]
+use esp_idf_hal::{adc, gpio, peripheral::Peripheral};
+
+/// Voltage at the pin with the reference network, two equal resistors from 3.3 V to ground.
+pub const EXPECTED_MV: u16 = 1650;
+/// Largest deviation from `EXPECTED_MV` of a healthy ADC.
+pub const TOLERANCE_MV: u16 = 80;
+
+/// Returns the voltage at the reference pin in mV.
+#[cfg(feature = "reference")]
+pub fn read(
+    adc: impl Peripheral<P = adc::ADC1>,
+    adc_pin: impl Peripheral<P = gpio::Gpio3>,
+) -> anyhow::Result<u16> {
+    let mut adc_driver = adc::AdcDriver::new(adc, &adc::config::Config::new().calibration(true))?;
+    let mut adc_channel_driver: adc::AdcChannelDriver<gpio::Gpio3, adc::Atten11dB<_>> =
+        adc::AdcChannelDriver::new(adc_pin)?;
+    Ok(adc_driver.read(&mut adc_channel_driver)?)
+}
+
+/// Keeps the reading of the current cycle for the next upload.
+pub fn record(reading_mv: u16) {
+    if !passes(reading_mv) {
+        println!(
+            "reference reads {} mV instead of {} mV, ADC degraded",
+            reading_mv, EXPECTED_MV
+        );
+    }
+    unsafe {
+        STATE.reference_mv = Some(reading_mv);
+    }
+}
+
+/// Returns the latest reading and whether it's within tolerance.
+pub fn latest() -> Option<(u16, bool)> {
+    unsafe { STATE.reference_mv }.map(|reading_mv| (reading_mv, passes(reading_mv)))
+}
+
+fn passes(reading_mv: u16) -> bool {
+    reading_mv.abs_diff(EXPECTED_MV) <= TOLERANCE_MV
+}
+
+#[test]
+pub fn test_passes() {
+    assert!(passes(EXPECTED_MV));
+    assert!(passes(EXPECTED_MV - TOLERANCE_MV));
+    assert!(passes(EXPECTED_MV + TOLERANCE_MV));
+    assert!(!passes(EXPECTED_MV + TOLERANCE_MV + 1));
+    assert!(!passes(0));
+}
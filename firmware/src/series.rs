@@ -0,0 +1,229 @@
+//! Compression of measurement series, so that more of them fit into flash. Times are stored as the
+//! change of the interval to the previous point, values as the change to the previous value, both
+//! zigzag encoded into LEB128 varints, so that a point of a regular series takes two bytes instead
+//! of eight. The info and battery bytes follow only if they changed.
+//!
+//! The module doesn't use any types of the firmware, as the host crate `soil-codec` includes it
+//! to decode series dumped by a sensor.
+
+/// Version of the format, stored as the first byte.
+const FORMAT: u8 = 1;
+const OFFSET_SIZE: usize = 8;
+/// Largest encoding of a point, with a flag and a change of interval of up to 35 bits, a change of
+/// value of up to 17 bits and the info and battery bytes.
+const MAX_POINT_SIZE: usize = 5 + 3 + 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Point {
+    pub time: u32,
+    pub value: u16,
+    pub info: u8,
+    pub battery: u8,
+}
+
+/// Points with the offset of their times to UTC in seconds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Series {
+    pub offset: i64,
+    pub points: Vec<Point>,
+}
+
+/// Encodes `series` into at most `max_size` bytes. The oldest series and points are dropped if they
+/// don't fit.
+pub fn encode(series: &[Series], max_size: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for series in series.iter().rev() {
+        let space = max_size.saturating_sub(1 + data.len() + OFFSET_SIZE);
+        let mut points = &series.points[..];
+        let encoded = loop {
+            let mut encoded = Vec::new();
+            encode_points(points, &mut encoded);
+            if encoded.len() <= space || points.is_empty() {
+                break encoded;
+            }
+            // Drops no more points than needed, if the dropped ones have the largest encoding.
+            let drop = (encoded.len() - space).div_ceil(MAX_POINT_SIZE);
+            points = &points[drop.min(points.len())..];
+        };
+        if points.is_empty() || encoded.len() > space {
+            break;
+        }
+        data.splice(0..0, series.offset.to_le_bytes().into_iter().chain(encoded));
+    }
+    if !data.is_empty() {
+        data.insert(0, FORMAT);
+    }
+    data
+}
+
+/// Decodes the series of `data`, up to the first one that is truncated. Data of another format
+/// decodes to no series.
+pub fn decode(data: &[u8]) -> Vec<Series> {
+    let mut series = Vec::new();
+    let mut data = match data.split_first() {
+        Some((&FORMAT, data)) => data,
+        _ => return series,
+    };
+    while data.len() >= OFFSET_SIZE {
+        let offset = i64::from_le_bytes(data[..OFFSET_SIZE].try_into().unwrap());
+        data = &data[OFFSET_SIZE..];
+        match decode_points(&mut data) {
+            Some(points) => series.push(Series { offset, points }),
+            None => break,
+        }
+    }
+    series
+}
+
+fn encode_points(points: &[Point], data: &mut Vec<u8>) {
+    write_varint(data, points.len() as u64);
+    let mut previous = Point::default();
+    let mut interval = 0;
+    for point in points {
+        let delta = i64::from(point.time) - i64::from(previous.time);
+        let changed = (point.info, point.battery) != (previous.info, previous.battery);
+        write_varint(data, zigzag(delta - interval) << 1 | u64::from(changed));
+        write_varint(
+            data,
+            zigzag(i64::from(point.value) - i64::from(previous.value)),
+        );
+        if changed {
+            data.extend([point.info, point.battery]);
+        }
+        interval = delta;
+        previous = *point;
+    }
+}
+
+/// Decodes points from the start of `data`, advancing it past them.
+fn decode_points(data: &mut &[u8]) -> Option<Vec<Point>> {
+    let count = read_varint(data)?;
+    // Every point takes at least two bytes, which bounds the allocation for invalid data.
+    let mut points = Vec::with_capacity(count.min(data.len() as u64 / 2) as usize);
+    let mut previous = Point::default();
+    let mut interval = 0;
+    for _ in 0..count {
+        let head = read_varint(data)?;
+        interval += unzigzag(head >> 1);
+        let value = i64::from(previous.value) + unzigzag(read_varint(data)?);
+        let (info, battery) = match head & 1 {
+            0 => (previous.info, previous.battery),
+            _ => match *data {
+                &[info, battery, ref rest @ ..] => {
+                    *data = rest;
+                    (info, battery)
+                }
+                _ => return None,
+            },
+        };
+        let point = Point {
+            time: (i64::from(previous.time) + interval).try_into().ok()?,
+            value: value.try_into().ok()?,
+            info,
+            battery,
+        };
+        points.push(point);
+        previous = point;
+    }
+    Some(points)
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, &byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[test]
+pub fn test_encode() {
+    for value in [0, 1, -1, 63, -64, i64::from(u32::MAX), -i64::from(u32::MAX)] {
+        assert_eq!(unzigzag(zigzag(value)), value);
+        let mut data = Vec::new();
+        write_varint(&mut data, zigzag(value));
+        assert_eq!(read_varint(&mut &data[..]), Some(zigzag(value)));
+    }
+
+    // Hourly readings with noise, and a change of battery level.
+    let point = |i: u32| Point {
+        time: 200_000 + 3600 * i + i % 3,
+        value: 2100 + (i * 7 % 23) as u16,
+        info: 0b1000_0101,
+        battery: if i < 40 { 150 } else { 149 },
+    };
+    let series = [
+        Series {
+            offset: 1_700_000_000,
+            points: (0..48).map(point).collect(),
+        },
+        Series {
+            offset: -5,
+            points: vec![point(1)],
+        },
+    ];
+    let data = encode(&series, 4000);
+    // About two bytes per point, against eight uncompressed.
+    assert!(
+        data.len() < 2 * OFFSET_SIZE + 49 * 3,
+        "{} bytes",
+        data.len()
+    );
+    assert_eq!(decode(&data), series);
+    assert!(decode(&data[..5]).is_empty());
+    assert_eq!(decode(&data[..data.len() - 1]), series[..1]);
+    assert!(decode(&data[1..]).is_empty());
+    assert!(encode(&[Series::default()], 4000).is_empty());
+
+    // A time before the previous one, and the largest changes.
+    let extremes = [Series {
+        offset: 0,
+        points: vec![
+            Point {
+                time: u32::MAX,
+                value: u16::MAX,
+                info: 0xff,
+                battery: 0xff,
+            },
+            Point::default(),
+            Point {
+                time: u32::MAX,
+                ..Point::default()
+            },
+        ],
+    }];
+    assert_eq!(decode(&encode(&extremes, 4000)), extremes);
+
+    // The oldest series and points are dropped to fit.
+    let long = Series {
+        offset: 0,
+        points: (0..3000).map(point).collect(),
+    };
+    let data = encode(&[series[1].clone(), long.clone()], 4000);
+    assert!(data.len() <= 4000);
+    let decoded = decode(&data);
+    assert_eq!(decoded.len(), 1);
+    let kept = decoded[0].points.len();
+    assert!(kept > 1900 && kept < 3000, "{} kept", kept);
+    assert_eq!(decoded[0].points[..], long.points[3000 - kept..]);
+}
@@ -0,0 +1,64 @@
+//! A connected session bundles all network tasks of a wake cycle (time sync, upload, command
+//! poll, ...) into a single connection window with an overall deadline, so that the radio is
+//! brought up at most once per cycle and for a bounded time. Points produced by tasks are queued
+//! and sent along with the upload, instead of in requests of its own.
+
+use crate::points::MeasurementBatch;
+use anyhow::{bail, Result};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+pub struct Session {
+    deadline: Instant,
+    queued: RefCell<MeasurementBatch>,
+}
+
+impl Session {
+    pub fn new(budget: Duration) -> Session {
+        Session {
+            deadline: Instant::now() + budget,
+            queued: RefCell::default(),
+        }
+    }
+
+    /// Queues the points of `batch` to be sent with the upload.
+    pub fn queue(&self, mut batch: MeasurementBatch) {
+        self.queued.borrow_mut().append(&mut batch);
+    }
+
+    /// Takes the queued points.
+    pub fn take_queued(&self) -> MeasurementBatch {
+        self.queued.take()
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Runs `task` unless the deadline has passed already.
+    pub fn run<T>(&self, name: &str, task: impl FnOnce() -> Result<T>) -> Result<T> {
+        if self.remaining().is_zero() {
+            bail!("session deadline exceeded, skipping {}", name);
+        }
+        task()
+    }
+}
+
+#[test]
+pub fn test_session() {
+    let session = Session::new(Duration::from_secs(60));
+    assert!(session.remaining() > Duration::from_secs(59));
+    assert_eq!(session.run("task", || Ok(1)).unwrap(), 1);
+    for measurement in ["a", "b"] {
+        let mut batch = MeasurementBatch::default();
+        batch.push_fields("m value=", measurement, &[], &[], 0);
+        session.queue(batch);
+    }
+    let queued = session.take_queued();
+    assert_eq!(queued.points.len(), 2);
+    assert_eq!(queued.points[1].measurement, "b");
+    assert!(session.take_queued().is_empty());
+
+    let session = Session::new(Duration::ZERO);
+    assert!(session.run("task", || Ok(1)).is_err());
+}
@@ -0,0 +1,136 @@
+//! Settling of the probe after its excitation has been switched on. The probe is polled until
+//! consecutive readings agree, instead of waiting for a fixed time. If they still don't after the
+//! budget, the probe is sampled anyway and a timeout is kept with the trace of the polled readings
+//! for the next upload, so that intermittent wiring problems show up in the uploaded data rather
+//! than as noise in the readings.
+
+use crate::rtc::STATE;
+use crate::sampling;
+use std::hash::{Hash, Hasher};
+
+pub const POLL_INTERVAL_MS: u32 = 2;
+/// Time after which the probe hasn't settled.
+const BUDGET_MS: u32 = 100;
+/// Number of consecutive polls whose readings must be within `SETTLED_SPREAD_MV`.
+const SETTLED_POLLS: usize = 4;
+const SETTLED_SPREAD_MV: u16 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeout {
+    /// Slow clock time in seconds of the latest timeout.
+    pub time: u32,
+    /// First and last reading polled while settling, in mV.
+    pub first: u16,
+    pub last: u16,
+    /// Variance of the readings polled while settling.
+    pub variance: f32,
+    /// Number of timeouts since the last upload.
+    pub count: u16,
+}
+
+impl Hash for Timeout {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let variance = self.variance.to_bits();
+        (self.time, self.first, self.last, variance, self.count).hash(state);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Progress {
+    Settling,
+    Settled,
+    /// The budget is used up without the probe having settled.
+    TimedOut,
+}
+
+/// Readings polled while the probe settles.
+pub struct Trace {
+    readings: Vec<u16>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace {
+            readings: Vec::with_capacity((BUDGET_MS / POLL_INTERVAL_MS) as usize),
+        }
+    }
+
+    /// Adds the reading of a poll, returning whether the probe has settled.
+    pub fn update(&mut self, reading: u16) -> Progress {
+        self.readings.push(reading);
+        let recent = &self.readings[self.readings.len().saturating_sub(SETTLED_POLLS)..];
+        let min = recent.iter().min().copied().unwrap_or(0);
+        let max = recent.iter().max().copied().unwrap_or(0);
+        if recent.len() == SETTLED_POLLS && max - min <= SETTLED_SPREAD_MV {
+            Progress::Settled
+        } else if self.readings.len() as u32 * POLL_INTERVAL_MS >= BUDGET_MS {
+            Progress::TimedOut
+        } else {
+            Progress::Settling
+        }
+    }
+
+    /// Keeps the trace as the latest timeout for the next upload.
+    pub fn record_timeout(&self, time: u32) {
+        let first = self.readings.first().copied().unwrap_or(0);
+        let last = self.readings.last().copied().unwrap_or(0);
+        println!(
+            "probe didn't settle within {} ms: {} mV to {} mV",
+            BUDGET_MS, first, last
+        );
+        unsafe {
+            let count = STATE.settling_timeout.map_or(0, |timeout| timeout.count);
+            STATE.settling_timeout = Some(Timeout {
+                time,
+                first,
+                last,
+                variance: sampling::variance(&self.readings),
+                count: count.saturating_add(1),
+            });
+        }
+    }
+}
+
+/// Returns the latest timeout since the last upload, if any.
+pub fn pending() -> Option<Timeout> {
+    unsafe { STATE.settling_timeout }
+}
+
+pub fn clear() {
+    unsafe {
+        STATE.settling_timeout = None;
+    }
+}
+
+#[test]
+pub fn test_trace() {
+    let mut trace = Trace::new();
+    for reading in [2900, 2400, 1900, 1700, 1604, 1600] {
+        assert_eq!(trace.update(reading), Progress::Settling);
+    }
+    assert_eq!(trace.update(1598), Progress::Settling);
+    assert_eq!(trace.update(1601), Progress::Settled);
+
+    let mut trace = Trace::new();
+    let mut progress = Progress::Settling;
+    for i in 0..BUDGET_MS / POLL_INTERVAL_MS {
+        assert_eq!(progress, Progress::Settling);
+        progress = trace.update(if i % 2 == 0 { 1500 } else { 1700 });
+    }
+    assert_eq!(progress, Progress::TimedOut);
+
+    trace.record_timeout(10);
+    trace.record_timeout(20);
+    assert_eq!(
+        pending(),
+        Some(Timeout {
+            time: 20,
+            first: 1500,
+            last: 1700,
+            variance: 10_000.0,
+            count: 2,
+        })
+    );
+    clear();
+    assert_eq!(pending(), None);
+}
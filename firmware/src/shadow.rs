@@ -0,0 +1,164 @@
+//! Device shadow: instead of being sent imperative commands, devices converge to a desired
+//! configuration. The desired document at the shadow URL is a configuration as exported, with a
+//! top-level `version`. A version newer than the applied one is imported as a whole, and each cycle
+//! reports the desired and applied versions with the hash of the configuration as a `shadow` point
+//! over the active transport, so that fleet tools can tell which devices have converged. A version
+//! that fails to import is reported as rejected and not retried until a newer one is published.
+
+use crate::config;
+use crate::http;
+use crate::line_protocol::FieldValue;
+use crate::storage::Namespace;
+use anyhow::{Context, Result};
+use embedded_svc::http::Method;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+const NVS_NAMESPACE: &str = "shadow";
+const NVS_KEY: &str = "state";
+/// Size of the desired document, which fits a configuration as exported.
+const MAX_SHADOW_SIZE: usize = 8192;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    /// Version of the desired document last imported, 0 if none.
+    applied: u64,
+    /// Version of the desired document that failed to import, if newer than `applied`.
+    rejected: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Desired {
+    version: u64,
+    document: String,
+}
+
+/// State of the shadow as reported with the upload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reported {
+    pub desired: u64,
+    pub applied: u64,
+    /// Hash of the configuration, see [`config::Config::hash`].
+    pub hash: String,
+    pub error: Option<String>,
+}
+
+impl Reported {
+    pub fn fields(&self) -> Vec<(&str, FieldValue)> {
+        let mut fields = vec![
+            ("desired", FieldValue::Integer(self.desired as i64)),
+            ("applied", FieldValue::Integer(self.applied as i64)),
+            ("hash", FieldValue::String(&self.hash)),
+            ("in_sync", FieldValue::Boolean(self.desired == self.applied)),
+        ];
+        if let Some(error) = &self.error {
+            fields.push(("error", FieldValue::String(error)));
+        }
+        fields
+    }
+}
+
+/// Fetches the desired document from `url`, imports it if newer than the applied one and returns
+/// the state to report.
+pub fn sync(
+    partition: &EspDefaultNvsPartition,
+    url: &str,
+    authorization: &str,
+) -> Result<Reported> {
+    let desired = parse_desired(&fetch(url, authorization)?)?;
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut state: State = namespace.get_or_default(NVS_KEY)?;
+    let mut error = None;
+    if is_due(&state, desired.version) {
+        println!(
+            "importing desired configuration version {}",
+            desired.version
+        );
+        match config::import(partition, &desired.document) {
+            Ok(()) => {
+                state.applied = desired.version;
+                state.rejected = None;
+            }
+            Err(e) => {
+                println!("error importing desired configuration: {:#}", e);
+                state.rejected = Some(desired.version);
+                error = Some(format!("{:#}", e));
+            }
+        }
+        namespace.set(NVS_KEY, &state)?;
+    } else if state.rejected == Some(desired.version) {
+        error = Some("rejected".into());
+    }
+    Ok(Reported {
+        desired: desired.version,
+        applied: state.applied,
+        hash: config::current(partition)?.hash(),
+        error,
+    })
+}
+
+/// Whether the desired document of `version` is to be imported.
+fn is_due(state: &State, version: u64) -> bool {
+    version > state.applied && state.rejected.map_or(true, |rejected| version > rejected)
+}
+
+fn parse_desired(text: &str) -> Result<Desired> {
+    let value: toml::Value = toml::from_str(text)?;
+    let version = value
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .filter(|&version| version > 0)
+        .context("desired configuration without a positive version")?;
+    Ok(Desired {
+        version: version as u64,
+        document: text.into(),
+    })
+}
+
+fn fetch(url: &str, authorization: &str) -> Result<String> {
+    let headers = [("Authorization", authorization)];
+    let mut http_client = http::new_connection()?;
+    let body = http::request(
+        &mut http_client,
+        Method::Get,
+        url,
+        &headers,
+        &[],
+        MAX_SHADOW_SIZE,
+    )?;
+    Ok(String::from_utf8(body)?)
+}
+
+#[test]
+pub fn test_shadow() {
+    let desired = parse_desired("version = 3\n\n[recorder]\nenabled = true\n").unwrap();
+    assert_eq!(desired.version, 3);
+    assert!(parse_desired("[recorder]\nenabled = true\n").is_err());
+    assert!(parse_desired("version = 0\n").is_err());
+    assert!(parse_desired("version = \"3\"\n").is_err());
+
+    let state = State::default();
+    assert!(is_due(&state, 1));
+    let state = State {
+        applied: 3,
+        rejected: None,
+    };
+    assert!(!is_due(&state, 3));
+    assert!(!is_due(&state, 2));
+    assert!(is_due(&state, 4));
+    let state = State {
+        applied: 3,
+        rejected: Some(4),
+    };
+    assert!(!is_due(&state, 4));
+    assert!(is_due(&state, 5));
+
+    let reported = Reported {
+        desired: 4,
+        applied: 3,
+        hash: "0a1b2c3d".into(),
+        error: Some("rejected".into()),
+    };
+    let names: Vec<_> = reported.fields().iter().map(|(name, _)| *name).collect();
+    assert_eq!(names, ["desired", "applied", "hash", "in_sync", "error"]);
+}
@@ -0,0 +1,82 @@
+//! Log of cycles in which the sensor decided not to upload, kept in RTC memory and uploaded with
+//! the next upload, so that gaps between uploads can be explained.
+
+use crate::rtc::LOGS;
+use crate::wake::WakeCause;
+
+pub const MAX_SKIPS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Not enough measurements buffered yet.
+    Buffering,
+    RateLimit,
+    DryRun,
+    /// The network failed in a recent cycle.
+    Backoff,
+    /// Outside the hours of the upload window.
+    Window,
+}
+
+impl SkipReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            SkipReason::Buffering => "buffering",
+            SkipReason::RateLimit => "rate_limit",
+            SkipReason::DryRun => "dry_run",
+            SkipReason::Backoff => "backoff",
+            SkipReason::Window => "upload_window",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Skip {
+    /// Slow clock time in seconds of the cycle.
+    pub time: u32,
+    pub reason: SkipReason,
+    pub wake_cause: WakeCause,
+}
+
+pub fn record(time: u32, reason: SkipReason, wake_cause: WakeCause) {
+    println!("not uploading: {}", reason.name());
+    unsafe {
+        LOGS.skips.overwriting_push_back(Skip {
+            time,
+            reason,
+            wake_cause,
+        });
+    }
+}
+
+/// Returns the skips not uploaded yet, oldest first.
+pub fn pending() -> Vec<Skip> {
+    unsafe { LOGS.skips.iter().copied().collect() }
+}
+
+pub fn clear() {
+    unsafe {
+        LOGS.skips.clear();
+    }
+}
+
+#[test]
+pub fn test_skips() {
+    for time in 0..MAX_SKIPS as u32 + 2 {
+        record(time, SkipReason::Buffering, WakeCause::Timer);
+    }
+    record(100, SkipReason::RateLimit, WakeCause::Reset);
+    let skips = pending();
+    assert_eq!(skips.len(), MAX_SKIPS);
+    assert_eq!(skips[0].time, 3);
+    assert_eq!(
+        skips.last(),
+        Some(&Skip {
+            time: 100,
+            reason: SkipReason::RateLimit,
+            wake_cause: WakeCause::Reset,
+        })
+    );
+    clear();
+    assert!(pending().is_empty());
+}
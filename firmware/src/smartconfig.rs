@@ -0,0 +1,72 @@
+//! Provisioning of WiFi credentials with ESP-Touch (SmartConfig) from the Espressif phone app,
+//! enabled with the `smartconfig` feature.
+
+use crate::provisioning::c_bytes;
+use anyhow::{anyhow, Result};
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_sys::esp;
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// SSID and password received by the event handler.
+static CREDENTIALS: Mutex<Option<(String, String)>> = Mutex::new(None);
+
+/// Waits for credentials sent from the phone app.
+pub fn receive(timeout: Duration) -> Result<(String, String)> {
+    unsafe {
+        esp!(esp_idf_sys::esp_event_handler_register(
+            esp_idf_sys::SC_EVENT,
+            esp_idf_sys::ESP_EVENT_ANY_ID,
+            Some(handle_event),
+            ptr::null_mut(),
+        ))?;
+        esp!(esp_idf_sys::esp_smartconfig_set_type(
+            esp_idf_sys::smartconfig_type_t_SC_TYPE_ESPTOUCH
+        ))?;
+        let config = esp_idf_sys::smartconfig_start_config_t {
+            enable_log: false,
+            esp_touch_v2_enable_crypt: false,
+            esp_touch_v2_key: ptr::null_mut(),
+        };
+        esp!(esp_idf_sys::esp_smartconfig_start(&config))?;
+    }
+    println!("waiting for SmartConfig credentials...");
+
+    let start = Instant::now();
+    let result = loop {
+        if let Some(credentials) = CREDENTIALS.lock().unwrap().take() {
+            break Ok(credentials);
+        }
+        if start.elapsed() >= timeout {
+            break Err(anyhow!("no SmartConfig credentials received"));
+        }
+        FreeRtos::delay_ms(100);
+    };
+
+    // The app isn't sent an acknowledgement, that would require staying in SmartConfig mode until
+    // connected.
+    unsafe {
+        esp_idf_sys::esp_smartconfig_stop();
+        esp_idf_sys::esp_event_handler_unregister(
+            esp_idf_sys::SC_EVENT,
+            esp_idf_sys::ESP_EVENT_ANY_ID,
+            Some(handle_event),
+        );
+    }
+    result
+}
+
+unsafe extern "C" fn handle_event(
+    _arg: *mut c_void,
+    _event_base: esp_idf_sys::esp_event_base_t,
+    event_id: i32,
+    event_data: *mut c_void,
+) {
+    if event_id as u32 != esp_idf_sys::smartconfig_event_t_SC_EVENT_GOT_SSID_PSWD {
+        return;
+    }
+    let event = &*(event_data as *const esp_idf_sys::smartconfig_event_got_ssid_pswd_t);
+    *CREDENTIALS.lock().unwrap() = Some((c_bytes(&event.ssid), c_bytes(&event.password)));
+}
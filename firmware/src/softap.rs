@@ -0,0 +1,314 @@
+//! Provisioning of WiFi credentials and the write URL via an access point with a captive portal,
+//! enabled with the `softap` feature. The sensor opens the network `soil-moisture-<MAC suffix>`
+//! and answers every DNS query with its own address, so that phones show the form right after
+//! joining. The network is open, but only exists while provisioning runs.
+
+use crate::device_config::{self, DeviceConfig};
+use anyhow::{anyhow, bail, Result};
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, Configuration};
+use esp_idf_svc::http::server::{self, EspHttpServer};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::wifi::EspWifi;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_FORM_SIZE: usize = 1024;
+const DNS_STACK_SIZE: usize = 4 * 1024;
+/// Paths requested by phones and laptops to detect captive portals.
+const PROBE_PATHS: &[&str] = &[
+    "/generate_204",
+    "/hotspot-detect.html",
+    "/connecttest.txt",
+    "/ncsi.txt",
+];
+
+/// Credentials and write URL entered in the form.
+#[derive(Debug, PartialEq, Eq)]
+struct Submission {
+    ssid: String,
+    password: String,
+    /// Empty to keep the current URL.
+    write_url: String,
+}
+
+/// Serves the portal until the form has been submitted.
+pub fn receive(
+    wifi: &mut EspWifi<'static>,
+    partition: &EspDefaultNvsPartition,
+    timeout: Duration,
+) -> Result<(String, String)> {
+    let client = match wifi.get_configuration()? {
+        Configuration::Client(client) | Configuration::Mixed(client, _) => client,
+        _ => Default::default(),
+    };
+    let ssid = access_point_ssid()?;
+    wifi.set_configuration(&Configuration::Mixed(
+        client,
+        AccessPointConfiguration {
+            ssid: ssid.as_str().into(),
+            auth_method: AuthMethod::None,
+            ..Default::default()
+        },
+    ))?;
+    let ip = wifi.ap_netif().get_ip_info()?.ip;
+    println!("join WiFi {} and open http://{}/ to provision", ssid, ip);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let dns_stop = stop.clone();
+    let dns = thread::Builder::new()
+        .stack_size(DNS_STACK_SIZE)
+        .spawn(move || serve_dns(ip, &dns_stop))?;
+    let submitted = serve_portal(partition, ip, timeout);
+    stop.store(true, Ordering::Relaxed);
+    match dns.join() {
+        Ok(Err(e)) => println!("error answering DNS queries: {}", e),
+        Err(_) => println!("error answering DNS queries: thread panicked"),
+        Ok(Ok(())) => {}
+    }
+
+    let submission = submitted?;
+    if !submission.write_url.is_empty() {
+        let mut device = DeviceConfig::load(partition)?;
+        device.write_url = submission.write_url;
+        device.save(partition)?;
+    }
+    Ok((submission.ssid, submission.password))
+}
+
+fn access_point_ssid() -> Result<String> {
+    let mut mac = [0; 6];
+    esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) })?;
+    let suffix: String = mac[3..].iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("soil-moisture-{}", suffix))
+}
+
+fn serve_portal(
+    partition: &EspDefaultNvsPartition,
+    ip: Ipv4Addr,
+    timeout: Duration,
+) -> Result<Submission> {
+    let mut server = EspHttpServer::new(&server::Configuration::default())?;
+    let write_url = DeviceConfig::load(partition)?.write_url;
+    server.fn_handler("/", Method::Get, move |request| {
+        let mut response =
+            request.into_response(200, None, &[("Content-Type", "text/html; charset=utf-8")])?;
+        response.write_all(form_page(&write_url).as_bytes())?;
+        Ok(())
+    })?;
+
+    let (submitted_tx, submitted_rx) = channel();
+    register_submit(&mut server, submitted_tx)?;
+
+    let portal_url = format!("http://{}/", ip);
+    for &path in PROBE_PATHS {
+        let location = portal_url.clone();
+        server.fn_handler(path, Method::Get, move |request| {
+            request.into_response(302, None, &[("Location", &location)])?;
+            Ok(())
+        })?;
+    }
+
+    submitted_rx
+        .recv_timeout(timeout)
+        .map_err(|_| anyhow!("no credentials submitted via the portal"))
+}
+
+fn register_submit(server: &mut EspHttpServer, submitted: Sender<Submission>) -> Result<()> {
+    server.fn_handler("/", Method::Post, move |mut request| {
+        let mut body = Vec::new();
+        let mut buffer = [0; 256];
+        loop {
+            let n = request.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            if body.len() + n > MAX_FORM_SIZE {
+                request.into_status_response(413)?;
+                return Ok(());
+            }
+            body.extend_from_slice(&buffer[..n]);
+        }
+        match parse_submission(&String::from_utf8_lossy(&body)) {
+            Ok(submission) => {
+                let mut response = request.into_response(
+                    200,
+                    None,
+                    &[("Content-Type", "text/html; charset=utf-8")],
+                )?;
+                let page = format!(
+                    "<p>Saved. The sensor now connects to {}.</p>",
+                    escape_html(&submission.ssid)
+                );
+                response.write_all(page.as_bytes())?;
+                submitted.send(submission)?;
+            }
+            Err(e) => {
+                let mut response = request.into_status_response(400)?;
+                response.write_all(escape_html(&e.to_string()).as_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn form_page(write_url: &str) -> String {
+    format!(
+        concat!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\">",
+            "<meta name=\"viewport\" content=\"width=device-width\">",
+            "<title>Soil moisture sensor</title></head><body>",
+            "<form method=\"post\" action=\"/\">",
+            "<p><label>WiFi network <input name=\"ssid\" maxlength=\"32\" required></label></p>",
+            "<p><label>Password <input name=\"password\" type=\"password\"></label></p>",
+            "<p><label>Write URL <input name=\"write_url\" type=\"url\" value=\"{}\"></label></p>",
+            "<p><button>Save</button></p></form></body></html>",
+        ),
+        escape_html(write_url)
+    )
+}
+
+/// Answers all A queries with `ip` until `stop` is set.
+fn serve_dns(ip: Ipv4Addr, stop: &AtomicBool) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:53")?;
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+    let mut buffer = [0; 512];
+    while !stop.load(Ordering::Relaxed) {
+        let (len, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(response) = dns_response(&buffer[..len], ip) {
+            socket.send_to(&response, peer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the response to the DNS `query`, with `ip` as answer if it asks for an A record.
+fn dns_response(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    const HEADER_LEN: usize = 12;
+    if query.len() < HEADER_LEN || query[2] & 0x80 != 0 || query[4..6] != [0, 1] {
+        return None;
+    }
+    let mut end = HEADER_LEN;
+    while *query.get(end)? != 0 {
+        end += usize::from(query[end]) + 1;
+    }
+    let question = query.get(HEADER_LEN..end + 5)?;
+    let is_a = question[question.len() - 4..] == [0, 1, 0, 1];
+
+    let mut response = Vec::with_capacity(HEADER_LEN + question.len() + 16);
+    response.extend_from_slice(&query[..2]);
+    response.extend_from_slice(&[0x80 | (query[2] & 0x01), 0x80]);
+    response.extend_from_slice(&[0, 1, 0, is_a.into(), 0, 0, 0, 0]);
+    response.extend_from_slice(question);
+    if is_a {
+        // Name pointer to the question, type A, class IN, TTL of 60 s and 4 bytes of data.
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        response.extend_from_slice(&ip.octets());
+    }
+    Some(response)
+}
+
+fn parse_submission(body: &str) -> Result<Submission> {
+    let mut submission = Submission {
+        ssid: String::new(),
+        password: String::new(),
+        write_url: String::new(),
+    };
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = decode_form_value(value)?;
+        match key {
+            "ssid" => submission.ssid = value,
+            "password" => submission.password = value,
+            "write_url" => submission.write_url = value.trim().into(),
+            _ => {}
+        }
+    }
+    if submission.ssid.is_empty() || submission.ssid.len() > 32 {
+        bail!("the network name must have 1 to 32 bytes");
+    }
+    let password_len = submission.password.len();
+    if password_len != 0 && !(8..=64).contains(&password_len) {
+        bail!("the password must be empty or have 8 to 64 characters");
+    }
+    if !submission.write_url.is_empty() {
+        device_config::validate_write_url(&submission.write_url)?;
+    }
+    Ok(submission)
+}
+
+/// Decodes a value of an `application/x-www-form-urlencoded` body.
+fn decode_form_value(value: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+                let decoded = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                bytes.push(decoded.ok_or_else(|| anyhow!("invalid form encoding"))?);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+pub fn test_portal() {
+    assert_eq!(
+        parse_submission("ssid=Home+Net&password=p%40ssw0rd%21&write_url=").unwrap(),
+        Submission {
+            ssid: "Home Net".into(),
+            password: "p@ssw0rd!".into(),
+            write_url: "".into(),
+        }
+    );
+    let submission =
+        parse_submission("ssid=x&write_url=https%3A%2F%2Fexample.com%2Fwrite").unwrap();
+    assert_eq!(submission.write_url, "https://example.com/write");
+    assert!(parse_submission("password=").is_err());
+    assert!(parse_submission("ssid=x&password=short").is_err());
+    assert!(parse_submission("ssid=x&write_url=example.com").is_err());
+    assert!(parse_submission("ssid=%4").is_err());
+    assert_eq!(escape_html("\"<a&b>\""), "&quot;&lt;a&amp;b&gt;&quot;");
+
+    let query = [
+        0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 3, b'f', b'o', b'o', 0, 0, 1, 0, 1,
+    ];
+    let response = dns_response(&query, Ipv4Addr::new(192, 168, 71, 1)).unwrap();
+    assert_eq!(
+        &response[..12],
+        &[0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]
+    );
+    assert_eq!(&response[12..21], &query[12..]);
+    assert_eq!(&response[response.len() - 4..], &[192, 168, 71, 1]);
+
+    let mut aaaa = query;
+    aaaa[18] = 28;
+    let response = dns_response(&aaaa, Ipv4Addr::new(192, 168, 71, 1)).unwrap();
+    assert_eq!(response.len(), 21);
+    assert_eq!(response[7], 0);
+    assert!(dns_response(&query[..15], Ipv4Addr::LOCALHOST).is_none());
+}
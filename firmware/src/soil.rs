@@ -0,0 +1,112 @@
+//! Calibration presets for common growing media, as starting points until the probe has been
+//! calibrated in the actual medium. A preset maps readings linearly between those in dry and in
+//! saturated medium to an estimated water content. The board has a single probe, so the preset
+//! applies to the whole device. The active preset is tagged on uploaded points as `soil`.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const NVS_NAMESPACE: &str = "soil";
+const NVS_KEY: &str = "medium";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Medium {
+    PottingMix,
+    CocoCoir,
+    Clay,
+    Sand,
+    Rockwool,
+}
+
+/// Readings in mV in dry and in saturated medium. Readings fall as the water content rises.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub dry: u16,
+    pub wet: u16,
+}
+
+impl Medium {
+    pub fn name(self) -> &'static str {
+        match self {
+            Medium::PottingMix => "potting_mix",
+            Medium::CocoCoir => "coco_coir",
+            Medium::Clay => "clay",
+            Medium::Sand => "sand",
+            Medium::Rockwool => "rockwool",
+        }
+    }
+
+    /// Returns the preset. Actual readings also depend on compaction and salinity.
+    pub fn calibration(self) -> Calibration {
+        let (dry, wet) = match self {
+            Medium::PottingMix => (2350, 1250),
+            Medium::CocoCoir => (2300, 1100),
+            Medium::Clay => (2150, 1400),
+            Medium::Sand => (2450, 1550),
+            Medium::Rockwool => (2400, 1000),
+        };
+        Calibration { dry, wet }
+    }
+}
+
+impl FromStr for Medium {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Medium> {
+        match s {
+            "potting_mix" => Ok(Medium::PottingMix),
+            "coco_coir" => Ok(Medium::CocoCoir),
+            "clay" => Ok(Medium::Clay),
+            "sand" => Ok(Medium::Sand),
+            "rockwool" => Ok(Medium::Rockwool),
+            _ => bail!(
+                "unknown medium, expected potting_mix, coco_coir, clay, sand or rockwool: {}",
+                s
+            ),
+        }
+    }
+}
+
+impl Calibration {
+    /// Returns the estimated water content for the reading `value` in whole percent of saturation.
+    pub fn percent(&self, value: u16) -> u8 {
+        let value = value.clamp(self.wet, self.dry);
+        let range = u32::from(self.dry - self.wet).max(1);
+        (u32::from(self.dry - value) * 100 / range) as u8
+    }
+}
+
+/// Returns the active preset, if any.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<Medium>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, medium: Option<Medium>) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match medium {
+        Some(medium) => namespace.set(NVS_KEY, &medium),
+        None => namespace.remove(NVS_KEY),
+    }
+}
+
+#[test]
+pub fn test_percent() {
+    let calibration = Medium::PottingMix.calibration();
+    assert_eq!(calibration.percent(2350), 0);
+    assert_eq!(calibration.percent(2600), 0);
+    assert_eq!(calibration.percent(1800), 50);
+    assert_eq!(calibration.percent(1250), 100);
+    assert_eq!(calibration.percent(900), 100);
+
+    for name in ["potting_mix", "coco_coir", "clay", "sand", "rockwool"] {
+        let medium: Medium = name.parse().unwrap();
+        assert_eq!(medium.name(), name);
+        let calibration = medium.calibration();
+        assert!(calibration.wet < calibration.dry);
+    }
+    assert!("peat".parse::<Medium>().is_err());
+}
@@ -0,0 +1,237 @@
+//! Spill of unsent measurements to NVS, as RTC memory is lost on power loss and brownout. Once the
+//! buffer holds `THRESHOLD` measurements, they're written to flash whenever `STEP` more have been
+//! taken since, which bounds the wear of the flash, and before every software restart. After a
+//! cold boot that lost the buffer, spilled measurements are uploaded ahead of it.
+//!
+//! The slow clock starts over on power loss, so a spill keeps the offset of the slow clock to UTC
+//! at the time, and is uploaded with it. Measurements spilled before the time was ever known can't
+//! be timestamped and are dropped. Measurements acknowledged after the last spill may be uploaded
+//! again, as points identical to the ones already stored.
+
+use crate::rtc::{LOGS, STATE};
+use crate::series::{self, Point, Series};
+use crate::storage::Namespace;
+use crate::Measurement;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::esp;
+use std::sync::Mutex;
+
+const NVS_NAMESPACE: &str = "spill";
+/// Measurements of the current power period.
+const CURRENT_NVS_KEY: &str = "current";
+/// Measurements of earlier power periods, restored at a cold boot.
+const RESTORED_NVS_KEY: &str = "restored";
+/// Unsent measurements from which the buffer is spilled.
+const THRESHOLD: usize = 48;
+/// Measurements taken after the last spill from which the buffer is spilled again.
+const STEP: usize = 24;
+/// Size of a stored segment, as limited by the size of NVS values.
+const MAX_SEGMENTS_SIZE: usize = 4000;
+
+/// Partition written to at a restart.
+static PARTITION: Mutex<Option<EspDefaultNvsPartition>> = Mutex::new(None);
+
+/// Spilled measurements with the offset of their slow clock times to UTC in seconds.
+pub struct Segment {
+    pub offset: i64,
+    pub measurements: Vec<Measurement>,
+}
+
+/// Spills the buffer before every software restart.
+pub fn install(partition: &EspDefaultNvsPartition) -> Result<()> {
+    *PARTITION.lock().unwrap() = Some(partition.clone());
+    esp!(unsafe { esp_idf_sys::esp_register_shutdown_handler(Some(spill_at_shutdown)) })?;
+    Ok(())
+}
+
+/// Doesn't spill if the heap is corrupted, as for a restart by [`crate::integrity`].
+extern "C" fn spill_at_shutdown() {
+    if !unsafe { esp_idf_sys::heap_caps_check_integrity_all(true) } {
+        return;
+    }
+    if let Some(partition) = PARTITION.lock().unwrap().as_ref() {
+        match spill(partition) {
+            Ok(count) => println!("spilled {} measurements before restarting", count),
+            Err(e) => println!("error spilling measurements: {}", e),
+        }
+    }
+}
+
+/// Spills the buffer if enough measurements were taken since the last spill.
+pub fn update(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let newest = Namespace::open(partition, NVS_NAMESPACE)?
+        .get_bytes(CURRENT_NVS_KEY)?
+        .and_then(|data| decode(&data).pop())
+        .and_then(|segment| segment.measurements.last().map(|m| m.time));
+    let unsent = unsent();
+    let unspilled = unsent
+        .iter()
+        .filter(|m| newest.map_or(true, |newest| m.time > newest))
+        .count();
+    if unsent.len() >= THRESHOLD && unspilled >= STEP {
+        println!("spilled {} measurements", spill(partition)?);
+    }
+    Ok(())
+}
+
+/// Writes the unsent measurements of the buffer to flash, returning their number.
+fn spill(partition: &EspDefaultNvsPartition) -> Result<usize> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let measurements = unsent();
+    let offset = unsafe { STATE.time_offset.or(STATE.manual_time_offset) };
+    match offset {
+        Some(offset) if !measurements.is_empty() => {
+            let count = measurements.len();
+            let segment = Segment {
+                offset,
+                measurements,
+            };
+            namespace.set_bytes(CURRENT_NVS_KEY, &encode(&[segment]))?;
+            Ok(count)
+        }
+        _ => {
+            namespace.remove(CURRENT_NVS_KEY)?;
+            Ok(0)
+        }
+    }
+}
+
+fn unsent() -> Vec<Measurement> {
+    unsafe { LOGS.measurements.iter().cloned().collect() }
+}
+
+/// Keeps the spill of the previous power period for upload, if the buffer was lost.
+pub fn restore(partition: &EspDefaultNvsPartition) -> Result<()> {
+    if unsafe { !LOGS.measurements.is_empty() } {
+        return Ok(());
+    }
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let current = match namespace.get_bytes(CURRENT_NVS_KEY)? {
+        Some(data) => decode(&data),
+        None => return Ok(()),
+    };
+    let mut segments = match namespace.get_bytes(RESTORED_NVS_KEY)? {
+        Some(data) => decode(&data),
+        None => Vec::new(),
+    };
+    let count: usize = current.iter().map(|s| s.measurements.len()).sum();
+    println!("restored {} spilled measurements", count);
+    segments.extend(current);
+    namespace.set_bytes(RESTORED_NVS_KEY, &encode(&segments))?;
+    namespace.remove(CURRENT_NVS_KEY)
+}
+
+/// Returns the measurements restored from earlier power periods.
+pub fn restored(partition: &EspDefaultNvsPartition) -> Result<Vec<Segment>> {
+    let namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    Ok(namespace
+        .get_bytes(RESTORED_NVS_KEY)?
+        .map_or_else(Vec::new, |data| decode(&data)))
+}
+
+pub fn clear_restored(partition: &EspDefaultNvsPartition) -> Result<()> {
+    Namespace::open(partition, NVS_NAMESPACE)?.remove(RESTORED_NVS_KEY)
+}
+
+/// Drops the spill of the buffer once it has been uploaded.
+pub fn clear(partition: &EspDefaultNvsPartition) -> Result<()> {
+    Namespace::open(partition, NVS_NAMESPACE)?.remove(CURRENT_NVS_KEY)
+}
+
+/// Prints the stored segments as hex, to be decoded on a host with `soil-codec`.
+pub fn dump(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    for key in [RESTORED_NVS_KEY, CURRENT_NVS_KEY] {
+        if let Some(data) = namespace.get_bytes(key)? {
+            let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("{}: {}", key, hex);
+        }
+    }
+    Ok(())
+}
+
+/// Segments are stored compressed, see [`series`], which fits about 2000 measurements. The oldest
+/// segments and measurements are dropped if they don't fit. Spills of firmware before the
+/// compression aren't decoded.
+fn encode(segments: &[Segment]) -> Vec<u8> {
+    let series: Vec<_> = segments
+        .iter()
+        .map(|segment| Series {
+            offset: segment.offset,
+            points: segment.measurements.iter().map(point).collect(),
+        })
+        .collect();
+    series::encode(&series, MAX_SEGMENTS_SIZE)
+}
+
+fn decode(data: &[u8]) -> Vec<Segment> {
+    series::decode(data)
+        .into_iter()
+        .map(|series| Segment {
+            offset: series.offset,
+            measurements: series.points.into_iter().map(measurement).collect(),
+        })
+        .collect()
+}
+
+fn point(m: &Measurement) -> Point {
+    Point {
+        time: m.time,
+        value: m.value,
+        info: m.info,
+        battery: m.battery,
+    }
+}
+
+fn measurement(point: Point) -> Measurement {
+    Measurement {
+        value: point.value,
+        info: point.info,
+        battery: point.battery,
+        time: point.time,
+    }
+}
+
+#[test]
+pub fn test_encode() {
+    let measurement = |time| Measurement {
+        value: 1234,
+        info: 0b1000_0101,
+        battery: 150,
+        time,
+    };
+    let segments = [
+        Segment {
+            offset: 1_700_000_000,
+            measurements: vec![measurement(10), measurement(20)],
+        },
+        Segment {
+            offset: -5,
+            measurements: vec![measurement(30)],
+        },
+    ];
+    let decoded = decode(&encode(&segments));
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0].offset, 1_700_000_000);
+    assert_eq!(decoded[1].offset, -5);
+    let m = &decoded[0].measurements[1];
+    assert_eq!(
+        (m.value, m.info, m.battery, m.time),
+        (1234, 0b1000_0101, 150, 20)
+    );
+    assert_eq!(decoded[1].measurements[0].time, 30);
+
+    // A full buffer fits.
+    let full = Segment {
+        offset: 0,
+        measurements: (0..crate::MAX_RECORDED_MEASUREMENTS as u32)
+            .map(|i| measurement(i * 600))
+            .collect(),
+    };
+    let decoded = decode(&encode(&[full]));
+    assert_eq!(
+        decoded[0].measurements.len(),
+        crate::MAX_RECORDED_MEASUREMENTS
+    );
+}
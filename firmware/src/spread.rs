@@ -0,0 +1,45 @@
+//! Spread of the samples of the readings, kept per cycle in RTC memory and uploaded as `sampling`
+//! points, so that a degrading sensor shows up as growing variance. See [`crate::sampling`] for
+//! the filtering of the samples.
+
+use crate::rtc::LOGS;
+
+/// Statistics of the cycles since the last upload, at most one per cycle.
+pub const MAX_STATS: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// Slow clock time in seconds of the cycle.
+    pub time: u32,
+    pub variance: f32,
+}
+
+pub fn record(time: u32, variance: f32) {
+    unsafe {
+        LOGS.sampling
+            .overwriting_push_back(Stats { time, variance });
+    }
+}
+
+/// Returns the statistics not uploaded yet, oldest first.
+pub fn pending() -> Vec<Stats> {
+    unsafe { LOGS.sampling.iter().copied().collect() }
+}
+
+pub fn clear() {
+    unsafe {
+        LOGS.sampling.clear();
+    }
+}
+
+#[test]
+pub fn test_record() {
+    for time in 0..MAX_STATS as u32 + 1 {
+        record(time, 2.0);
+    }
+    let stats = pending();
+    assert_eq!(stats.len(), MAX_STATS);
+    assert_eq!(stats[0].time, 1);
+    clear();
+    assert!(pending().is_empty());
+}
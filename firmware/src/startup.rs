@@ -0,0 +1,132 @@
+//! Initialization of the subsystems at startup. Each subsystem declares the subsystems it depends
+//! on. One that fails to initialize, or whose dependency did, is left out of the cycle instead of
+//! aborting it, as the reading and its upload don't need any of them. The subsystems that aren't
+//! ready are recorded in RTC memory and uploaded with the health point of the next upload.
+
+use anyhow::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    AwakeLimit,
+    Recorder,
+    Health,
+    Diagnostics,
+    SpillRestore,
+    SpillHook,
+}
+
+impl Subsystem {
+    /// All subsystems, in the order of their bits.
+    const ALL: [Subsystem; 6] = [
+        Subsystem::AwakeLimit,
+        Subsystem::Recorder,
+        Subsystem::Health,
+        Subsystem::Diagnostics,
+        Subsystem::SpillRestore,
+        Subsystem::SpillHook,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::AwakeLimit => "awake_limit",
+            Subsystem::Recorder => "recorder",
+            Subsystem::Health => "health",
+            Subsystem::Diagnostics => "diagnostics",
+            Subsystem::SpillRestore => "spill_restore",
+            Subsystem::SpillHook => "spill_hook",
+        }
+    }
+
+    /// Subsystems that have to be ready before this one is initialized.
+    pub fn dependencies(self) -> &'static [Subsystem] {
+        match self {
+            // Diagnostics are recorded for the first boots counted by the health counters.
+            Subsystem::Diagnostics => &[Subsystem::Health],
+            _ => &[],
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// Statuses of the subsystems initialized so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Startup {
+    ready: u16,
+    /// Subsystems that failed or whose dependencies weren't ready.
+    not_ready: u16,
+}
+
+impl Startup {
+    /// Initializes `subsystem` with `init` if its dependencies are ready, returning the result.
+    pub fn init<T>(&mut self, subsystem: Subsystem, init: impl FnOnce() -> Result<T>) -> Option<T> {
+        if let Some(dependency) = subsystem
+            .dependencies()
+            .iter()
+            .find(|dependency| !self.is_ready(**dependency))
+        {
+            println!(
+                "skipping {}, as {} isn't ready",
+                subsystem.name(),
+                dependency.name()
+            );
+            self.not_ready |= subsystem.bit();
+            return None;
+        }
+        match init() {
+            Ok(value) => {
+                self.ready |= subsystem.bit();
+                Some(value)
+            }
+            Err(e) => {
+                println!("error initializing {}: {:#}", subsystem.name(), e);
+                self.not_ready |= subsystem.bit();
+                None
+            }
+        }
+    }
+
+    pub fn is_ready(&self, subsystem: Subsystem) -> bool {
+        self.ready & subsystem.bit() != 0
+    }
+
+    /// Returns the subsystems that aren't ready as a bit set, to be recorded in RTC memory.
+    pub fn not_ready(&self) -> u16 {
+        self.not_ready
+    }
+}
+
+/// Returns the names of the subsystems in the bit set `subsystems`, separated by commas.
+pub fn names(subsystems: u16) -> String {
+    let names: Vec<_> = Subsystem::ALL
+        .iter()
+        .filter(|subsystem| subsystems & subsystem.bit() != 0)
+        .map(|subsystem| subsystem.name())
+        .collect();
+    names.join(",")
+}
+
+#[test]
+pub fn test_init() {
+    let mut startup = Startup::default();
+    assert_eq!(startup.init(Subsystem::Recorder, || Ok(3)), Some(3));
+    assert!(startup.is_ready(Subsystem::Recorder));
+    assert_eq!(
+        startup.init(Subsystem::Health, || -> Result<()> {
+            anyhow::bail!("no NVS")
+        }),
+        None
+    );
+    let mut initialized = false;
+    let diagnostics = startup.init(Subsystem::Diagnostics, || {
+        initialized = true;
+        Ok(())
+    });
+    assert_eq!(diagnostics, None);
+    assert!(!initialized);
+    assert!(!startup.is_ready(Subsystem::Diagnostics));
+    assert_eq!(names(startup.not_ready()), "health,diagnostics");
+    assert_eq!(names(0), "");
+}
@@ -0,0 +1,78 @@
+//! StatsD gauges over UDP for LAN deployments. Delivery isn't acknowledged, so gauges are only
+//! sent in addition to the regular upload, never instead of it, and failures to send them are
+//! ignored. In powered mode, every reading is sent as it's taken.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::net::UdpSocket;
+
+pub const NVS_NAMESPACE: &str = "statsd";
+const NVS_KEY: &str = "target";
+pub const DEFAULT_PREFIX: &str = "soil_moisture";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    /// Host and port of the StatsD server, usually port 8125.
+    pub address: String,
+    pub prefix: String,
+}
+
+/// Returns the StatsD target, if gauges are sent.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<Target>> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get(NVS_KEY)
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, target: Option<&Target>) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match target {
+        Some(target) => namespace.set(NVS_KEY, target),
+        None => namespace.remove(NVS_KEY),
+    }
+}
+
+impl Target {
+    /// Sends `gauges` in a single datagram.
+    pub fn send(&self, gauges: &[(&str, f64)]) -> Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.send_to(
+            format(&self.prefix, gauges).as_bytes(),
+            self.address.as_str(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Checks that `prefix` can be used in metric names.
+pub fn validate_prefix(prefix: &str) -> Result<()> {
+    if prefix.contains(|c: char| matches!(c, ':' | '|' | '@') || c.is_whitespace()) {
+        bail!("invalid StatsD prefix: {:?}", prefix);
+    }
+    Ok(())
+}
+
+fn format(prefix: &str, gauges: &[(&str, f64)]) -> String {
+    let lines: Vec<_> = gauges
+        .iter()
+        .map(|(name, value)| match prefix {
+            "" => format!("{}:{}|g", name, value),
+            _ => format!("{}.{}:{}|g", prefix, name, value),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[test]
+pub fn test_format() {
+    assert_eq!(
+        format("garden.balcony", &[("moisture", 1234.0), ("rssi", -67.5)]),
+        "garden.balcony.moisture:1234|g\ngarden.balcony.rssi:-67.5|g"
+    );
+    assert_eq!(format("", &[("moisture", 1.0)]), "moisture:1|g");
+
+    validate_prefix(DEFAULT_PREFIX).unwrap();
+    validate_prefix("").unwrap();
+    assert!(validate_prefix("a:b").is_err());
+    assert!(validate_prefix("a b").is_err());
+}
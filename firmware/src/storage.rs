@@ -0,0 +1,87 @@
+//! Typed persistence in NVS. Every module keeps its values in its own namespace, serialized as
+//! JSON, instead of handling NVS keys and buffers itself. Listeners subscribed to a namespace are
+//! notified of changed values, whichever `Namespace` changed them, so that settings changed by
+//! another task take effect in a running one.
+
+use crate::error::Classify;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Maximum size of a single value, as limited by the size of NVS blobs.
+const MAX_VALUE_SIZE: usize = 4000;
+
+type Listener = Box<dyn FnMut(&str) + Send>;
+
+/// Listeners with the name of the namespace they are subscribed to.
+static LISTENERS: Mutex<Vec<(&'static str, Listener)>> = Mutex::new(Vec::new());
+
+/// Registers a listener called with the key of every value of namespace `name` that changes. The
+/// listener must not change values itself.
+pub fn subscribe(name: &'static str, listener: impl FnMut(&str) + Send + 'static) {
+    LISTENERS.lock().unwrap().push((name, Box::new(listener)));
+}
+
+pub struct Namespace {
+    nvs: EspNvs<NvsDefault>,
+    name: String,
+}
+
+impl Namespace {
+    pub fn open(partition: &EspDefaultNvsPartition, name: &str) -> Result<Namespace> {
+        Ok(Namespace {
+            nvs: EspNvs::new(partition.clone(), name, true).storage()?,
+            name: name.into(),
+        })
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get_bytes(key)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_or_default<T: DeserializeOwned + Default>(&self, key: &str) -> Result<T> {
+        Ok(self.get(key)?.unwrap_or_default())
+    }
+
+    /// Stores `value`, notifying listeners if it differs from the stored one.
+    pub fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.set_bytes(key, &serde_json::to_vec(value)?)
+    }
+
+    pub fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut buffer = vec![0; MAX_VALUE_SIZE];
+        Ok(self.nvs.get_raw(key, &mut buffer)?.map(<[u8]>::to_vec))
+    }
+
+    pub fn set_bytes(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        if data.len() > MAX_VALUE_SIZE {
+            bail!("value of {} exceeds {} bytes", key, MAX_VALUE_SIZE);
+        }
+        if self.get_bytes(key)?.as_deref() == Some(data) {
+            return Ok(());
+        }
+        self.nvs.set_raw(key, data).storage()?;
+        self.notify(key);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        if self.nvs.remove(key)? {
+            self.notify(key);
+        }
+        Ok(())
+    }
+
+    fn notify(&self, key: &str) {
+        for (name, listener) in LISTENERS.lock().unwrap().iter_mut() {
+            if *name == self.name {
+                listener(key);
+            }
+        }
+    }
+}
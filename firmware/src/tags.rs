@@ -0,0 +1,35 @@
+//! Static tags such as site or plant species, stored in NVS and attached to every uploaded point.
+
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const NVS_NAMESPACE: &str = "tags";
+const NVS_KEY: &str = "tags";
+
+pub type Tags = Vec<(String, String)>;
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Tags> {
+    Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+}
+
+pub fn set(partition: &EspDefaultNvsPartition, key: &str, value: &str) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut tags: Tags = namespace.get_or_default(NVS_KEY)?;
+    match tags.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = value.into(),
+        None => tags.push((key.into(), value.into())),
+    }
+    namespace.set(NVS_KEY, &tags)
+}
+
+pub fn remove(partition: &EspDefaultNvsPartition, key: &str) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    let mut tags: Tags = namespace.get_or_default(NVS_KEY)?;
+    tags.retain(|(k, _)| k != key);
+    namespace.set(NVS_KEY, &tags)
+}
+
+pub fn replace(partition: &EspDefaultNvsPartition, tags: &Tags) -> Result<()> {
+    Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, tags)
+}
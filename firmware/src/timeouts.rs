@@ -0,0 +1,96 @@
+//! Timeouts of the stages of connecting, so that an event of the WiFi driver or the network
+//! interface that never arrives fails the cycle with the error code of its stage, instead of
+//! blocking until the awake time limit ends the cycle without telling which stage hung.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const NVS_NAMESPACE: &str = "timeouts";
+const NVS_KEY: &str = "stages";
+const MAX_SECONDS: u32 = 300;
+
+/// Timeouts in seconds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Until the WiFi driver has started.
+    pub wifi_start: u32,
+    /// Until associated with the access point.
+    pub connect: u32,
+    /// Until an IP address has been assigned by DHCP.
+    pub dhcp: u32,
+    /// Until the time has been synced by SNTP, also limited by the budget of the session.
+    pub sntp: u32,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            wifi_start: 5,
+            connect: 20,
+            dhcp: 15,
+            sntp: 30,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Timeouts> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+
+    pub fn wifi_start(&self) -> Duration {
+        Duration::from_secs(self.wifi_start.into())
+    }
+
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(self.connect.into())
+    }
+
+    pub fn dhcp(&self) -> Duration {
+        Duration::from_secs(self.dhcp.into())
+    }
+
+    pub fn sntp(&self) -> Duration {
+        Duration::from_secs(self.sntp.into())
+    }
+}
+
+pub fn validate(timeouts: &Timeouts) -> Result<()> {
+    let stages = [
+        ("wifi_start", timeouts.wifi_start),
+        ("connect", timeouts.connect),
+        ("dhcp", timeouts.dhcp),
+        ("sntp", timeouts.sntp),
+    ];
+    for (stage, seconds) in stages {
+        if seconds == 0 || seconds > MAX_SECONDS {
+            bail!("{} timeout must be from 1 to {} s", stage, MAX_SECONDS);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_validate() {
+    let timeouts = Timeouts::default();
+    validate(&timeouts).unwrap();
+    assert_eq!(timeouts.connect(), Duration::from_secs(20));
+    assert!(validate(&Timeouts {
+        dhcp: 0,
+        ..timeouts.clone()
+    })
+    .is_err());
+    assert!(validate(&Timeouts {
+        sntp: 301,
+        ..timeouts
+    })
+    .is_err());
+}
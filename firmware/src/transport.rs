@@ -0,0 +1,198 @@
+//! Transports that uploads are sent over. The upload builds batches of typed points from the
+//! buffered measurements, which every transport formats its own way, the server at the write URL
+//! as line protocol. A new transport implements [`Transport`] and is selected in [`configured`]
+//! from its settings in NVS.
+
+use crate::build_info::BuildInfo;
+use crate::coarse::{self, Sink};
+use crate::device_config::DeviceConfig;
+use crate::error::{Classify, FirmwareError};
+use crate::error_code::ErrorCode;
+use crate::http;
+use crate::points::MeasurementBatch;
+use crate::{dry_run, graphite, mqtt, otlp, postgrest};
+use anyhow::Result;
+use embedded_svc::http::Method;
+use esp_idf_svc::http::client::EspHttpConnection;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+pub trait Transport {
+    /// Sends a batch of points, returning once it has been acknowledged. Batches a server rejects
+    /// fail with [`ErrorCode::Http4xx`], so that they aren't retried.
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError>;
+
+    /// Whether sent batches count as uploaded.
+    fn acknowledges(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the configured transport, by default the server at the write URL via `http_client` if
+/// already connected. Batches are converted to coarse points if the sink is configured as coarse.
+pub fn configured(
+    partition: &EspDefaultNvsPartition,
+    http_client: Option<EspHttpConnection>,
+) -> Result<Box<dyn Transport>> {
+    let (sink, transport) = selected(partition, http_client)?;
+    let settings = coarse::Settings::load(partition)?;
+    if !settings.is_coarse(sink) {
+        return Ok(transport);
+    }
+    Ok(Box::new(Coarse {
+        inner: transport,
+        settings,
+        line_prefix: DeviceConfig::load(partition)?.line_prefix,
+    }))
+}
+
+fn selected(
+    partition: &EspDefaultNvsPartition,
+    http_client: Option<EspHttpConnection>,
+) -> Result<(Sink, Box<dyn Transport>)> {
+    if let Some(target) = graphite::load(partition)? {
+        return Ok((Sink::Graphite, Box::new(target)));
+    }
+    if let Some(target) = otlp::load(partition)? {
+        let transport = Otlp {
+            target,
+            http_client: http::new_connection()?,
+            resource: otlp_resource()?,
+        };
+        return Ok((Sink::Otlp, Box::new(transport)));
+    }
+    if let Some(target) = mqtt::load(partition)? {
+        return Ok((Sink::Mqtt, Box::new(target)));
+    }
+    if let Some(target) = postgrest::load(partition)? {
+        let transport = Postgrest {
+            target,
+            http_client: http::new_connection()?,
+        };
+        return Ok((Sink::Postgrest, Box::new(transport)));
+    }
+    let http_client = match http_client {
+        Some(http_client) => http_client,
+        None => http::new_connection()?,
+    };
+    let transport = Influx {
+        http_client,
+        device: DeviceConfig::load(partition)?,
+    };
+    Ok((Sink::Influx, Box::new(transport)))
+}
+
+fn request_headers<'a>(authorization: &'a str, content_length: &'a str) -> [(&'a str, &'a str); 2] {
+    [
+        ("Authorization", authorization),
+        ("Content-Length", content_length),
+    ]
+}
+
+/// The server at the write URL of the device, with the InfluxDB write API.
+struct Influx {
+    http_client: EspHttpConnection,
+    device: DeviceConfig,
+}
+
+impl Transport for Influx {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        let data = batch.to_line_protocol();
+        println!("{}", data);
+
+        let content_length = data.len().to_string();
+        let headers = request_headers(&self.device.authorization, &content_length);
+        http::submit(
+            &mut self.http_client,
+            Method::Post,
+            &self.device.write_url,
+            &headers,
+            data.as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Requests to the server at the write URL are validated and printed instead of sent, and nothing
+/// is acknowledged.
+pub struct DryRun {
+    pub device: DeviceConfig,
+}
+
+impl Transport for DryRun {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        let data = batch.to_line_protocol();
+        let content_length = data.len().to_string();
+        let headers = request_headers(&self.device.authorization, &content_length);
+        dry_run::print_request(&self.device.write_url, &headers, &data).http(ErrorCode::Http4xx)
+    }
+
+    fn acknowledges(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for graphite::Target {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        graphite::Target::send(self, batch)
+    }
+}
+
+impl Transport for mqtt::Target {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        mqtt::Target::send(self, batch)
+    }
+}
+
+struct Otlp {
+    target: otlp::Target,
+    http_client: EspHttpConnection,
+    resource: otlp::Resource,
+}
+
+impl Transport for Otlp {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        self.target
+            .send(&mut self.http_client, batch, &self.resource)
+    }
+}
+
+struct Postgrest {
+    target: postgrest::Target,
+    http_client: EspHttpConnection,
+}
+
+impl Transport for Postgrest {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        self.target.send(&mut self.http_client, batch)
+    }
+}
+
+/// Another transport, sent only the coarse points converted from each batch.
+struct Coarse {
+    inner: Box<dyn Transport>,
+    settings: coarse::Settings,
+    line_prefix: String,
+}
+
+impl Transport for Coarse {
+    fn send(&mut self, batch: &MeasurementBatch) -> Result<(), FirmwareError> {
+        let converted = self.settings.convert(batch, &self.line_prefix);
+        if converted.is_empty() {
+            return Ok(());
+        }
+        self.inner.send(&converted)
+    }
+
+    fn acknowledges(&self) -> bool {
+        self.inner.acknowledges()
+    }
+}
+
+fn otlp_resource() -> Result<otlp::Resource> {
+    let mut mac = [0; 6];
+    esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) })?;
+    Ok(otlp::Resource {
+        version: BuildInfo::current().version,
+        instance_id: mac.iter().map(|b| format!("{:02x}", b)).collect(),
+    })
+}
@@ -0,0 +1,55 @@
+//! Hours of the day in which the sensor uploads, so that a server at home isn't woken at night and
+//! transmissions are batched into the day. Outside the window, cycles only measure, even if the
+//! buffer is full, but pending alerts are still uploaded. Hours are local, with the UTC offset of
+//! the location configured, as the firmware has no time zone database. The time is that of the
+//! latest sync carried forward with the slow clock, so uploads aren't deferred before the first
+//! sync after power-on. The window is stored here and checked by [`crate::scheduler`].
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const NVS_NAMESPACE: &str = "upload";
+const NVS_KEY: &str = "window";
+
+pub use crate::scheduler::UploadWindow as Window;
+
+impl Window {
+    pub fn load(partition: &EspDefaultNvsPartition) -> Result<Window> {
+        Namespace::open(partition, NVS_NAMESPACE)?.get_or_default(NVS_KEY)
+    }
+
+    pub fn save(&self, partition: &EspDefaultNvsPartition) -> Result<()> {
+        Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, self)
+    }
+}
+
+pub fn validate(window: &Window) -> Result<()> {
+    if window.start > 23 || window.end > 23 {
+        bail!("upload window hours must be from 0 to 23");
+    }
+    if window.utc_offset.abs() > 14 * 60 {
+        bail!("invalid UTC offset of {} minutes", window.utc_offset);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_validate() {
+    let day = Window {
+        start: 8,
+        end: 20,
+        utc_offset: 0,
+    };
+    validate(&day).unwrap();
+    assert!(validate(&Window {
+        end: 24,
+        ..day.clone()
+    })
+    .is_err());
+    assert!(validate(&Window {
+        utc_offset: 15 * 60,
+        ..day
+    })
+    .is_err());
+}
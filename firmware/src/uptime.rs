@@ -0,0 +1,89 @@
+//! Counters of the wake cycles in RTC memory for modeling battery life: cycles, their total awake
+//! time and the uploads attempted and succeeded. They are uploaded as an `uptime` point with every
+//! upload and never reset, so they start over only when RTC memory is lost. Unlike the health
+//! counters in NVS, they don't wear the flash and cover the uploads as well.
+
+use crate::line_protocol::FieldValue;
+use crate::rtc::STATE;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Counters {
+    /// Awake time of the cycles up to the previous one.
+    pub awake_ms: u64,
+    /// Uploads attempted, including retries within a cycle.
+    pub upload_attempts: u32,
+    pub upload_successes: u32,
+}
+
+impl Counters {
+    pub const fn new() -> Counters {
+        Counters {
+            awake_ms: 0,
+            upload_attempts: 0,
+            upload_successes: 0,
+        }
+    }
+
+    /// Returns the fields of the point, with the number of wake cycles `wakes`.
+    pub fn fields(&self, wakes: u32) -> Vec<(&'static str, FieldValue<'static>)> {
+        vec![
+            ("wakes", FieldValue::Integer(wakes.into())),
+            ("awake_ms", FieldValue::Integer(self.awake_ms as i64)),
+            (
+                "upload_attempts",
+                FieldValue::Integer(self.upload_attempts.into()),
+            ),
+            (
+                "upload_successes",
+                FieldValue::Integer(self.upload_successes.into()),
+            ),
+        ]
+    }
+}
+
+/// Adds the awake time of the current cycle, at its end.
+pub fn add_awake_time(awake_ms: u32) {
+    unsafe {
+        STATE.uptime.awake_ms += u64::from(awake_ms);
+    }
+}
+
+pub fn count_upload_attempt() {
+    unsafe {
+        STATE.uptime.upload_attempts = STATE.uptime.upload_attempts.wrapping_add(1);
+    }
+}
+
+pub fn count_upload_success() {
+    unsafe {
+        STATE.uptime.upload_successes = STATE.uptime.upload_successes.wrapping_add(1);
+    }
+}
+
+/// Returns the counters with the number of wake cycles, including the current one.
+pub fn current() -> (Counters, u32) {
+    unsafe { (STATE.uptime, STATE.wakes) }
+}
+
+#[test]
+pub fn test_fields() {
+    let counters = Counters {
+        awake_ms: 5_000_000_000,
+        upload_attempts: 12,
+        upload_successes: 10,
+    };
+    let fields: Vec<_> = counters
+        .fields(40)
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            "wakes=40i",
+            "awake_ms=5000000000i",
+            "upload_attempts=12i",
+            "upload_successes=10i"
+        ]
+    );
+}
@@ -0,0 +1,29 @@
+//! Why the sensor is awake in the current cycle. The cause is tagged on the readings and skips of
+//! the cycle, so that wake storms and missed timer wakes can be diagnosed from the backend. The
+//! ESP32-C3 has no ULP coprocessor or touch sensor, so there are no threshold wakes, and the button
+//! isn't on a GPIO that wakes it, see [`crate::button`], so the timer is the only wake source.
+
+pub use crate::packing::WakeCause;
+use crate::rtc::STATE;
+use esp_idf_hal::reset::ResetReason;
+
+/// Returns the cause of the current cycle.
+pub fn current() -> WakeCause {
+    match ResetReason::get() {
+        ResetReason::DeepSleep => match unsafe { esp_idf_sys::esp_sleep_get_wakeup_cause() } {
+            esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => WakeCause::Timer,
+            _ => WakeCause::Other,
+        },
+        ResetReason::PowerOn => WakeCause::PowerOn,
+        ResetReason::Brownout => WakeCause::BrownOut,
+        _ => WakeCause::Reset,
+    }
+}
+
+/// Counts the current cycle, returning the number of cycles since RTC memory was lost.
+pub fn count() -> u32 {
+    unsafe {
+        STATE.wakes = STATE.wakes.wrapping_add(1);
+        STATE.wakes
+    }
+}
@@ -0,0 +1,138 @@
+//! Detection of waterings and of the soil drying out again in the readings of the on-board probe.
+//! Readings rise as the soil dries. A watering shows as a drop of at least `watered_drop` below the
+//! highest reading of the last `max_gap`, which also catches water soaking in from below over a
+//! few readings. The soil counts as dried out once readings have risen by `dried_rise` above the
+//! wettest reading since the watering.
+//!
+//! The thresholds are checked against the labeled series in `fixtures/watering`, so that tuning
+//! them doesn't silently change what is detected.
+
+use std::fmt;
+
+pub struct Thresholds {
+    /// Drop of readings in mV that counts as watering.
+    pub watered_drop: u16,
+    /// Time in seconds over which the drop of a watering may be spread.
+    pub max_gap: u32,
+    /// Rise of readings in mV above the wettest reading after which the soil counts as dried out.
+    pub dried_rise: u16,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds {
+            watered_drop: 300,
+            max_gap: 3 * 3600,
+            dried_rise: 400,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Watered,
+    DriedOut,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Watered => "watered",
+            Event::DriedOut => "dried_out",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Hash)]
+pub struct Detector {
+    /// Slow clock time in seconds and value of the previous reading.
+    previous: Option<(u32, u16)>,
+    /// Highest reading before the previous one within `max_gap`.
+    peak: Option<(u32, u16)>,
+    /// Slow clock time in seconds of the last watering. Drops within `max_gap` belong to it.
+    watered: Option<u32>,
+    /// Lowest reading since the last watering, until the soil has dried out.
+    wettest: Option<u16>,
+}
+
+impl Default for Detector {
+    fn default() -> Detector {
+        Detector::new()
+    }
+}
+
+impl Detector {
+    pub const fn new() -> Detector {
+        Detector {
+            previous: None,
+            peak: None,
+            watered: None,
+            wettest: None,
+        }
+    }
+
+    /// Adds the reading `value` taken at slow clock time `time`, returning the event it completes.
+    pub fn update(&mut self, thresholds: &Thresholds, time: u32, value: u16) -> Option<Event> {
+        // The slow clock starts over after a power loss.
+        if self.previous.is_some_and(|(previous, _)| time < previous) {
+            self.previous = None;
+            self.peak = None;
+            self.watered = None;
+        }
+        let recent = |at: u32| time - at <= thresholds.max_gap;
+        let peak = match (self.peak, self.previous) {
+            (Some(peak), Some(previous)) if recent(peak.0) && peak.1 >= previous.1 => Some(peak),
+            (_, previous) => previous.filter(|&(at, _)| recent(at)),
+        };
+        self.peak = peak;
+        self.previous = Some((time, value));
+
+        let dropped =
+            peak.is_some_and(|(_, peak)| peak.saturating_sub(value) >= thresholds.watered_drop);
+        if dropped && !self.watered.is_some_and(recent) {
+            self.watered = Some(time);
+            self.wettest = Some(value);
+            return Some(Event::Watered);
+        }
+
+        let wettest = self.wettest?.min(value);
+        if value - wettest >= thresholds.dried_rise {
+            self.wettest = None;
+            return Some(Event::DriedOut);
+        }
+        self.wettest = Some(wettest);
+        None
+    }
+}
+
+#[test]
+pub fn test_fixtures() {
+    let fixtures = [
+        include_str!("../fixtures/watering/top_watered.csv"),
+        include_str!("../fixtures/watering/bottom_watered.csv"),
+        include_str!("../fixtures/watering/no_watering.csv"),
+    ];
+    for fixture in fixtures {
+        let mut detector = Detector::new();
+        let mut detected = Vec::new();
+        let mut labeled = Vec::new();
+        for line in fixture.lines().filter(|l| !l.starts_with(['#', 't'])) {
+            let columns: Vec<_> = line.split(',').collect();
+            let time: u32 = columns[0].parse().unwrap();
+            let value = columns[1].parse().unwrap();
+            if let Some(event) = detector.update(&Thresholds::default(), time, value) {
+                detected.push((time, event.name()));
+            }
+            if !columns[2].is_empty() {
+                labeled.push((time, columns[2]));
+            }
+        }
+        assert_eq!(detected, labeled, "{}", fixture.lines().next().unwrap());
+    }
+}
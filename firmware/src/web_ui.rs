@@ -0,0 +1,122 @@
+//! Single-page web UI of powered mode, served from flash at `/`. It shows the live reading and the
+//! buffered history and edits the configuration, using the other endpoints of the local server
+//! and those added here. It is served over plain HTTP, as the server of esp-idf-svc 0.43 has no TLS
+//! configuration, so the password of `local_auth` is only safe on trusted networks.
+
+use crate::local_auth::Verifier;
+use crate::{config, Measurement};
+use anyhow::Result;
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const PAGE: &str = include_str!("web_ui.html");
+const MAX_CONFIG_SIZE: usize = 4096;
+
+/// Adds the UI to `server`. `history` are the buffered measurements, which don't change while
+/// the sensor stays online.
+pub fn register(
+    server: &mut EspHttpServer,
+    partition: EspDefaultNvsPartition,
+    history: Vec<Measurement>,
+    verifier: Verifier,
+) -> Result<()> {
+    server.fn_handler("/", Method::Get, |request| {
+        let mut response =
+            request.into_response(200, None, &[("Content-Type", "text/html; charset=utf-8")])?;
+        response.write_all(PAGE.as_bytes())?;
+        Ok(())
+    })?;
+
+    let history_verifier = verifier.clone();
+    server.fn_handler("/api/history", Method::Get, move |request| {
+        if !history_verifier.verify_header(request.header("Authorization")) {
+            request.into_status_response(401)?;
+            return Ok(());
+        }
+        let json = history_json(&history, crate::slow_clock_seconds());
+        let mut response =
+            request.into_response(200, None, &[("Content-Type", "application/json")])?;
+        response.write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    let export_partition = partition.clone();
+    let export_verifier = verifier.clone();
+    server.fn_handler("/api/config", Method::Get, move |request| {
+        if !export_verifier.verify_header(request.header("Authorization")) {
+            request.into_status_response(401)?;
+            return Ok(());
+        }
+        let text = config::export(&export_partition)?;
+        let mut response = request.into_response(200, None, &[("Content-Type", "text/plain")])?;
+        response.write_all(text.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler("/api/config", Method::Post, move |mut request| {
+        if !verifier.verify_header(request.header("Authorization")) {
+            request.into_status_response(401)?;
+            return Ok(());
+        }
+        let mut body = Vec::new();
+        let mut buffer = [0; 256];
+        loop {
+            let n = request.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            if body.len() + n > MAX_CONFIG_SIZE {
+                request.into_status_response(413)?;
+                return Ok(());
+            }
+            body.extend_from_slice(&buffer[..n]);
+        }
+        match config::import(&partition, &String::from_utf8_lossy(&body)) {
+            Ok(()) => request.into_ok_response()?,
+            Err(e) => {
+                println!("error importing configuration: {}", e);
+                let mut response = request.into_status_response(400)?;
+                response.write_all(e.to_string().as_bytes())?;
+                response
+            }
+        };
+        Ok(())
+    })?;
+
+    println!("serving web UI");
+    Ok(())
+}
+
+/// Lists measurements oldest first, with their age in seconds at slow clock time `now`.
+fn history_json(history: &[Measurement], now: u32) -> String {
+    let entries: Vec<_> = history
+        .iter()
+        .map(|m| {
+            serde_json::json!({
+                "age": now.saturating_sub(m.time),
+                "value": m.value,
+                "maintenance": m.maintenance(),
+                "watered": m.watered(),
+            })
+        })
+        .collect();
+    serde_json::Value::from(entries).to_string()
+}
+
+#[test]
+pub fn test_history_json() {
+    use crate::wake::WakeCause;
+    let history = [
+        Measurement::new(1200, 100, WakeCause::Timer, false, true),
+        Measurement::new(1300, 3700, WakeCause::Timer, true, false),
+    ];
+    assert_eq!(
+        history_json(&history, 4000),
+        concat!(
+            r#"[{"age":3900,"maintenance":false,"value":1200,"watered":true},"#,
+            r#"{"age":300,"maintenance":true,"value":1300,"watered":false}]"#
+        )
+    );
+}
@@ -0,0 +1,27 @@
+//! WiFi credentials provisioned at runtime and stored in NVS. The credentials compiled into the
+//! firmware are used until others have been provisioned.
+
+use crate::storage::Namespace;
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+const NVS_NAMESPACE: &str = "wifi";
+const SSID_NVS_KEY: &str = "ssid";
+const PASSWORD_NVS_KEY: &str = "password";
+
+/// Returns SSID and password.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<(String, String)> {
+    let namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    match namespace.get(SSID_NVS_KEY)? {
+        Some(ssid) => Ok((ssid, namespace.get_or_default(PASSWORD_NVS_KEY)?)),
+        None => Ok((crate::WIFI_SSID.into(), crate::WIFI_PASSWORD.into())),
+    }
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, ssid: &str, password: &str) -> Result<()> {
+    let mut namespace = Namespace::open(partition, NVS_NAMESPACE)?;
+    namespace.set(PASSWORD_NVS_KEY, &password)?;
+    namespace.set(SSID_NVS_KEY, &ssid)?;
+    println!("WiFi credentials for {} stored.", ssid);
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Control of the MAC address used on WiFi. Devices either keep a stable address, e.g. for DHCP
+//! reservations on the router, or use a random one for every association for privacy on shared
+//! networks.
+
+use crate::storage::Namespace;
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_sys::esp;
+use std::fmt;
+use std::str::FromStr;
+
+const NVS_NAMESPACE: &str = "wifi";
+const NVS_KEY: &str = "mac";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MacMode {
+    /// The address burnt into the chip.
+    #[default]
+    Factory,
+    Static([u8; 6]),
+    /// A random locally administered address for every association.
+    Random,
+}
+
+impl FromStr for MacMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "factory" => return Ok(MacMode::Factory),
+            "random" => return Ok(MacMode::Random),
+            _ => {}
+        }
+        let mut mac = [0; 6];
+        let mut parts = s.split(':');
+        for byte in &mut mac {
+            *byte = match parts.next().map(|part| u8::from_str_radix(part, 16)) {
+                Some(Ok(byte)) => byte,
+                _ => bail!("invalid MAC address: {}", s),
+            };
+        }
+        if parts.next().is_some() {
+            bail!("invalid MAC address: {}", s);
+        }
+        if mac[0] & 0x01 != 0 {
+            bail!("MAC address {} is a multicast address", s);
+        }
+        Ok(MacMode::Static(mac))
+    }
+}
+
+impl fmt::Display for MacMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacMode::Factory => f.write_str("factory"),
+            MacMode::Random => f.write_str("random"),
+            MacMode::Static(mac) => write!(
+                f,
+                "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+            ),
+        }
+    }
+}
+
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<MacMode> {
+    match Namespace::open(partition, NVS_NAMESPACE)?.get::<String>(NVS_KEY)? {
+        Some(mode) => mode.parse(),
+        None => Ok(MacMode::Factory),
+    }
+}
+
+pub fn save(partition: &EspDefaultNvsPartition, mode: MacMode) -> Result<()> {
+    Namespace::open(partition, NVS_NAMESPACE)?.set(NVS_KEY, &mode.to_string())
+}
+
+/// Sets the station MAC address according to `mode`. WiFi has to be configured, but not started.
+pub fn apply(mode: MacMode) -> Result<()> {
+    let mac = match mode {
+        MacMode::Factory => return Ok(()),
+        MacMode::Static(mac) => mac,
+        MacMode::Random => {
+            let mut mac = [0; 6];
+            unsafe {
+                esp_idf_sys::esp_fill_random(mac.as_mut_ptr() as *mut _, mac.len() as _);
+            }
+            // Locally administered unicast address.
+            mac[0] = (mac[0] & 0xfc) | 0x02;
+            mac
+        }
+    };
+    esp!(unsafe {
+        esp_idf_sys::esp_wifi_set_mac(esp_idf_sys::wifi_interface_t_WIFI_IF_STA, mac.as_ptr())
+    })?;
+    println!("using MAC address {}", MacMode::Static(mac));
+    Ok(())
+}
+
+#[test]
+pub fn test_mac_mode() {
+    for mode in ["factory", "random", "02:00:5e:10:aa:0f"] {
+        assert_eq!(mode.parse::<MacMode>().unwrap().to_string(), mode);
+    }
+    assert_eq!(
+        "02:00:5E:10:AA:0F".parse::<MacMode>().unwrap(),
+        MacMode::Static([0x02, 0x00, 0x5e, 0x10, 0xaa, 0x0f])
+    );
+    assert!("01:00:5e:10:aa:0f".parse::<MacMode>().is_err());
+    assert!("02:00:5e:10:aa".parse::<MacMode>().is_err());
+    assert!("02:00:5e:10:aa:0f:01".parse::<MacMode>().is_err());
+    assert!("stable".parse::<MacMode>().is_err());
+}
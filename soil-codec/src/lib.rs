@@ -0,0 +1,224 @@
+//! Decoders of the binary formats of the soil moisture sensor, for gateways and analysis scripts
+//! on a host. The modules of the firmware that define the formats are included as they are, so
+//! that decoding stays in lockstep with the encoders of the firmware:
+//!
+//! - [`series`]: measurements spilled to flash, as printed by the console command `spill dump`
+//! - [`packing`]: the info and battery bytes of a measurement
+//! - [`peer_message`]: the time broadcast between sensors over ESP-NOW
+//!
+//! Cycles recorded by the debug recorder, as printed by the console command `record dump`, are
+//! replayed through the filtering of [`sampling`] and the detection of [`watering`], so that
+//! anomalies observed in the field can be reproduced and debugged on a host.
+//!
+//! The firmware has no LoRa radio, and its RTC state is raw memory without a stable layout, so
+//! neither has a format to decode.
+
+#[path = "../../firmware/src/packing.rs"]
+pub mod packing;
+#[path = "../../firmware/src/peer_message.rs"]
+pub mod peer_message;
+#[path = "../../firmware/src/sampling.rs"]
+pub mod sampling;
+#[path = "../../firmware/src/series.rs"]
+pub mod series;
+#[path = "../../firmware/src/watering.rs"]
+pub mod watering;
+
+use packing::Info;
+use sampling::Sampled;
+use std::str::FromStr;
+
+/// A measurement decoded from a spill.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    /// Unix time in seconds.
+    pub time: i64,
+    /// Raw reading in mV.
+    pub value: u16,
+    pub info: Info,
+    pub battery_mv: Option<u16>,
+}
+
+/// Decodes the measurements of a spill as printed by `spill dump`, a line `<key>: <hex>` for each
+/// stored value.
+pub fn decode_spill_dump(text: &str) -> Result<Vec<Measurement>, String> {
+    let mut measurements = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let hex = line.rsplit(':').next().unwrap_or_default().trim();
+        if hex.is_empty() {
+            continue;
+        }
+        let data = parse_hex(hex).ok_or_else(|| format!("line {}: invalid hex", number + 1))?;
+        let decoded = series::decode(&data);
+        if decoded.is_empty() {
+            return Err(format!("line {}: unknown format", number + 1));
+        }
+        for series in decoded {
+            measurements.extend(series.points.into_iter().map(|point| Measurement {
+                time: series.offset + i64::from(point.time),
+                value: point.value,
+                info: Info::unpack(point.info),
+                battery_mv: packing::unpack_battery(point.battery),
+            }));
+        }
+    }
+    Ok(measurements)
+}
+
+/// A recorded cycle, replayed on the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Replayed {
+    /// Slow clock time in seconds.
+    pub time: u32,
+    /// Reading in mV as recorded by the sensor.
+    pub recorded: Option<u16>,
+    /// Reading filtered from the recorded samples, if there are any.
+    pub sampled: Option<Sampled>,
+    /// WiFi signal strength in dBm, if connected.
+    pub rssi: Option<i8>,
+    pub awake_ms: u32,
+    /// Event detected in the reading with the default thresholds.
+    pub event: Option<watering::Event>,
+}
+
+/// Replays the cycles of a record dump, as printed by `record dump`, in order. The filtered
+/// reading is passed on to the watering detector, or the recorded one for cycles recorded
+/// without samples.
+pub fn replay_record_dump(text: &str) -> Result<Vec<Replayed>, String> {
+    let thresholds = watering::Thresholds::default();
+    let mut detector = watering::Detector::new();
+    let mut replayed = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("time,") {
+            continue;
+        }
+        let invalid = |column| format!("line {}: invalid {}", number + 1, column);
+        let columns: Vec<_> = line.split(',').collect();
+        let (time, recorded, rssi, awake_ms, samples) = match columns[..] {
+            [time, recorded, rssi, awake_ms] => (time, recorded, rssi, awake_ms, ""),
+            [time, recorded, rssi, awake_ms, samples] => (time, recorded, rssi, awake_ms, samples),
+            _ => return Err(format!("line {}: expected 4 or 5 columns", number + 1)),
+        };
+        let mut samples = samples
+            .split_whitespace()
+            .map(|sample| sample.parse())
+            .collect::<Result<Vec<u16>, _>>()
+            .map_err(|_| invalid("samples"))?;
+        let cycle = Replayed {
+            time: time.parse().map_err(|_| invalid("time"))?,
+            recorded: parse_optional(recorded).map_err(|_| invalid("value"))?,
+            sampled: (!samples.is_empty()).then(|| sampling::filter(&mut samples)),
+            rssi: parse_optional(rssi).map_err(|_| invalid("rssi"))?,
+            awake_ms: awake_ms.parse().map_err(|_| invalid("awake time"))?,
+            event: None,
+        };
+        let value = cycle
+            .sampled
+            .map(|sampled| sampled.value)
+            .or(cycle.recorded);
+        replayed.push(Replayed {
+            event: value.and_then(|value| detector.update(&thresholds, cycle.time, value)),
+            ..cycle
+        });
+    }
+    Ok(replayed)
+}
+
+/// Parses a column that is empty if there's no value.
+fn parse_optional<T: FromStr>(column: &str) -> Result<Option<T>, T::Err> {
+    (!column.is_empty()).then(|| column.parse()).transpose()
+}
+
+pub fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[test]
+pub fn test_decode_spill_dump() {
+    use packing::WakeCause;
+    use series::{Point, Series};
+
+    let info = Info {
+        maintenance: false,
+        watered: true,
+        wake_cause: WakeCause::Timer,
+        channel: 4,
+    };
+    let point = |time, value| Point {
+        time,
+        value,
+        info: info.pack(),
+        battery: packing::pack_battery(Some(3000)),
+    };
+    let data = series::encode(
+        &[
+            Series {
+                offset: 1_700_000_000,
+                points: vec![point(10, 2100), point(610, 2095)],
+            },
+            Series {
+                offset: -5,
+                points: vec![point(30, 1800)],
+            },
+        ],
+        4000,
+    );
+    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    let measurement = |time, value| Measurement {
+        time,
+        value,
+        info,
+        battery_mv: Some(3000),
+    };
+    assert_eq!(
+        decode_spill_dump(&format!("restored: {}\n\n", hex)).unwrap(),
+        [
+            measurement(1_700_000_010, 2100),
+            measurement(1_700_000_610, 2095),
+            measurement(25, 1800)
+        ]
+    );
+    assert!(decode_spill_dump("current: 0x").is_err());
+    assert!(decode_spill_dump("current: 00").is_err());
+    assert!(decode_spill_dump("").unwrap().is_empty());
+}
+
+#[test]
+pub fn test_replay_record_dump() {
+    let replayed = replay_record_dump(
+        "time,value,rssi,awake_ms,samples\n\
+         1000,2600,,400,2600 2601 2599 2600 2600 2602 2598 2600 2600\n\
+         4600,2610,-67,4300,2610 2610 2611 2609 2610 2610 2610 3300 0\n\
+         8200,2100,,300\n\
+         \n\
+         11800,,,250,\n",
+    )
+    .unwrap();
+    assert_eq!(replayed.len(), 4);
+    assert_eq!(replayed[0].sampled.unwrap().value, 2600);
+    assert_eq!(replayed[0].event, None);
+    // The outliers are trimmed, but show in the variance.
+    let sampled = replayed[1].sampled.unwrap();
+    assert_eq!(sampled.value, 2610);
+    assert!(sampled.variance > 100_000.0);
+    assert_eq!(replayed[1].rssi, Some(-67));
+    assert_eq!(replayed[2].sampled, None);
+    assert_eq!(replayed[2].event, Some(watering::Event::Watered));
+    assert_eq!(
+        replayed[3],
+        Replayed {
+            time: 11800,
+            recorded: None,
+            sampled: None,
+            rssi: None,
+            awake_ms: 250,
+            event: None,
+        }
+    );
+    assert!(replay_record_dump("1000,2100,,400,21x0\n").is_err());
+    assert!(replay_record_dump("1000,2100,-67\n").is_err());
+}
@@ -0,0 +1,92 @@
+//! Decodes binary formats of the sensor into CSV, see the library for the formats.
+//!
+//! ```text
+//! soil-codec spill [FILE ...]   output of `spill dump`, from the files or standard input
+//! soil-codec replay [FILE ...]  output of `record dump`, replayed through the firmware's pipeline
+//! soil-codec peer HEX ...       payloads of ESP-NOW time broadcasts
+//! ```
+
+use soil_codec::{decode_spill_dump, parse_hex, peer_message, replay_record_dump};
+use std::io::{self, Read};
+use std::{env, fs, process};
+
+/// Reads the files at `paths`, or standard input if there are none.
+fn read_input(paths: &[String]) -> Result<String, String> {
+    let mut text = String::new();
+    if paths.is_empty() {
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("standard input: {}", e))?;
+    }
+    for path in paths {
+        text += &fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn spill(paths: &[String]) -> Result<(), String> {
+    let text = read_input(paths)?;
+    println!("time,value,channel,wake_cause,maintenance,watered,battery_mv");
+    for m in decode_spill_dump(&text)? {
+        println!(
+            "{},{},{},{},{},{},{}",
+            m.time,
+            m.value,
+            m.info.channel,
+            m.info.wake_cause.name(),
+            m.info.maintenance,
+            m.info.watered,
+            m.battery_mv.map_or(String::new(), |mv| mv.to_string())
+        );
+    }
+    Ok(())
+}
+
+fn replay(paths: &[String]) -> Result<(), String> {
+    let text = read_input(paths)?;
+    println!("time,recorded,value,variance,rssi,awake_ms,event");
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    for cycle in replay_record_dump(&text)? {
+        println!(
+            "{},{},{},{},{},{},{}",
+            cycle.time,
+            optional(cycle.recorded.map(|v| v.to_string())),
+            optional(cycle.sampled.map(|s| s.value.to_string())),
+            optional(cycle.sampled.map(|s| s.variance.to_string())),
+            optional(cycle.rssi.map(|v| v.to_string())),
+            cycle.awake_ms,
+            cycle.event.map_or("", |event| event.name())
+        );
+    }
+    Ok(())
+}
+
+fn peer(payloads: &[String]) -> Result<(), String> {
+    println!("unix_millis");
+    for payload in payloads {
+        let unix_millis = parse_hex(payload)
+            .as_deref()
+            .and_then(peer_message::decode)
+            .ok_or_else(|| format!("not a time broadcast: {}", payload))?;
+        println!("{}", unix_millis);
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    match args.split_first() {
+        Some((command, args)) if command == "spill" => spill(args),
+        Some((command, args)) if command == "replay" => replay(args),
+        Some((command, args)) if command == "peer" => peer(args),
+        _ => Err("usage: soil-codec spill [FILE ...] | replay [FILE ...] | peer HEX ...".into()),
+    }
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}